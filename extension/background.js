@@ -1,5 +1,6 @@
 var webRequests = [];
 var alt_down = false;
+const ATOM_DEFAULT_ADDRESS = '127.0.0.1:6682'; // until another one is set in the options
 
 chrome.runtime.onMessage.addListener((message, sender, sendResponse) => {
     if ('key' in message && 'state' in message) {
@@ -70,7 +71,7 @@ chrome.webRequest.onBeforeRequest.addListener(
         webRequests.push({
             url: details.url,
             requestHeaders: details.requestHeaders,
-            body: details.requestBody,
+            body: encodeRequestBody(details.requestBody),
             method: details.method,
             sequential: true,
         });
@@ -102,23 +103,59 @@ function toBinary(string) {
     return btoa(String.fromCharCode(...new Uint8Array(codeUnits.buffer)));
 }
 
+// raw bodies go to ATOM byte for byte as base64, the browser only hands over the fields of
+// parsed forms so those are sent url encoded. Encoded right away since storage drops ArrayBuffers
+function encodeRequestBody(requestBody) {
+    if (!requestBody) return null;
+    if (requestBody.raw) {
+        let binary = '';
+        requestBody.raw
+            .filter((part) => part.bytes)
+            .forEach((part) => new Uint8Array(part.bytes).forEach((byte) => (binary += String.fromCharCode(byte))));
+        return { body: btoa(binary), base64: true, form: false };
+    }
+    if (requestBody.formData) {
+        let params = new URLSearchParams();
+        Object.entries(requestBody.formData).forEach(([name, values]) =>
+            values.forEach((value) => params.append(name, value))
+        );
+        return { body: params.toString(), base64: false, form: true };
+    }
+    return null;
+}
+
 function postData(jsonObject, id) {
     if (alt_down) return;
 
     let jsonString = JSON.stringify(jsonObject);
     console.log(jsonObject);
 
-    fetch(`http://127.0.0.1:6682/`, {
-        method: 'POST',
-        cache: 'no-cache',
-        mode: 'no-cors',
-        body: `${jsonString}\n<END>\n`,
-    })
-        .then((value) => value.text())
-        .then((text) => {
-            chrome.downloads.cancel(id, (e) => { });
+    // the address is set with the token in the options, ATOM's listen address can be changed
+    chrome.storage.local.get(['atomAddress', 'atomToken'], (items) => {
+        if (!items.atomToken) {
+            console.log('ATOM pairing token is not set, open the extension options to pair');
+            return;
+        }
+
+        fetch(`http://${items.atomAddress || ATOM_DEFAULT_ADDRESS}/downloads`, {
+            method: 'POST',
+            cache: 'no-cache',
+            headers: {
+                'Content-Type': 'application/json',
+                Authorization: `Bearer ${items.atomToken}`,
+                'X-Atom-Client': 'browser-extension',
+            },
+            body: jsonString,
         })
-        .catch(console.log);
+            .then((response) => {
+                if (!response.ok) throw new Error(`ATOM rejected the download: ${response.status}`);
+                return response.text();
+            })
+            .then((text) => {
+                chrome.downloads.cancel(id, (e) => { });
+            })
+            .catch(console.log);
+    });
 }
 
 function handleDownload(e) {
@@ -147,9 +184,10 @@ function handleDownload(e) {
         if (index != -1) {
             let req = httpRequests.httpRequests[index];
             jsonObject.sequential = req.sequential;
-            if (req.method === 'POST') {
-                jsonObject.method = 'POST';
-                jsonObject.body = new URLSearchParams(req.body.formData).toString();
+            jsonObject.method = req.method || 'GET';
+            if (req.body) {
+                jsonObject.body = req.body.body;
+                jsonObject.body_base64 = req.body.base64;
             }
             let requestHeaders = httpRequests.httpRequests[index].requestHeaders || [];
             for (let i = 0; i < requestHeaders.length; i++) {
@@ -158,6 +196,13 @@ function handleDownload(e) {
                     headers[values[0]] = values[1];
                 }
             }
+            // a multipart form can't be rebuilt from its fields, it is sent url encoded instead
+            if (req.body && req.body.form) {
+                Object.keys(headers)
+                    .filter((name) => name.toLowerCase() === 'content-type')
+                    .forEach((name) => delete headers[name]);
+                headers['Content-Type'] = 'application/x-www-form-urlencoded';
+            }
             jsonObject.headers = headers;
         } else {
             headers['referer'] = e.referrer;
@@ -0,0 +1,38 @@
+const DEFAULT_ADDRESS = '127.0.0.1:6682';
+const addressInput = document.getElementById('address');
+const tokenInput = document.getElementById('token');
+const status = document.getElementById('status');
+
+chrome.storage.local.get(['atomAddress', 'atomToken'], (items) => {
+    addressInput.value = items.atomAddress || DEFAULT_ADDRESS;
+    tokenInput.value = items.atomToken || '';
+});
+
+document.getElementById('save').addEventListener('click', () => {
+    const address = addressInput.value.trim().replace(/^https?:\/\//, '').replace(/\/+$/, '') || DEFAULT_ADDRESS;
+    const token = tokenInput.value.trim();
+    chrome.storage.local.set({ atomAddress: address }, () => {
+        // trades the pairing token for a token of the extension's own, ATOM renews the pairing
+        // token when a client is revoked and paired clients keep working
+        status.textContent = 'Saved, pairing with ATOM...';
+        fetch(`http://${address}/pair`, {
+            method: 'POST',
+            headers: { Authorization: `Bearer ${token}`, 'X-Atom-Client': 'browser-extension' },
+        })
+            .then((response) => {
+                if (!response.ok) {
+                    status.textContent = `Saved, but ATOM at ${address} rejected the token (${response.status}).`;
+                    return;
+                }
+                return response.json().then((paired) =>
+                    chrome.storage.local.set({ atomToken: paired.token }, () => {
+                        tokenInput.value = paired.token;
+                        status.textContent = `Saved, paired with ATOM at ${address}.`;
+                    })
+                );
+            })
+            .catch(() => {
+                status.textContent = `Saved, but ATOM is not reachable at ${address}.`;
+            });
+    });
+});
@@ -0,0 +1,675 @@
+use super::ApiResponse;
+use crate::{
+    components::{atom::Atom, download::AtomDownload},
+    engine::EngineCommand,
+    messages::{DownloadMessage, Message},
+    utils::helpers::ATOM_MAX_THREADS,
+};
+use iced::Task as Command;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+pub const ARIA2_RPC_PATH: &str = "jsonrpc";
+const ARIA2_VERSION: &str = "1.37.0";
+const ARIA2_TOKEN_PREFIX: &str = "token:";
+// aria2 reports every failure with this code
+const ARIA2_ERROR_CODE: i64 = 1;
+
+type RpcResult = Result<Value, String>;
+
+/**
+ * aria2 GIDs are 16 hex digits, the first half of the download id is used as GID
+ */
+pub fn format_gid(id: Uuid) -> String {
+    format!("{:016x}", id.as_u64_pair().0)
+}
+
+/**
+ * aria2 clients pass the RPC secret as the first positional param (`token:<secret>`)
+ */
+pub fn secret_from_body(body: &Value) -> Option<String> {
+    let call = match body {
+        Value::Array(calls) => calls.first()?,
+        call => call,
+    };
+
+    call.get("params")?
+        .as_array()?
+        .first()?
+        .as_str()?
+        .strip_prefix(ARIA2_TOKEN_PREFIX)
+        .map(str::to_string)
+}
+
+fn aria2_status(download: &AtomDownload) -> &'static str {
+    match download.status() {
+        "downloading" | "joining" | "verifying" | "seeding" => "active",
+        "finished" => "complete",
+        "failed" | "mismatch" => "error",
+        "deleted" => "removed",
+        _ => "paused",
+    }
+}
+
+fn status_object(download: &AtomDownload, keys: Option<&Vec<Value>>) -> Value {
+    let speed = if download.downloading {
+        (download.transfer_rate * 1000.0 * 1000.0) as usize
+    } else {
+        0
+    };
+    let threads = download.threads.max(1);
+    let connections = if download.downloading { threads } else { 0 };
+    let path = std::path::Path::new(&download.file_path)
+        .join(&download.file_name)
+        .to_string_lossy()
+        .to_string();
+
+    let status = json!({
+        "gid": format_gid(download.id),
+        "status": aria2_status(download),
+        "totalLength": download.size.to_string(),
+        "completedLength": download.downloaded.to_string(),
+        "uploadLength": "0",
+        "downloadSpeed": speed.to_string(),
+        "uploadSpeed": "0",
+        "connections": connections.to_string(),
+        "numPieces": threads.to_string(),
+        "pieceLength": (download.size / threads as usize).to_string(),
+        "dir": download.file_path,
+        "errorCode": if download.error.is_empty() { "0" } else { "1" },
+        "errorMessage": download.error,
+        "files": [{
+            "index": "1",
+            "path": path,
+            "length": download.size.to_string(),
+            "completedLength": download.downloaded.to_string(),
+            "selected": "true",
+            "uris": std::iter::once(&download.url)
+                .chain(&download.mirrors)
+                .filter(|url| !download.dropped_mirrors.contains(url))
+                .map(|url| json!({ "uri": url, "status": "used" }))
+                .collect::<Vec<_>>(),
+        }],
+    });
+
+    match (keys, status) {
+        (Some(keys), Value::Object(fields)) if !keys.is_empty() => Value::Object(
+            fields
+                .into_iter()
+                .filter(|(key, _)| keys.iter().any(|wanted| wanted.as_str() == Some(key)))
+                .collect::<Map<String, Value>>(),
+        ),
+        (_, status) => status,
+    }
+}
+
+fn option_string(options: Option<&Value>, name: &str) -> Option<String> {
+    match options?.get(name)? {
+        Value::String(value) => Some(value.to_string()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+fn option_headers(options: Option<&Value>) -> HashMap<String, String> {
+    let headers = match options.and_then(|options| options.get("header")) {
+        Some(Value::Array(headers)) => headers.iter().filter_map(Value::as_str).collect(),
+        Some(Value::String(header)) => vec![header.as_str()],
+        _ => vec![],
+    };
+
+    headers
+        .into_iter()
+        .filter_map(|header| header.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .filter(|(name, _)| !name.is_empty())
+        .collect()
+}
+
+/**
+ * aria2 limits are bytes with an optional K/M suffix, 0 means unlimited
+ */
+fn option_speed_limit(options: Option<&Value>) -> Result<Option<usize>, String> {
+    option_string(options, "max-download-limit")
+        .map(|limit| {
+            let (number, unit) = match limit.trim().to_uppercase() {
+                limit if limit.ends_with('K') => (limit.trim_end_matches('K').to_string(), 1024),
+                limit if limit.ends_with('M') => {
+                    (limit.trim_end_matches('M').to_string(), 1024 * 1024)
+                }
+                limit => (limit, 1),
+            };
+            number
+                .parse::<usize>()
+                .map(|number| number * unit)
+                .map_err(|_| format!("max-download-limit `{limit}` is invalid"))
+        })
+        .transpose()
+}
+
+fn option_threads(options: Option<&Value>) -> Result<Option<u8>, String> {
+    option_string(options, "split")
+        .or_else(|| option_string(options, "max-connection-per-server"))
+        .map(|threads| {
+            threads
+                .parse::<u8>()
+                .ok()
+                .filter(|threads| (1..=ATOM_MAX_THREADS).contains(threads))
+                .ok_or_else(|| format!("split/max-connection-per-server `{threads}` is invalid"))
+        })
+        .transpose()
+}
+
+impl Atom<'_> {
+    fn find_gid(&self, gid: Option<&Value>) -> Result<Uuid, String> {
+        let gid = gid
+            .and_then(Value::as_str)
+            .filter(|gid| gid.len() == 16 && gid.chars().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("GID {} is not valid", gid.unwrap_or(&Value::Null)))?;
+        self.find_download(gid)
+            .map_err(|_| format!("GID {gid} is not found"))
+    }
+
+    /**
+     * handles a single call or a batch of aria2 JSON-RPC calls
+     */
+    pub fn handle_aria2_rpc(&mut self, body: Value) -> (ApiResponse, Command<Message>) {
+        let mut commands = vec![];
+        let response = match body {
+            Value::Array(calls) => Value::Array(
+                calls
+                    .into_iter()
+                    .map(|call| self.handle_aria2_call(call, &mut commands))
+                    .collect(),
+            ),
+            call => self.handle_aria2_call(call, &mut commands),
+        };
+
+        (ApiResponse::ok(response), Command::batch(commands))
+    }
+
+    fn handle_aria2_call(&mut self, call: Value, commands: &mut Vec<Command<Message>>) -> Value {
+        let id = call.get("id").cloned().unwrap_or(Value::Null);
+        let method = call
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let mut params = call
+            .get("params")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+
+        // the secret was already verified by the server
+        if params
+            .first()
+            .and_then(Value::as_str)
+            .is_some_and(|param| param.starts_with(ARIA2_TOKEN_PREFIX))
+        {
+            params.remove(0);
+        }
+
+        let result = match method.as_str() {
+            "aria2.addUri" => self.aria2_add_uri(&params, commands),
+            "aria2.tellStatus" => self.find_gid(params.first()).map(|id| {
+                status_object(
+                    &self.downloads[&id],
+                    params.get(1).and_then(Value::as_array),
+                )
+            }),
+            "aria2.tellActive" => Ok(self.aria2_list(
+                |status| status == "active",
+                0,
+                usize::MAX,
+                params.first().and_then(Value::as_array),
+            )),
+            "aria2.tellWaiting" | "aria2.tellStopped" => {
+                let offset = params.first().and_then(Value::as_u64).unwrap_or(0) as usize;
+                let num = params.get(1).and_then(Value::as_u64).unwrap_or(1000) as usize;
+                let keys = params.get(2).and_then(Value::as_array);
+                if method == "aria2.tellWaiting" {
+                    Ok(self.aria2_list(|status| status == "paused", offset, num, keys))
+                } else {
+                    Ok(self.aria2_list(
+                        |status| matches!(status, "complete" | "error" | "removed"),
+                        offset,
+                        num,
+                        keys,
+                    ))
+                }
+            }
+            "aria2.pause" | "aria2.forcePause" => {
+                self.aria2_with_download(&params, DownloadMessage::Paused, commands)
+            }
+            "aria2.unpause" => {
+                self.aria2_with_download(&params, DownloadMessage::Downloading, commands)
+            }
+            // aria2 never deletes files, not forced moves the download to the trash and keeps them
+            "aria2.remove" | "aria2.forceRemove" => {
+                self.aria2_with_download(&params, DownloadMessage::RemoveDownload(false), commands)
+            }
+            "aria2.changeOption" => self.aria2_change_option(&params),
+            "aria2.getGlobalStat" => Ok(self.aria2_global_stat()),
+            "aria2.getVersion" => Ok(json!({ "version": ARIA2_VERSION, "enabledFeatures": [] })),
+            _ => Err(format!("No such method: {method}")),
+        };
+
+        match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(message) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": ARIA2_ERROR_CODE, "message": message },
+            }),
+        }
+    }
+
+    fn aria2_add_uri(
+        &mut self,
+        params: &[Value],
+        commands: &mut Vec<Command<Message>>,
+    ) -> RpcResult {
+        // every URI is the same file, the ones after the first are mirrors
+        let uris: Vec<String> = params
+            .first()
+            .and_then(Value::as_array)
+            .map(|uris| {
+                uris.iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let (url, mirrors) = uris
+            .split_first()
+            .ok_or_else(|| "no URI to download".to_string())?;
+        let options = params.get(1);
+
+        let download = AtomDownload::new()
+            .url(url)
+            .mirrors(mirrors.to_vec())
+            .file_path(option_string(options, "dir").unwrap_or(self.settings.downloads_dir.clone()))
+            .file_name(option_string(options, "out").unwrap_or_default())
+            .headers(option_headers(options))
+            .threads(option_threads(options)?.unwrap_or(0))
+            .speed_limit(option_speed_limit(options)?.unwrap_or(0))
+            .build()
+            .map_err(str::to_string)?;
+
+        let id = self.add_download(download);
+        commands.push(Command::done(Message::SaveDownloads));
+        Ok(Value::String(format_gid(id)))
+    }
+
+    fn aria2_with_download(
+        &mut self,
+        params: &[Value],
+        message: DownloadMessage,
+        commands: &mut Vec<Command<Message>>,
+    ) -> RpcResult {
+        let id = self.find_gid(params.first())?;
+        let download = &self.downloads[&id];
+
+        if matches!(message, DownloadMessage::Downloading) && !download.is_resumable() {
+            return Err(format!("GID {} cannot be unpaused now", format_gid(id)));
+        }
+
+        commands.push(self.update(Message::Download(message, id)));
+        Ok(Value::String(format_gid(id)))
+    }
+
+    fn aria2_change_option(&mut self, params: &[Value]) -> RpcResult {
+        let id = self.find_gid(params.first())?;
+        let options = params.get(1);
+        let threads = option_threads(options)?;
+        let speed_limit = option_speed_limit(options)?;
+        let download = self
+            .downloads
+            .get_mut(&id)
+            .ok_or_else(|| format!("GID {} is not found", format_gid(id)))?;
+
+        // chunk files are laid out by thread count and name, they can't change mid-download
+        let not_started = download.downloaded == 0 && !download.downloading;
+        let changes_layout = threads.is_some()
+            || option_string(options, "out").is_some()
+            || option_string(options, "dir").is_some();
+        if changes_layout && !not_started {
+            return Err(format!(
+                "GID {} has started, split/out/dir can't be changed",
+                format_gid(id)
+            ));
+        }
+
+        if let Some(threads) = threads {
+            download.threads = threads;
+        }
+        if let Some(file_name) = option_string(options, "out") {
+            download.file_name = file_name;
+        }
+        if let Some(dir) = option_string(options, "dir") {
+            download.file_path = dir;
+        }
+        let headers = option_headers(options);
+        if !headers.is_empty() {
+            download.headers.extend(headers);
+        }
+        if let Some(speed_limit) = speed_limit {
+            download.speed_limit = speed_limit;
+            self.engines
+                .send(id, EngineCommand::SetSpeedLimit(speed_limit));
+        }
+
+        Ok(Value::String("OK".to_string()))
+    }
+
+    fn aria2_list(
+        &self,
+        filter: impl Fn(&str) -> bool,
+        offset: usize,
+        num: usize,
+        keys: Option<&Vec<Value>>,
+    ) -> Value {
+        Value::Array(
+            self.ordered_downloads()
+                .into_iter()
+                .filter(|(_, download)| filter(aria2_status(download)))
+                .skip(offset)
+                .take(num)
+                .map(|(_, download)| status_object(download, keys))
+                .collect(),
+        )
+    }
+
+    fn aria2_global_stat(&self) -> Value {
+        let count = |status: &str| {
+            self.downloads
+                .values()
+                .filter(|download| aria2_status(download) == status)
+                .count()
+        };
+        let stopped = count("complete") + count("error") + count("removed");
+        let speed: f64 = self
+            .downloads
+            .values()
+            .filter(|download| download.downloading)
+            .map(|download| download.transfer_rate)
+            .sum();
+
+        json!({
+            "downloadSpeed": ((speed * 1000.0 * 1000.0) as usize).to_string(),
+            "uploadSpeed": "0",
+            "numActive": count("active").to_string(),
+            "numWaiting": count("paused").to_string(),
+            "numStopped": stopped.to_string(),
+            "numStoppedTotal": stopped.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::events::DownloadEventKind;
+
+    fn call(atom: &mut Atom, method: &str, params: Value) -> Value {
+        let (response, _) = atom.handle_aria2_rpc(
+            json!({ "jsonrpc": "2.0", "id": "1", "method": method, "params": params }),
+        );
+        response.body
+    }
+
+    fn result(atom: &mut Atom, method: &str, params: Value) -> Value {
+        let response = call(atom, method, params);
+        assert!(
+            response.get("error").is_none(),
+            "{method} failed: {response}"
+        );
+        response["result"].clone()
+    }
+
+    fn add(atom: &mut Atom, url: &str) -> (String, Uuid) {
+        let gid = result(atom, "aria2.addUri", json!([[url], { "dir": "/tmp" }]));
+        let gid = gid.as_str().unwrap().to_string();
+        let id = atom.find_download(&gid).unwrap();
+        // probed and paused, as if its engine had run
+        let download = atom.downloads.get_mut(&id).unwrap();
+        download.size = 100;
+        download.downloading = false;
+        (gid, id)
+    }
+
+    #[test]
+    fn secret_is_read_from_the_first_param() {
+        let call = json!({ "method": "aria2.getVersion", "params": ["token:abc"] });
+        assert_eq!(secret_from_body(&call), Some("abc".to_string()));
+        let batch = json!([call, { "method": "aria2.getGlobalStat", "params": [] }]);
+        assert_eq!(secret_from_body(&batch), Some("abc".to_string()));
+        let unsigned = json!({ "method": "aria2.getVersion", "params": ["abc"] });
+        assert_eq!(secret_from_body(&unsigned), None);
+        assert_eq!(
+            secret_from_body(&json!({ "method": "aria2.getVersion" })),
+            None
+        );
+    }
+
+    #[test]
+    fn token_param_is_stripped_before_dispatch() {
+        let mut atom = Atom::default();
+        let (gid, _) = add(&mut atom, "http://127.0.0.1/file.bin");
+        let status = result(&mut atom, "aria2.tellStatus", json!(["token:abc", gid]));
+        assert_eq!(status["gid"], gid);
+    }
+
+    #[test]
+    fn add_uri_maps_mirrors_and_options() {
+        let mut atom = Atom::default();
+        let gid = result(
+            &mut atom,
+            "aria2.addUri",
+            json!([
+                ["http://127.0.0.1/a.iso", "http://127.0.0.2/a.iso"],
+                {
+                    "dir": "/tmp",
+                    "out": "b.iso",
+                    "split": "4",
+                    "max-download-limit": "1M",
+                    "header": ["Cookie: a=b"],
+                }
+            ]),
+        );
+        let id = atom.find_download(gid.as_str().unwrap()).unwrap();
+        let download = &atom.downloads[&id];
+        assert_eq!(download.url, "http://127.0.0.1/a.iso");
+        assert_eq!(download.mirrors, vec!["http://127.0.0.2/a.iso".to_string()]);
+        assert_eq!(download.file_path, "/tmp");
+        assert_eq!(download.file_name, "b.iso");
+        assert_eq!(download.threads, 4);
+        assert_eq!(download.speed_limit, 1024 * 1024);
+        assert_eq!(
+            download.headers.get("cookie").map(String::as_str),
+            Some("a=b")
+        );
+        assert_eq!(gid.as_str(), Some(format_gid(id).as_str()));
+    }
+
+    #[test]
+    fn add_uri_rejects_invalid_options() {
+        let mut atom = Atom::default();
+        let response = call(&mut atom, "aria2.addUri", json!([[]]));
+        assert_eq!(response["error"]["code"], ARIA2_ERROR_CODE);
+        let response = call(
+            &mut atom,
+            "aria2.addUri",
+            json!([["http://127.0.0.1/a"], { "split": "99" }]),
+        );
+        assert_eq!(response["error"]["code"], ARIA2_ERROR_CODE);
+    }
+
+    #[test]
+    fn tell_status_uses_aria2_field_names() {
+        let mut atom = Atom::default();
+        let (gid, id) = add(&mut atom, "http://127.0.0.1/file.bin");
+        atom.downloads.get_mut(&id).unwrap().downloaded = 40;
+
+        let status = result(&mut atom, "aria2.tellStatus", json!([gid]));
+        for key in [
+            "gid",
+            "status",
+            "totalLength",
+            "completedLength",
+            "uploadLength",
+            "downloadSpeed",
+            "uploadSpeed",
+            "connections",
+            "numPieces",
+            "pieceLength",
+            "dir",
+            "errorCode",
+            "errorMessage",
+            "files",
+        ] {
+            assert!(status.get(key).is_some(), "tellStatus has no `{key}`");
+        }
+        assert_eq!(status["status"], "paused");
+        assert_eq!(status["totalLength"], "100");
+        assert_eq!(status["completedLength"], "40");
+        assert_eq!(status["files"][0]["path"], "/tmp/file.bin");
+        assert_eq!(
+            status["files"][0]["uris"][0]["uri"],
+            "http://127.0.0.1/file.bin"
+        );
+
+        let status = result(
+            &mut atom,
+            "aria2.tellStatus",
+            json!([gid, ["gid", "totalLength"]]),
+        );
+        assert_eq!(
+            status.as_object().unwrap().keys().collect::<Vec<_>>(),
+            vec!["gid", "totalLength"]
+        );
+    }
+
+    #[test]
+    fn tell_status_rejects_unknown_gids() {
+        let mut atom = Atom::default();
+        let response = call(&mut atom, "aria2.tellStatus", json!(["0123456789abcdef"]));
+        assert_eq!(
+            response["error"]["message"],
+            "GID 0123456789abcdef is not found"
+        );
+        let response = call(&mut atom, "aria2.tellStatus", json!(["nope"]));
+        assert_eq!(response["error"]["code"], ARIA2_ERROR_CODE);
+    }
+
+    #[test]
+    fn pause_and_unpause_map_to_download_messages() {
+        for method in ["aria2.pause", "aria2.forcePause"] {
+            let mut atom = Atom::default();
+            let (gid, id) = add(&mut atom, "http://127.0.0.1/file.bin");
+            atom.downloads.get_mut(&id).unwrap().downloading = true;
+            assert_eq!(result(&mut atom, method, json!([gid])), json!(gid));
+            assert!(!atom.downloads[&id].downloading, "{method}");
+
+            result(&mut atom, "aria2.unpause", json!([gid]));
+            assert!(atom.downloads[&id].downloading);
+        }
+    }
+
+    #[test]
+    fn remove_and_force_remove_keep_the_files() {
+        for method in ["aria2.remove", "aria2.forceRemove"] {
+            let mut atom = Atom::default();
+            let (gid, id) = add(&mut atom, "http://127.0.0.1/file.bin");
+            let events = atom.events.subscribe();
+            assert_eq!(result(&mut atom, method, json!([gid])), json!(gid));
+            // moved to the trash, a forced delete would have dropped it from the list
+            assert!(atom.downloads[&id].deleted, "{method}");
+            let kinds: Vec<_> = events.try_iter().map(|event| event.event).collect();
+            assert_eq!(kinds, [DownloadEventKind::Status], "{method}");
+            let status = result(&mut atom, "aria2.tellStatus", json!([gid, ["status"]]));
+            assert_eq!(status["status"], "removed");
+        }
+    }
+
+    #[test]
+    fn lists_filter_by_status() {
+        let mut atom = Atom::default();
+        let (active, id) = add(&mut atom, "http://127.0.0.1/a.bin");
+        atom.downloads.get_mut(&id).unwrap().downloading = true;
+        let (waiting, _) = add(&mut atom, "http://127.0.0.1/b.bin");
+        let (stopped, _) = add(&mut atom, "http://127.0.0.1/c.bin");
+        result(&mut atom, "aria2.remove", json!([stopped]));
+
+        let gids = |list: Value| {
+            list.as_array()
+                .unwrap()
+                .iter()
+                .map(|status| status["gid"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            gids(result(&mut atom, "aria2.tellActive", json!([]))),
+            vec![active]
+        );
+        assert_eq!(
+            gids(result(&mut atom, "aria2.tellWaiting", json!([0, 10]))),
+            vec![waiting]
+        );
+        assert_eq!(
+            gids(result(&mut atom, "aria2.tellStopped", json!([0, 10]))),
+            vec![stopped]
+        );
+
+        let stat = result(&mut atom, "aria2.getGlobalStat", json!([]));
+        assert_eq!(stat["numActive"], "1");
+        assert_eq!(stat["numWaiting"], "1");
+        assert_eq!(stat["numStopped"], "1");
+    }
+
+    #[test]
+    fn change_option_only_changes_layout_before_start() {
+        let mut atom = Atom::default();
+        let (gid, id) = add(&mut atom, "http://127.0.0.1/file.bin");
+        result(
+            &mut atom,
+            "aria2.changeOption",
+            json!([gid, { "split": "2", "out": "renamed.bin" }]),
+        );
+        assert_eq!(atom.downloads[&id].threads, 2);
+        assert_eq!(atom.downloads[&id].file_name, "renamed.bin");
+
+        atom.downloads.get_mut(&id).unwrap().downloaded = 10;
+        let response = call(
+            &mut atom,
+            "aria2.changeOption",
+            json!([gid, { "split": "3" }]),
+        );
+        assert_eq!(response["error"]["code"], ARIA2_ERROR_CODE);
+        result(
+            &mut atom,
+            "aria2.changeOption",
+            json!([gid, { "max-download-limit": "512K" }]),
+        );
+        assert_eq!(atom.downloads[&id].speed_limit, 512 * 1024);
+    }
+
+    #[test]
+    fn version_batches_and_unknown_methods() {
+        let mut atom = Atom::default();
+        let version = result(&mut atom, "aria2.getVersion", json!(["token:abc"]));
+        assert_eq!(version["version"], ARIA2_VERSION);
+
+        let (response, _) = atom.handle_aria2_rpc(json!([
+            { "id": 1, "method": "aria2.getVersion", "params": [] },
+            { "id": 2, "method": "aria2.shutdown", "params": [] },
+        ]));
+        assert_eq!(response.body[0]["id"], 1);
+        assert_eq!(
+            response.body[1]["error"]["message"],
+            "No such method: aria2.shutdown"
+        );
+    }
+}
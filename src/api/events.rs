@@ -0,0 +1,192 @@
+use crate::components::download::AtomDownload;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+// progress is reported far more often than any client needs it
+const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_millis(500);
+// events a client may fall behind by before it is disconnected, it reconnects and catches up
+const SUBSCRIBER_QUEUE_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadEventKind {
+    Added,
+    Started,
+    Progress,
+    Paused,
+    Finished,
+    Error,
+    Status, // changed outside the transfer, like a move to the trash
+    Removed,
+}
+
+impl DownloadEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Started => "started",
+            Self::Progress => "progress",
+            Self::Paused => "paused",
+            Self::Finished => "finished",
+            Self::Error => "error",
+            Self::Status => "status",
+            Self::Removed => "removed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadEvent {
+    pub event: DownloadEventKind,
+    pub id: Uuid,
+    pub file_name: String,
+    pub downloaded: usize,
+    pub size: usize,
+    pub speed: usize,       // bytes per second
+    pub eta: Option<usize>, // seconds, unknown without a size or speed
+    pub error: String,
+}
+
+impl DownloadEvent {
+    pub fn new(event: DownloadEventKind, download: &AtomDownload) -> Self {
+        let speed = if download.downloading {
+            (download.transfer_rate * 1000.0 * 1000.0) as usize
+        } else {
+            0
+        };
+        let eta = if speed > 0 && download.size > download.downloaded {
+            Some((download.size - download.downloaded) / speed)
+        } else {
+            None
+        };
+
+        Self {
+            event,
+            id: download.id,
+            file_name: download.file_name.clone(),
+            downloaded: download.downloaded,
+            size: download.size,
+            speed,
+            eta,
+            error: download.error.clone(),
+        }
+    }
+
+    pub fn removed(id: Uuid) -> Self {
+        Self {
+            event: DownloadEventKind::Removed,
+            id,
+            file_name: String::default(),
+            downloaded: 0,
+            size: 0,
+            speed: 0,
+            eta: None,
+            error: String::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Subscribers {
+    senders: Vec<mpsc::SyncSender<DownloadEvent>>,
+    last_progress: HashMap<Uuid, Instant>,
+}
+
+/**
+ * fans download events out to every connected event stream client
+ */
+#[derive(Clone, Default)]
+pub struct EventBus(Arc<Mutex<Subscribers>>);
+
+impl EventBus {
+    pub fn subscribe(&self) -> mpsc::Receiver<DownloadEvent> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_QUEUE_LEN);
+        if let Ok(mut subscribers) = self.0.lock() {
+            subscribers.senders.push(sender);
+        }
+        receiver
+    }
+
+    pub fn publish(&self, event: DownloadEvent) {
+        let Ok(mut subscribers) = self.0.lock() else {
+            return;
+        };
+
+        match event.event {
+            DownloadEventKind::Progress => {
+                let now = Instant::now();
+                if subscribers
+                    .last_progress
+                    .get(&event.id)
+                    .is_some_and(|last| now.duration_since(*last) < PROGRESS_EVENT_INTERVAL)
+                {
+                    return;
+                }
+                subscribers.last_progress.insert(event.id, now);
+            }
+            _ => {
+                subscribers.last_progress.remove(&event.id);
+            }
+        }
+
+        // disconnected clients drop their receiver and stalled ones are dropped with a full
+        // queue, the stream ends for both so nothing piles up behind a slow reader
+        subscribers
+            .senders
+            .retain(|sender| sender.try_send(event.clone()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: DownloadEventKind) -> DownloadEvent {
+        DownloadEvent {
+            event: kind,
+            ..DownloadEvent::removed(Uuid::new_v4())
+        }
+    }
+
+    #[test]
+    fn stalled_subscribers_are_dropped() {
+        let bus = EventBus::default();
+        let stalled = bus.subscribe();
+        let reader = bus.subscribe();
+
+        for _ in 0..SUBSCRIBER_QUEUE_LEN {
+            bus.publish(event(DownloadEventKind::Added));
+            assert!(reader.try_recv().is_ok());
+        }
+        bus.publish(event(DownloadEventKind::Added));
+        assert!(reader.try_recv().is_ok());
+
+        // the queued events are still delivered, then the stream ends
+        assert_eq!(stalled.try_iter().count(), SUBSCRIBER_QUEUE_LEN);
+        assert!(stalled.recv().is_err());
+    }
+
+    #[test]
+    fn progress_is_throttled_per_download() {
+        let bus = EventBus::default();
+        let receiver = bus.subscribe();
+        let progress = event(DownloadEventKind::Progress);
+
+        bus.publish(progress.clone());
+        bus.publish(progress.clone());
+        bus.publish(event(DownloadEventKind::Progress));
+        assert_eq!(receiver.try_iter().count(), 2);
+
+        bus.publish(DownloadEvent {
+            event: DownloadEventKind::Paused,
+            ..progress.clone()
+        });
+        bus.publish(progress);
+        assert_eq!(receiver.try_iter().count(), 2);
+    }
+}
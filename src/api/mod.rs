@@ -0,0 +1,169 @@
+pub mod aria2;
+pub mod events;
+pub mod server;
+use crate::{
+    components::{download::AtomDownload, settings::AtomSettings},
+    utils::json_from_browser::JSONFromBrowser,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    fmt,
+    sync::{mpsc, Arc, Mutex, PoisonError, RwLock},
+};
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub enum ApiRequest {
+    AddDownload(Box<JSONFromBrowser>),
+    ListDownloads,
+    GetDownload(String), // download id or a unique prefix of it
+    PauseDownload(String),
+    ResumeDownload(String),
+    RemoveDownload(String, bool), // force delete is true (skips the trash)
+    Aria2(Value),                 // aria2 compatible JSON-RPC call or batch
+    Open(Vec<String>),            // URLs and links files from a second `atom` launch
+    Pair,                         // issues the client a token of its own
+    Events,                       // streamed by the server thread, never reaches the app
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiClient {
+    pub id: String,
+    pub name: String,
+    pub origin: String,
+}
+
+#[derive(Clone, Default, PartialEq)]
+pub struct ApiAuthState {
+    pub token: String,
+    pub revoked: Vec<String>, // client ids rejected even with the right token
+    pub clients: Vec<(String, String)>, // token and id of every client that paired
+}
+
+impl ApiAuthState {
+    fn new(settings: &AtomSettings) -> Self {
+        Self {
+            token: settings.api_token.clone(),
+            revoked: settings.revoked_clients.clone(),
+            clients: settings
+                .paired_clients
+                .iter()
+                .filter(|client| !client.token.is_empty())
+                .map(|client| (client.token.clone(), client.id.clone()))
+                .collect(),
+        }
+    }
+}
+
+/**
+ * what the local API checks requests against, updated in place by the app so a new token or
+ * a revoked client applies without restarting the listener
+ */
+#[derive(Clone, Default)]
+pub struct ApiAuth(Arc<RwLock<ApiAuthState>>);
+
+impl ApiAuth {
+    pub fn new(settings: &AtomSettings) -> Self {
+        let auth = Self::default();
+        auth.sync(settings);
+        auth
+    }
+
+    /**
+     * takes the tokens and revoked clients from `settings`, called after every update
+     */
+    pub fn sync(&self, settings: &AtomSettings) {
+        let state = ApiAuthState::new(settings);
+        if *self.0.read().unwrap_or_else(PoisonError::into_inner) != state {
+            *self.0.write().unwrap_or_else(PoisonError::into_inner) = state;
+        }
+    }
+
+    pub fn state(&self) -> ApiAuthState {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl ApiResponse {
+    pub fn new(status: u16, body: Value) -> Self {
+        Self { status, body }
+    }
+
+    pub fn ok<T: Serialize>(body: T) -> Self {
+        Self::new(200, serde_json::to_value(body).unwrap_or_default())
+    }
+
+    pub fn error<T: Into<String>>(status: u16, message: T) -> Self {
+        Self::new(status, json!({ "error": message.into() }))
+    }
+}
+
+/**
+ * one-shot reply channel, the API thread blocks on the receiving end until the app answers
+ */
+#[derive(Clone)]
+pub struct ApiResponder(Arc<Mutex<Option<mpsc::Sender<ApiResponse>>>>);
+
+impl ApiResponder {
+    pub fn channel() -> (Self, mpsc::Receiver<ApiResponse>) {
+        let (sender, receiver) = mpsc::channel();
+        (Self(Arc::new(Mutex::new(Some(sender)))), receiver)
+    }
+
+    pub fn respond(&self, response: ApiResponse) {
+        if let Ok(mut sender) = self.0.lock() {
+            if let Some(sender) = sender.take() {
+                sender.send(response).ok();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ApiResponder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApiResponder")
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DownloadSummary {
+    pub id: Uuid,
+    pub url: String,
+    pub mirrors: Vec<String>,
+    pub file_name: String,
+    pub file_path: String,
+    pub size: usize,
+    pub downloaded: usize,
+    pub status: String,
+    pub transfer_rate: f64,
+    pub error: String,
+    pub added: String,
+}
+
+impl DownloadSummary {
+    pub fn new(download: &AtomDownload) -> Self {
+        Self {
+            id: download.id,
+            url: download.url.clone(),
+            mirrors: download.mirrors.clone(),
+            file_name: download.file_name.clone(),
+            file_path: download.file_path.clone(),
+            size: download.size,
+            downloaded: download.downloaded,
+            status: download.status().to_string(),
+            transfer_rate: download.transfer_rate,
+            error: download.error.clone(),
+            added: download.added.clone(),
+        }
+    }
+}
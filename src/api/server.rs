@@ -0,0 +1,935 @@
+use super::{
+    aria2,
+    events::{DownloadEvent, EventBus},
+    ApiAuth, ApiAuthState, ApiClient, ApiRequest, ApiResponder, ApiResponse,
+};
+use crate::{
+    messages::Message,
+    utils::{
+        checksum::Checksum,
+        json_from_browser::JSONFromBrowser,
+        request::{decode_body, parse_method},
+    },
+};
+use iced::{
+    futures::{channel::mpsc::Sender, Stream},
+    Subscription,
+};
+use std::{
+    io::{Read, Write},
+    sync::mpsc::RecvTimeoutError,
+    time::Duration,
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{debug, warn};
+
+const API_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+const API_POLL_INTERVAL: Duration = Duration::from_millis(500);
+// a restarted listener waits this long at first, doubling up to the poll interval
+const API_BIND_RETRY_DELAY: Duration = Duration::from_millis(50);
+const API_BIND_ATTEMPTS: usize = 10;
+// proxies and clients drop event streams that stay silent for too long
+const EVENT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+// the old extension terminates the JSON payload with this marker
+const LEGACY_PAYLOAD_MARKER: &str = "<END>";
+// client ids and names end up in settings.toml
+const MAX_CLIENT_ID_LEN: usize = 64;
+
+// browser extensions are the only web origins allowed to talk to the API
+const ALLOWED_ORIGIN_SCHEMES: [&str; 3] = [
+    "chrome-extension://",
+    "moz-extension://",
+    "safari-web-extension://",
+];
+
+// request replays can carry large bodies, anything past this is refused with 413
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+const CORS_ALLOWED_METHODS: &str = "GET, POST, DELETE, OPTIONS";
+const CORS_ALLOWED_HEADERS: &str =
+    "Authorization, Content-Type, X-Atom-Token, X-Atom-Client, X-Atom-Client-Id";
+
+/**
+ * the listener only restarts when the address changes, token and revoked clients are read
+ * from `auth` on every request
+ */
+pub fn subscription(address: String, auth: ApiAuth, events: EventBus) -> Subscription<Message> {
+    Subscription::run_with_id(address.clone(), serve(address, auth, events))
+}
+
+pub fn serve(address: String, auth: ApiAuth, events: EventBus) -> impl Stream<Item = Message> {
+    // the server thread is only started once iced runs the subscription
+    iced::stream::channel(100, move |sender| async move {
+        std::thread::spawn(move || listen(address, auth, events, sender));
+    })
+}
+
+/**
+ * binds `address`, retried for a while as the listener it replaces may still hold it until
+ * its next poll
+ */
+fn bind(address: &str, sender: &Sender<Message>) -> Option<Server> {
+    let mut delay = API_BIND_RETRY_DELAY;
+    for attempt in 1..=API_BIND_ATTEMPTS {
+        match Server::http(address) {
+            Ok(server) => return Some(server),
+            Err(error) if attempt == API_BIND_ATTEMPTS || sender.is_closed() => {
+                warn!("Error: local API cannot listen on {address}: {error}");
+                return None;
+            }
+            Err(error) => {
+                debug!("local API cannot listen on {address} yet: {error}");
+                std::thread::sleep(delay);
+                delay = (delay * 2).min(API_POLL_INTERVAL);
+            }
+        }
+    }
+    None
+}
+
+fn listen(address: String, auth: ApiAuth, events: EventBus, mut sender: Sender<Message>) {
+    let Some(server) = bind(&address, &sender) else {
+        sender
+            .try_send(Message::StatusBar(
+                "Download Capture: OFF(ERROR)".to_string(),
+            ))
+            .ok();
+        return;
+    };
+
+    debug!("local API started on {address}");
+    sender
+        .try_send(Message::StatusBar("Download Capture: ON".to_string()))
+        .ok();
+
+    // the subscription dropping the receiver is the signal to release the address
+    while !sender.is_closed() {
+        match server.recv_timeout(API_POLL_INTERVAL) {
+            Ok(Some(request)) => handle_request(request, &auth, &events, &mut sender),
+            Ok(None) => {}
+            Err(error) => {
+                warn!("Error: local API failed to receive request: {error}");
+                break;
+            }
+        }
+    }
+
+    debug!("local API on {address} stopped");
+}
+
+fn handle_request(
+    mut request: Request,
+    auth: &ApiAuth,
+    events: &EventBus,
+    sender: &mut Sender<Message>,
+) {
+    if *request.method() == Method::Options {
+        respond_preflight(request);
+        return;
+    }
+
+    // the body is only read before authorizing when it carries aria2's `token:` secret, never
+    // for origins that are turned away whatever the token
+    let mut body = None;
+    let received = match header_token(&request).or_else(|| query_token(&request)) {
+        Some(received) => received,
+        None if is_aria2_request(&request) && has_allowed_origin(&request) => {
+            match read_body(&mut request) {
+                Ok(text) => {
+                    let secret = serde_json::from_str(&text)
+                        .ok()
+                        .and_then(|rpc| aria2::secret_from_body(&rpc))
+                        .unwrap_or_default();
+                    body = Some(text);
+                    secret
+                }
+                Err(response) => {
+                    respond(request, response);
+                    return;
+                }
+            }
+        }
+        None => String::new(),
+    };
+
+    let client = match authorize(&request, &auth.state(), &received) {
+        Ok(client) => client,
+        Err(response) => {
+            warn!(
+                "rejected local API request from {:?}: {}",
+                request.remote_addr(),
+                response.body
+            );
+            sender
+                .try_send(Message::StatusBar(
+                    "Rejected an unpaired local API request".to_string(),
+                ))
+                .ok();
+            respond(request, response);
+            return;
+        }
+    };
+
+    let body = match body.map_or_else(|| read_body(&mut request), Ok) {
+        Ok(body) => body,
+        Err(response) => {
+            respond(request, response);
+            return;
+        }
+    };
+    let api_request = match route(request.method(), request.url(), &body) {
+        Ok(api_request) => api_request,
+        Err(response) => {
+            respond(request, response);
+            return;
+        }
+    };
+
+    if let ApiRequest::Events = api_request {
+        let receiver = events.subscribe();
+        std::thread::spawn(move || stream_events(request, receiver));
+        return;
+    }
+
+    let (responder, reply) = ApiResponder::channel();
+    if sender
+        .try_send(Message::Api(client, api_request, responder))
+        .is_err()
+    {
+        respond(request, ApiResponse::error(503, "ATOM is busy or exiting"));
+        return;
+    }
+
+    let response = reply
+        .recv_timeout(API_REPLY_TIMEOUT)
+        .unwrap_or_else(|_| ApiResponse::error(503, "ATOM did not answer in time"));
+    respond(request, response);
+}
+
+fn header_value<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+fn tokens_match(expected: &str, received: &str) -> bool {
+    expected.len() == received.len()
+        && expected
+            .bytes()
+            .zip(received.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/**
+ * the pairing token from `Authorization: Bearer <token>` or `X-Atom-Token`
+ */
+fn header_token(request: &Request) -> Option<String> {
+    header_value(request, "Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| header_value(request, "X-Atom-Token"))
+        .map(|token| token.trim().to_string())
+}
+
+/**
+ * `EventSource` can't send headers, `/events?token=<token>` is accepted instead
+ */
+fn query_token(request: &Request) -> Option<String> {
+    let (path, query) = request.url().split_once('?')?;
+    if *request.method() != Method::Get || path.trim_matches('/') != "events" {
+        return None;
+    }
+
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+        .and_then(|token| urlencoding::decode(token).ok())
+        .map(|token| token.trim().to_string())
+}
+
+fn is_aria2_request(request: &Request) -> bool {
+    *request.method() == Method::Post
+        && request
+            .url()
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .trim_matches('/')
+            == aria2::ARIA2_RPC_PATH
+}
+
+/**
+ * the whole body, refused with 413 past `MAX_BODY_LEN` whatever Content-Length claims
+ */
+fn read_body(request: &mut Request) -> Result<String, ApiResponse> {
+    let too_large = || {
+        ApiResponse::error(
+            413,
+            format!(
+                "request body is larger than {} MiB",
+                MAX_BODY_LEN / 1024 / 1024
+            ),
+        )
+    };
+    if request.body_length().is_some_and(|len| len > MAX_BODY_LEN) {
+        return Err(too_large());
+    }
+
+    let mut body = vec![];
+    request
+        .as_reader()
+        .take(MAX_BODY_LEN as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(|error| ApiResponse::error(400, format!("cannot read request body: {error}")))?;
+    if body.len() > MAX_BODY_LEN {
+        return Err(too_large());
+    }
+
+    String::from_utf8(body).map_err(|_| ApiResponse::error(400, "request body is not valid UTF-8"))
+}
+
+/**
+ * requests without an `Origin` come from local processes, browsers send one and only
+ * extensions may use the API
+ */
+fn is_allowed_origin(origin: &str) -> bool {
+    origin.is_empty()
+        || ALLOWED_ORIGIN_SCHEMES
+            .iter()
+            .any(|scheme| origin.starts_with(scheme))
+}
+
+fn has_allowed_origin(request: &Request) -> bool {
+    header_value(request, "Origin").is_none_or(is_allowed_origin)
+}
+
+/**
+ * requests must carry the pairing token or the token a client got from `/pair`
+ * (`Authorization: Bearer <token>`, `X-Atom-Token` or aria2's `token:<token>` RPC param), web
+ * pages are rejected by origin even if they know it. A client token decides the client id,
+ * with the pairing token the client names itself
+ */
+fn authorize(
+    request: &Request,
+    auth: &ApiAuthState,
+    received: &str,
+) -> Result<ApiClient, ApiResponse> {
+    let origin = header_value(request, "Origin").unwrap_or_default();
+    if !has_allowed_origin(request) {
+        return Err(ApiResponse::error(
+            403,
+            format!("origin `{origin}` is not allowed"),
+        ));
+    }
+
+    let paired = auth
+        .clients
+        .iter()
+        .find(|(token, _)| tokens_match(token, received))
+        .map(|(_, id)| id.clone());
+    if paired.is_none() && (auth.token.is_empty() || !tokens_match(&auth.token, received)) {
+        return Err(ApiResponse::error(
+            401,
+            "missing or invalid pairing token, copy it from ATOM's settings",
+        ));
+    }
+
+    let origin = if origin.is_empty() {
+        "local process"
+    } else {
+        origin
+    };
+    // extension origins are stable per install, other clients without an id share one entry
+    let id = paired.unwrap_or_else(|| {
+        header_value(request, "X-Atom-Client-Id")
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .unwrap_or(origin)
+            .chars()
+            .take(MAX_CLIENT_ID_LEN)
+            .collect()
+    });
+    if auth.revoked.contains(&id) {
+        return Err(ApiResponse::error(
+            403,
+            format!("client `{id}` was revoked in ATOM's settings"),
+        ));
+    }
+
+    Ok(ApiClient {
+        id,
+        name: header_value(request, "X-Atom-Client")
+            .or_else(|| header_value(request, "User-Agent"))
+            .unwrap_or("unknown client")
+            .chars()
+            .take(MAX_CLIENT_ID_LEN)
+            .collect(),
+        origin: origin.to_string(),
+    })
+}
+
+/**
+ * the headers that let an extension read the response, other origins get none
+ */
+fn cors_headers(request: &Request) -> Vec<Header> {
+    let Some(origin) = header_value(request, "Origin") else {
+        return vec![];
+    };
+    if !is_allowed_origin(origin) {
+        return vec![];
+    }
+
+    [
+        ("Access-Control-Allow-Origin", origin),
+        ("Access-Control-Allow-Methods", CORS_ALLOWED_METHODS),
+        ("Access-Control-Allow-Headers", CORS_ALLOWED_HEADERS),
+        ("Access-Control-Max-Age", "600"),
+        ("Vary", "Origin"),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Header::from_bytes(name, value).ok())
+    .collect()
+}
+
+fn respond_preflight(request: Request) {
+    let status = if has_allowed_origin(&request) {
+        204
+    } else {
+        403
+    };
+    let response = cors_headers(&request)
+        .into_iter()
+        .fold(Response::empty(status), |response, header| {
+            response.with_header(header)
+        });
+    if let Err(error) = request.respond(response) {
+        warn!("Error: local API failed to send response: {error}");
+    }
+}
+
+fn respond(request: Request, response: ApiResponse) {
+    let mut http_response =
+        Response::from_string(response.body.to_string()).with_status_code(response.status);
+    if let Ok(header) = Header::from_bytes("Content-Type", "application/json") {
+        http_response = http_response.with_header(header);
+    }
+    for header in cors_headers(&request) {
+        http_response = http_response.with_header(header);
+    }
+
+    if let Err(error) = request.respond(http_response) {
+        warn!("Error: local API failed to send response: {error}");
+    }
+}
+
+/**
+ * writes download events as server-sent events until the client disconnects, the response is
+ * written by hand because tiny_http buffers chunked bodies
+ */
+fn stream_events(request: Request, receiver: std::sync::mpsc::Receiver<DownloadEvent>) {
+    let cors = cors_headers(&request)
+        .iter()
+        .map(|header| format!("{}: {}\r\n", header.field, header.value))
+        .collect::<String>();
+    let mut writer = request.into_writer();
+    let headers = format!(
+        "HTTP/1.1 200 OK\r\n\
+        Content-Type: text/event-stream\r\n\
+        Cache-Control: no-cache\r\n\
+        {cors}Connection: close\r\n\r\n"
+    );
+    if writer.write_all(headers.as_bytes()).is_err() || writer.flush().is_err() {
+        return;
+    }
+
+    loop {
+        let frame = match receiver.recv_timeout(EVENT_KEEP_ALIVE_INTERVAL) {
+            Ok(event) => format!(
+                "event: {}\ndata: {}\n\n",
+                event.event.as_str(),
+                serde_json::to_string(&event).unwrap_or_default()
+            ),
+            Err(RecvTimeoutError::Timeout) => ": keep-alive\n\n".to_string(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        if writer.write_all(frame.as_bytes()).is_err() || writer.flush().is_err() {
+            debug!("local API event stream client disconnected");
+            break;
+        }
+    }
+}
+
+fn parse_download_payload(body: &str) -> Result<JSONFromBrowser, ApiResponse> {
+    let body = body
+        .split(LEGACY_PAYLOAD_MARKER)
+        .next()
+        .unwrap_or_default()
+        .trim();
+
+    let json = serde_json::from_str::<JSONFromBrowser>(body).map_err(|error| {
+        warn!("parsing download JSON failed: {error}");
+        ApiResponse::error(400, format!("invalid download JSON: {error}"))
+    })?;
+
+    if json.url.is_empty() {
+        return Err(ApiResponse::error(400, "download URL is empty"));
+    }
+
+    parse_method(&json.method).map_err(|error| ApiResponse::error(400, error))?;
+    decode_body(&json.body, json.body_base64).map_err(|error| ApiResponse::error(400, error))?;
+    if !json.checksum.is_empty() && Checksum::parse(&json.checksum).is_none() {
+        return Err(ApiResponse::error(
+            400,
+            format!("checksum `{}` is not a known digest", json.checksum),
+        ));
+    }
+
+    if let Some(mirror) = json.mirrors.iter().find(|mirror| {
+        !reqwest::Url::parse(mirror).is_ok_and(|url| ["http", "https"].contains(&url.scheme()))
+    }) {
+        return Err(ApiResponse::error(
+            400,
+            format!("mirror `{mirror}` is not an http(s) URL"),
+        ));
+    }
+
+    Ok(json)
+}
+
+/**
+ * `{"items": [...]}`, an empty list only brings the window to front
+ */
+fn parse_open_payload(body: &str) -> Result<Vec<String>, ApiResponse> {
+    let invalid = || ApiResponse::error(400, "expected {\"items\": [\"<URL or file>\", ...]}");
+    let body = serde_json::from_str::<serde_json::Value>(body).map_err(|_| invalid())?;
+
+    body.get("items")
+        .and_then(serde_json::Value::as_array)
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|item| item.as_str().map(str::to_string).ok_or_else(invalid))
+        .collect()
+}
+
+/**
+ * ids are UUIDs, a unique prefix is enough and gets resolved by the app
+ */
+fn parse_id(id: &str) -> Result<String, ApiResponse> {
+    if !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        Ok(id.to_string())
+    } else {
+        Err(ApiResponse::error(
+            400,
+            format!("invalid download id `{id}`"),
+        ))
+    }
+}
+
+/**
+ * maps method and path to an API request:
+ *  POST   /                         (browser extension)
+ *  POST   /downloads
+ *  GET    /downloads
+ *  GET    /downloads/{id}
+ *  POST   /downloads/{id}/pause
+ *  POST   /downloads/{id}/resume
+ *  DELETE /downloads/{id}[?force=true]
+ *  GET    /events                   (server-sent download events)
+ *  POST   /jsonrpc                  (aria2 compatible JSON-RPC)
+ *  POST   /pair                     (a token of the client's own, see `authorize`)
+ */
+fn route(method: &Method, url: &str, body: &str) -> Result<ApiRequest, ApiResponse> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let method_not_allowed =
+        || ApiResponse::error(405, format!("{method} is not allowed on {path}"));
+
+    match segments[..] {
+        [] => match method {
+            Method::Post => {
+                parse_download_payload(body).map(|json| ApiRequest::AddDownload(Box::new(json)))
+            }
+            _ => Err(method_not_allowed()),
+        },
+        ["downloads"] => match method {
+            Method::Get => Ok(ApiRequest::ListDownloads),
+            Method::Post => {
+                parse_download_payload(body).map(|json| ApiRequest::AddDownload(Box::new(json)))
+            }
+            _ => Err(method_not_allowed()),
+        },
+        ["downloads", id] => match method {
+            Method::Get => Ok(ApiRequest::GetDownload(parse_id(id)?)),
+            Method::Delete => {
+                let force = query
+                    .split('&')
+                    .any(|pair| pair == "force" || pair == "force=true" || pair == "force=1");
+                Ok(ApiRequest::RemoveDownload(parse_id(id)?, force))
+            }
+            _ => Err(method_not_allowed()),
+        },
+        ["downloads", id, action @ ("pause" | "resume")] => match method {
+            Method::Post if action == "pause" => Ok(ApiRequest::PauseDownload(parse_id(id)?)),
+            Method::Post => Ok(ApiRequest::ResumeDownload(parse_id(id)?)),
+            _ => Err(method_not_allowed()),
+        },
+        ["open"] => match method {
+            Method::Post => parse_open_payload(body).map(ApiRequest::Open),
+            _ => Err(method_not_allowed()),
+        },
+        ["pair"] => match method {
+            Method::Post => Ok(ApiRequest::Pair),
+            _ => Err(method_not_allowed()),
+        },
+        ["events"] => match method {
+            Method::Get => Ok(ApiRequest::Events),
+            _ => Err(method_not_allowed()),
+        },
+        [aria2::ARIA2_RPC_PATH] => match method {
+            Method::Post => serde_json::from_str(body)
+                .map(ApiRequest::Aria2)
+                .map_err(|error| ApiResponse::error(400, format!("invalid JSON-RPC: {error}"))),
+            _ => Err(method_not_allowed()),
+        },
+        _ => Err(ApiResponse::error(404, format!("{path} not found"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::ApiAuthState, components::settings::AtomSettings};
+    use iced::futures::{channel::mpsc, executor::block_on, StreamExt};
+    use reqwest::{blocking::Client, StatusCode};
+    use serde_json::{json, Value};
+    use std::{
+        io::BufRead,
+        net::TcpStream,
+        sync::{Arc, RwLock},
+    };
+
+    const TOKEN: &str = "secret";
+
+    fn auth(revoked: Vec<String>) -> ApiAuth {
+        ApiAuth(Arc::new(RwLock::new(ApiAuthState {
+            token: TOKEN.to_string(),
+            revoked,
+            clients: vec![],
+        })))
+    }
+
+    fn free_address() -> String {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .to_string()
+    }
+
+    /**
+     * a local API on a free port, every request reaching the app is answered with its name
+     */
+    fn start_api(auth: ApiAuth) -> String {
+        let address = free_address();
+        let (sender, mut receiver) = mpsc::channel(100);
+        let listen_address = address.clone();
+        std::thread::spawn(move || listen(listen_address, auth, EventBus::default(), sender));
+        std::thread::spawn(move || {
+            while let Some(message) = block_on(receiver.next()) {
+                if let Message::Api(client, request, responder) = message {
+                    responder.respond(ApiResponse::ok(json!({
+                        "client": client.id,
+                        "request": format!("{request:?}").split('(').next().unwrap_or_default(),
+                    })));
+                }
+            }
+        });
+
+        // the listener binds on its own thread
+        for _ in 0..50 {
+            if TcpStream::connect(&address).is_ok() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        address
+    }
+
+    fn status_line(address: &str, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut line = String::new();
+        std::io::BufReader::new(stream).read_line(&mut line).ok();
+        line
+    }
+
+    #[test]
+    fn token_is_checked_before_the_body_is_read() {
+        let address = start_api(auth(vec![]));
+        // neither sent nor read, the missing token is all that counts
+        let line = status_line(
+            &address,
+            "POST /downloads HTTP/1.1\r\nHost: x\r\nContent-Length: 1000000000\r\n\r\n",
+        );
+        assert!(line.contains(" 401 "), "{line}");
+    }
+
+    #[test]
+    fn oversized_bodies_are_refused() {
+        let address = start_api(auth(vec![]));
+        let line = status_line(
+            &address,
+            &format!(
+                "POST /downloads HTTP/1.1\r\nHost: x\r\nAuthorization: Bearer {TOKEN}\r\nContent-Length: {}\r\n\r\n",
+                MAX_BODY_LEN + 1
+            ),
+        );
+        assert!(line.contains(" 413 "), "{line}");
+    }
+
+    #[test]
+    fn only_extension_origins_are_accepted() {
+        let address = start_api(auth(vec![]));
+        let list = |origin: &str| {
+            Client::new()
+                .get(format!("http://{address}/downloads"))
+                .header("Origin", origin)
+                .bearer_auth(TOKEN)
+                .send()
+                .unwrap()
+        };
+
+        let response = list("chrome-extension://abc");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "chrome-extension://abc"
+        );
+        let body: Value = response.json().unwrap();
+        assert_eq!(body["client"], "chrome-extension://abc");
+        assert_eq!(body["request"], "ListDownloads");
+
+        // the token doesn't help a web page
+        let response = list("https://example.com");
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!response
+            .headers()
+            .contains_key("access-control-allow-origin"));
+
+        let response = Client::new()
+            .get(format!("http://{address}/downloads"))
+            .header("Origin", "moz-extension://abc")
+            .send()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn preflight_requests_are_answered() {
+        let address = start_api(auth(vec![]));
+        let preflight = |origin: &str| {
+            Client::new()
+                .request(
+                    reqwest::Method::OPTIONS,
+                    format!("http://{address}/downloads"),
+                )
+                .header("Origin", origin)
+                .header("Access-Control-Request-Method", "POST")
+                .header("Access-Control-Request-Headers", "authorization")
+                .send()
+                .unwrap()
+        };
+        let response = preflight("https://example.com");
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!response
+            .headers()
+            .contains_key("access-control-allow-origin"));
+
+        let response = preflight("safari-web-extension://abc");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let headers = response.headers();
+        assert_eq!(
+            headers["access-control-allow-origin"],
+            "safari-web-extension://abc"
+        );
+        assert!(headers["access-control-allow-methods"]
+            .to_str()
+            .unwrap()
+            .contains("POST"));
+        assert!(headers["access-control-allow-headers"]
+            .to_str()
+            .unwrap()
+            .contains("Authorization"));
+    }
+
+    #[test]
+    fn aria2_secret_authorizes_without_headers() {
+        let address = start_api(auth(vec![]));
+        let rpc = |secret: &str| {
+            Client::new()
+                .post(format!("http://{address}/jsonrpc"))
+                .json(&json!({ "id": 1, "method": "aria2.getVersion", "params": [secret] }))
+                .send()
+                .unwrap()
+                .status()
+        };
+        assert_eq!(rpc(&format!("token:{TOKEN}")), StatusCode::OK);
+        assert_eq!(rpc("token:wrong"), StatusCode::UNAUTHORIZED);
+        assert_eq!(rpc(TOKEN), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn revoked_clients_are_rejected() {
+        let address = start_api(auth(vec!["atom-cli".to_string()]));
+        let request = |id: &str| {
+            Client::new()
+                .get(format!("http://{address}/downloads"))
+                .bearer_auth(TOKEN)
+                .header("X-Atom-Client-Id", id)
+                .send()
+                .unwrap()
+                .status()
+        };
+        assert_eq!(request("atom-cli"), StatusCode::FORBIDDEN);
+        assert_eq!(request("script"), StatusCode::OK);
+    }
+
+    #[test]
+    fn revoked_clients_stay_out_under_another_id() {
+        let mut settings = AtomSettings {
+            api_token: TOKEN.to_string(),
+            ..AtomSettings::default()
+        };
+        settings.record_paired_client("chrome-extension://a", "extension", "chrome-extension://a");
+        settings.record_paired_client("atom-cli", "atom-cli", "local process");
+        let revoked_token = settings.issue_client_token("chrome-extension://a").unwrap();
+        let kept_token = settings.issue_client_token("atom-cli").unwrap();
+        let auth = ApiAuth::new(&settings);
+        let address = start_api(auth.clone());
+        let request = |token: &str, id: &str| {
+            let response = Client::new()
+                .get(format!("http://{address}/downloads"))
+                .bearer_auth(token)
+                .header("X-Atom-Client-Id", id)
+                .send()
+                .unwrap();
+            let status = response.status();
+            let body: Value = response.json().unwrap_or_default();
+            (
+                status,
+                body["client"].as_str().unwrap_or_default().to_string(),
+            )
+        };
+
+        // a client token decides the id, whatever the client sends
+        assert_eq!(
+            request(&revoked_token, "spoofed"),
+            (StatusCode::OK, "chrome-extension://a".to_string())
+        );
+
+        settings.revoke_paired_client("chrome-extension://a");
+        auth.sync(&settings);
+        assert_eq!(request(&revoked_token, "other").0, StatusCode::UNAUTHORIZED);
+        // the pairing token it knew was renewed
+        assert_eq!(request(TOKEN, "other").0, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            request(&kept_token, "other"),
+            (StatusCode::OK, "atom-cli".to_string())
+        );
+        assert_eq!(request(&settings.api_token, "other").0, StatusCode::OK);
+    }
+
+    #[test]
+    fn event_streams_accept_the_token_in_the_query() {
+        let address = start_api(auth(vec![]));
+        let events = |url: &str| {
+            let request =
+                format!("GET {url} HTTP/1.1\r\nHost: x\r\nOrigin: chrome-extension://abc\r\n\r\n");
+            status_line(&address, &request)
+        };
+        assert!(events(&format!("/events?token={TOKEN}")).contains(" 200 "));
+        assert!(events("/events?token=wrong").contains(" 401 "));
+        assert!(events("/events").contains(" 401 "));
+        // only the event stream takes it from the URL
+        assert!(events(&format!("/downloads?token={TOKEN}")).contains(" 401 "));
+    }
+
+    #[test]
+    fn token_changes_apply_without_a_restart() {
+        let auth = auth(vec![]);
+        let address = start_api(auth.clone());
+        let list = || {
+            Client::new()
+                .get(format!("http://{address}/downloads"))
+                .bearer_auth(TOKEN)
+                .header("X-Atom-Client-Id", "script")
+                .send()
+                .unwrap()
+                .status()
+        };
+        assert_eq!(list(), StatusCode::OK);
+
+        let mut settings = AtomSettings {
+            api_token: "regenerated".to_string(),
+            ..AtomSettings::default()
+        };
+        auth.sync(&settings);
+        assert_eq!(list(), StatusCode::UNAUTHORIZED);
+
+        settings.api_token = TOKEN.to_string();
+        settings.revoked_clients = vec!["script".to_string()];
+        auth.sync(&settings);
+        assert_eq!(list(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn restarted_listener_waits_for_the_address() {
+        let address = free_address();
+        let (sender, receiver) = mpsc::channel(100);
+        let old_address = address.clone();
+        let old = std::thread::spawn(move || {
+            listen(old_address, auth(vec![]), EventBus::default(), sender)
+        });
+        while TcpStream::connect(&address).is_err() {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+
+        // the old listener holds the address until its next poll notices the dropped receiver
+        drop(receiver);
+        let (sender, _receiver) = mpsc::channel(100);
+        let new_address = address.clone();
+        std::thread::spawn(move || listen(new_address, auth(vec![]), EventBus::default(), sender));
+        old.join().unwrap();
+
+        // answered by the new listener within its retries
+        let answered = (0..100).any(|_| {
+            std::thread::sleep(Duration::from_millis(20));
+            TcpStream::connect(&address).is_ok()
+                && status_line(&address, "GET /downloads HTTP/1.1\r\nHost: x\r\n\r\n")
+                    .contains(" 401 ")
+        });
+        assert!(answered);
+    }
+
+    #[test]
+    fn routes_map_to_requests() {
+        let route = |method, url| route(&method, url, "").map(|request| format!("{request:?}"));
+        assert_eq!(route(Method::Get, "/downloads").unwrap(), "ListDownloads");
+        assert_eq!(route(Method::Get, "/events").unwrap(), "Events");
+        assert_eq!(route(Method::Post, "/pair").unwrap(), "Pair");
+        assert_eq!(
+            route(Method::Delete, "/downloads/abc?force=1").unwrap(),
+            "RemoveDownload(\"abc\", true)"
+        );
+        assert_eq!(
+            route(Method::Post, "/downloads/abc/pause").unwrap(),
+            "PauseDownload(\"abc\")"
+        );
+        assert_eq!(route(Method::Put, "/downloads").unwrap_err().status, 405);
+        assert_eq!(route(Method::Get, "/nope").unwrap_err().status, 404);
+    }
+}
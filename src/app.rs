@@ -1,12 +1,10 @@
 use crate::{
-    components::atom::Atom,
+    api,
+    components::{atom::Atom, download::AtomDownload},
     font::{ICOFONT_BYTES, JOSEFIN_BYTES, LEXEND_BYTES, MONOSPACED_FONT_BYTES, SYMBOLS_BYTES},
     messages::Message,
     style::AtomTheme,
-    utils::{
-        helpers::{ATOM_ICON, ATOM_SOCKET_ADDRESS},
-        json_from_browser::JSONFromBrowser,
-    },
+    utils::helpers::{ATOM_AUTOSAVE_INTERVAL, ATOM_ICON, ATOM_SETTINGS_POLL_INTERVAL},
 };
 use iced::{
     event,
@@ -18,20 +16,15 @@ use iced::{
     Length::Fill,
     Size, Subscription, Task as Command,
 };
-use std::{
-    io::{prelude::*, BufReader, Write},
-    net::TcpListener,
-};
-use tracing::{debug, warn};
 use tray_icon::menu::MenuEvent;
 
 pub enum App<'a> {
-    Loading,
+    Loading(Vec<String>), // URLs and links files from the command line, opened once loaded
     Loaded(Atom<'a>),
 }
 
 impl App<'_> {
-    pub fn new() -> (Self, Command<Message>) {
+    pub fn new(open: Vec<String>) -> (Self, Command<Message>) {
         #[cfg(target_os = "windows")]
         let platform_specific_settings = PlatformSpecific {
             undecorated_shadow: true,
@@ -40,7 +33,7 @@ impl App<'_> {
         #[cfg(not(target_os = "windows"))]
         let platform_specific_settings = PlatformSpecific::default();
 
-        let (_id, open) = window::open(window::Settings {
+        let (_id, open_window) = window::open(window::Settings {
             size: Size {
                 width: 1086.0,
                 height: 610.0,
@@ -62,14 +55,14 @@ impl App<'_> {
         });
 
         (
-            App::Loading,
+            App::Loading(open),
             Command::batch(vec![
                 iced::font::load(MONOSPACED_FONT_BYTES).map(Message::FontLoaded),
                 iced::font::load(LEXEND_BYTES).map(Message::FontLoaded),
                 iced::font::load(JOSEFIN_BYTES).map(Message::FontLoaded),
                 iced::font::load(ICOFONT_BYTES).map(Message::FontLoaded),
                 iced::font::load(SYMBOLS_BYTES).map(Message::FontLoaded),
-                open.map(Message::MainWindow),
+                open_window.map(Message::MainWindow),
                 Command::done(Message::LoadingComplete),
             ]),
         )
@@ -81,14 +74,14 @@ impl App<'_> {
 
     pub fn theme(&self, _: Id) -> AtomTheme {
         match self {
-            App::Loading => AtomTheme::Default,
+            App::Loading(_) => AtomTheme::Default,
             App::Loaded(atom) => atom.theme,
         }
     }
 
     pub fn scale_factor(&self, window_id: Id) -> f64 {
         match self {
-            App::Loading => 1.0,
+            App::Loading(_) => 1.0,
             App::Loaded(atom) => {
                 if let Some(window) = atom.windows.get(&window_id) {
                     if window.0 == "main" {
@@ -102,13 +95,13 @@ impl App<'_> {
 
     pub fn subscription(&self) -> Subscription<Message> {
         match self {
-            App::Loading => Subscription::none(),
+            App::Loading(_) => Subscription::none(),
             App::Loaded(atom) => {
                 let mut subscriptions: Vec<_> = atom
                     .downloads
-                    .iter()
-                    .map(|(&index, download)| {
-                        download.subscription(index, &atom.settings.cache_dir, atom.client.clone())
+                    .values()
+                    .map(|download| {
+                        download.subscription(&atom.settings, atom.client.clone(), &atom.engines)
                     })
                     .collect();
 
@@ -131,9 +124,23 @@ impl App<'_> {
                 subscriptions.push(window::resize_events().map(Message::WindowResized));
 
                 if !atom.should_exit {
-                    subscriptions.push(Subscription::run(App::subscribe_web_requests));
+                    subscriptions.push(api::server::subscription(
+                        atom.settings.api_address.clone(),
+                        atom.api_auth.clone(),
+                        atom.events.clone(),
+                    ));
+                }
+
+                if atom.unsaved_progress || atom.downloads.values().any(AtomDownload::is_active) {
+                    subscriptions
+                        .push(iced::time::every(ATOM_AUTOSAVE_INTERVAL).map(|_| Message::AutoSave));
                 }
 
+                subscriptions.push(
+                    iced::time::every(ATOM_SETTINGS_POLL_INTERVAL)
+                        .map(|_| Message::CheckSettingsFile),
+                );
+
                 if atom.tray.is_some() && !atom.should_exit {
                     subscriptions.push(Subscription::run(App::subscribe_tray_events));
                 }
@@ -146,10 +153,10 @@ impl App<'_> {
 
     pub fn update(&mut self, message: Message) -> Command<Message> {
         match self {
-            App::Loading => {
+            App::Loading(open) => {
                 let mut command = Command::none();
                 if let Message::LoadingComplete = message {
-                    let mut atom = Atom::new();
+                    let mut atom = Atom::new(false);
                     if atom.settings.maximized {
                         if let Some(entry) = atom.windows.first_entry() {
                             let window_id = *entry.key();
@@ -163,18 +170,26 @@ impl App<'_> {
                             command = window::get_oldest().and_then(iced::window::toggle_maximize);
                         }
                     }
+                    if !open.is_empty() {
+                        command = command.chain(Command::done(Message::Open(std::mem::take(open))));
+                    }
                     *self = App::Loaded(atom);
                 }
 
                 command
             }
-            App::Loaded(atom) => atom.update(message),
+            App::Loaded(atom) => {
+                let command = atom.update(message);
+                // a regenerated token or revoked client applies to the next API request
+                atom.api_auth.sync(&atom.settings);
+                command
+            }
         }
     }
 
     pub fn view(&self, window_id: Id) -> Element<Message, AtomTheme> {
         match self {
-            App::Loading => container(
+            App::Loading(_) => container(
                 text("loading...")
                     .size(50)
                     .align_x(iced::alignment::Horizontal::Center),
@@ -200,69 +215,4 @@ impl App<'_> {
 
         receiver
     }
-
-    fn subscribe_web_requests() -> impl Stream<Item = Message> {
-        let (mut sender, receiver) = futures::channel::mpsc::channel(100);
-
-        std::thread::spawn(move || {
-            let listener = TcpListener::bind(ATOM_SOCKET_ADDRESS);
-
-            if listener.is_err() {
-                warn!("Error: {}", listener.unwrap_err());
-                sender
-                    .try_send(Message::StatusBar(
-                        "Download Capture: OFF(ERROR)".to_string(),
-                    ))
-                    .ok();
-                return;
-            } else {
-                debug!("TCP listener started on {}", ATOM_SOCKET_ADDRESS);
-                sender
-                    .try_send(Message::StatusBar("Download Capture: ON".to_string()))
-                    .ok();
-            }
-
-            let listener = listener.unwrap();
-
-            while let Ok((stream, _)) = listener.accept() {
-                let mut stream = stream;
-                let buf_reader = BufReader::new(&mut stream);
-                let http_request: Vec<_> = buf_reader
-                    .lines()
-                    .map(|result| result.unwrap())
-                    .take_while(|line| !line.ends_with("<END>"))
-                    .collect();
-
-                let response = "HTTP/1.1 200 OK\r\n\r\n";
-                stream.write_all(response.as_bytes()).ok();
-
-                if http_request.is_empty() {
-                    warn!("HTTP request from the browser is empty, maybe the app is exiting//");
-                    continue;
-                }
-
-                let json = http_request.last().unwrap();
-                let json = serde_json::from_str::<JSONFromBrowser>(json);
-                if json.is_err() {
-                    warn!(
-                        "parsing JSON from the browser failed, received JSON: {:#?}",
-                        json
-                    );
-                    continue;
-                }
-
-                let json = json.unwrap();
-                if json.file_name.is_empty() || json.url.is_empty() {
-                    debug!("received empty filename or URL in JSON received from the browser, skipping download");
-                    continue;
-                }
-
-                sender
-                    .try_send(Message::NewDownloadReceivedFromBrowser(json))
-                    .ok();
-            }
-        });
-
-        receiver
-    }
 }
@@ -0,0 +1,825 @@
+use crate::{
+    engine::torrent,
+    utils::{
+        checksum::Checksum,
+        desktop,
+        helpers::{
+            get_relative_file_size, parse_settings_toml, ATOM_INSTANCE_ID, ATOM_MAX_THREADS,
+        },
+        json_from_browser::{JSONFromBrowser, ATOM_URI_SCHEME},
+        paths::atom_dirs,
+        request::{encode_body, parse_method},
+    },
+};
+use reqwest::{blocking::Client, Method, StatusCode};
+use serde_json::{json, Value};
+use single_instance::SingleInstance;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+const CLI_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// URLs `atom <URL>` hands to the app, anything else has to be a links file
+const OPEN_SCHEMES: [&str; 6] = ["http", "https", "ftp", "ftps", "sftp", "magnet"];
+const CLI_USAGE: &str = "Usage:
+  atom                                  start the app, or bring the running one to front
+  atom <URL|links file>...              open URLs and import links files in the running
+                                        app, starting it when needed
+  atom --headless                       run downloads and the local API without a window
+  atom install-desktop                  add ATOM to the Linux app menu, \"Open with\" and
+                                        as the handler of atom://add?url=... links
+  atom uninstall-desktop
+  atom add <URL> [-o <path>] [-H <'Name: value'>]... [-m <mirror URL>]...
+           [--threads <N>] [--sequential] [--files <N,N...>]
+           [-X <method>] [-d <body> | --data-file <file>] [--checksum <algorithm:hex>]
+  atom list [--json]
+  atom pause <ID>
+  atom resume <ID>
+  atom rm <ID> [--force]
+
+Options:
+  --config-dir <DIR>                    keep settings, downloads and chunks in DIR, goes
+                                        before the command (same as ATOM_CONFIG_DIR)
+
+Commands talk to the running ATOM instance through its local API.
+<ID> is a download id from `atom list`, any unique prefix of it works.
+<URL> of `atom add` can be a magnet link or a .torrent file, --files picks which of the
+torrent's files to download, counting from 1. --checksum is checked once the download
+finishes, e.g. sha256:<hex>; without it a hash the server publishes is used.";
+
+#[derive(Debug, PartialEq)]
+pub enum CliCommand {
+    Add {
+        url: String,
+        output: Option<PathBuf>,
+        headers: HashMap<String, String>,
+        mirrors: Vec<String>,
+        threads: u8,
+        sequential: bool,
+        torrent_files: Vec<usize>, // counting from 0
+        method: String,
+        body: Vec<u8>,
+        checksum: String, // normalized, empty for none
+    },
+    List {
+        json: bool,
+    },
+    Pause(String),
+    Resume(String),
+    Remove {
+        id: String,
+        force: bool,
+    },
+    Open(Vec<String>), // URLs, atom:// links and absolute paths of links files
+    InstallDesktop,
+    UninstallDesktop,
+    Headless,
+    Help,
+}
+
+/**
+ * splits a leading `--config-dir <DIR>` or `--config-dir=<DIR>` off the arguments
+ */
+pub fn take_config_dir(args: &[String]) -> Result<(Option<PathBuf>, &[String]), String> {
+    match args {
+        [flag, dir, rest @ ..] if flag == "--config-dir" => Ok((Some(PathBuf::from(dir)), rest)),
+        [flag] if flag == "--config-dir" => Err("--config-dir needs a directory".to_string()),
+        [flag, rest @ ..] if flag.starts_with("--config-dir=") => {
+            let dir = &flag["--config-dir=".len()..];
+            if dir.is_empty() {
+                return Err("--config-dir needs a directory".to_string());
+            }
+            Ok((Some(PathBuf::from(dir)), rest))
+        }
+        _ => Ok((None, args)),
+    }
+}
+
+/**
+ * parses the arguments after the binary name, `Ok(None)` means start the app
+ */
+pub fn parse(all_args: &[String]) -> Result<Option<CliCommand>, String> {
+    let Some((command, args)) = all_args.split_first() else {
+        return Ok(None);
+    };
+
+    let command = match &command[..] {
+        "add" => parse_add(args)?,
+        "list" | "ls" => CliCommand::List {
+            json: match args {
+                [] => false,
+                [flag] if flag == "--json" => true,
+                _ => return Err(format!("unexpected arguments for list: {}", args.join(" "))),
+            },
+        },
+        "pause" => CliCommand::Pause(parse_id(args, &[])?.0),
+        "resume" => CliCommand::Resume(parse_id(args, &[])?.0),
+        "rm" | "remove" => {
+            let (id, flags) = parse_id(args, &["--force", "-f"])?;
+            CliCommand::Remove {
+                id,
+                force: !flags.is_empty(),
+            }
+        }
+        "--headless" if args.is_empty() => CliCommand::Headless,
+        "install-desktop" if args.is_empty() => CliCommand::InstallDesktop,
+        "uninstall-desktop" if args.is_empty() => CliCommand::UninstallDesktop,
+        "help" | "-h" | "--help" => CliCommand::Help,
+        _ => parse_open(all_args)?,
+    };
+
+    Ok(Some(command))
+}
+
+fn parse_add(args: &[String]) -> Result<CliCommand, String> {
+    let mut url = None;
+    let mut output = None;
+    let mut headers = HashMap::new();
+    let mut mirrors = vec![];
+    let mut threads = 0;
+    let mut sequential = false;
+    let mut torrent_files = vec![];
+    let mut method = String::new();
+    let mut body = None;
+    let mut checksum = String::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{name} needs a value"))
+        };
+
+        match &arg[..] {
+            "-o" | "--output" => output = Some(PathBuf::from(value(arg)?)),
+            "-H" | "--header" => {
+                let header = value(arg)?;
+                let (name, value) = header
+                    .split_once(':')
+                    .filter(|(name, _)| !name.trim().is_empty())
+                    .ok_or_else(|| format!("header `{header}` is not `Name: value`"))?;
+                headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+            }
+            "-m" | "--mirror" => mirrors.push(value(arg)?),
+            "--threads" => {
+                let count = value(arg)?;
+                threads = count
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|threads| (1..=ATOM_MAX_THREADS).contains(threads))
+                    .ok_or_else(|| {
+                        format!("--threads must be between 1 and {ATOM_MAX_THREADS}, got `{count}`")
+                    })?;
+            }
+            "--sequential" => sequential = true,
+            "--files" => {
+                let files = value(arg)?;
+                torrent_files = files
+                    .split(',')
+                    .map(|file| {
+                        file.trim()
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|file| file.checked_sub(1))
+                            .ok_or_else(|| format!("--files needs numbers from 1, got `{files}`"))
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "-X" | "--method" => {
+                let name = value(arg)?;
+                method = parse_method(&name)?;
+            }
+            "-d" | "--data" if body.is_none() => body = Some(value(arg)?.into_bytes()),
+            "--data-file" if body.is_none() => {
+                let path = value(arg)?;
+                body = Some(
+                    std::fs::read(&path).map_err(|error| format!("cannot read {path}: {error}"))?,
+                );
+            }
+            "--checksum" => {
+                let digest = value(arg)?;
+                checksum = Checksum::parse(&digest)
+                    .ok_or_else(|| format!("--checksum needs <algorithm:hex>, got `{digest}`"))?
+                    .to_string();
+            }
+            "-d" | "--data" | "--data-file" => {
+                return Err("only one of --data and --data-file can be given".to_string())
+            }
+            flag if flag.starts_with('-') => return Err(format!("unknown option `{flag}`")),
+            _ if url.is_none() => url = Some(arg.to_string()),
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+
+    let mut url: String = url.ok_or("add needs a URL")?;
+    // the running instance has its own working directory
+    let path = Path::new(&url);
+    if torrent::is_torrent_file(path) && path.is_file() {
+        url = torrent::torrent_file_url(path).ok_or(format!("cannot resolve {url}"))?;
+    }
+
+    Ok(CliCommand::Add {
+        url,
+        output,
+        headers,
+        mirrors,
+        threads,
+        sequential,
+        torrent_files,
+        // a body without a method is a POST, like curl sends it
+        method: match (&method[..], &body) {
+            ("", Some(_)) => "POST".to_string(),
+            _ => method,
+        },
+        body: body.unwrap_or_default(),
+        checksum,
+    })
+}
+
+/**
+ * `atom <URL|file>...` from file managers and scripts, relative paths are resolved here
+ * because the running instance has its own working directory
+ */
+fn parse_open(args: &[String]) -> Result<CliCommand, String> {
+    args.iter()
+        .enumerate()
+        .map(|(index, arg)| {
+            if let Ok(url) = reqwest::Url::parse(arg) {
+                // "open with" hands over file:// URLs for local files
+                if url.scheme() == "file" {
+                    return url
+                        .to_file_path()
+                        .map(|path| path.to_string_lossy().to_string())
+                        .map_err(|_| format!("`{arg}` is not a local file"));
+                }
+                if url.scheme() == ATOM_URI_SCHEME {
+                    return JSONFromBrowser::from_atom_uri(arg).map(|_| arg.to_string());
+                }
+                if OPEN_SCHEMES.contains(&url.scheme()) {
+                    return Ok(arg.to_string());
+                }
+            }
+
+            let path = Path::new(arg);
+            if path.is_file() {
+                return std::path::absolute(path)
+                    .map(|path| path.to_string_lossy().to_string())
+                    .map_err(|error| format!("cannot resolve {arg}: {error}"));
+            }
+
+            Err(if index == 0 {
+                format!("unknown command `{arg}`")
+            } else {
+                format!("`{arg}` is neither a URL nor a file")
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(CliCommand::Open)
+}
+
+fn parse_id<'a>(
+    args: &'a [String],
+    allowed_flags: &[&str],
+) -> Result<(String, Vec<&'a str>), String> {
+    let (flags, values): (Vec<&str>, Vec<&str>) = args
+        .iter()
+        .map(String::as_str)
+        .partition(|arg| arg.starts_with('-'));
+
+    if let Some(flag) = flags.iter().find(|flag| !allowed_flags.contains(flag)) {
+        return Err(format!("unknown option `{flag}`"));
+    }
+
+    match values[..] {
+        [id] if id.chars().all(|c| c.is_ascii_hexdigit() || c == '-') => {
+            Ok((id.to_string(), flags))
+        }
+        [id] => Err(format!("`{id}` is not a download ID")),
+        [] => Err("a download ID is needed, see `atom list`".to_string()),
+        _ => Err(format!("unexpected arguments: {}", values.join(" "))),
+    }
+}
+
+/**
+ * splits `-o` into the directory and file name the running instance should use, relative
+ * paths are resolved here because the instance has its own working directory
+ */
+fn output_location(output: Option<PathBuf>) -> Result<(String, String), String> {
+    let Some(output) = output else {
+        return Ok((String::default(), String::default()));
+    };
+
+    let output = std::path::absolute(&output)
+        .map_err(|error| format!("cannot resolve {}: {error}", output.display()))?;
+    let is_dir = output.is_dir()
+        || output
+            .to_string_lossy()
+            .ends_with(std::path::MAIN_SEPARATOR);
+
+    if is_dir {
+        Ok((output.to_string_lossy().to_string(), String::default()))
+    } else {
+        Ok((
+            output
+                .parent()
+                .map(|parent| parent.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            output
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        ))
+    }
+}
+
+struct ApiConnection {
+    client: Client,
+    address: String,
+    token: String,
+}
+
+impl ApiConnection {
+    fn new() -> Result<Self, String> {
+        let settings_path = atom_dirs().settings_file();
+        if !settings_path.exists() {
+            return Err("ATOM is not running (no settings.toml found), start the app first".into());
+        }
+        let settings = parse_settings_toml(&settings_path)
+            .map_err(|error| format!("cannot load {}, {error}", settings_path.display()))?;
+        // ATOM writes the token on its first start, an older file has none until then
+        if settings.api_token.is_empty() {
+            return Err(format!(
+                "no pairing token in {}, start ATOM once to create it",
+                settings_path.display()
+            ));
+        }
+
+        let client = Client::builder()
+            .timeout(CLI_REQUEST_TIMEOUT)
+            .build()
+            .map_err(|error| format!("cannot create HTTP client: {error}"))?;
+
+        Ok(Self {
+            client,
+            address: settings.api_address,
+            token: settings.api_token,
+        })
+    }
+
+    fn request(&self, method: Method, path: &str, body: Option<Value>) -> Result<Value, String> {
+        let mut request = self
+            .client
+            .request(method, format!("http://{}{path}", self.address))
+            .bearer_auth(&self.token)
+            .header("X-Atom-Client", "atom-cli")
+            .header("X-Atom-Client-Id", "atom-cli");
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+
+        let response = request.send().map_err(|error| {
+            if error.is_connect() || error.is_timeout() {
+                format!(
+                    "ATOM is not running or its local API is off (nothing answered on {})",
+                    self.address
+                )
+            } else {
+                format!("request to ATOM failed: {}", error.without_url())
+            }
+        })?;
+
+        let status = response.status();
+        let body = response.json::<Value>().unwrap_or_default();
+        if status.is_success() {
+            Ok(body)
+        } else if status == StatusCode::UNAUTHORIZED {
+            Err("ATOM rejected the pairing token in settings.toml, restart the app".to_string())
+        } else {
+            Err(body
+                .get("error")
+                .and_then(Value::as_str)
+                .map_or_else(|| format!("ATOM answered {status}"), str::to_string))
+        }
+    }
+}
+
+fn print_table(downloads: &[Value]) {
+    let field = |download: &Value, name: &str| download.get(name).cloned().unwrap_or_default();
+    let number =
+        |download: &Value, name: &str| field(download, name).as_u64().unwrap_or(0) as usize;
+
+    println!(
+        "{:<8} {:<12} {:>7} {:>12} {:>12}  NAME",
+        "ID", "STATUS", "DONE", "SIZE", "SPEED"
+    );
+    downloads.iter().for_each(|download| {
+        let size = number(download, "size");
+        let downloaded = number(download, "downloaded");
+        let done = if size > 0 {
+            format!("{:.1}%", (downloaded as f64 / size as f64) * 100.0)
+        } else {
+            "-".to_string()
+        };
+        let status = field(download, "status");
+        let speed = if status.as_str() == Some("downloading") {
+            let rate = field(download, "transfer_rate").as_f64().unwrap_or(0.0);
+            format!(
+                "{}/s",
+                get_relative_file_size((rate * 1000.0 * 1000.0) as usize)
+            )
+        } else {
+            "-".to_string()
+        };
+
+        println!(
+            "{:<8} {:<12} {:>7} {:>12} {:>12}  {}",
+            // the short form is enough to address a download, `--json` has the full id
+            field(download, "id")
+                .as_str()
+                .unwrap_or_default()
+                .get(..8)
+                .unwrap_or_default(),
+            status.as_str().unwrap_or_default(),
+            done,
+            get_relative_file_size(size),
+            speed,
+            field(download, "file_name").as_str().unwrap_or_default()
+        );
+    });
+}
+
+fn execute(command: CliCommand) -> Result<(), String> {
+    match command {
+        CliCommand::Help => {
+            println!("{CLI_USAGE}");
+            return Ok(());
+        }
+        CliCommand::InstallDesktop => {
+            let (desktop_file, registered) = desktop::install()?;
+            println!("installed {}", desktop_file.display());
+            if !registered {
+                println!(
+                    "xdg-mime is not available, atom:// links work once the desktop picks up the file"
+                );
+            }
+            return Ok(());
+        }
+        CliCommand::UninstallDesktop => {
+            desktop::uninstall()?
+                .iter()
+                .for_each(|removed| println!("removed {}", removed.display()));
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let api = ApiConnection::new()?;
+    match command {
+        CliCommand::Add {
+            url,
+            output,
+            headers,
+            mirrors,
+            threads,
+            sequential,
+            torrent_files,
+            method,
+            body,
+            checksum,
+        } => {
+            let (file_path, file_name) = output_location(output)?;
+            let download = api.request(
+                Method::POST,
+                "/downloads",
+                Some(json!({
+                    "url": url,
+                    "file_path": file_path,
+                    "file_name": file_name,
+                    "headers": headers,
+                    "mirrors": mirrors,
+                    "threads": threads,
+                    "sequential": sequential,
+                    "torrent_files": torrent_files,
+                    "method": method,
+                    "body": encode_body(&body),
+                    "body_base64": true,
+                    "checksum": checksum,
+                    "start": true,
+                })),
+            )?;
+            println!(
+                "added {} as {}",
+                download
+                    .get("file_name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+                download
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+            );
+        }
+        CliCommand::List { json } => {
+            let downloads = api.request(Method::GET, "/downloads", None)?;
+            if json {
+                println!("{downloads:#}");
+            } else {
+                print_table(downloads.as_array().map(Vec::as_slice).unwrap_or_default());
+            }
+        }
+        CliCommand::Pause(id) => {
+            api.request(Method::POST, &format!("/downloads/{id}/pause"), None)?;
+            println!("paused {id}");
+        }
+        CliCommand::Resume(id) => {
+            api.request(Method::POST, &format!("/downloads/{id}/resume"), None)?;
+            println!("resumed {id}");
+        }
+        CliCommand::Remove { id, force } => {
+            let query = if force { "?force=true" } else { "" };
+            api.request(Method::DELETE, &format!("/downloads/{id}{query}"), None)?;
+            println!("removed {id}");
+        }
+        CliCommand::Open(items) => {
+            api.request(Method::POST, "/open", Some(json!({ "items": items })))?;
+            println!("opened {} item(s) in the running ATOM", items.len());
+        }
+        CliCommand::Headless
+        | CliCommand::Help
+        | CliCommand::InstallDesktop
+        | CliCommand::UninstallDesktop => {}
+    }
+
+    Ok(())
+}
+
+/**
+ * another ATOM (app or headless) holds the single instance lock
+ */
+pub fn instance_running() -> bool {
+    SingleInstance::new(ATOM_INSTANCE_ID).is_ok_and(|instance| !instance.is_single())
+}
+
+/**
+ * asks the running instance to bring its window to front
+ */
+pub fn show_running_instance() -> bool {
+    match ApiConnection::new()
+        .and_then(|api| api.request(Method::POST, "/open", Some(json!({ "items": [] }))))
+    {
+        Ok(_) => {
+            println!("ATOM is already running");
+            true
+        }
+        Err(error) => {
+            eprintln!("atom: {error}");
+            false
+        }
+    }
+}
+
+/**
+ * release builds are GUI programs on Windows and start without a console, subcommands print
+ * to the one of the shell that started them. Fails harmlessly when there is none
+ */
+pub fn attach_console() {
+    #[cfg(target_os = "windows")]
+    unsafe {
+        use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+pub fn run(command: CliCommand) -> ExitCode {
+    match execute(command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("atom: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+pub fn usage_error(error: &str) -> ExitCode {
+    eprintln!("atom: {error}, see `atom help`");
+    ExitCode::from(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn add(url: &str) -> CliCommand {
+        CliCommand::Add {
+            url: url.to_string(),
+            output: None,
+            headers: HashMap::new(),
+            mirrors: vec![],
+            threads: 0,
+            sequential: false,
+            torrent_files: vec![],
+            method: String::new(),
+            body: vec![],
+            checksum: String::new(),
+        }
+    }
+
+    #[test]
+    fn subcommands() {
+        let url = "https://example.com/file.iso";
+        for (line, command) in [
+            (vec!["list"], CliCommand::List { json: false }),
+            (vec!["ls", "--json"], CliCommand::List { json: true }),
+            (vec!["pause", "3f2a"], CliCommand::Pause("3f2a".to_string())),
+            (
+                vec!["resume", "3f2a-9b0c"],
+                CliCommand::Resume("3f2a-9b0c".to_string()),
+            ),
+            (
+                vec!["rm", "3f2a"],
+                CliCommand::Remove {
+                    id: "3f2a".to_string(),
+                    force: false,
+                },
+            ),
+            (
+                vec!["remove", "-f", "3f2a"],
+                CliCommand::Remove {
+                    id: "3f2a".to_string(),
+                    force: true,
+                },
+            ),
+            (vec!["--headless"], CliCommand::Headless),
+            (vec!["install-desktop"], CliCommand::InstallDesktop),
+            (vec!["uninstall-desktop"], CliCommand::UninstallDesktop),
+            (vec!["--help"], CliCommand::Help),
+            (vec!["add", url], add(url)),
+        ] {
+            assert_eq!(parse(&args(&line)), Ok(Some(command)), "{line:?}");
+        }
+        assert_eq!(parse(&[]), Ok(None));
+    }
+
+    #[test]
+    fn add_options() {
+        let url = "https://example.com/file.iso";
+        let checksum = format!("SHA-256:{}", SHA256.to_uppercase());
+        let parsed = parse(&args(&[
+            "add",
+            "-o",
+            "out/file.iso",
+            url,
+            "-H",
+            "X-Token: a:b ",
+            "--mirror",
+            "https://mirror.example/file.iso",
+            "--threads",
+            "16",
+            "--sequential",
+            "--files",
+            "1, 3",
+            "-X",
+            "patch",
+            "--data",
+            "{}",
+            "--checksum",
+            &checksum,
+        ]));
+
+        assert_eq!(
+            parsed,
+            Ok(Some(CliCommand::Add {
+                url: url.to_string(),
+                output: Some(PathBuf::from("out/file.iso")),
+                headers: HashMap::from([("x-token".to_string(), "a:b".to_string())]),
+                mirrors: vec!["https://mirror.example/file.iso".to_string()],
+                threads: 16,
+                sequential: true,
+                torrent_files: vec![0, 2],
+                method: "PATCH".to_string(),
+                body: b"{}".to_vec(),
+                checksum: format!("SHA-256 {SHA256}"),
+            }))
+        );
+
+        // a body alone is a POST
+        let Ok(Some(CliCommand::Add { method, .. })) = parse(&args(&["add", url, "-d", "x=1"]))
+        else {
+            panic!("not an add command");
+        };
+        assert_eq!(method, "POST");
+    }
+
+    #[test]
+    fn errors() {
+        let url = "https://example.com/file.iso";
+        for (line, error) in [
+            (
+                vec!["list", "--all"],
+                "unexpected arguments for list: --all",
+            ),
+            (vec!["pause"], "a download ID is needed, see `atom list`"),
+            (vec!["pause", "xyz"], "`xyz` is not a download ID"),
+            (vec!["resume", "ab", "cd"], "unexpected arguments: ab cd"),
+            (vec!["rm", "ab", "--yes"], "unknown option `--yes`"),
+            (vec!["add"], "add needs a URL"),
+            (vec!["add", url, "-o"], "-o needs a value"),
+            (
+                vec!["add", url, "-H", "no value"],
+                "header `no value` is not `Name: value`",
+            ),
+            (
+                vec!["add", url, "-H", " : value"],
+                "header ` : value` is not `Name: value`",
+            ),
+            (
+                vec!["add", url, "--threads", "17"],
+                "--threads must be between 1 and 16, got `17`",
+            ),
+            (
+                vec!["add", url, "--files", "1,0"],
+                "--files needs numbers from 1, got `1,0`",
+            ),
+            (
+                vec!["add", url, "-X", "NOT A METHOD"],
+                "`NOT A METHOD` is not an HTTP method",
+            ),
+            (
+                vec!["add", url, "-d", "a", "--data-file", "body.json"],
+                "only one of --data and --data-file can be given",
+            ),
+            (
+                vec!["add", url, "--checksum", "md5:1234"],
+                "--checksum needs <algorithm:hex>, got `md5:1234`",
+            ),
+            (vec!["add", url, "--resume"], "unknown option `--resume`"),
+            (
+                vec!["add", url, url],
+                &format!("unexpected argument `{url}`"),
+            ),
+            (vec!["frobnicate"], "unknown command `frobnicate`"),
+            (vec!["--headless", "now"], "unknown command `--headless`"),
+        ] {
+            assert_eq!(parse(&args(&line)), Err(error.to_string()), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn open_urls_and_files() {
+        let file = std::env::temp_dir().join(format!("atom-cli-{}.txt", std::process::id()));
+        std::fs::write(&file, "https://example.com/file.iso\n").unwrap();
+        let file_url = reqwest::Url::from_file_path(&file).unwrap().to_string();
+        let path = file.to_string_lossy().to_string();
+        let atom_link = "atom://add?url=https%3A%2F%2Fexample.com%2Ffile.iso";
+
+        let parsed = parse(&args(&[
+            "https://example.com/file.iso",
+            "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a",
+            "sftp://host/file.iso",
+            atom_link,
+            &file_url,
+            &path,
+        ]));
+        let errors = [
+            (
+                vec!["mailto:someone@example.com"],
+                "unknown command `mailto:someone@example.com`".to_string(),
+            ),
+            (
+                vec!["https://example.com/file.iso", "missing.txt"],
+                "`missing.txt` is neither a URL nor a file".to_string(),
+            ),
+            (
+                vec!["atom://add?url=ftp%3A%2F%2Fexample.com%2Ffile.iso"],
+                "the link has no http(s) url parameter".to_string(),
+            ),
+        ]
+        .map(|(line, error)| (parse(&args(&line)), error));
+        std::fs::remove_file(&file).ok();
+
+        assert_eq!(
+            parsed,
+            Ok(Some(CliCommand::Open(args(&[
+                "https://example.com/file.iso",
+                "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a",
+                "sftp://host/file.iso",
+                atom_link,
+                &path,
+                &path,
+            ]))))
+        );
+        for (parsed, error) in errors {
+            assert_eq!(parsed, Err(error));
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use super::Atom;
+use crate::{
+    api::{
+        events::{DownloadEvent, DownloadEventKind},
+        ApiClient, ApiRequest, ApiResponder, ApiResponse, DownloadSummary,
+    },
+    messages::{DownloadMessage, Message},
+};
+use iced::Task as Command;
+use serde_json::json;
+use uuid::Uuid;
+
+impl Atom<'_> {
+    pub fn handle_api_request(
+        &mut self,
+        client: &ApiClient,
+        request: ApiRequest,
+        responder: ApiResponder,
+    ) -> Command<Message> {
+        let (response, command) = match request {
+            ApiRequest::AddDownload(json) if json.start => {
+                match self.download_from_browser(*json) {
+                    Ok(download) => {
+                        let id = self.add_download(download);
+                        self.status_bar_message = "Added new download to the list".to_string();
+                        (
+                            ApiResponse::new(
+                                201,
+                                json!(self.downloads.get(&id).map(DownloadSummary::new)),
+                            ),
+                            Command::done(Message::SaveDownloads),
+                        )
+                    }
+                    Err(error) => (ApiResponse::error(400, error), Command::none()),
+                }
+            }
+            ApiRequest::AddDownload(json) => (
+                ApiResponse::new(202, json!({ "status": "accepted" })),
+                Command::done(Message::NewDownloadReceivedFromBrowser(*json)),
+            ),
+            ApiRequest::ListDownloads => (
+                ApiResponse::ok(
+                    self.ordered_downloads()
+                        .into_iter()
+                        .map(|(_, download)| DownloadSummary::new(download))
+                        .collect::<Vec<_>>(),
+                ),
+                Command::none(),
+            ),
+            ApiRequest::GetDownload(id) => match self.find_download(&id) {
+                Ok(id) => (self.download_summary(id), Command::none()),
+                Err(error) => (ApiResponse::error(404, error), Command::none()),
+            },
+            ApiRequest::PauseDownload(id) => match self.find_download(&id) {
+                Ok(id) => {
+                    let command = self.update(Message::Download(DownloadMessage::Paused, id));
+                    (self.download_summary(id), command)
+                }
+                Err(error) => (ApiResponse::error(404, error), Command::none()),
+            },
+            ApiRequest::ResumeDownload(id) => match self.find_download(&id) {
+                Ok(id) if !self.downloads[&id].is_resumable() => (
+                    ApiResponse::error(409, format!("download {id} cannot be resumed")),
+                    Command::none(),
+                ),
+                Ok(id) => {
+                    let command = self.update(Message::Download(DownloadMessage::Downloading, id));
+                    (self.download_summary(id), command)
+                }
+                Err(error) => (ApiResponse::error(404, error), Command::none()),
+            },
+            ApiRequest::Aria2(body) => self.handle_aria2_rpc(body),
+            ApiRequest::Open(items) => (
+                ApiResponse::new(202, json!({ "status": "accepted" })),
+                Command::done(Message::Open(items)),
+            ),
+            ApiRequest::Pair => (self.pair_client(client), Command::none()),
+            ApiRequest::Events => (
+                ApiResponse::error(400, "events are only available as a stream"),
+                Command::none(),
+            ),
+            ApiRequest::RemoveDownload(id, force) => match self.find_download(&id) {
+                Ok(id) => {
+                    let command = self.update(Message::Download(
+                        DownloadMessage::RemoveDownload(force),
+                        id,
+                    ));
+                    (
+                        ApiResponse::ok(json!({ "id": id, "removed": true, "force": force })),
+                        command,
+                    )
+                }
+                Err(error) => (ApiResponse::error(404, error), Command::none()),
+            },
+        };
+
+        responder.respond(response);
+        command
+    }
+
+    /**
+     * the client was recorded as paired before, its token is saved right away and checked
+     * from the next request on
+     */
+    fn pair_client(&mut self, client: &ApiClient) -> ApiResponse {
+        let Some(token) = self.settings.issue_client_token(&client.id) else {
+            return ApiResponse::error(500, "the client was not recorded");
+        };
+        if let Some(pending) = self
+            .phantom_settings
+            .paired_clients
+            .iter_mut()
+            .find(|pending| pending.id == client.id)
+        {
+            pending.token = token.clone();
+        }
+        if !self.save_settings() {
+            return ApiResponse::error(500, "cannot save the client token to settings.toml");
+        }
+        self.api_auth.sync(&self.settings);
+        self.status_bar_message = format!("Paired with {}", client.name);
+
+        ApiResponse::ok(json!({ "id": client.id, "token": token }))
+    }
+
+    pub fn publish_event(&self, kind: DownloadEventKind, id: Uuid) {
+        if let Some(download) = self.downloads.get(&id) {
+            self.events.publish(DownloadEvent::new(kind, download));
+        }
+    }
+
+    fn download_summary(&self, id: Uuid) -> ApiResponse {
+        self.downloads.get(&id).map_or_else(
+            || ApiResponse::error(404, format!("no download with id `{id}`")),
+            |download| ApiResponse::ok(DownloadSummary::new(download)),
+        )
+    }
+}
@@ -1,30 +1,40 @@
+mod api;
+mod settings_file;
 mod update;
 mod view;
 use crate::{
+    api::{events::EventBus, ApiAuth},
     components::{
         download::AtomDownload, download_state::AtomDownloadStatesFilterBar,
         form::AtomDownloadForm, import::AtomImport, metadata::AtomDownloadMetadata,
         settings::AtomSettings, sidebar::AtomSidebar, titlebar::AtomTitleBar,
     },
+    engine::EngineRegistry,
     messages::{DownloadsListFilterMessage, Message},
     style::AtomTheme,
-    utils::helpers::{
-        get_conf_directory, parse_downloads_toml, parse_settings_toml, save_settings_toml,
-        ATOM_ICON,
+    utils::{
+        helpers::{
+            parse_downloads_toml, parse_settings_toml, save_settings_toml, ATOM_ICON,
+            ATOM_INSTANCE_ID,
+        },
+        paths::atom_dirs,
+        storage::{existing_backups, file_stamp, set_aside, FileStamp, StorageError},
     },
 };
 use iced::window::Id;
 use reqwest::Client;
+use rfd::{MessageButtons, MessageDialog, MessageDialogResult, MessageLevel};
 use single_instance::SingleInstance;
 use std::{
     collections::{BTreeMap, HashMap},
-    fs::create_dir_all,
+    path::Path,
 };
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 use tray_icon::{
     menu::{Menu, MenuId, MenuItem},
     TrayIcon, TrayIconBuilder,
 };
+use uuid::Uuid;
 
 use super::sidebar::{SideBarActiveButton, SideBarState};
 
@@ -59,9 +69,11 @@ pub struct Atom<'a> {
     pub titlebar: AtomTitleBar,
     pub download_state_filter_bar: AtomDownloadStatesFilterBar<'a>,
     pub download_form: AtomDownloadForm,
-    pub downloads: BTreeMap<usize, AtomDownload>,
+    pub downloads: HashMap<Uuid, AtomDownload>,
     pub settings: AtomSettings,
     pub phantom_settings: AtomSettings,
+    pub saved_settings: AtomSettings, // as last loaded from or written to settings.toml
+    pub settings_stamp: FileStamp,
     pub metadata: AtomDownloadMetadata,
     pub filter_type: DownloadsListFilterMessage,
     pub import: AtomImport,
@@ -75,18 +87,22 @@ pub struct Atom<'a> {
     pub alt_pressed: bool,
     pub mouse_over_titlebar: bool,
     pub windows: BTreeMap<Id, (&'a str, AtomDownloadForm)>,
+    pub events: EventBus,
+    pub api_auth: ApiAuth, // what the local API checks requests against, see `App::update`
+    pub engines: EngineRegistry,
+    pub headless: bool,
+    pub unsaved_progress: bool,
 }
 
 impl Atom<'_> {
-    pub fn new() -> Self {
+    pub fn new(headless: bool) -> Self {
         // check single instance of application
-        let app_instance =
-            single_instance::SingleInstance::new("fade9985-845c-4ca3-84b2-8a1b29a6c636")
-                .map_err(|_| {
-                    error!("SingleInstance cannot be initialized!");
-                    std::process::exit(-1);
-                })
-                .unwrap();
+        let app_instance = single_instance::SingleInstance::new(ATOM_INSTANCE_ID)
+            .map_err(|_| {
+                error!("SingleInstance cannot be initialized!");
+                std::process::exit(-1);
+            })
+            .unwrap();
 
         let client_builder = reqwest::ClientBuilder::new();
 
@@ -104,20 +120,15 @@ impl Atom<'_> {
             })
             .unwrap();
 
-        // check if config path can be created or exists
-        let config_dir_path = get_conf_directory()
-            .map_err(|e| {
-                error!("cannot get configuration directory, error: {e:#?}");
-                std::process::exit(1);
-            })
-            .unwrap();
-
-        if !config_dir_path.exists() && create_dir_all(&config_dir_path).is_err() {
-            error!("cannot create config directory `{config_dir_path:#?}`, exiting.");
+        // check if the config, data and cache paths can be created or exist
+        let dirs = atom_dirs();
+        if let Err(error) = dirs.create() {
+            error!("cannot create the ATOM directories {dirs:#?}: {error}, exiting.");
             std::process::exit(1);
         }
+        dirs.migrate_legacy_files();
 
-        let settings_path = config_dir_path.join("settings.toml");
+        let settings_path = dirs.settings_file();
         if !settings_path.exists() {
             warn!("No settings.toml found, using defaults");
             save_settings_toml(&AtomSettings {
@@ -125,10 +136,30 @@ impl Atom<'_> {
             });
         }
 
-        let settings = parse_settings_toml(&settings_path);
-        let downloads_toml_path =
-            std::path::PathBuf::from(&settings.config_dir).join("downloads.toml");
-        let downloads: BTreeMap<usize, AtomDownload> = parse_downloads_toml(&downloads_toml_path);
+        let mut settings = parse_settings_toml(&settings_path).unwrap_or_else(|error| {
+            Atom::recover(&settings_path, error, headless, parse_settings_toml)
+        });
+        let token_missing = settings.api_token.is_empty();
+        settings.file_problems = settings.sanitize(&AtomSettings::default());
+        settings
+            .file_problems
+            .iter()
+            .for_each(|problem| warn!("Warning: settings.toml: {problem}"));
+        // the generated token is written right away so the CLI and reloads read the same one,
+        // a file with invalid values is left for the user to fix and gets it on the next save
+        if token_missing && settings.file_problems.is_empty() {
+            info!("No pairing token in settings.toml, generated one");
+            save_settings_toml(&settings);
+        }
+        let settings_stamp = file_stamp(&settings_path);
+        let downloads_toml_path = dirs.downloads_file();
+        let mut downloads = parse_downloads_toml(&downloads_toml_path).unwrap_or_else(|error| {
+            Atom::recover(&downloads_toml_path, error, headless, parse_downloads_toml)
+        });
+        // the saved progress may be a few seconds behind the files after a crash
+        downloads
+            .values_mut()
+            .for_each(|download| download.reconcile_with_disk(&settings.cache_dir));
 
         let sidebar = AtomSidebar::new(
             if downloads.is_empty() {
@@ -143,12 +174,16 @@ impl Atom<'_> {
             },
         );
 
-        let (tray_icon, tray_messages) = Atom::load_system_tray(app_instance.is_single());
+        let (tray_icon, tray_messages) =
+            Atom::load_system_tray(app_instance.is_single() && !headless);
 
         Self {
             client,
             theme: settings.theme.clone().into(),
+            api_auth: ApiAuth::new(&settings),
             phantom_settings: settings.clone(),
+            saved_settings: settings.clone(),
+            settings_stamp,
             settings,
             sidebar,
             downloads,
@@ -158,10 +193,83 @@ impl Atom<'_> {
             status_bar_message: String::from("App loaded"),
             alt_pressed: false,
             mouse_over_titlebar: false,
+            headless,
             ..Default::default()
         }
     }
 
+    /**
+     * asks whether to restore the newest working backup of a file that can't be loaded or to
+     * start over, the damaged file is set aside either way and nothing is written before that
+     */
+    fn recover<T: Default>(
+        path: &Path,
+        error: StorageError,
+        headless: bool,
+        load: fn(&Path) -> Result<T, StorageError>,
+    ) -> T {
+        let file_name = path.display();
+        error!("Error: {file_name} can't be loaded, {error}");
+
+        if headless {
+            error!(
+                "Move {file_name} away or replace it with one of its backups ({}), then start again.",
+                existing_backups(path)
+                    .iter()
+                    .map(|backup| backup.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            std::process::exit(1);
+        }
+
+        let backup = existing_backups(path)
+            .into_iter()
+            .find_map(|backup| load(&backup).ok().map(|restored| (backup, restored)));
+        let description = format!("{file_name} can't be loaded, {error}.\n\n");
+        let choice = match &backup {
+            Some((backup, _)) => MessageDialog::new()
+                .set_buttons(MessageButtons::YesNoCancel)
+                .set_description(format!(
+                    "{description}Yes: restore the backup {}\nNo: start over\nCancel: quit without changing anything\n\nThe damaged file is kept next to it.",
+                    backup.display()
+                )),
+            None => MessageDialog::new()
+                .set_buttons(MessageButtons::OkCancel)
+                .set_description(format!(
+                    "{description}There is no working backup.\n\nOk: start over\nCancel: quit without changing anything\n\nThe damaged file is kept next to it."
+                )),
+        }
+        .set_level(MessageLevel::Error)
+        .set_title("A.T.O.M")
+        .show();
+
+        if !matches!(
+            choice,
+            MessageDialogResult::Yes | MessageDialogResult::No | MessageDialogResult::Ok
+        ) {
+            std::process::exit(1);
+        }
+
+        match set_aside(path) {
+            Ok(damaged) => warn!("{file_name} was moved to {}", damaged.display()),
+            Err(error) => {
+                error!("cannot move {file_name} away, exiting: {error}");
+                std::process::exit(1);
+            }
+        }
+
+        match (choice, backup) {
+            (MessageDialogResult::Yes, Some((backup, restored))) => {
+                if let Err(error) = std::fs::copy(&backup, path) {
+                    warn!("Error: restoring {} failed: {error}", backup.display());
+                }
+                restored
+            }
+            _ => T::default(),
+        }
+    }
+
     fn load_tray_icon(image_data: &[u8]) -> tray_icon::Icon {
         let (icon_rgba, icon_width, icon_height) = {
             let image = image::load_from_memory(image_data)
@@ -240,4 +348,47 @@ impl Atom<'_> {
             (tray_icon, tray_messages)
         }
     }
+    /**
+     * downloads in list order, the map itself is keyed by id and has no order
+     */
+    pub fn ordered_downloads(&self) -> Vec<(&Uuid, &AtomDownload)> {
+        let mut downloads: Vec<_> = self.downloads.iter().collect();
+        downloads.sort_by_key(|(_, download)| download.position);
+        downloads
+    }
+
+    /**
+     * position that puts a download at the top or the bottom of the list
+     */
+    pub fn next_position(&self, first: bool) -> i64 {
+        let positions = self.downloads.values().map(|download| download.position);
+        if first {
+            positions.min().map_or(0, |position| position - 1)
+        } else {
+            positions.max().map_or(0, |position| position + 1)
+        }
+    }
+
+    /**
+     * resolves a full download id or a unique prefix of it, hyphens are optional
+     */
+    pub fn find_download(&self, id: &str) -> Result<Uuid, String> {
+        let prefix = id.replace('-', "").to_lowercase();
+        if let Ok(id) = Uuid::parse_str(id) {
+            if self.downloads.contains_key(&id) {
+                return Ok(id);
+            }
+        }
+
+        let mut matches = self.downloads.keys().filter(|download_id| {
+            !prefix.is_empty() && download_id.simple().to_string().starts_with(&prefix)
+        });
+        match (matches.next(), matches.next()) {
+            (Some(&download_id), None) => Ok(download_id),
+            (Some(_), Some(_)) => Err(format!(
+                "download id `{id}` is ambiguous, use more characters"
+            )),
+            _ => Err(format!("no download with id `{id}`")),
+        }
+    }
 }
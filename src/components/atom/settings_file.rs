@@ -0,0 +1,77 @@
+use super::Atom;
+use crate::utils::{
+    helpers::{parse_settings_toml, save_settings_toml},
+    storage::file_stamp,
+};
+use tracing::{info, warn};
+
+impl Atom<'_> {
+    /**
+     * applies changes other programs made to settings.toml, invalid values are reported and
+     * the current ones kept
+     */
+    pub fn reload_settings_if_changed(&mut self) {
+        let settings_path = self.settings.config_dir.join("settings.toml");
+        let stamp = file_stamp(&settings_path);
+        if stamp == self.settings_stamp {
+            return;
+        }
+        self.settings_stamp = stamp;
+
+        // deleted or being replaced right now, the next poll sees the new file
+        if stamp.is_none() {
+            return;
+        }
+
+        let mut external = match parse_settings_toml(&settings_path) {
+            Ok(external) => external,
+            Err(error) => {
+                warn!("Error: settings.toml changed but was not applied, {error}");
+                self.settings.file_problems =
+                    vec![format!("settings.toml was not applied, {error}")];
+                self.status_bar_message = "settings.toml has errors, see settings".to_string();
+                return;
+            }
+        };
+
+        let problems = external.sanitize(&self.settings);
+        problems
+            .iter()
+            .for_each(|problem| warn!("Warning: settings.toml: {problem}"));
+
+        self.settings = self.settings.merged_with(&self.saved_settings, &external);
+        self.phantom_settings = self
+            .phantom_settings
+            .merged_with(&self.saved_settings, &external);
+        self.saved_settings = external;
+        self.theme = self.settings.theme.clone().into();
+        self.status_bar_message = if problems.is_empty() {
+            "Settings reloaded from settings.toml".to_string()
+        } else {
+            "settings.toml has invalid values, see settings".to_string()
+        };
+        self.settings.file_problems = problems;
+        info!("settings.toml changed on disk, settings reloaded");
+    }
+
+    /**
+     * writes the settings after picking up edits made on disk since the last load, so a newer
+     * external edit is never overwritten
+     */
+    pub fn save_settings(&mut self) -> bool {
+        self.reload_settings_if_changed();
+        let settings_path = self.settings.config_dir.join("settings.toml");
+        // a file that doesn't parse is most likely still being edited, leave it alone
+        if settings_path.exists() && parse_settings_toml(&settings_path).is_err() {
+            warn!("Warning: settings.toml has errors, not overwriting it");
+            return false;
+        }
+        if !save_settings_toml(&self.settings) {
+            return false;
+        }
+        self.settings.file_problems.clear();
+        self.saved_settings = self.settings.clone();
+        self.settings_stamp = file_stamp(&settings_path);
+        true
+    }
+}
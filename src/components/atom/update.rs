@@ -1,18 +1,23 @@
 use super::{Atom, View};
 use crate::{
+    api::events::{DownloadEvent, DownloadEventKind},
     components::{
         download::AtomDownload,
         form::AtomDownloadForm,
         settings::{AtomSettings, ListLayout},
         sidebar::{SideBarActiveButton, SideBarState},
     },
+    engine::{torrent, EngineCommand},
     messages::{
-        DownloadMessage, DownloadsListFilterMessage, Message, SettingsMessage, SidebarMessage,
-        TitleBarMessage,
+        DownloadMessage, DownloadsListFilterMessage, ImportMessage, Message, SettingsMessage,
+        SidebarMessage, TitleBarMessage,
     },
-    utils::helpers::{
-        get_current_time_in_millis, save_downloads_toml, save_settings_toml, ATOM_ICON,
-        ATOM_SOCKET_ADDRESS,
+    utils::{
+        checksum::Checksum,
+        helpers::{save_downloads_toml, stream_cache_dir, ATOM_ICON},
+        json_from_browser::{JSONFromBrowser, ATOM_URI_SCHEME},
+        metalink::{is_metalink, read_metalink},
+        request::decode_body,
     },
 };
 use iced::{
@@ -20,8 +25,9 @@ use iced::{
     window::{self, settings::PlatformSpecific, Settings},
     Event, Size, Task as Command,
 };
-use std::path::PathBuf;
-use tracing::{debug, error, warn};
+use std::path::{Path, PathBuf};
+use tracing::{error, warn};
+use uuid::Uuid;
 
 impl Atom<'_> {
     fn update_view(&mut self, view: View) {
@@ -49,6 +55,139 @@ impl Atom<'_> {
         self.view = view;
     }
 
+    /**
+     * adds the download to the list (or restarts an existing one with the same URL/path)
+     * and returns its id
+     */
+    pub fn add_download(&mut self, mut new_download: AtomDownload) -> Uuid {
+        if new_download.threads == 0 {
+            new_download.threads = self.settings.threads;
+        }
+
+        let existing_download_id = self.downloads.iter().find_map(|(&id, download)| {
+            if (download.url == new_download.url
+                || (download.file_name == new_download.file_name
+                    && download.file_path == new_download.file_path))
+                && !download.deleted
+            {
+                Some(id)
+            } else {
+                None
+            }
+        });
+
+        if let Some(id) = existing_download_id {
+            // restarted downloads keep their id and move to the top
+            let position = self.next_position(true);
+            if let Some(existing_download) = self.downloads.get_mut(&id) {
+                existing_download.downloading = true;
+                existing_download.position = position;
+            }
+            self.publish_event(DownloadEventKind::Started, id);
+            id
+        } else {
+            new_download.position = self.next_position(self.settings.new_download_pos == "First");
+            let id = new_download.id;
+            let downloading = new_download.downloading;
+            self.downloads.insert(id, new_download);
+            self.publish_event(DownloadEventKind::Added, id);
+            if downloading {
+                self.publish_event(DownloadEventKind::Started, id);
+            }
+            id
+        }
+    }
+
+    /**
+     * builds the download described by the extension/API payload, falling back to settings
+     */
+    pub fn download_from_browser<'a>(
+        &self,
+        json: JSONFromBrowser,
+    ) -> Result<AtomDownload, &'a str> {
+        let mut download = AtomDownload::new()
+            .headers(json.headers)
+            .url(json.url)
+            .mirrors(json.mirrors)
+            .file_name(json.file_name)
+            .file_size(json.size)
+            .file_path(if json.file_path.is_empty() {
+                self.settings.downloads_dir.clone()
+            } else {
+                json.file_path
+            })
+            .threads(json.threads)
+            .torrent_files(json.torrent_files)
+            .download_type(json.sequential)
+            .checksum(Checksum::parse(&json.checksum))
+            .method(json.method);
+
+        match decode_body(&json.body, json.body_base64) {
+            Ok(body) => download = download.request_body(body),
+            Err(_) => return Err("the download body is not valid base64"),
+        }
+
+        download.build()
+    }
+
+    /**
+     * every file of the metalink becomes a download, its other urls mirrors and its hash
+     * checked once it finishes
+     */
+    fn import_metalink(&mut self, path: &Path) -> Command<Message> {
+        let files = match read_metalink(path) {
+            Ok(files) => files,
+            Err(error) => {
+                warn!("Error: importing {} failed, {error}", path.display());
+                self.status_bar_message = format!("Metalink import failed, {error}");
+                return Command::none();
+            }
+        };
+
+        files.into_iter().for_each(|file| {
+            let Some((url, mirrors)) = file.urls.split_first() else {
+                return;
+            };
+            match AtomDownload::new()
+                .url(url)
+                .mirrors(mirrors.to_vec())
+                .file_path(&self.import.download_path)
+                .file_name(file.name)
+                .file_size(file.size)
+                .checksum(file.checksum)
+                .download_type(self.import.is_sequential)
+                .build()
+            {
+                Ok(atom_download) => {
+                    let _ = self.update(Message::AddNewDownload(atom_download));
+                }
+                Err(e) => warn!("Error: {:#?}", e),
+            }
+        });
+        Command::done(Message::SaveDownloads)
+    }
+
+    /**
+     * a torrent file becomes one download of all its files, the form picks files instead
+     */
+    fn import_torrent(&mut self, path: &Path) -> Command<Message> {
+        let Some(url) = torrent::torrent_file_url(path) else {
+            warn!("Error: importing {} failed", path.display());
+            return Command::none();
+        };
+        match AtomDownload::new()
+            .url(url)
+            .file_path(&self.import.download_path)
+            .build()
+        {
+            Ok(atom_download) => {
+                let _ = self.update(Message::AddNewDownload(atom_download));
+            }
+            Err(e) => warn!("Error: {:#?}", e),
+        }
+        Command::done(Message::SaveDownloads)
+    }
+
     pub fn update(&mut self, message: Message) -> Command<Message> {
         match message {
             Message::Ignore => {}
@@ -57,8 +196,10 @@ impl Atom<'_> {
                     .insert(id, ("main", AtomDownloadForm::default()));
             }
             Message::WindowOpened(id, download) => {
-                let window = AtomDownloadForm::new(download.unwrap(), &self.settings);
+                let mut window = AtomDownloadForm::new(download.unwrap(), &self.settings);
+                let variants = window.load_variants(&self.client, &self.settings, false);
                 self.windows.insert(id, ("", window));
+                return variants.map(move |message| Message::DownloadForm(message, Some(id)));
             }
             Message::WindowClosed(id) => {
                 self.windows.remove(&id);
@@ -248,30 +389,28 @@ impl Atom<'_> {
                         .and_then(|id| window::change_mode(id, window::Mode::Hidden));
                 }
                 TitleBarMessage::AppShow => {
-                    return window::get_oldest()
-                        .and_then(|id| window::change_mode(id, window::Mode::Windowed));
+                    return window::get_oldest().and_then(|id| {
+                        window::change_mode(id, window::Mode::Windowed)
+                            .chain(window::minimize(id, false))
+                            .chain(window::gain_focus(id))
+                    });
                 }
                 TitleBarMessage::AppExit => {
-                    if !save_settings_toml(&self.settings) {
+                    if !self.save_settings() {
                         warn!("Error: saving settings failed!");
                     }
 
                     if !save_downloads_toml(
-                        self.downloads.clone().into_values().collect(),
-                        &PathBuf::from(&self.settings.config_dir).join("downloads.toml"),
+                        self.ordered_downloads()
+                            .into_iter()
+                            .map(|(_, download)| download.clone())
+                            .collect(),
+                        &self.settings.data_dir.join("downloads.toml"),
                     ) {
                         warn!("Error: saving downloads failed!");
                     }
 
                     self.should_exit = true;
-                    std::net::TcpStream::connect(ATOM_SOCKET_ADDRESS).map_or_else(
-                        |e| {
-                            warn!("Error: TcpStream::connect(failed), error({e:#?})");
-                        },
-                        |_| {
-                            debug!("Info: TcpStream::connect(success)");
-                        },
-                    );
 
                     // return window::get_oldest().and_then(window::close);
                     return iced::exit();
@@ -285,6 +424,13 @@ impl Atom<'_> {
                     return Command::done(Message::GotoHomePage)
                 }
                 crate::messages::ImportMessage::StartImportDownload => {
+                    let import_file = PathBuf::from(&self.import.import_file);
+                    if is_metalink(&import_file) {
+                        return self.import_metalink(&import_file);
+                    }
+                    if torrent::is_torrent_file(&import_file) {
+                        return self.import_torrent(&import_file);
+                    }
                     if let Ok(file_contents) = std::fs::read_to_string(&self.import.import_file) {
                         file_contents
                             .split('\n')
@@ -324,8 +470,6 @@ impl Atom<'_> {
                                         }
                                     }
                                 }
-
-                                std::thread::sleep(std::time::Duration::from_millis(2));
                             });
                         return Command::done(Message::SaveDownloads);
                     }
@@ -334,11 +478,19 @@ impl Atom<'_> {
             },
             Message::Metadata(message) => match message {
                 crate::messages::MetadataMessage::ClosePane => self.metadata.enabled = false,
+                crate::messages::MetadataMessage::Checksum(id, Ok(ref digest)) => {
+                    // kept with the download so it isn't calculated again
+                    if let Some(download) = self.downloads.get_mut(&id) {
+                        download.cache_digest(digest.clone());
+                    }
+                    self.metadata.update(message);
+                    return Command::done(Message::SaveDownloads);
+                }
                 _ => self.metadata.update(message),
             },
-            Message::ShowMetadata(index) => {
+            Message::ShowMetadata(id) => {
                 self.metadata.enabled = true;
-                if let Some(download) = self.downloads.get(&index) {
+                if let Some(download) = self.downloads.get(&id) {
                     self.metadata.update_info(download);
                 }
             }
@@ -372,6 +524,20 @@ impl Atom<'_> {
                     self.phantom_settings = self.settings.clone();
                     let _ = self.update(Message::GotoHomePage);
                 }
+                crate::messages::SettingsMessage::RegenerateApiToken
+                | crate::messages::SettingsMessage::RevokeClient(_) => {
+                    // locks clients out right away, not once the pane is saved
+                    let _ = self.settings.update(message);
+                    self.phantom_settings.api_token = self.settings.api_token.clone();
+                    self.phantom_settings.paired_clients = self.settings.paired_clients.clone();
+                    self.phantom_settings.revoked_clients = self.settings.revoked_clients.clone();
+                    self.api_auth.sync(&self.settings);
+                    if !self.save_settings() {
+                        warn!("Warning: unable to save the new API token or revoked client");
+                        self.status_bar_message =
+                            "Cannot save the API token to settings.toml".to_string();
+                    }
+                }
                 crate::messages::SettingsMessage::ResetSettings(force) => {
                     if force {
                         let settings = AtomSettings::default();
@@ -382,8 +548,22 @@ impl Atom<'_> {
                     }
                 }
                 crate::messages::SettingsMessage::SaveSettings(update_view) => {
+                    // edits made on disk meanwhile win over the same fields in the pane
+                    self.reload_settings_if_changed();
+                    if self
+                        .phantom_settings
+                        .api_address
+                        .parse::<std::net::SocketAddr>()
+                        .is_err()
+                    {
+                        warn!(
+                            "Warning: invalid API address `{}`, keeping `{}`",
+                            self.phantom_settings.api_address, self.settings.api_address
+                        );
+                        self.phantom_settings.api_address = self.settings.api_address.clone();
+                    }
                     self.settings = self.phantom_settings.clone();
-                    if !save_settings_toml(&self.settings) {
+                    if !self.save_settings() {
                         warn!("Warning: unable to save settings => {:#?}", self.settings);
                     }
 
@@ -394,15 +574,36 @@ impl Atom<'_> {
                 }
                 _ => return self.phantom_settings.update(message),
             },
-            Message::Download(state, index) => match state {
+            Message::Download(state, id) => match state {
                 DownloadMessage::DownloadSelected => {
-                    return Command::done(Message::ShowMetadata(index));
+                    return Command::done(Message::ShowMetadata(id));
                 }
                 DownloadMessage::RemoveDownload(force) => {
                     if force {
-                        if let Some(download) = self.downloads.remove(&index) {
+                        self.events.publish(DownloadEvent::removed(id));
+                        if let Some(download) = self.downloads.remove(&id) {
                             if !download.is_downloaded() || download.deleted {
-                                if download.sequential {
+                                if download.stream.is_some() {
+                                    let path = stream_cache_dir(
+                                        &self.settings.cache_dir,
+                                        &download.file_name,
+                                    );
+                                    if let Err(e) = std::fs::remove_dir_all(&path) {
+                                        warn!("Error deleting segments {path:#?} : {e:#?}");
+                                    }
+                                } else if download.is_torrent() {
+                                    // torrents write in place, a file or a folder of them
+                                    let path =
+                                        PathBuf::from(download.file_path).join(download.file_name);
+                                    let removed = if path.is_dir() {
+                                        std::fs::remove_dir_all(&path)
+                                    } else {
+                                        std::fs::remove_file(&path)
+                                    };
+                                    if let Err(e) = removed {
+                                        warn!("Error deleting torrent {path:#?} : {e:#?}");
+                                    }
+                                } else if download.sequential {
                                     let path =
                                         PathBuf::from(download.file_path).join(download.file_name);
                                     if let Err(e) = std::fs::remove_file(&path) {
@@ -428,39 +629,92 @@ impl Atom<'_> {
                             self.update_view(View::Downloads);
                         }
                         return Command::done(Message::SaveDownloads);
-                    } else if let Some(download) = self.downloads.get_mut(&index) {
+                    } else if let Some(download) = self.downloads.get_mut(&id) {
+                        // still listed, only in the trash
                         download.update(state, &self.settings);
+                        self.publish_event(DownloadEventKind::Status, id);
                     }
                 }
                 DownloadMessage::Finished => {
-                    if let Some(download) = self.downloads.get_mut(&index) {
+                    if let Some(download) = self.downloads.get_mut(&id) {
                         download.update(state, &self.settings);
+                        if self.metadata.download_id == id {
+                            self.metadata.update_checksums(download);
+                        }
                     }
+                    self.publish_event(DownloadEventKind::Finished, id);
                     return Command::done(Message::SaveDownloads);
                 }
                 _ => {
-                    if let Some(download) = self.downloads.get_mut(&index) {
+                    let event = match state {
+                        DownloadMessage::Downloading => Some(DownloadEventKind::Started),
+                        DownloadMessage::DownloadProgress(_) | DownloadMessage::Segments(..) => {
+                            Some(DownloadEventKind::Progress)
+                        }
+                        DownloadMessage::Paused => Some(DownloadEventKind::Paused),
+                        DownloadMessage::Error(_) => Some(DownloadEventKind::Error),
+                        _ => None,
+                    };
+                    if let DownloadMessage::Paused = state {
+                        self.engines.send(id, EngineCommand::Pause);
+                    }
+                    let mirror_dropped = matches!(state, DownloadMessage::MirrorDropped(_));
+                    let checksums_changed = matches!(
+                        state,
+                        DownloadMessage::Downloading | DownloadMessage::Verified(_)
+                    );
+                    let torrent_changed = matches!(
+                        state,
+                        DownloadMessage::TorrentLoaded(..)
+                            | DownloadMessage::Swarm(_)
+                            | DownloadMessage::Seeding
+                    );
+                    if let Some(download) = self.downloads.get_mut(&id) {
                         download.update(state, &self.settings);
+                        self.unsaved_progress = true;
+                        if mirror_dropped && self.metadata.download_id == id {
+                            self.metadata.update_mirrors(download);
+                        }
+                        if torrent_changed && self.metadata.download_id == id {
+                            self.metadata.update_torrent(download);
+                        }
+                        if checksums_changed && self.metadata.download_id == id {
+                            self.metadata.update_checksums(download);
+                        }
+                    }
+                    if let Some(event) = event {
+                        self.publish_event(event, id);
                     }
                 }
             },
+            Message::Api(client, request, responder) => {
+                self.settings
+                    .record_paired_client(&client.id, &client.name, &client.origin);
+                self.phantom_settings.record_paired_client(
+                    &client.id,
+                    &client.name,
+                    &client.origin,
+                );
+                return self.handle_api_request(&client, request, responder);
+            }
             Message::NewDownloadReceivedFromBrowser(json) => {
                 self.status_bar_message = "Adding new download to the list".to_string();
-                let mut download = AtomDownload::new()
-                    .headers(json.headers)
-                    .url(json.url)
-                    .file_name(json.file_name)
-                    .file_size(json.size)
-                    .file_path(&self.settings.downloads_dir)
-                    .download_type(json.sequential);
-
-                if json.method == "POST" {
-                    download = download.request_body(json.body);
+                let start = json.start;
+                let from_uri = json.from_uri;
+                // a web page picked the URL and name, only the user may start it
+                if from_uri && self.headless {
+                    warn!("Error: atom:// links need the confirmation window, not added headless");
+                    self.status_bar_message =
+                        "atom:// links can't be confirmed in headless mode".to_string();
+                    return Command::none();
                 }
 
-                match download.build() {
+                match self.download_from_browser(json) {
                     Ok(atom_download) => {
-                        if self.settings.auto_start_download {
+                        // there is no window to confirm in headless mode
+                        if !from_uri
+                            && (self.settings.auto_start_download || start || self.headless)
+                        {
                             return Command::done(Message::AddNewDownload(atom_download));
                         } else {
                             #[cfg(target_os = "windows")]
@@ -497,55 +751,79 @@ impl Atom<'_> {
                     Err(e) => warn!("Error: new download from browser, {:#?}", e),
                 }
             }
-            Message::AddNewDownload(mut new_download) => {
-                new_download.threads = self.settings.threads;
-
-                if let Some(existing_download_id) =
-                    self.downloads.iter().find_map(|(&index, download)| {
-                        if (download.url == new_download.url
-                            || (download.file_name == new_download.file_name
-                                && download.file_path == new_download.file_path))
-                            && !download.deleted
-                        {
-                            Some(index)
-                        } else {
-                            None
+            Message::Open(items) => {
+                let mut commands = vec![Command::done(Message::TitleBar(TitleBarMessage::AppShow))];
+                items.into_iter().for_each(|item| {
+                    if item.starts_with(&format!("{ATOM_URI_SCHEME}:")) {
+                        match JSONFromBrowser::from_atom_uri(&item) {
+                            Ok(json) => commands
+                                .push(Command::done(Message::NewDownloadReceivedFromBrowser(json))),
+                            Err(error) => {
+                                warn!("Error: {error}");
+                                self.status_bar_message = error;
+                            }
                         }
-                    })
-                {
-                    let mut existing_download =
-                        self.downloads.remove(&existing_download_id).unwrap();
-                    existing_download.downloading = true;
-
-                    if let Some(entry) = self.downloads.first_key_value() {
-                        self.downloads.insert(entry.0 - 1, existing_download);
-                    } else {
-                        self.downloads
-                            .insert(get_current_time_in_millis(), existing_download);
+                        return;
                     }
-                } else {
-                    match (
-                        self.downloads.first_key_value(),
-                        &self.settings.new_download_pos[..],
-                    ) {
-                        (Some(entry), "First") => {
-                            self.downloads.insert(entry.0 - 1, new_download);
-                        }
-                        _ => {
-                            self.downloads
-                                .insert(get_current_time_in_millis(), new_download);
+
+                    // torrent files open the form to pick which of their files to download
+                    let path = PathBuf::from(&item);
+                    if torrent::is_torrent_file(&path) && path.is_file() {
+                        if let Some(url) = torrent::torrent_file_url(&path) {
+                            commands.push(Command::done(Message::NewDownloadReceivedFromBrowser(
+                                JSONFromBrowser {
+                                    url,
+                                    ..Default::default()
+                                },
+                            )));
                         }
-                    };
-                }
+                        return;
+                    }
+
+                    if !path.is_file() {
+                        commands.push(Command::done(Message::NewDownloadReceivedFromBrowser(
+                            JSONFromBrowser {
+                                url: item,
+                                ..Default::default()
+                            },
+                        )));
+                        return;
+                    }
 
+                    // links files go through the import pane so the folder can be picked
+                    self.import.import_file = item;
+                    if self.import.download_path.is_empty() {
+                        self.import.download_path = self.settings.downloads_dir.clone();
+                    }
+                    if self.headless {
+                        commands
+                            .push(self.update(Message::Import(ImportMessage::StartImportDownload)));
+                    } else {
+                        self.update_view(View::Import);
+                    }
+                });
+                return Command::batch(commands);
+            }
+            Message::AddNewDownload(new_download) => {
+                self.add_download(new_download);
                 let _ = self.update(Message::GotoHomePage);
                 self.status_bar_message = "Added new download to the list".to_string();
                 return Command::done(Message::SaveDownloads);
             }
+            Message::AutoSave => {
+                if self.unsaved_progress {
+                    return Command::done(Message::SaveDownloads);
+                }
+            }
+            Message::CheckSettingsFile => self.reload_settings_if_changed(),
             Message::SaveDownloads => {
+                self.unsaved_progress = false;
                 if !save_downloads_toml(
-                    self.downloads.clone().into_values().collect(),
-                    &PathBuf::from(&self.settings.config_dir).join("downloads.toml"),
+                    self.ordered_downloads()
+                        .into_iter()
+                        .map(|(_, download)| download.clone())
+                        .collect(),
+                    &self.settings.data_dir.join("downloads.toml"),
                 ) {
                     warn!("Error: saving downloads failed!");
                 }
@@ -589,7 +867,7 @@ impl Atom<'_> {
                             _ => {
                                 return window
                                     .1
-                                    .update(message, &self.settings)
+                                    .update(message, &self.settings, &self.client)
                                     .map(move |message| Message::DownloadForm(message, window_id))
                             }
                         }
@@ -607,7 +885,7 @@ impl Atom<'_> {
                         _ => {
                             return self
                                 .download_form
-                                .update(message, &self.settings)
+                                .update(message, &self.settings, &self.client)
                                 .map(move |message| Message::DownloadForm(message, window_id))
                         }
                     }
@@ -631,11 +909,16 @@ impl Atom<'_> {
                     self.update_view(View::Import);
                 }
                 SidebarMessage::ResumeAll => {
-                    self.downloads.iter_mut().for_each(|(&_, download)| {
+                    let mut resumed = vec![];
+                    self.downloads.iter_mut().for_each(|(&id, download)| {
                         if !download.is_downloaded() && !download.downloading {
                             download.update(DownloadMessage::Downloading, &self.settings);
+                            resumed.push(id);
                         }
                     });
+                    resumed
+                        .into_iter()
+                        .for_each(|id| self.publish_event(DownloadEventKind::Started, id));
                     self.sidebar.active = SideBarActiveButton::Overview;
                     self.metadata.enabled = false;
                 }
@@ -643,6 +926,7 @@ impl Atom<'_> {
                     self.sidebar.show_dialog = true;
                 }
                 SidebarMessage::DeleteAll => {
+                    let before: Vec<Uuid> = self.downloads.keys().copied().collect();
                     match self.sidebar.active {
                         SideBarActiveButton::Overview => {
                             if !self.titlebar.search_text.is_empty() {
@@ -681,6 +965,11 @@ impl Atom<'_> {
                         _ => {}
                     }
 
+                    before
+                        .into_iter()
+                        .filter(|id| !self.downloads.contains_key(id))
+                        .for_each(|id| self.events.publish(DownloadEvent::removed(id)));
+
                     if self.downloads.is_empty() {
                         self.filter_type = DownloadsListFilterMessage::All;
                         self.view = View::Downloads;
@@ -691,9 +980,17 @@ impl Atom<'_> {
                     self.sidebar.show_dialog = false;
                 }
                 SidebarMessage::PauseAll => {
-                    self.downloads.iter_mut().for_each(|(&_, download)| {
+                    let mut paused = vec![];
+                    self.downloads.iter_mut().for_each(|(&id, download)| {
+                        if download.downloading {
+                            self.engines.send(id, EngineCommand::Pause);
+                            paused.push(id);
+                        }
                         download.update(DownloadMessage::Paused, &self.settings);
                     });
+                    paused
+                        .into_iter()
+                        .for_each(|id| self.publish_event(DownloadEventKind::Paused, id));
                     self.metadata.enabled = false;
                     self.sidebar.active = SideBarActiveButton::Overview;
                 }
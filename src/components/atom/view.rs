@@ -17,45 +17,55 @@ use iced::{
     Length::{Fill, FillPortion, Shrink},
     Padding,
 };
+use uuid::Uuid;
 
-type DownloadTuple<'a> = (&'a usize, &'a AtomDownload);
+type DownloadTuple<'a> = (&'a Uuid, &'a AtomDownload);
 
 impl Atom<'_> {
     fn filter_downloads_view(&self) -> Element<Message, AtomTheme> {
         let failed_filter: Box<dyn Fn(&DownloadTuple) -> bool> =
-            Box::new(|f: &(&usize, &AtomDownload)| !f.1.error.is_empty() && !f.1.deleted);
+            Box::new(|f: &DownloadTuple| !f.1.error.is_empty() && !f.1.deleted);
         let deleted_filter: Box<dyn Fn(&DownloadTuple) -> bool> =
-            Box::new(|f: &(&usize, &AtomDownload)| f.1.deleted);
-        let all_filter: Box<dyn Fn(&DownloadTuple) -> bool> =
-            Box::new(|f: &(&usize, &AtomDownload)| {
-                if self.titlebar.search_text.is_empty() {
-                    !f.1.deleted
-                } else {
-                    !f.1.deleted
-                        && f.1
-                            .get_file_name()
-                            .to_lowercase()
-                            .contains(&self.titlebar.search_text)
-                }
-            });
+            Box::new(|f: &DownloadTuple| f.1.deleted);
+        let all_filter: Box<dyn Fn(&DownloadTuple) -> bool> = Box::new(|f: &DownloadTuple| {
+            if self.titlebar.search_text.is_empty() {
+                !f.1.deleted
+            } else {
+                !f.1.deleted
+                    && f.1
+                        .get_file_name()
+                        .to_lowercase()
+                        .contains(&self.titlebar.search_text)
+            }
+        });
         let downloading_filter: Box<dyn Fn(&DownloadTuple) -> bool> =
-            Box::new(|f: &(&usize, &AtomDownload)| f.1.downloading && !f.1.deleted);
-        let paused_filter: Box<dyn Fn(&DownloadTuple) -> bool> =
-            Box::new(|f: &(&usize, &AtomDownload)| {
-                !f.1.is_downloading() && !f.1.is_downloaded() && !f.1.deleted
-            });
+            Box::new(|f: &DownloadTuple| f.1.downloading && !f.1.deleted);
+        let paused_filter: Box<dyn Fn(&DownloadTuple) -> bool> = Box::new(|f: &DownloadTuple| {
+            !f.1.is_downloading() && !f.1.is_downloaded() && !f.1.deleted
+        });
         let finished_filter: Box<dyn Fn(&DownloadTuple) -> bool> =
-            Box::new(|f: &(&usize, &AtomDownload)| f.1.is_downloaded() && !f.1.deleted);
+            Box::new(|f: &DownloadTuple| f.1.is_downloaded() && !f.1.deleted);
 
         let filtered_downloads = match &self.filter_type {
-            DownloadsListFilterMessage::Downloading => {
-                self.downloads.iter().filter(downloading_filter)
+            DownloadsListFilterMessage::Downloading => self
+                .ordered_downloads()
+                .into_iter()
+                .filter(downloading_filter),
+            DownloadsListFilterMessage::Paused => {
+                self.ordered_downloads().into_iter().filter(paused_filter)
+            }
+            DownloadsListFilterMessage::Finished => {
+                self.ordered_downloads().into_iter().filter(finished_filter)
+            }
+            DownloadsListFilterMessage::Deleted => {
+                self.ordered_downloads().into_iter().filter(deleted_filter)
+            }
+            DownloadsListFilterMessage::All => {
+                self.ordered_downloads().into_iter().filter(all_filter)
+            }
+            DownloadsListFilterMessage::Failed => {
+                self.ordered_downloads().into_iter().filter(failed_filter)
             }
-            DownloadsListFilterMessage::Paused => self.downloads.iter().filter(paused_filter),
-            DownloadsListFilterMessage::Finished => self.downloads.iter().filter(finished_filter),
-            DownloadsListFilterMessage::Deleted => self.downloads.iter().filter(deleted_filter),
-            DownloadsListFilterMessage::All => self.downloads.iter().filter(all_filter),
-            DownloadsListFilterMessage::Failed => self.downloads.iter().filter(failed_filter),
         };
 
         let responsive = check_responsive_threshold(
@@ -72,12 +82,12 @@ impl Atom<'_> {
                 crate::components::settings::ListLayout::ListExtended => Padding::new(0.0),
                 crate::components::settings::ListLayout::List => Padding::new(2.0).left(1).right(1),
             }),
-            |column, (index, download)| {
+            |column, (id, download)| {
                 count += 1;
                 column.push(
                     download
                         .view(&self.settings, responsive)
-                        .map(|message| Message::Download(message, *index)),
+                        .map(|message| Message::Download(message, *id)),
                 )
             },
         );
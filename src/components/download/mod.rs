@@ -1,26 +1,45 @@
 mod subscription;
 mod update;
 mod view;
-use crate::messages::DownloadMessage;
+use crate::{
+    engine::{
+        torrent::{self, TorrentFile},
+        StreamFormat, SwarmStatus,
+    },
+    messages::DownloadMessage,
+    utils::{
+        checksum::Checksum,
+        helpers::split_file_name,
+        redact::{redact_body, redact_headers, redact_url},
+        request::{base64_body, parse_method},
+    },
+};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::Path, time::SystemTime};
+use std::{collections::HashMap, fmt, path::Path, time::SystemTime};
+use tracing::debug;
+use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum DownloadMethod {
-    Get,
-    Post,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Verification {
+    #[default]
+    Unverified,
+    Verifying, // kept across restarts so an interrupted check runs again
+    Verified,
+    Mismatch,
 }
 
-#[derive(Debug)]
-pub enum DownloadType {
-    Sequential,
-    Threaded,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AtomDownload {
+    #[serde(default = "Uuid::new_v4")]
+    pub id: Uuid,
+    #[serde(default)]
+    pub position: i64, // list order, smaller comes first
     pub url: String,
-    pub method: DownloadMethod,
+    #[serde(default)]
+    pub mirrors: Vec<String>, // other URLs serving the same file
+    #[serde(skip_deserializing, skip_serializing)]
+    pub dropped_mirrors: Vec<String>, // gave up on during this session
+    pub method: String, // HTTP method of the request, replayed for every range
     pub file_path: String,
     pub file_name: String,
     pub downloaded: usize,
@@ -34,10 +53,31 @@ pub struct AtomDownload {
     pub sequential: bool,
     pub added: String,
     pub headers: HashMap<String, String>,
-    pub request_body: String,
+    #[serde(with = "base64_body")]
+    pub request_body: Vec<u8>, // sent byte for byte with every request, empty for none
     pub transfer_rate: f64,
     pub eta: f64,
     pub auto_open: bool,
+    #[serde(default)]
+    pub speed_limit: usize, // bytes per second, 0 = unlimited
+    #[serde(default)]
+    pub checksum: Option<Checksum>, // checked once the download finishes
+    #[serde(default)]
+    pub verification: Verification,
+    #[serde(default)]
+    pub digests: Vec<Checksum>, // of the file on disk, one per algorithm, dropped when it changes
+    #[serde(default)]
+    pub stream: Option<StreamFormat>, // downloaded as segments listed by a playlist
+    #[serde(skip_deserializing, skip_serializing)]
+    pub segments: (usize, usize), // done and in total, known once the playlist is loaded
+    #[serde(default)]
+    pub torrent_files: Vec<usize>, // picked files of a torrent, none for all of them
+    #[serde(skip_deserializing, skip_serializing)]
+    pub torrent_contents: Vec<TorrentFile>, // known once the torrent's metadata is in
+    #[serde(skip_deserializing, skip_serializing)]
+    pub swarm: SwarmStatus,
+    #[serde(skip_deserializing, skip_serializing)]
+    pub seeding: bool,
     #[serde(skip_deserializing, skip_serializing)]
     pub joined_bytes: usize,
     #[serde(skip_deserializing, skip_serializing)]
@@ -48,24 +88,89 @@ pub struct AtomDownload {
     pub show_delete_confirm_dialog: bool,
 }
 
+// headers, request body and URL query values may carry credentials, keep them out of the logs
+impl fmt::Debug for AtomDownload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtomDownload")
+            .field("id", &self.id)
+            .field("position", &self.position)
+            .field("url", &redact_url(&self.url))
+            .field(
+                "mirrors",
+                &self
+                    .mirrors
+                    .iter()
+                    .map(|url| redact_url(url))
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "dropped_mirrors",
+                &self
+                    .dropped_mirrors
+                    .iter()
+                    .map(|url| redact_url(url))
+                    .collect::<Vec<_>>(),
+            )
+            .field("method", &self.method)
+            .field("file_path", &self.file_path)
+            .field("file_name", &self.file_name)
+            .field("downloaded", &self.downloaded)
+            .field("download_this_session", &self.download_this_session)
+            .field("size", &self.size)
+            .field("downloading", &self.downloading)
+            .field("threads", &self.threads)
+            .field("error", &self.error)
+            .field("deleted", &self.deleted)
+            .field("sequential", &self.sequential)
+            .field("added", &self.added)
+            .field("headers", &redact_headers(&self.headers))
+            .field("request_body", &redact_body(&self.request_body))
+            .field("transfer_rate", &self.transfer_rate)
+            .field("eta", &self.eta)
+            .field("auto_open", &self.auto_open)
+            .field("speed_limit", &self.speed_limit)
+            .field("checksum", &self.checksum)
+            .field("verification", &self.verification)
+            .field("digests", &self.digests)
+            .field("stream", &self.stream)
+            .field("segments", &self.segments)
+            .field("torrent_files", &self.torrent_files)
+            .field("torrent_contents", &self.torrent_contents.len())
+            .field("swarm", &self.swarm)
+            .field("seeding", &self.seeding)
+            .field("joined_bytes", &self.joined_bytes)
+            .field("elapsed_time", &self.elapsed_time)
+            .field("joining", &self.joining)
+            .field(
+                "show_delete_confirm_dialog",
+                &self.show_delete_confirm_dialog,
+            )
+            .finish()
+    }
+}
+
 impl Default for AtomDownload {
     fn default() -> Self {
         Self {
+            id: Uuid::new_v4(),
+            position: 0,
             url: String::default(),
-            method: DownloadMethod::Get,
+            mirrors: vec![],
+            dropped_mirrors: vec![],
+            method: "GET".to_string(),
             file_path: String::default(),
             file_name: String::default(),
             downloaded: 0,
             download_this_session: 0,
             size: 0,
             downloading: true,
-            threads: 6,
+            threads: 0, // 0 = use the threads from settings when added
             error: String::default(),
             deleted: false,
             sequential: false,
             added: chrono::Local::now().date_naive().to_string(),
             headers: HashMap::default(),
-            request_body: String::default(),
+            request_body: vec![],
             transfer_rate: 0.0,
             eta: 0.0,
             elapsed_time: Some(SystemTime::now()),
@@ -73,6 +178,16 @@ impl Default for AtomDownload {
             joining: false,
             show_delete_confirm_dialog: false,
             auto_open: false,
+            speed_limit: 0,
+            checksum: None,
+            verification: Verification::Unverified,
+            digests: vec![],
+            stream: None,
+            segments: (0, 0),
+            torrent_files: vec![],
+            torrent_contents: vec![],
+            swarm: SwarmStatus::default(),
+            seeding: false,
         }
     }
 }
@@ -87,6 +202,26 @@ impl AtomDownload {
         self
     }
 
+    pub fn mirrors(mut self, mirrors: Vec<String>) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
+    pub fn checksum(mut self, checksum: Option<Checksum>) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn torrent_files(mut self, files: Vec<usize>) -> Self {
+        self.torrent_files = files;
+        self
+    }
+
+    pub fn stream_format(mut self, stream: Option<StreamFormat>) -> Self {
+        self.stream = stream;
+        self
+    }
+
     pub fn headers(mut self, headers: HashMap<String, String>) -> Self {
         self.headers = headers;
         self
@@ -112,14 +247,28 @@ impl AtomDownload {
         self
     }
 
+    pub fn threads(mut self, threads: u8) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn speed_limit(mut self, speed_limit: usize) -> Self {
+        self.speed_limit = speed_limit;
+        self
+    }
+
     pub fn download_type(mut self, sequential: bool) -> Self {
         self.sequential = sequential;
         self
     }
 
-    pub fn request_body(mut self, body: String) -> Self {
+    pub fn method(mut self, method: impl Into<String>) -> Self {
+        self.method = method.into();
+        self
+    }
+
+    pub fn request_body(mut self, body: Vec<u8>) -> Self {
         self.request_body = body;
-        self.method = DownloadMethod::Post;
         self
     }
 
@@ -141,6 +290,12 @@ impl AtomDownload {
     }
 
     pub fn build<'a>(mut self) -> Result<Self, &'a str> {
+        // magnet links have no path, the name comes with the link or later from peers
+        if self.file_name.is_empty() {
+            if let Some(name) = torrent::magnet_name(&self.url) {
+                self.file_name = name;
+            }
+        }
         if self.file_name.is_empty() {
             if let Ok(url) = reqwest::Url::parse(&self.url) {
                 if let Some(mut file_name) = url.path_segments() {
@@ -155,6 +310,23 @@ impl AtomDownload {
             }
         }
 
+        // playlist URLs are downloaded as the stream they list, streams are always joined
+        if self.stream.is_none() {
+            self.stream = StreamFormat::from_url(&self.url);
+        }
+        if let Some(stream) = &self.stream {
+            self.file_name = stream.file_name(&self.file_name);
+            self.sequential = false;
+        }
+        if self.is_torrent() {
+            self.sequential = false;
+        }
+
+        let Ok(method) = parse_method(&self.method) else {
+            return Err("AtomDownload has an invalid HTTP method!");
+        };
+        self.method = method;
+
         if self.file_name.is_empty() || self.file_path.is_empty() {
             Err("AtomDownload has empty filename or path!")
         } else {
@@ -175,10 +347,100 @@ impl AtomDownload {
     }
 
     pub fn is_downloaded(&self) -> bool {
-        self.downloaded >= self.size || self.deleted
+        // a magnet link has no size until its metadata is in
+        let size_known = self.size != 0 || !self.is_torrent();
+        (size_known && self.downloaded >= self.size) || self.deleted
     }
 
     pub fn is_downloading(&self) -> bool {
         self.downloading
     }
+
+    pub fn is_torrent(&self) -> bool {
+        torrent::is_torrent_url(&self.url)
+    }
+
+    /**
+     * a download whose checksum didn't match is downloaded again when resumed
+     */
+    pub fn is_resumable(&self) -> bool {
+        !self.deleted && (!self.is_downloaded() || self.verification == Verification::Mismatch)
+    }
+
+    /**
+     * remembers a digest of the finished file, replacing an older one of the same algorithm
+     */
+    pub fn cache_digest(&mut self, digest: Checksum) {
+        self.digests
+            .retain(|cached| cached.algorithm != digest.algorithm);
+        self.digests.push(digest);
+    }
+
+    pub fn status(&self) -> &'static str {
+        if self.deleted {
+            "deleted"
+        } else if self.verification == Verification::Mismatch {
+            "mismatch"
+        } else if !self.error.is_empty() {
+            "failed"
+        } else if self.joining {
+            "joining"
+        } else if self.verification == Verification::Verifying {
+            "verifying"
+        } else if self.seeding {
+            "seeding"
+        } else if self.size != 0 && self.downloaded >= self.size {
+            "finished"
+        } else if self.downloading {
+            "downloading"
+        } else {
+            "paused"
+        }
+    }
+
+    /**
+     * brings `downloaded` in line with the part or chunk files, they are what a resume
+     * continues from. Chunks are removed once joined, chunks left behind mean the join
+     * never finished and its partial output is removed so it runs again
+     */
+    pub fn reconcile_with_disk(&mut self, cache_dir: &Path) {
+        // the engine counts the segments of a stream in the cache when it resumes, and checks
+        // the pieces of a torrent
+        if self.deleted || self.stream.is_some() || self.is_torrent() {
+            return;
+        }
+
+        let destination = Path::new(&self.file_path).join(&self.file_name);
+        let chunk_sizes: Vec<usize> = split_file_name(&self.file_name, self.threads)
+            .iter()
+            .filter_map(|chunk| std::fs::metadata(cache_dir.join(chunk)).ok())
+            .map(|metadata| metadata.len() as usize)
+            .collect();
+        let finished = self.size > 0 && self.downloaded >= self.size;
+
+        let on_disk = if !self.sequential && !chunk_sizes.is_empty() {
+            let on_disk = chunk_sizes.iter().sum();
+            if self.size > 0 && on_disk >= self.size {
+                std::fs::remove_file(&destination).ok();
+            }
+            on_disk
+        } else if !finished {
+            std::fs::metadata(&destination).map_or(0, |metadata| metadata.len() as usize)
+        } else {
+            return;
+        };
+
+        let on_disk = if self.size > 0 {
+            on_disk.min(self.size)
+        } else {
+            on_disk
+        };
+        if on_disk != self.downloaded {
+            debug!(
+                "{}: {} bytes saved, {on_disk} bytes on disk",
+                self.file_name, self.downloaded
+            );
+            self.downloaded = on_disk;
+        }
+    }
 }
@@ -1,571 +1,154 @@
-use super::{AtomDownload, DownloadType};
+use super::{AtomDownload, Verification};
 use crate::{
-    messages::{DownloadMessage, DownloadProperties, Message},
-    utils::helpers::{get_content_length, hashmap2headermap, split_file_name, ATOM_USER_AGENT},
+    components::settings::AtomSettings,
+    engine::{self, torrent::TorrentOptions, DownloadJob, EngineEvent, EngineRegistry},
+    messages::{DownloadMessage, Message},
 };
-use iced::{futures::stream::unfold, Subscription};
-use reqwest::{
-    header::{RANGE, USER_AGENT},
-    Client, Method, Response,
+use iced::{
+    futures::stream::{self, BoxStream, StreamExt},
+    Subscription,
 };
-use std::{
-    fs::File,
-    io::{BufReader, BufWriter, Read, Write},
-    path::{Path, PathBuf},
-};
-use tracing::{debug, error, warn};
-
-#[derive(Debug)]
-struct SubDownloads {
-    response: Response,
-    file: BufWriter<File>,
-}
-
-#[derive(Debug)]
-enum State {
-    Starting(Client, AtomDownload, String),
-    ThreadedStarting(Client, AtomDownload, String, Vec<String>),
-    SequentialDownloading(Response, BufWriter<File>, usize),
-    ThreadedDownloading(AtomDownload, Vec<SubDownloads>, String, Vec<String>, usize),
-    FileJoining(BufWriter<File>, Vec<BufReader<File>>, usize, usize),
-    SequentialFinished,
-    ThreadedFinished(String, Vec<String>),
-    Wait,
+use reqwest::{Client, Method};
+use std::{path::PathBuf, time::Duration};
+use tracing::debug;
+
+impl From<EngineEvent> for DownloadMessage {
+    fn from(event: EngineEvent) -> Self {
+        match event {
+            EngineEvent::SizeKnown { size, downloaded } => {
+                DownloadMessage::SetFileSize(size, downloaded)
+            }
+            EngineEvent::Progress(downloaded) => DownloadMessage::DownloadProgress(downloaded),
+            EngineEvent::Downloaded => DownloadMessage::DownloadDoneJoining,
+            EngineEvent::Joining(bytes) => DownloadMessage::JoiningProgress(bytes),
+            EngineEvent::Finished => DownloadMessage::Finished,
+            EngineEvent::Paused => DownloadMessage::Paused,
+            EngineEvent::Error(error) => DownloadMessage::Error(error),
+            EngineEvent::MirrorDropped(url) => DownloadMessage::MirrorDropped(url),
+            EngineEvent::StreamDetected { format, file_name } => {
+                DownloadMessage::StreamDetected(format, file_name)
+            }
+            EngineEvent::Segments {
+                done,
+                total,
+                downloaded,
+            } => DownloadMessage::Segments(done, total, downloaded),
+            EngineEvent::TorrentLoaded { file_name, files } => {
+                DownloadMessage::TorrentLoaded(file_name, files)
+            }
+            EngineEvent::Swarm(status) => DownloadMessage::Swarm(status),
+            EngineEvent::Seeding => DownloadMessage::Seeding,
+            EngineEvent::ChecksumFound(checksum) => DownloadMessage::ChecksumFound(checksum),
+        }
+    }
 }
 
 impl AtomDownload {
-    #[tracing::instrument(name = "Subscription", skip(self))]
     pub fn subscription(
         &self,
-        index: usize,
-        cache_dir: &Path,
+        settings: &AtomSettings,
         client: Client,
+        engines: &EngineRegistry,
     ) -> Subscription<Message> {
-        if !self.downloading && (self.sequential || !self.joining) {
-            return Subscription::none();
-        }
-
-        let file_path = PathBuf::from(&self.file_path).join(&self.file_name);
-
-        if self.is_downloaded() && file_path.exists() {
-            return self.unfold_subscription(State::SequentialFinished, index);
-        }
-
-        let state = State::Starting(
-            client,
-            self.clone(),
-            cache_dir.to_string_lossy().to_string(),
-        );
-
-        debug!(download=?self);
-
-        self.unfold_subscription(state, index)
+        // the check gets its own id, the finished engine stream may still be around
+        self.stream(settings, client, engines)
+            .map_or_else(Subscription::none, |stream| {
+                Subscription::run_with_id((self.id, self.is_verifying()), stream)
+            })
     }
 
-    fn unfold_subscription(&self, state: State, index: usize) -> Subscription<Message> {
-        Subscription::run_with_id(
-            index,
-            unfold(state, move |state| async move {
-                match state {
-                    State::Wait => iced::futures::future::pending().await,
-                    State::SequentialFinished => Some((
-                        Message::Download(DownloadMessage::Finished, index),
-                        State::Wait,
-                    )),
-                    State::ThreadedFinished(destination_file, files) => Some(
-                        handle_threaded_download_finish(&destination_file, &files, index),
-                    ),
-                    State::ThreadedDownloading(
-                        download,
-                        sub_downloads,
-                        destination_file,
-                        chunk_files,
-                        downloaded,
-                    ) => Some(
-                        handle_threaded_downloading(
-                            download,
-                            sub_downloads,
-                            destination_file,
-                            chunk_files,
-                            downloaded,
-                            index,
-                        )
-                        .await,
-                    ),
-                    State::ThreadedStarting(client, download, destination_file, chunk_files) => {
-                        Some(
-                            handle_threaded_download_starting(
-                                download,
-                                destination_file,
-                                chunk_files,
-                                client,
-                                index,
-                            )
-                            .await,
-                        )
-                    }
-                    State::SequentialDownloading(response, file, downloaded) => {
-                        Some(handle_sequential_downloading(response, file, downloaded, index).await)
-                    }
-                    State::Starting(client, download, cache_dir) => {
-                        Some(handle_download_starting(download, client, cache_dir, index).await)
-                    }
-                    State::FileJoining(bw, chunk_files, current_index, index) => {
-                        Some(handle_joining_progress(bw, chunk_files, current_index, index).await)
-                    }
-                }
-            }),
-        )
+    /**
+     * the engine needs to run while the download is active or its chunks are being joined
+     */
+    pub fn is_active(&self) -> bool {
+        self.downloading
+            || (!self.sequential && self.joining)
+            || self.is_verifying()
+            || self.seeding
     }
-}
-
-async fn handle_download_starting(
-    mut download: AtomDownload,
-    client: Client,
-    cache_dir: String,
-    index: usize,
-) -> (Message, State) {
-    let file_path = Path::new(&download.file_path)
-        .join(&download.file_name)
-        .to_str()
-        .unwrap_or_default()
-        .to_string();
 
-    let mut options = DownloadProperties {
-        content_length: download.size,
-        download_type: if download.sequential {
-            DownloadType::Sequential
-        } else {
-            DownloadType::Threaded
-        },
-        error: "".to_string(),
-    };
-
-    if download.downloaded == 0 && download.size == 0 {
-        options = get_content_length(client.clone(), &download.url, &download.headers).await;
-    }
-
-    if !options.error.is_empty() {
-        return (
-            Message::Download(DownloadMessage::Error(options.error), index),
-            State::Wait,
-        );
+    pub fn is_verifying(&self) -> bool {
+        self.verification == Verification::Verifying
     }
 
-    match (options.download_type, download.sequential) {
-        (DownloadType::Threaded, false) => {
-            let cache_dir_path = PathBuf::from(&cache_dir);
-            let files: Vec<String> = split_file_name(&download.file_name, download.threads)
-                .iter()
-                .map(|m| cache_dir_path.join(m).to_string_lossy().to_string())
-                .collect();
-
-            let downloaded_bytes_len = files.iter().fold(0, |size, file_path| {
-                if let Ok(file) = std::fs::OpenOptions::new()
-                    .create(false)
-                    .write(false)
-                    .append(true)
-                    .open(file_path)
-                {
-                    return size + file.metadata().map_err(|_| Some(0)).unwrap().len();
-                }
-                size
-            });
-
-            download.size = options.content_length;
-            (
-                Message::Download(
-                    DownloadMessage::SetFileSize(
-                        options.content_length,
-                        downloaded_bytes_len as usize,
-                    ),
-                    index,
-                ),
-                State::ThreadedStarting(client, download, file_path, files),
-            )
-        }
-        _ => {
-            if let Ok(file) = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&file_path)
-            {
-                let mut file_size = 0;
-                let mut client = client
-                    .request(
-                        match download.method {
-                            super::DownloadMethod::Get => Method::GET,
-                            super::DownloadMethod::Post => Method::POST,
-                        },
-                        &download.url,
-                    )
-                    .header(USER_AGENT, ATOM_USER_AGENT)
-                    .headers(hashmap2headermap(&download.headers));
-
-                if let super::DownloadMethod::Post = download.method {
-                    client = client.body(download.request_body);
-                }
-
-                download.size = options.content_length;
-
-                if options.content_length != 0 {
-                    file_size = file.metadata().unwrap().len() as usize;
-                    client = client.header(
-                        RANGE,
-                        format!("bytes={}-{}", file_size, options.content_length),
-                    );
-                }
-
-                if let Ok(response) = client.send().await {
-                    (
-                        Message::Download(
-                            DownloadMessage::SetFileSize(options.content_length, file_size),
-                            index,
-                        ),
-                        State::SequentialDownloading(response, BufWriter::new(file), file_size),
-                    )
-                } else {
-                    (
-                        Message::Download(
-                            DownloadMessage::Error("failed to create download client!".to_string()),
-                            index,
-                        ),
-                        State::Wait,
-                    )
-                }
-            } else {
-                (
-                    Message::Download(
-                        DownloadMessage::Error(format!("failed to create {}!", download.file_name)),
-                        index,
-                    ),
-                    State::Wait,
-                )
-            }
+    pub fn job(&self, settings: &AtomSettings) -> DownloadJob {
+        DownloadJob {
+            url: self.url.clone(),
+            mirrors: self.mirrors.clone(),
+            method: Method::from_bytes(self.method.as_bytes()).unwrap_or(Method::GET),
+            headers: self.headers.clone(),
+            body: self.request_body.clone(),
+            file_path: self.file_path.clone(),
+            file_name: self.file_name.clone(),
+            cache_dir: settings.cache_dir.clone(),
+            threads: self.threads,
+            sequential: self.sequential,
+            size: self.size,
+            downloaded: self.downloaded,
+            speed_limit: self.speed_limit,
+            stream: self.stream.clone(),
+            ftp_active: settings.ftp_active_mode,
+            torrent: TorrentOptions {
+                files: self.torrent_files.clone(),
+                port: settings.torrent_port,
+                seed_ratio: settings.torrent_seed_ratio,
+                seed_time: Duration::from_secs(settings.torrent_seed_minutes as u64 * 60),
+                dht: settings.torrent_dht,
+            },
+            find_checksum: self.checksum.is_none() && settings.find_checksums,
         }
     }
-}
-
-async fn handle_sequential_downloading(
-    mut response: Response,
-    mut file: BufWriter<File>,
-    mut downloaded: usize,
-    index: usize,
-) -> (Message, State) {
-    let chunk = response.chunk().await;
-
-    if !response.status().is_success() {
-        return (
-            Message::Download(
-                DownloadMessage::Error(format!(
-                    "The server responded with an {} status code",
-                    response.status()
-                )),
-                index,
-            ),
-            State::Wait,
-        );
-    }
-
-    match chunk {
-        Ok(Some(chunk)) => {
-            if file.write_all(&chunk[..]).is_err() {
-                return (
-                    Message::Download(
-                        DownloadMessage::Error("error occurred while downloading!".to_string()),
-                        index,
-                    ),
-                    State::Wait,
-                );
-            }
-            downloaded += chunk.len();
 
-            (
-                Message::Download(DownloadMessage::DownloadProgress(downloaded), index),
-                State::SequentialDownloading(response, file, downloaded),
-            )
+    /**
+     * adapts the download engine to app messages, the GUI runs it as a subscription and
+     * headless mode polls it directly
+     */
+    #[tracing::instrument(name = "Subscription", skip(self, settings, client, engines))]
+    pub fn stream(
+        &self,
+        settings: &AtomSettings,
+        client: Client,
+        engines: &EngineRegistry,
+    ) -> Option<BoxStream<'static, Message>> {
+        if !self.is_active() {
+            return None;
         }
-        Ok(None) => (
-            Message::Download(DownloadMessage::Finished, index),
-            State::Wait,
-        ),
-        Err(error) => (
-            Message::Download(
-                DownloadMessage::Error(format!("download error : {:?}", error)),
-                index,
-            ),
-            State::Wait,
-        ),
-    }
-}
-
-#[tracing::instrument]
-async fn handle_threaded_download_starting(
-    download: AtomDownload,
-    destination_file: String,
-    chunk_files: Vec<String>,
-    client: Client,
-    index: usize,
-) -> (Message, State) {
-    let mut sub_downloads: Vec<SubDownloads> = vec![];
-    let mut downloaded = 0;
-    let chunk_size = download.size / (download.threads as usize);
-    let mut previous_chunk_start;
 
-    let mut responses = vec![];
-    let mut open_chunk_files = vec![];
-
-    for (i, f) in chunk_files.iter().enumerate() {
-        previous_chunk_start = (i * chunk_size) + i;
-        if let Ok(file) = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(f)
-        {
-            let mut file_len: usize = 0;
-            if let Ok(meta) = file.metadata() {
-                file_len = meta.len() as usize;
-            }
-            downloaded += file_len;
-
-            debug!("file_len: {file_len}, chunk_len: {chunk_size}, previous_chunk_start: {previous_chunk_start}");
-
-            if file_len >= chunk_size || (previous_chunk_start + file_len) >= download.size {
-                continue;
-            }
-
-            let mut client = client
-                .request(
-                    match download.method {
-                        super::DownloadMethod::Get => Method::GET,
-                        super::DownloadMethod::Post => Method::POST,
-                    },
-                    &download.url,
-                )
-                // .get(&download.url)
-                .header(USER_AGENT, ATOM_USER_AGENT)
-                .headers(hashmap2headermap(&download.headers));
-
-            if let super::DownloadMethod::Post = download.method {
-                client = client.form(&download.request_body);
-            };
-
-            client = client.header(
-                RANGE,
-                format!(
-                    "bytes={}-{}",
-                    previous_chunk_start + file_len,
-                    if (i + 1) == (download.threads as usize) {
-                        download.size
-                    } else {
-                        previous_chunk_start + chunk_size
-                    }
-                ),
-            );
-            open_chunk_files.push(file);
-
-            debug!(client=?client);
+        let id = self.id;
+        let file_path = PathBuf::from(&self.file_path).join(&self.file_name);
 
-            responses.push(client.send());
-        } else {
-            return (
-                Message::Download(
-                    DownloadMessage::Error(format!(
-                        "Error: failed to create {}!",
-                        download.file_name
-                    )),
-                    index,
-                ),
-                State::Wait,
+        if let (true, Some(checksum)) = (self.is_verifying(), self.checksum.clone()) {
+            return Some(
+                stream::once(async move {
+                    let result = tokio::task::spawn_blocking(move || checksum.verify(&file_path))
+                        .await
+                        .unwrap_or_else(|error| Err(format!("verification failed: {error}")));
+                    Message::Download(DownloadMessage::Verified(result), id)
+                })
+                .boxed(),
             );
         }
-    }
 
-    for (response, file) in responses.into_iter().zip(open_chunk_files) {
-        if let Ok(response) = response.await {
-            debug!("response = {response:#?}");
-            if response.status().is_success() {
-                sub_downloads.push(SubDownloads {
-                    response,
-                    file: BufWriter::new(file),
-                });
-            } else {
-                return (
-                    Message::Download(
-                        DownloadMessage::Error(format!(
-                            "The server has returned an error status code for {}!",
-                            download.file_name
-                        )),
-                        index,
-                    ),
-                    State::Wait,
-                );
-            }
-        } else {
-            return (
-                Message::Download(
-                    DownloadMessage::Error(format!(
-                        "failed to create request for {}!",
-                        download.file_name
-                    )),
-                    index,
-                ),
-                State::Wait,
+        if self.is_downloaded() && file_path.exists() {
+            return Some(
+                stream::once(async move { Message::Download(DownloadMessage::Finished, id) })
+                    .boxed(),
             );
         }
-    }
-
-    (
-        Message::Download(DownloadMessage::DownloadProgress(downloaded), index),
-        State::ThreadedDownloading(
-            download,
-            sub_downloads,
-            destination_file,
-            chunk_files,
-            downloaded,
-        ),
-    )
-}
-
-#[tracing::instrument]
-async fn handle_threaded_downloading(
-    download: AtomDownload,
-    sub_downloads: Vec<SubDownloads>,
-    destination_file: String,
-    chunk_files: Vec<String>,
-    mut downloaded: usize,
-    index: usize,
-) -> (Message, State) {
-    let mut filtered_sub_downloads = vec![];
-
-    for mut sub_download in sub_downloads.into_iter() {
-        match sub_download.response.chunk().await {
-            Ok(Some(chunk)) => {
-                if sub_download.file.write_all(&chunk[..]).is_err() {
-                    return (
-                        Message::Download(
-                            DownloadMessage::Error("writing to chunk file failed!".to_string()),
-                            index,
-                        ),
-                        State::Wait,
-                    );
-                }
-                downloaded += chunk.len();
-                filtered_sub_downloads.push(sub_download);
-            }
-            Ok(None) => {}
-            Err(error) => {
-                return (
-                    Message::Download(
-                        DownloadMessage::Error(format!("download error : {:?}", error)),
-                        index,
-                    ),
-                    State::Wait,
-                )
-            }
-        }
-    }
 
-    debug!(filtered_downloads = ?filtered_sub_downloads);
+        debug!(download=?self);
 
-    if filtered_sub_downloads.is_empty() {
-        (
-            Message::Download(DownloadMessage::DownloadDoneJoining, index),
-            State::ThreadedFinished(destination_file, chunk_files),
-        )
-    } else {
-        (
-            Message::Download(DownloadMessage::DownloadProgress(downloaded), index),
-            State::ThreadedDownloading(
-                download,
-                filtered_sub_downloads,
-                destination_file,
-                chunk_files,
-                downloaded,
-            ),
+        // the engine is only registered once the stream runs, iced builds and discards
+        // subscriptions on every update
+        let (handle, events) = engine::start(self.job(settings), client);
+        let engines = engines.clone();
+        let register = stream::once(async move { engines.register(id, handle) })
+            .filter_map(|_| async { None });
+
+        Some(
+            register
+                .chain(events.map(move |event| Message::Download(event.into(), id)))
+                .boxed(),
         )
     }
 }
-
-#[tracing::instrument]
-fn handle_threaded_download_finish(
-    destination_file: &str,
-    chunk_files: &Vec<String>,
-    index: usize,
-) -> (Message, State) {
-    let error_msg = (
-        Message::Download(
-            DownloadMessage::Error("Error in joining file!".to_string()),
-            index,
-        ),
-        State::Wait,
-    );
-    match File::create(destination_file) {
-        Ok(out) => {
-            let mut chunk_file_handles = vec![];
-            for file in chunk_files {
-                if let Ok(f) = File::open(file) {
-                    chunk_file_handles.push(BufReader::new(f));
-                } else {
-                    error!("[ATOM] : opening {} failed!", file);
-                    return error_msg;
-                }
-            }
-
-            debug!(chunk_files=?chunk_files);
-
-            (
-                Message::Download(DownloadMessage::JoiningProgress(0), index),
-                State::FileJoining(BufWriter::new(out), chunk_file_handles, 0, 0),
-            )
-        }
-        Err(error) => {
-            error!("[ATOM] : {}", error);
-            error_msg
-        }
-    }
-}
-
-#[tracing::instrument]
-async fn handle_joining_progress(
-    mut bw: BufWriter<File>,
-    mut chunk_files: Vec<BufReader<File>>,
-    mut current_index: usize,
-    index: usize,
-) -> (Message, State) {
-    let error_msg = (
-        Message::Download(
-            DownloadMessage::Error("Error in joining file!".to_string()),
-            index,
-        ),
-        State::Wait,
-    );
-
-    let buffer_len = 102400;
-    let mut copied = 0;
-
-    debug!(chunk_files=?chunk_files);
-    if chunk_files.is_empty() || current_index >= chunk_files.len() {
-        return (
-            Message::Download(DownloadMessage::Finished, index),
-            State::SequentialFinished,
-        );
-    }
-
-    let mut buffer = vec![0; buffer_len];
-    if let Ok(_read) = chunk_files[current_index].read(&mut buffer) {
-        if bw.write_all(&buffer[.._read]).is_err() {
-            return error_msg;
-        }
-        copied += _read;
-
-        if _read < buffer_len {
-            current_index += 1;
-        }
-    }
-
-    (
-        Message::Download(DownloadMessage::JoiningProgress(copied), index),
-        State::FileJoining(bw, chunk_files, current_index, index),
-    )
-}
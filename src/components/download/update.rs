@@ -1,7 +1,7 @@
-use super::{AtomDownload, DownloadMessage};
+use super::{AtomDownload, DownloadMessage, Verification};
 use crate::{
     components::settings::AtomSettings,
-    utils::helpers::{open_file, show_notification},
+    utils::helpers::{open_file, show_notification, stream_cache_dir},
 };
 use std::{path::PathBuf, time::SystemTime};
 use tracing::warn;
@@ -16,6 +16,7 @@ impl AtomDownload {
             DownloadMessage::Error(error) => {
                 self.error = error;
                 self.downloading = false;
+                self.seeding = false;
                 warn!("{:#?}", self.error);
                 if settings.show_notifications {
                     show_notification(
@@ -29,6 +30,8 @@ impl AtomDownload {
                 self.joining = true;
                 self.downloading = false;
             }
+            // the torrent was completed when it started seeding
+            DownloadMessage::Finished if self.seeding => self.seeding = false,
             DownloadMessage::Finished => {
                 self.downloading = false;
                 self.joining = false;
@@ -36,24 +39,35 @@ impl AtomDownload {
                 if self.size < 1 {
                     self.size = self.downloaded;
                 }
-                if settings.show_notifications {
-                    show_notification("Download Complete", &self.file_name, 6000);
-                }
 
                 let cache_dir = settings.cache_dir.to_string_lossy().to_string();
                 (1..=self.threads).for_each(|i| {
                     let file = format!("{}/{}.atom.{}", cache_dir, self.file_name, i);
                     std::fs::remove_file(file).ok();
                 });
+                if self.stream.is_some() {
+                    std::fs::remove_dir_all(stream_cache_dir(&settings.cache_dir, &self.file_name))
+                        .ok();
+                }
 
-                if self.auto_open {
-                    let path = PathBuf::from(&self.file_path)
-                        .join(&self.file_name)
-                        .to_string_lossy()
-                        .to_string();
-                    open_file(&path);
+                // the file only counts as complete once its checksum matches
+                if self.checksum.is_some() && self.verification != Verification::Verified {
+                    self.verification = Verification::Verifying;
+                } else {
+                    self.completed(settings);
                 }
             }
+            DownloadMessage::Verified(Ok(())) => {
+                self.verification = Verification::Verified;
+                if let Some(checksum) = self.checksum.clone() {
+                    self.cache_digest(checksum);
+                }
+                self.completed(settings);
+            }
+            DownloadMessage::Verified(Err(error)) => {
+                self.verification = Verification::Mismatch;
+                self.update(DownloadMessage::Error(error), settings);
+            }
             DownloadMessage::DownloadProgress(downloaded) => {
                 if downloaded > self.downloaded {
                     let chunk_len = downloaded - self.downloaded;
@@ -74,18 +88,68 @@ impl AtomDownload {
                     }
                 }
             }
+            DownloadMessage::MirrorDropped(url) if !self.dropped_mirrors.contains(&url) => {
+                self.dropped_mirrors.push(url);
+            }
+            DownloadMessage::StreamDetected(format, file_name) => {
+                self.stream = Some(format);
+                self.file_name = file_name;
+                self.sequential = false;
+            }
+            DownloadMessage::Segments(done, total, downloaded) => {
+                // the first count of a run includes the segments cached by earlier runs
+                if self.segments.1 == 0 {
+                    self.downloaded = downloaded;
+                }
+                self.segments = (done, total);
+                // estimated from the segments so far, exact once all are in
+                if done >= total {
+                    self.size = downloaded;
+                } else if let Some(average) = downloaded.checked_div(done) {
+                    self.size = (average * total).max(downloaded + 1);
+                }
+                self.update(DownloadMessage::DownloadProgress(downloaded), settings);
+            }
+            DownloadMessage::TorrentLoaded(file_name, files) => {
+                self.file_name = file_name;
+                self.torrent_contents = files;
+            }
+            DownloadMessage::Swarm(status) => self.swarm = status,
+            DownloadMessage::ChecksumFound(checksum) if self.checksum.is_none() => {
+                self.checksum = Some(checksum);
+            }
+            DownloadMessage::Seeding => {
+                self.downloading = false;
+                self.seeding = true;
+                self.transfer_rate = 0.0;
+                self.completed(settings);
+            }
             DownloadMessage::JoiningProgress(bytes) => {
                 self.joined_bytes += bytes;
                 self.joining = true;
             }
             DownloadMessage::Downloading => {
+                // the file on disk is known to be bad, download it again
+                if self.verification == Verification::Mismatch {
+                    std::fs::remove_file(PathBuf::from(&self.file_path).join(&self.file_name)).ok();
+                    self.downloaded = 0;
+                    self.joined_bytes = 0;
+                    self.verification = Verification::Unverified;
+                }
                 self.downloading = true;
                 self.error = String::default();
+                // what is on disk changes from here on
+                if !self.is_downloaded() {
+                    self.digests.clear();
+                }
+                self.dropped_mirrors.clear();
+                self.segments = (0, 0);
                 self.elapsed_time = Some(SystemTime::now());
                 self.download_this_session = 0;
             }
             DownloadMessage::Paused => {
                 self.downloading = false;
+                self.seeding = false;
                 self.joining = false;
                 self.download_this_session = 0;
             }
@@ -106,4 +170,21 @@ impl AtomDownload {
             _ => {}
         }
     }
+
+    /**
+     * notifies and opens the file once it is downloaded and, when it has a checksum, verified
+     */
+    fn completed(&mut self, settings: &AtomSettings) {
+        if settings.show_notifications {
+            show_notification("Download Complete", &self.file_name, 6000);
+        }
+
+        if self.auto_open {
+            let path = PathBuf::from(&self.file_path)
+                .join(&self.file_name)
+                .to_string_lossy()
+                .to_string();
+            open_file(&path);
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use super::AtomDownload;
+use super::{AtomDownload, Verification};
 use crate::{
     components::{
         listview_header::get_list_view_header_column_length,
@@ -73,8 +73,10 @@ impl AtomDownload {
     }
 
     fn get_download_state_icon<'a>(&self) -> Text<'a, AtomTheme> {
-        if self.downloading {
+        if self.downloading || self.seeding {
             icons::pause()
+        } else if self.verification == Verification::Mismatch {
+            icons::rotation()
         } else if self.is_downloaded() {
             icons::reply()
         } else {
@@ -127,7 +129,7 @@ impl AtomDownload {
         let downloaded = self.get_formatted_size(self.downloaded);
         let size = self.get_formatted_size(self.size);
 
-        let text_col = col![col![
+        let mut sizes = col![
             row![
                 icons::download_alt().size(text_size),
                 text(format!("{0:0>6.2} {1}", downloaded.0, downloaded.1))
@@ -144,9 +146,23 @@ impl AtomDownload {
             .spacing(5)
         ]
         .align_x(Alignment::Start)
-        .spacing(5)]
-        .spacing(5)
-        .align_x(Alignment::Start);
+        .spacing(5);
+
+        // the size of a stream is an estimate until every segment is in
+        if self.segments.1 > 0 && self.segments.0 < self.segments.1 {
+            sizes = sizes.push(
+                row![
+                    icons::list().size(text_size),
+                    text(format!("{}/{} segments", self.segments.0, self.segments.1))
+                        .size(text_size - 2.0)
+                        .class(AtomStyleText::Dimmed)
+                ]
+                .align_y(Alignment::Center)
+                .spacing(5),
+            );
+        }
+
+        let text_col = col![sizes].spacing(5).align_x(Alignment::Start);
 
         text_col
             .width(get_list_view_header_column_length(
@@ -165,7 +181,12 @@ impl AtomDownload {
                 container(
                     row![
                         icons::close_circled().size(text_size),
-                        text("Failed").size(text_size - 2.0)
+                        text(if self.verification == Verification::Mismatch {
+                            "Checksum mismatch"
+                        } else {
+                            "Failed"
+                        })
+                        .size(text_size - 2.0)
                     ]
                     .spacing(5)
                     .align_y(iced::Alignment::Center),
@@ -185,7 +206,12 @@ impl AtomDownload {
         row![container(
             row![
                 icons::check_circled().size(text_size),
-                text("Completed").size(text_size - 2.0)
+                text(if self.verification == Verification::Verified {
+                    "Verified"
+                } else {
+                    "Completed"
+                })
+                .size(text_size - 2.0)
             ]
             .spacing(5)
             .align_y(iced::Alignment::Center),
@@ -196,6 +222,25 @@ impl AtomDownload {
         .into()
     }
 
+    fn get_seeding_view(
+        &self,
+        text_size: f32,
+        length: Length,
+    ) -> Element<'_, DownloadMessage, AtomTheme, Renderer> {
+        row![container(
+            row![
+                icons::spinner().size(text_size),
+                text(format!("Seeding • {} peers", self.swarm.peers)).size(text_size - 2.0)
+            ]
+            .spacing(5)
+            .align_y(iced::Alignment::Center),
+        )
+        .class(AtomStyleContainer::PillSuccess)
+        .padding(Padding::from([3, 10])),]
+        .width(length)
+        .into()
+    }
+
     fn get_joining_view(
         &self,
         text_size: f32,
@@ -215,6 +260,25 @@ impl AtomDownload {
         .into()
     }
 
+    fn get_verifying_view(
+        &self,
+        text_size: f32,
+        length: Length,
+    ) -> Element<'_, DownloadMessage, AtomTheme, Renderer> {
+        row![container(
+            row![
+                icons::spinner().size(text_size),
+                text("Verifying").size(text_size - 2.0)
+            ]
+            .spacing(5)
+            .align_y(iced::Alignment::Center),
+        )
+        .class(AtomStyleContainer::PillSuccess)
+        .padding(Padding::from([3, 10])),]
+        .width(length)
+        .into()
+    }
+
     fn get_joining_progress_view(
         &self,
         text_size: f32,
@@ -252,6 +316,10 @@ impl AtomDownload {
 
         if !self.error.is_empty() {
             return self.get_failed_view(text_size, length);
+        } else if self.is_verifying() {
+            return self.get_verifying_view(text_size, length);
+        } else if self.seeding {
+            return self.get_seeding_view(text_size, length);
         } else if self.joined_bytes > 0 {
             return self.get_joining_progress_view(text_size, length);
         } else if self.size != 0 && self.downloaded >= self.size && !self.joining {
@@ -320,7 +388,7 @@ impl AtomDownload {
             let mut start_pause_btn = GuiElements::round_button(self.get_download_state_icon());
             // let mut edit_btn = GuiElements::round_button('\u{ec55}');
 
-            if self.downloading && self.downloaded <= self.size {
+            if (self.downloading && self.downloaded <= self.size) || self.seeding {
                 start_pause_btn = start_pause_btn.on_press(DownloadMessage::Paused);
             } else if self.joining || (self.downloaded > self.size && self.downloading) {
             } else {
@@ -328,6 +396,22 @@ impl AtomDownload {
                 // edit_btn = edit_btn.on_press(DownloadMessage::MarkDeleted);
             }
 
+            // the file on disk is bad, starting again downloads it from scratch
+            let start_pause_btn: Element<DownloadMessage, AtomTheme, Renderer> =
+                if self.verification == Verification::Mismatch && !self.downloading {
+                    tooltip(
+                        start_pause_btn,
+                        text("Re-download").size(10),
+                        tooltip::Position::Top,
+                    )
+                    .class(AtomStyleContainer::ToolTipContainer)
+                    .gap(10)
+                    .padding(10)
+                    .into()
+                } else {
+                    start_pause_btn.into()
+                };
+
             actions_row = actions_row
                 .push(start_pause_btn)
                 // .push(edit_btn)
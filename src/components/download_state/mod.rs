@@ -10,7 +10,8 @@ use iced::{
     Length::{Fill, Shrink},
     Padding, Renderer,
 };
-use std::collections::BTreeMap;
+use std::collections::HashMap;
+use uuid::Uuid;
 
 use super::{download::AtomDownload, sidebar::SideBarActiveButton};
 
@@ -85,7 +86,7 @@ impl AtomDownloadStatesFilterBar<'_> {
     pub fn view(
         &self,
         active: &SideBarActiveButton,
-        downloads: &BTreeMap<usize, AtomDownload>,
+        downloads: &HashMap<Uuid, AtomDownload>,
         icons_only: bool,
     ) -> Element<Message, AtomTheme, Renderer> {
         let mut count_downloading = 0;
@@ -1,7 +1,22 @@
 mod update;
 mod view;
-use crate::components::{download::AtomDownload, settings::AtomSettings};
-use std::collections::HashMap;
+use crate::{
+    components::{download::AtomDownload, settings::AtomSettings},
+    engine::{
+        dash::{self, Representation, TrackKind},
+        hls::{self, Variant},
+        torrent::{self, TorrentFile, TorrentOptions},
+        StreamFormat,
+    },
+    messages::DownloadFormMessage,
+    utils::checksum::Checksum,
+};
+use iced::{task::Handle, Task as Command};
+use reqwest::Client;
+use std::{collections::HashMap, time::Duration};
+
+// typed URLs are looked up once the typing pauses, not for every partial URL
+const LOOKUP_DELAY: Duration = Duration::from_millis(700);
 
 #[derive(Debug, Default)]
 pub struct AtomDownloadForm {
@@ -10,12 +25,25 @@ pub struct AtomDownloadForm {
     pub size: usize,
     pub sequential: bool,
     pub headers: HashMap<String, String>,
+    pub method: String,        // of a captured request, replayed as it was
+    pub request_body: Vec<u8>, // of a captured request, replayed as it was
+    pub mirrors: Vec<String>,
+    pub mirror_url: String,
+    pub checksum: String, // expected hash as typed, empty to rely on what the server publishes
+    pub stream: Option<StreamFormat>,
+    pub variants: Vec<Variant>, // of a master playlist, highest bandwidth first
+    pub variant: Option<Variant>,
+    pub representations: Vec<Representation>, // of a DASH manifest, highest bandwidth first
+    pub video: Option<Representation>,
+    pub audio: Option<Representation>,
+    pub torrent_files: Vec<(TorrentFile, bool)>, // of a torrent, and whether each is picked
     pub is_valid_url: bool,
     pub header_name: String,
     pub header_value: String,
     pub auto_referer: bool,
     pub auto_open: bool,
     pub is_mouse_over_heading: bool,
+    lookup: Option<Handle>, // of the variants or files of `url`, dropping it aborts the lookup
 }
 
 impl AtomDownloadForm {
@@ -28,6 +56,14 @@ impl AtomDownloadForm {
             file_name: format!("{}/{}", settings.downloads_dir, download.file_name),
             size: download.size,
             headers: download.headers,
+            method: download.method,
+            request_body: download.request_body,
+            mirrors: download.mirrors,
+            checksum: download
+                .checksum
+                .map(|checksum| checksum.to_string())
+                .unwrap_or_default(),
+            stream: download.stream,
             sequential: download.size == 0 || download.sequential,
             is_valid_url: true,
             is_mouse_over_heading: false,
@@ -35,18 +71,153 @@ impl AtomDownloadForm {
         }
     }
 
+    /**
+     * none for an empty field, an error for text that isn't a digest
+     */
+    pub fn expected_checksum(&self) -> Result<Option<Checksum>, &str> {
+        if self.checksum.trim().is_empty() {
+            Ok(None)
+        } else {
+            Checksum::parse(&self.checksum)
+                .map(Some)
+                .ok_or("not an MD5, SHA-1, SHA-256, SHA-384 or SHA-512 digest")
+        }
+    }
+
     pub fn make_download(&self) -> Result<AtomDownload, &str> {
+        // a picked variant is downloaded in place of the master playlist
+        let url = self
+            .variant
+            .as_ref()
+            .map_or(&self.url, |variant| &variant.url);
+        // the picked representations of a manifest, the best video and audio when none are
+        let stream = match &self.stream {
+            Some(StreamFormat::Dash { .. }) => Some(StreamFormat::Dash {
+                representations: self
+                    .video
+                    .iter()
+                    .chain(self.audio.iter())
+                    .map(|representation| representation.id.clone())
+                    .collect(),
+            }),
+            stream => stream.clone(),
+        };
+
+        // all files of a torrent are downloaded when all of them are picked
+        let torrent_files = if self.torrent_files.iter().all(|(_, picked)| *picked) {
+            vec![]
+        } else {
+            self.torrent_files
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, picked))| *picked)
+                .map(|(index, _)| index)
+                .collect()
+        };
+
         AtomDownload::new()
-            .url(&self.url)
+            .url(url)
             .auto_set_file_name_path(&self.file_name)
             .file_size(self.size)
             .headers(self.headers.clone())
+            .method(&self.method)
+            .request_body(self.request_body.clone())
+            .mirrors(self.mirrors.clone())
+            .checksum(self.expected_checksum()?)
+            .stream_format(stream)
+            .torrent_files(torrent_files)
             .download_type(self.sequential)
             .auto_open(self.auto_open)
             .build()
     }
 
+    /**
+     * loads the variants of an HLS playlist URL, the representations of a DASH manifest URL
+     * or the files of a torrent so they can be picked, after `LOOKUP_DELAY` when `debounce` is
+     * set. A lookup still running for the previous URL is aborted
+     */
+    pub fn load_variants(
+        &mut self,
+        client: &Client,
+        settings: &AtomSettings,
+        debounce: bool,
+    ) -> Command<DownloadFormMessage> {
+        self.lookup = None;
+        self.variants.clear();
+        self.variant = None;
+        self.representations.clear();
+        self.video = None;
+        self.audio = None;
+        self.torrent_files.clear();
+
+        let url = self.url.clone();
+        let delay = if debounce {
+            LOOKUP_DELAY
+        } else {
+            Duration::ZERO
+        };
+        // nothing runs before the delay, the lookups start when first polled
+        let lookup = if torrent::is_torrent_url(&url) {
+            let options = TorrentOptions {
+                port: settings.torrent_port,
+                dht: settings.torrent_dht,
+                ..Default::default()
+            };
+            let files = torrent::files(
+                client.clone(),
+                url.clone(),
+                self.headers.clone(),
+                settings.cache_dir.clone(),
+                options,
+            );
+            Command::perform(after(delay, files), move |files| {
+                DownloadFormMessage::TorrentFiles(url.clone(), files)
+            })
+        } else {
+            match self.stream {
+                Some(StreamFormat::Hls) => Command::perform(
+                    after(
+                        delay,
+                        hls::variants(client.clone(), url.clone(), self.headers.clone()),
+                    ),
+                    move |variants| DownloadFormMessage::StreamVariants(url.clone(), variants),
+                ),
+                Some(StreamFormat::Dash { .. }) => Command::perform(
+                    after(
+                        delay,
+                        dash::representations(client.clone(), url.clone(), self.headers.clone()),
+                    ),
+                    move |representations| {
+                        DownloadFormMessage::StreamRepresentations(url.clone(), representations)
+                    },
+                ),
+                None => return Command::none(),
+            }
+        };
+
+        let (lookup, handle) = lookup.abortable();
+        self.lookup = Some(handle.abort_on_drop());
+        lookup
+    }
+
+    /**
+     * the representations of one kind, for its pick list
+     */
+    pub fn representations(&self, kind: TrackKind) -> Vec<Representation> {
+        self.representations
+            .iter()
+            .filter(|representation| representation.kind == kind)
+            .cloned()
+            .collect()
+    }
+
     pub fn reset(&mut self) {
         *self = Self::default()
     }
 }
+
+// a dropped lookup is aborted during the delay as well
+async fn after<T>(delay: Duration, lookup: impl std::future::Future<Output = T>) -> T {
+    tokio::time::sleep(delay).await;
+    lookup.await
+}
@@ -1,12 +1,19 @@
 use super::AtomDownloadForm;
-use crate::{components::settings::AtomSettings, messages::DownloadFormMessage};
+use crate::{
+    components::settings::AtomSettings,
+    engine::{dash::TrackKind, torrent, StreamFormat},
+    messages::DownloadFormMessage,
+};
 use iced::Task as Command;
+use reqwest::Client;
+use tracing::warn;
 
 impl AtomDownloadForm {
     pub fn update(
         &mut self,
         message: DownloadFormMessage,
         settings: &AtomSettings,
+        client: &Client,
     ) -> Command<DownloadFormMessage> {
         match message {
             DownloadFormMessage::UrlChange(url) => {
@@ -30,6 +37,53 @@ impl AtomDownloadForm {
                 } else {
                     self.is_valid_url = false;
                 }
+
+                self.stream = StreamFormat::from_url(&self.url);
+                if let Some(stream) = &self.stream {
+                    self.file_name = stream.file_name(&self.file_name);
+                }
+                if let Some(name) = torrent::magnet_name(&self.url) {
+                    self.file_name = format!("{}{}", settings.downloads_dir, name);
+                }
+                return self.load_variants(client, settings, true);
+            }
+            DownloadFormMessage::StreamVariants(url, variants) if url == self.url => match variants
+            {
+                Ok(variants) => {
+                    self.variant = variants.first().cloned();
+                    self.variants = variants;
+                }
+                Err(error) => warn!("Error: loading the HLS variants failed: {error}"),
+            },
+            DownloadFormMessage::VariantSelected(variant) => self.variant = Some(variant),
+            DownloadFormMessage::StreamRepresentations(url, representations) if url == self.url => {
+                match representations {
+                    Ok(representations) => {
+                        self.representations = representations;
+                        self.video = self.representations(TrackKind::Video).first().cloned();
+                        self.audio = self.representations(TrackKind::Audio).first().cloned();
+                    }
+                    Err(error) => {
+                        warn!("Error: loading the DASH representations failed: {error}")
+                    }
+                }
+            }
+            DownloadFormMessage::RepresentationSelected(representation) => {
+                match representation.kind {
+                    TrackKind::Video => self.video = Some(representation),
+                    TrackKind::Audio => self.audio = Some(representation),
+                }
+            }
+            DownloadFormMessage::TorrentFiles(url, files) if url == self.url => match files {
+                Ok(files) => {
+                    self.torrent_files = files.into_iter().map(|file| (file, true)).collect();
+                }
+                Err(error) => warn!("Error: loading the torrent files failed: {error}"),
+            },
+            DownloadFormMessage::TorrentFileToggled(index, picked) => {
+                if let Some(file) = self.torrent_files.get_mut(index) {
+                    file.1 = picked;
+                }
             }
             DownloadFormMessage::DownloadSequentially(checked) => self.sequential = checked,
             DownloadFormMessage::AddHeader => {
@@ -48,6 +102,20 @@ impl AtomDownloadForm {
             DownloadFormMessage::DeleteHeader(header_name) => {
                 self.headers.remove(&header_name);
             }
+            DownloadFormMessage::MirrorUrlChange(mirror_url) => self.mirror_url = mirror_url,
+            DownloadFormMessage::AddMirror => {
+                let mirror_url = self.mirror_url.trim().to_string();
+                let is_http = reqwest::Url::parse(&mirror_url)
+                    .is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+                if is_http && mirror_url != self.url && !self.mirrors.contains(&mirror_url) {
+                    self.mirrors.push(mirror_url);
+                    self.mirror_url = String::default();
+                }
+            }
+            DownloadFormMessage::DeleteMirror(mirror_url) => {
+                self.mirrors.retain(|mirror| *mirror != mirror_url);
+            }
+            DownloadFormMessage::ChecksumChange(checksum) => self.checksum = checksum,
             DownloadFormMessage::AutoOpen(open) => self.auto_open = open,
             DownloadFormMessage::AutoReferer(checked) => {
                 if !self.url.is_empty() {
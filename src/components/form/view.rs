@@ -2,6 +2,7 @@ use super::AtomDownloadForm;
 use crate::{
     components::settings::AtomSettings,
     elements::GuiElements,
+    engine::dash::TrackKind,
     font::{ICOFONT, SYMBOLS},
     icons,
     messages::DownloadFormMessage,
@@ -9,12 +10,12 @@ use crate::{
         button::AtomStyleButton, container::AtomStyleContainer, input::AtomStyleInput,
         AtomStyleText, AtomTheme,
     },
-    utils::helpers::ATOM_INPUT_DEFAULT_PADDING,
+    utils::helpers::{get_relative_file_size, ATOM_INPUT_DEFAULT_PADDING},
 };
 use iced::{
     widget::{
-        button, column as col, container, horizontal_space, mouse_area, row, text, text_input,
-        toggler, tooltip, tooltip::Position, vertical_space,
+        button, column as col, container, horizontal_space, mouse_area, pick_list, row, text,
+        text_input, toggler, tooltip, tooltip::Position, vertical_space,
     },
     window::Id,
     Alignment, Element,
@@ -71,6 +72,71 @@ impl AtomDownloadForm {
             .into()
     }
 
+    fn mirrors_view(&self) -> Element<'_, DownloadFormMessage, AtomTheme> {
+        let text_size = 12;
+        self.mirrors
+            .iter()
+            .fold(
+                col!().spacing(2).align_x(iced::Alignment::Center),
+                |column, mirror| {
+                    column.push(
+                        container(
+                            row![
+                                icons::grip3()
+                                    .class(AtomStyleText::Dimmed)
+                                    .size(text_size - 2),
+                                text(mirror.to_string()).width(Fill).size(text_size),
+                                self.vertical_line(),
+                                GuiElements::round_button(icons::trash_bin_open())
+                                    .on_press(
+                                        DownloadFormMessage::DeleteMirror(mirror.to_string(),)
+                                    )
+                                    .width(Shrink)
+                            ]
+                            .padding(Padding::from([5, 10]))
+                            .spacing(10)
+                            .align_y(Alignment::Center),
+                        )
+                        .class(AtomStyleContainer::ListItemContainer),
+                    )
+                },
+            )
+            .into()
+    }
+
+    fn torrent_files_view(&self) -> Element<'_, DownloadFormMessage, AtomTheme> {
+        let text_size = 12;
+        self.torrent_files
+            .iter()
+            .enumerate()
+            .fold(
+                col!().spacing(2).align_x(iced::Alignment::Center),
+                |column, (index, (file, picked))| {
+                    column.push(
+                        container(
+                            row![
+                                toggler(*picked)
+                                    .on_toggle(move |picked| {
+                                        DownloadFormMessage::TorrentFileToggled(index, picked)
+                                    })
+                                    .width(Shrink),
+                                text(file.path.to_string()).width(Fill).size(text_size),
+                                self.vertical_line(),
+                                text(get_relative_file_size(file.length))
+                                    .class(AtomStyleText::Dimmed)
+                                    .size(text_size)
+                            ]
+                            .padding(Padding::from([5, 10]))
+                            .spacing(10)
+                            .align_y(Alignment::Center),
+                        )
+                        .class(AtomStyleContainer::ListItemContainer),
+                    )
+                },
+            )
+            .into()
+    }
+
     fn toggles_view(&self) -> Element<DownloadFormMessage, AtomTheme> {
         let sequential_tooltip_text = "Switch modes only if you are certain that the server supports the selected download method; otherwise, the download may fail.";
 
@@ -168,7 +234,14 @@ impl AtomDownloadForm {
     ) -> Element<DownloadFormMessage, AtomTheme> {
         let mut download_btn = GuiElements::primary_button(icons::cloud_download(), "download");
 
-        if self.is_valid_url && !self.file_name.is_empty() {
+        // a torrent needs at least one of its files picked
+        let has_files =
+            self.torrent_files.is_empty() || self.torrent_files.iter().any(|(_, picked)| *picked);
+        if self.is_valid_url
+            && !self.file_name.is_empty()
+            && has_files
+            && self.expected_checksum().is_ok()
+        {
             download_btn = download_btn.on_press(DownloadFormMessage::AddNewDownload);
         }
 
@@ -184,13 +257,79 @@ impl AtomDownloadForm {
             );
         }
 
-        let url_input = col!().spacing(5).push(text("URL")).push(
+        let mut url_input = col!().spacing(5).push(text("URL")).push(
             text_input("e.g: https://www.example.org/file.mp4", &self.url)
                 .icon(GuiElements::text_input_icon('\u{ef71}', ICOFONT, 12))
                 .on_input(DownloadFormMessage::UrlChange)
                 .padding(ATOM_INPUT_DEFAULT_PADDING),
         );
 
+        // captured requests other than a plain GET are sent again as they were
+        if !self.method.is_empty() && (self.method != "GET" || !self.request_body.is_empty()) {
+            let request = if self.request_body.is_empty() {
+                format!("Sends a {} request", self.method)
+            } else {
+                format!(
+                    "Sends a {} request with a {} body",
+                    self.method,
+                    get_relative_file_size(self.request_body.len())
+                )
+            };
+            url_input = url_input.push(text(request).class(AtomStyleText::Dimmed).size(12));
+        }
+
+        if !self.variants.is_empty() {
+            url_input = url_input.push(
+                row![
+                    text("Variant").width(Shrink),
+                    pick_list(
+                        &self.variants[..],
+                        self.variant.clone(),
+                        DownloadFormMessage::VariantSelected,
+                    )
+                    .width(Fill),
+                ]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            );
+        }
+
+        for (label, kind, selected) in [
+            ("Video", TrackKind::Video, &self.video),
+            ("Audio", TrackKind::Audio, &self.audio),
+        ] {
+            let representations = self.representations(kind);
+            if representations.is_empty() {
+                continue;
+            }
+            url_input = url_input.push(
+                row![
+                    text(label).width(Shrink),
+                    pick_list(
+                        representations,
+                        selected.clone(),
+                        DownloadFormMessage::RepresentationSelected,
+                    )
+                    .width(Fill),
+                ]
+                .spacing(10)
+                .align_y(Alignment::Center),
+            );
+        }
+
+        if !self.torrent_files.is_empty() {
+            url_input = url_input.push(text("Files")).push(
+                container(GuiElements::scrollbar(
+                    self.torrent_files_view(),
+                    settings.scrollbars_visible,
+                ))
+                .padding(15)
+                .width(Fill)
+                .max_height(200)
+                .class(AtomStyleContainer::ListContainer),
+            );
+        }
+
         let file_path_input = col!().spacing(5).push(text("File Path")).push(
             row![
                 text_input("e.g: file.mp4", &self.file_name)
@@ -253,6 +392,56 @@ impl AtomDownloadForm {
             headers_container = headers_container.height(200);
         }
 
+        let mirrors_tooltip_text = "Other URLs serving the same file.\nThreaded downloads are spread across them,\na mirror that fails or is much slower is dropped.";
+        let mut mirrors_input = col![
+            text("Mirrors").width(Fill),
+            row![
+                GuiElements::tooltip_top(
+                    text_input("e.g: https://mirror.example.org/file.mp4", &self.mirror_url)
+                        .icon(GuiElements::text_input_icon('\u{ef71}', ICOFONT, 12))
+                        .on_input(DownloadFormMessage::MirrorUrlChange)
+                        .on_submit(DownloadFormMessage::AddMirror)
+                        .padding(ATOM_INPUT_DEFAULT_PADDING),
+                    mirrors_tooltip_text,
+                ),
+                GuiElements::primary_button(icons::plus(), "Add")
+                    .on_press(DownloadFormMessage::AddMirror)
+                    .padding(Padding::from([7, 15])),
+            ]
+            .align_y(Alignment::Center)
+            .spacing(10)
+        ]
+        .spacing(5);
+
+        if !self.mirrors.is_empty() {
+            mirrors_input = mirrors_input.push(
+                container(GuiElements::scrollbar(
+                    self.mirrors_view(),
+                    settings.scrollbars_visible,
+                ))
+                .padding(15)
+                .width(Fill)
+                .max_height(150)
+                .class(AtomStyleContainer::ListContainer),
+            );
+        }
+
+        let checksum_tooltip_text = "The hash the file should have, checked once it is downloaded.\nLeave empty to use the one the server publishes, if any.";
+        let mut checksum_input = col![
+            text("Checksum").width(Fill),
+            GuiElements::tooltip_top(
+                text_input("e.g: sha256:9f86d081884c7d65...", &self.checksum)
+                    .icon(GuiElements::text_input_icon('\u{ed68}', ICOFONT, 12))
+                    .on_input(DownloadFormMessage::ChecksumChange)
+                    .padding(ATOM_INPUT_DEFAULT_PADDING),
+                checksum_tooltip_text,
+            ),
+        ]
+        .spacing(5);
+        if let Err(error) = self.expected_checksum() {
+            checksum_input = checksum_input.push(text(error).class(AtomStyleText::Dimmed).size(12));
+        }
+
         let mut page_title = row![GuiElements::panel_title("Add New Download").into()];
         if window_id.is_some() {
             page_title = page_title.push(horizontal_space().width(Fill)).push(
@@ -281,6 +470,8 @@ impl AtomDownloadForm {
                         col![
                             url_input,
                             file_path_input,
+                            mirrors_input,
+                            checksum_input,
                             col![text("Additional Headers").width(Fill), headers_list].spacing(5),
                             headers_container,
                             container(col!().push(toggles))
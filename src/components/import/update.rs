@@ -1,8 +1,10 @@
 use super::AtomImport;
 use crate::{
     components::settings::AtomSettings,
+    engine::torrent::TORRENT_EXTENSION,
     // styles::style::{AtomInputDisabled, AtomToggler},
     messages::{ImportMessage, Message},
+    utils::metalink::METALINK_EXTENSIONS,
 };
 use iced::Task as Command;
 use rfd::FileDialog;
@@ -14,6 +16,8 @@ impl AtomImport {
             ImportMessage::ImportFileClicked => {
                 if let Some(file) = FileDialog::new()
                     .add_filter("text", &["txt", "*.*"])
+                    .add_filter("metalink", &METALINK_EXTENSIONS)
+                    .add_filter("torrent", &[TORRENT_EXTENSION])
                     .set_directory("/")
                     .pick_file()
                 {
@@ -1,21 +1,44 @@
 mod subscription;
 mod view;
 use crate::{
-    components::download::AtomDownload, messages::MetadataMessage, utils::helpers::open_file,
+    components::download::AtomDownload,
+    engine::{torrent::TorrentFile, SwarmStatus},
+    messages::MetadataMessage,
+    utils::{
+        checksum::{Checksum, ChecksumAlgorithm},
+        helpers::open_file,
+    },
 };
-use std::{collections::HashMap, path::Path};
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use uuid::Uuid;
 
 #[derive(Debug, Default)]
 pub struct AtomDownloadMetadata {
     pub enabled: bool,
+    pub download_id: Uuid,
     pub url: String,
+    pub mirrors: Vec<(String, bool)>, // url, dropped
     pub extension: String,
     pub file_path: String,
     pub size: usize,
-    pub checksums: HashMap<String, String>,
+    pub digests: Vec<Checksum>, // cached on the download, one per algorithm
+    pub checksum_algorithm: ChecksumAlgorithm,
+    pub expected_checksum: String, // pasted to compare against, the download's own by default
     pub finished: bool,
+    pub downloaded: usize,
+    pub swarm: Option<SwarmStatus>,              // torrents only
+    pub torrent_files: Vec<(TorrentFile, bool)>, // and whether each is downloaded
     download_error: String,
     is_calculating_checksum: bool,
+    hashed: (usize, usize), // bytes hashed so far and the file's size
+    checksum_error: String,
+    checksum_cancel: Arc<AtomicBool>, // set to stop the running calculation
 }
 
 impl AtomDownloadMetadata {
@@ -25,15 +48,43 @@ impl AtomDownloadMetadata {
             MetadataMessage::DeleteFile => {
                 std::fs::remove_file(&self.file_path).ok();
             }
-            MetadataMessage::Checksum(checksum, url) => {
+            MetadataMessage::ChecksumAlgorithmSelected(algorithm) => {
+                // the running calculation is for the previous algorithm
+                if algorithm != self.checksum_algorithm {
+                    self.cancel_checksum();
+                }
+                self.checksum_algorithm = algorithm;
+                self.checksum_error.clear();
+            }
+            MetadataMessage::CalculateChecksum => {
+                self.is_calculating_checksum = true;
+                self.checksum_cancel = Arc::new(AtomicBool::new(false));
+                let size = std::fs::metadata(&self.file_path).map_or(0, |file| file.len());
+                self.hashed = (0, size as usize);
+                self.checksum_error.clear();
+            }
+            MetadataMessage::CancelChecksum => self.cancel_checksum(),
+            MetadataMessage::ChecksumProgress(hashed) => self.hashed.0 = hashed,
+            MetadataMessage::Checksum(id, result) if id == self.download_id => {
                 self.is_calculating_checksum = false;
-                if let Some(key) = self.checksums.get_mut(&url) {
-                    *key = checksum;
-                } else {
-                    self.checksums.insert(url, checksum);
+                match result {
+                    Ok(digest) => {
+                        self.digests
+                            .retain(|cached| cached.algorithm != digest.algorithm);
+                        self.digests.push(digest);
+                    }
+                    Err(error) => self.checksum_error = error,
+                }
+            }
+            MetadataMessage::ExpectedChecksumChange(expected) => {
+                // the pasted hash tells which algorithm to compare with
+                if let Some(checksum) = Checksum::parse(&expected) {
+                    self.update(MetadataMessage::ChecksumAlgorithmSelected(
+                        checksum.algorithm,
+                    ));
                 }
+                self.expected_checksum = expected;
             }
-            MetadataMessage::CalculateChecksum => self.is_calculating_checksum = true,
             _ => {}
         };
     }
@@ -46,10 +97,65 @@ impl AtomDownloadMetadata {
                 .to_string_lossy()
                 .to_string();
         }
+        self.download_id = download.id;
         self.url = download.get_url();
+        self.update_mirrors(download);
+        self.update_torrent(download);
         self.size = download.get_download_size();
-        self.is_calculating_checksum = false;
-        self.finished = download.is_downloaded();
+        self.cancel_checksum();
+        self.checksum_error.clear();
+        self.expected_checksum = download
+            .checksum
+            .as_ref()
+            .map(|checksum| checksum.to_string())
+            .unwrap_or_default();
+        if let Some(checksum) = &download.checksum {
+            self.checksum_algorithm = checksum.algorithm;
+        }
+        self.update_checksums(download);
         self.download_error = download.error.clone();
     }
+
+    pub fn update_checksums(&mut self, download: &AtomDownload) {
+        self.digests = download.digests.clone();
+        self.finished = download.is_downloaded();
+    }
+
+    /**
+     * the digest of the selected algorithm, when it was calculated for the file on disk
+     */
+    pub fn digest(&self) -> Option<&Checksum> {
+        self.digests
+            .iter()
+            .find(|digest| digest.algorithm == self.checksum_algorithm)
+    }
+
+    fn cancel_checksum(&mut self) {
+        self.checksum_cancel.store(true, Ordering::Relaxed);
+        self.is_calculating_checksum = false;
+        self.hashed = (0, 0);
+    }
+
+    pub fn update_torrent(&mut self, download: &AtomDownload) {
+        self.downloaded = download.downloaded;
+        self.swarm = download.is_torrent().then_some(download.swarm);
+        self.torrent_files = download
+            .torrent_contents
+            .iter()
+            .enumerate()
+            .map(|(index, file)| {
+                let picked =
+                    download.torrent_files.is_empty() || download.torrent_files.contains(&index);
+                (file.clone(), picked)
+            })
+            .collect();
+    }
+
+    pub fn update_mirrors(&mut self, download: &AtomDownload) {
+        self.mirrors = download
+            .mirrors
+            .iter()
+            .map(|mirror| (mirror.clone(), download.dropped_mirrors.contains(mirror)))
+            .collect();
+    }
 }
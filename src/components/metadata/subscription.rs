@@ -1,79 +1,59 @@
 use super::AtomDownloadMetadata;
-use crate::messages::MetadataMessage;
-use iced::{futures::stream::unfold, Subscription};
-use ring::digest::{Context, SHA256};
-use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::PathBuf;
+use crate::{
+    messages::MetadataMessage,
+    utils::checksum::{file_digest_with_progress, Checksum},
+};
+use iced::{futures::SinkExt, Subscription};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+const CHECKSUM_PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
 
 impl AtomDownloadMetadata {
-    fn sha256_digest(path: &PathBuf) -> Result<String, bool> {
-        if let Ok(input) = File::open(path) {
-            let mut reader = BufReader::new(input);
-
-            let mut context = Context::new(&SHA256);
-            let mut buffer = [0; 1024];
-
-            loop {
-                if let Ok(count) = reader.read(&mut buffer) {
-                    if count == 0 {
-                        break;
-                    }
-                    context.update(&buffer[..count]);
-                } else {
-                    return Err(false);
-                }
-            }
-
-            let mut digest = String::default();
-            for c in context.finish().as_ref() {
-                digest.push_str(&format!("{:02x}", c));
-            }
-            Ok(digest)
-        } else {
-            Err(false)
-        }
-    }
-
+    /**
+     * hashes the file on a blocking thread, progress is sent a few times a second and
+     * cancelling stops the thread at its next read
+     */
     pub fn subscription(&self) -> Subscription<MetadataMessage> {
-        if !self.is_calculating_checksum {
+        if !self.is_calculating_checksum || self.file_path.is_empty() {
             return Subscription::none();
         }
 
-        Subscription::run_with_id(
-            self.url.len(),
-            unfold(
-                (self.file_path.clone(), self.url.clone()),
-                move |(file_path, url)| async {
-                    if file_path.is_empty() {
-                        iced::futures::future::pending().await
-                    } else {
-                        let file_path = PathBuf::from(file_path);
-
-                        if file_path.exists() {
-                            if let Ok(digest) = AtomDownloadMetadata::sha256_digest(&file_path) {
-                                return Some((
-                                    MetadataMessage::Checksum(digest, url),
-                                    (String::default(), String::default()),
-                                ));
-                            }
+        let id = self.download_id;
+        let algorithm = self.checksum_algorithm;
+        let file_path = PathBuf::from(&self.file_path);
+        let cancel = self.checksum_cancel.clone();
 
-                            Some((
-                                MetadataMessage::Checksum(
-                                    "failed to calculate checksum!".to_owned(),
-                                    url,
-                                ),
-                                (String::default(), String::default()),
-                            ))
-                        } else {
-                            Some((
-                                MetadataMessage::Checksum("error: file not found!".to_owned(), url),
-                                (String::default(), String::default()),
-                            ))
+        Subscription::run_with_id(
+            (id, algorithm),
+            iced::stream::channel(100, move |mut sender| async move {
+                let mut progress = sender.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    let mut reported = Instant::now();
+                    file_digest_with_progress(&file_path, algorithm, &cancel, |hashed| {
+                        if reported.elapsed() >= CHECKSUM_PROGRESS_INTERVAL {
+                            reported = Instant::now();
+                            progress
+                                .try_send(MetadataMessage::ChecksumProgress(hashed))
+                                .ok();
                         }
-                    }
-                },
-            ),
+                    })
+                })
+                .await;
+
+                let result = match result {
+                    Ok(Ok(Some(digest))) => Ok(Checksum { algorithm, digest }),
+                    Ok(Ok(None)) => return, // cancelled
+                    Ok(Err(error)) => Err(format!("failed to calculate checksum, {error}")),
+                    Err(error) => Err(format!("failed to calculate checksum, {error}")),
+                };
+                sender
+                    .send(MetadataMessage::Checksum(id, result))
+                    .await
+                    .ok();
+            }),
         )
     }
 }
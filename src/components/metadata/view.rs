@@ -6,19 +6,205 @@ use crate::{
     icons,
     messages::MetadataMessage,
     style::{container::AtomStyleContainer, AtomStyleText, AtomTheme},
-    utils::helpers::{
-        get_file_type, get_formatted_time, get_relative_file_size, METADATA_PANEL_WIDTH,
+    utils::{
+        checksum::{Checksum, ChecksumAlgorithm},
+        helpers::{
+            get_file_type, get_formatted_time, get_relative_file_size, METADATA_PANEL_WIDTH,
+        },
     },
 };
 use iced::{
-    widget::{column as col, container, image, row, text, text_input, vertical_space},
-    Alignment, Element,
+    widget::{
+        column as col, container, image, pick_list, progress_bar, row, text, text_input,
+        vertical_space,
+    },
+    Alignment, Element, Length,
     Length::{Fill, FillPortion},
     Padding,
 };
 use std::{path::Path, time::Duration};
 
 impl AtomDownloadMetadata {
+    /**
+     * peers, pieces, what was uploaded and the files of a torrent
+     */
+    fn torrent_view(&self) -> Element<'_, MetadataMessage, AtomTheme> {
+        let Some(swarm) = self.swarm else {
+            return col![].into();
+        };
+        let info_row = |label: &str, value: String| {
+            row![
+                text(label.to_string())
+                    .class(AtomStyleText::Dimmed)
+                    .size(12)
+                    .width(FillPortion(1)),
+                text(value).size(12),
+            ]
+            .width(Fill)
+            .align_y(Alignment::Center)
+        };
+        let ratio = if self.downloaded == 0 {
+            0.0
+        } else {
+            swarm.uploaded as f64 / self.downloaded as f64
+        };
+
+        let files_col = self.torrent_files.iter().fold(
+            col!()
+                .spacing(2)
+                .push_maybe((!self.torrent_files.is_empty()).then(|| text("FILES").width(Fill))),
+            |column, (file, picked)| {
+                column.push(
+                    row![
+                        text(file.path.to_string())
+                            .class(if *picked {
+                                AtomStyleText::Default
+                            } else {
+                                AtomStyleText::Dimmed
+                            })
+                            .size(12)
+                            .width(Fill),
+                        text(if *picked {
+                            get_relative_file_size(file.length)
+                        } else {
+                            "skipped".to_string()
+                        })
+                        .class(AtomStyleText::Dimmed)
+                        .size(12),
+                    ]
+                    .spacing(5)
+                    .align_y(Alignment::Center),
+                )
+            },
+        );
+
+        col![
+            vertical_space().height(5),
+            text("TORRENT").width(Fill),
+            info_row("Peers", format!("{} ({} seeds)", swarm.peers, swarm.seeds)),
+            info_row("Pieces", format!("{} / {}", swarm.pieces.0, swarm.pieces.1)),
+            info_row(
+                "Uploaded",
+                format!(
+                    "{} • ratio {ratio:.2}",
+                    get_relative_file_size(swarm.uploaded)
+                )
+            ),
+            vertical_space().height(5),
+            files_col,
+        ]
+        .spacing(5)
+        .into()
+    }
+
+    /**
+     * the digest of the picked algorithm, its progress while it is calculated and how it
+     * compares to a pasted hash
+     */
+    fn checksum_view(&self, file_exists: bool) -> Element<'_, MetadataMessage, AtomTheme> {
+        let mut checksum_btn = if self.is_calculating_checksum {
+            GuiElements::round_button(icons::close_line())
+                .padding(Padding::from([4, 6]))
+                .on_press(MetadataMessage::CancelChecksum)
+        } else {
+            GuiElements::round_button(icons::calculator()).padding(Padding::from([4, 6]))
+        };
+        if !self.is_calculating_checksum && self.finished && file_exists {
+            checksum_btn = checksum_btn.on_press(MetadataMessage::CalculateChecksum);
+        }
+
+        let algorithm_list = pick_list(
+            &ChecksumAlgorithm::ALL[..],
+            Some(self.checksum_algorithm),
+            MetadataMessage::ChecksumAlgorithmSelected,
+        )
+        .text_size(14)
+        .padding(Padding::from([4, 8]));
+
+        let digest = self.digest();
+        let digest_text = if self.is_calculating_checksum {
+            "calculating..."
+        } else if !self.checksum_error.is_empty() {
+            &self.checksum_error
+        } else {
+            digest.map_or("", |digest| &digest.digest)
+        };
+
+        let mut checksum_col = col!()
+            .spacing(5)
+            .align_x(Alignment::Start)
+            .push(
+                row!()
+                    .spacing(10)
+                    .align_y(Alignment::Center)
+                    .push(text("CHECKSUM").width(Fill))
+                    .push(algorithm_list)
+                    .push(checksum_btn),
+            )
+            .push(
+                text_input(&format!("{} hash...", self.checksum_algorithm), digest_text)
+                    .size(14)
+                    .on_input(|_| MetadataMessage::Ignore),
+            );
+
+        if self.is_calculating_checksum {
+            let (hashed, size) = self.hashed;
+            let percent = if size == 0 {
+                0.0
+            } else {
+                (hashed as f32 / size as f32 * 100.0).min(100.0)
+            };
+            checksum_col = checksum_col.push(
+                row![
+                    progress_bar(0.0..=100.0, percent).height(Length::Fixed(5.0)),
+                    text(format!("{percent:>4.0} %"))
+                        .class(AtomStyleText::Dimmed)
+                        .size(12),
+                ]
+                .spacing(5)
+                .align_y(Alignment::Center),
+            );
+        }
+
+        // a hash that isn't the picked algorithm's switches the picker, see `update`
+        let comparison = if self.expected_checksum.trim().is_empty() {
+            None
+        } else {
+            Some(match (Checksum::parse(&self.expected_checksum), digest) {
+                (None, _) => "not an MD5, SHA-1, SHA-256, SHA-384 or SHA-512 hash".to_string(),
+                (Some(_), None) => format!(
+                    "calculate the {} checksum to compare",
+                    self.checksum_algorithm
+                ),
+                (Some(expected), Some(digest)) if expected == *digest => {
+                    format!("{} matches", digest.algorithm)
+                }
+                (Some(_), Some(digest)) => format!("{} does not match", digest.algorithm),
+            })
+        };
+
+        checksum_col
+            .push(
+                text_input("paste the expected hash...", &self.expected_checksum)
+                    .size(14)
+                    .on_input(MetadataMessage::ExpectedChecksumChange),
+            )
+            .push_maybe(comparison.map(|comparison| {
+                row![
+                    if comparison.ends_with(" matches") {
+                        icons::check_circled()
+                    } else {
+                        icons::info_circle()
+                    }
+                    .size(12),
+                    text(comparison).class(AtomStyleText::Dimmed).size(12),
+                ]
+                .spacing(5)
+                .align_y(Alignment::Center)
+            }))
+            .into()
+    }
+
     pub fn view(&self, settings: &AtomSettings) -> Element<MetadataMessage, AtomTheme> {
         let file_path = Path::new(&self.file_path);
         let mut open_btn =
@@ -120,39 +306,30 @@ impl AtomDownloadMetadata {
                 (String::default(), String::default(), String::default())
             };
 
-        let mut checksum_btn =
-            GuiElements::round_button(icons::calculator()).padding(Padding::from([4, 6]));
+        let checksum_col = self.checksum_view(file_path.exists());
 
-        if !self.is_calculating_checksum && self.finished && file_path.exists() {
-            checksum_btn = checksum_btn.on_press(MetadataMessage::CalculateChecksum);
-        }
+        let mirrors_col = self.mirrors.iter().fold(
+            col!()
+                .spacing(5)
+                .align_x(Alignment::Start)
+                .push_maybe((!self.mirrors.is_empty()).then(|| text("MIRRORS").width(Fill))),
+            |column, (mirror, dropped)| {
+                column.push(
+                    row![
+                        text_input("", mirror)
+                            .size(14)
+                            .on_input(|_| MetadataMessage::Ignore),
+                        text(if *dropped { "dropped" } else { "" })
+                            .class(AtomStyleText::Dimmed)
+                            .size(12),
+                    ]
+                    .spacing(5)
+                    .align_y(Alignment::Center),
+                )
+            },
+        );
 
-        let checksum_col = col!()
-            .spacing(5)
-            .align_x(Alignment::Start)
-            .push(
-                row!()
-                    .spacing(10)
-                    .align_y(Alignment::Center)
-                    .push(text("SHA256").width(Fill))
-                    .push(checksum_btn),
-            )
-            .push(
-                row!().spacing(5).align_y(Alignment::Center).push(
-                    text_input(
-                        "sha256 hash...",
-                        if self.is_calculating_checksum {
-                            "calculating..."
-                        } else if let Some(checksum) = self.checksums.get(&self.url) {
-                            checksum
-                        } else {
-                            ""
-                        },
-                    )
-                    .size(14)
-                    .on_input(|_| MetadataMessage::Ignore),
-                ),
-            );
+        let torrent_col = self.torrent_view();
 
         let mut download_info_col = col![].spacing(5);
 
@@ -164,6 +341,8 @@ impl AtomDownloadMetadata {
                         .size(14)
                         .on_input(|_| MetadataMessage::Ignore),
                 )
+                .push(mirrors_col)
+                .push(torrent_col)
                 .push(vertical_space().height(5))
                 .push(checksum_col)
         } else {
@@ -174,6 +353,8 @@ impl AtomDownloadMetadata {
                         .size(14)
                         .on_input(|_| MetadataMessage::Ignore),
                 )
+                .push(mirrors_col)
+                .push(torrent_col)
                 .push(vertical_space().height(5))
                 .push(checksum_col)
                 .push(vertical_space().height(5))
@@ -1,9 +1,21 @@
 mod update;
 mod view;
-use crate::utils::helpers::{get_conf_directory, get_downloads_directory};
+use crate::{
+    style::AtomTheme,
+    utils::{
+        helpers::{
+            generate_api_token, get_downloads_directory, ATOM_DEFAULT_API_ADDRESS,
+            ATOM_MAX_PAIRED_CLIENTS, ATOM_MAX_THREADS,
+        },
+        paths::atom_dirs,
+        redact::redact_secret,
+    },
+};
 use serde::{Deserialize, Serialize};
-use std::{fs::create_dir_all, path::PathBuf};
-use tracing::warn;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub enum ListLayout {
@@ -37,9 +49,77 @@ impl From<ListLayout> for String {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct PairedClient {
+    // `X-Atom-Client-Id`, else the extension origin, names alone are self-reported and change
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub origin: String,
+    pub paired: String,
+    pub last_seen: String,
+    // issued by `/pair`, decides the id of the client's requests instead of what it sends
+    #[serde(default)]
+    pub token: String,
+}
+
+impl fmt::Debug for PairedClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PairedClient")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("origin", &self.origin)
+            .field("paired", &self.paired)
+            .field("last_seen", &self.last_seen)
+            .field("token", &redact_secret(&self.token))
+            .finish()
+    }
+}
+
+fn default_config_dir() -> PathBuf {
+    atom_dirs().config.clone()
+}
+
+fn default_data_dir() -> PathBuf {
+    atom_dirs().data.clone()
+}
+
+fn default_cache_dir() -> PathBuf {
+    atom_dirs().cache.clone()
+}
+
+fn default_api_address() -> String {
+    ATOM_DEFAULT_API_ADDRESS.to_string()
+}
+
+fn default_torrent_port() -> u16 {
+    6881
+}
+
+fn default_torrent_seed_ratio() -> f64 {
+    1.0
+}
+
+fn default_torrent_seed_minutes() -> u32 {
+    60
+}
+
+fn default_torrent_dht() -> bool {
+    true
+}
+
+fn default_find_checksums() -> bool {
+    true
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AtomSettings {
+    // resolved on every start (see `utils::paths`), older settings files still list them
+    #[serde(skip, default = "default_config_dir")]
     pub config_dir: PathBuf,
+    #[serde(skip, default = "default_data_dir")]
+    pub data_dir: PathBuf,
+    #[serde(skip, default = "default_cache_dir")]
     pub cache_dir: PathBuf,
     pub downloads_dir: String,
     pub threads: u8,
@@ -56,30 +136,266 @@ pub struct AtomSettings {
     pub new_download_pos: String,
     pub font_size: f32,
     pub metadata_always_enabled: bool,
+    #[serde(default = "default_api_address")]
+    pub api_address: String,
+    // generated once when missing and written back, see `Atom::new`
+    #[serde(default)]
+    pub api_token: String,
+    #[serde(default)]
+    pub paired_clients: Vec<PairedClient>,
+    // client ids the local API rejects even with the right token
+    #[serde(default)]
+    pub revoked_clients: Vec<String>,
+    // FTP servers connect back to ATOM for data, for clients the server can't be reached from
+    #[serde(default)]
+    pub ftp_active_mode: bool,
+    // peers connect to this port, another one is taken while it is in use
+    #[serde(default = "default_torrent_port")]
+    pub torrent_port: u16,
+    // finished torrents seed until they uploaded this many times their size, 0 = no seeding
+    #[serde(default = "default_torrent_seed_ratio")]
+    pub torrent_seed_ratio: f64,
+    // or for at most this long, 0 = until the ratio is reached
+    #[serde(default = "default_torrent_seed_minutes")]
+    pub torrent_seed_minutes: u32,
+    // find peers through the DHT as well as the trackers, never for private torrents
+    #[serde(default = "default_torrent_dht")]
+    pub torrent_dht: bool,
+    // downloads without an expected hash look for one in headers and checksum files
+    #[serde(default = "default_find_checksums")]
+    pub find_checksums: bool,
     #[serde(skip_deserializing, skip_serializing)]
     pub show_confirm_dialog: bool,
     #[serde(skip_deserializing, skip_serializing)]
     pub reset_settings: bool,
+    // invalid values found in settings.toml the last time it was loaded, shown in settings
+    #[serde(skip_deserializing, skip_serializing)]
+    pub file_problems: Vec<String>,
 }
 
-impl Default for AtomSettings {
-    fn default() -> Self {
-        let config_dir_path = get_conf_directory()
-            .map_err(|e| {
-                warn!("{e:#?}");
-            })
-            .unwrap();
+impl fmt::Debug for AtomSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtomSettings")
+            .field("config_dir", &self.config_dir)
+            .field("data_dir", &self.data_dir)
+            .field("cache_dir", &self.cache_dir)
+            .field("downloads_dir", &self.downloads_dir)
+            .field("threads", &self.threads)
+            .field("sidebar_collapsed", &self.sidebar_collapsed)
+            .field("show_notifications", &self.show_notifications)
+            .field("minimize_to_tray", &self.minimize_to_tray)
+            .field("auto_start_download", &self.auto_start_download)
+            .field("theme", &self.theme)
+            .field("list_layout", &self.list_layout)
+            .field("stretch_list_view", &self.stretch_list_view)
+            .field("scrollbars_visible", &self.scrollbars_visible)
+            .field("scaling", &self.scaling)
+            .field("maximized", &self.maximized)
+            .field("new_download_pos", &self.new_download_pos)
+            .field("font_size", &self.font_size)
+            .field("metadata_always_enabled", &self.metadata_always_enabled)
+            .field("api_address", &self.api_address)
+            .field("api_token", &redact_secret(&self.api_token))
+            .field("paired_clients", &self.paired_clients)
+            .field("revoked_clients", &self.revoked_clients)
+            .field("ftp_active_mode", &self.ftp_active_mode)
+            .field("torrent_port", &self.torrent_port)
+            .field("torrent_seed_ratio", &self.torrent_seed_ratio)
+            .field("torrent_seed_minutes", &self.torrent_seed_minutes)
+            .field("torrent_dht", &self.torrent_dht)
+            .field("find_checksums", &self.find_checksums)
+            .field("show_confirm_dialog", &self.show_confirm_dialog)
+            .field("reset_settings", &self.reset_settings)
+            .field("file_problems", &self.file_problems)
+            .finish()
+    }
+}
+
+impl AtomSettings {
+    /**
+     * replaces values the app can't use (usually from a hand edited settings.toml) with the
+     * ones from `fallback` and describes what was replaced
+     */
+    pub fn sanitize(&mut self, fallback: &AtomSettings) -> Vec<String> {
+        let mut problems = vec![];
+        let mut check = |valid: bool, problem: String| {
+            if !valid {
+                problems.push(problem);
+            }
+            valid
+        };
+
+        if !check(
+            (1..=ATOM_MAX_THREADS).contains(&self.threads),
+            format!(
+                "threads must be between 1 and {ATOM_MAX_THREADS}, got {}",
+                self.threads
+            ),
+        ) {
+            self.threads = fallback.threads;
+        }
+        if !check(
+            AtomTheme::Default.variants().contains(&self.theme),
+            format!("unknown theme `{}`", self.theme),
+        ) {
+            self.theme = fallback.theme.clone();
+        }
+        if !check(
+            (0.7..=2.0).contains(&self.scaling),
+            format!("scaling must be between 0.7 and 2.0, got {}", self.scaling),
+        ) {
+            self.scaling = fallback.scaling;
+        }
+        if !check(
+            (12.0..=28.0).contains(&self.font_size),
+            format!(
+                "font_size must be between 12 and 28, got {}",
+                self.font_size
+            ),
+        ) {
+            self.font_size = fallback.font_size;
+        }
+        if !check(
+            ["First", "Last"].contains(&self.new_download_pos.as_str()),
+            format!(
+                "new_download_pos must be `First` or `Last`, got `{}`",
+                self.new_download_pos
+            ),
+        ) {
+            self.new_download_pos = fallback.new_download_pos.clone();
+        }
+        if !check(
+            self.api_address.parse::<std::net::SocketAddr>().is_ok(),
+            format!("invalid api_address `{}`", self.api_address),
+        ) {
+            self.api_address = fallback.api_address.clone();
+        }
+        if !check(
+            Path::new(&self.downloads_dir).is_dir(),
+            format!("downloads_dir `{}` is not a directory", self.downloads_dir),
+        ) {
+            self.downloads_dir = fallback.downloads_dir.clone();
+        }
+        if !check(
+            (0.0..=100.0).contains(&self.torrent_seed_ratio),
+            format!(
+                "torrent_seed_ratio must be between 0 and 100, got {}",
+                self.torrent_seed_ratio
+            ),
+        ) {
+            self.torrent_seed_ratio = fallback.torrent_seed_ratio;
+        }
+        // a missing token is not a mistake, older files have none
+        if self.api_token.is_empty() {
+            self.api_token = fallback.api_token.clone();
+        }
 
-        let cache_dir_path = config_dir_path.join("cache");
-        if !cache_dir_path.exists() {
-            create_dir_all(&cache_dir_path).ok();
+        problems
+    }
+
+    /**
+     * three way merge for a settings.toml changed by another program: values that changed on
+     * disk since `base` (the last loaded or saved state) win, everything else is kept
+     */
+    pub fn merged_with(&self, base: &AtomSettings, external: &AtomSettings) -> AtomSettings {
+        let tables = (
+            toml::Table::try_from(self),
+            toml::Table::try_from(base),
+            toml::Table::try_from(external),
+        );
+        let (Ok(mut merged), Ok(base), Ok(external_table)) = tables else {
+            return external.clone();
+        };
+
+        external_table
+            .into_iter()
+            .filter(|(key, value)| base.get(key) != Some(value))
+            .for_each(|(key, value)| {
+                merged.insert(key, value);
+            });
+
+        AtomSettings {
+            show_confirm_dialog: self.show_confirm_dialog,
+            reset_settings: self.reset_settings,
+            file_problems: self.file_problems.clone(),
+            ..merged.try_into().unwrap_or_else(|_| external.clone())
         }
+    }
 
+    /**
+     * one entry per client id, entries written before ids existed are matched by name and
+     * origin. Past `ATOM_MAX_PAIRED_CLIENTS` the least recently seen clients without a token are
+     * forgotten, any caller can make up ids but only a paired client holds a token
+     */
+    pub fn record_paired_client(&mut self, id: &str, name: &str, origin: &str) {
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M").to_string();
+        if let Some(client) = self.paired_clients.iter_mut().find(|client| {
+            client.id == id
+                || (client.id.is_empty() && client.name == name && client.origin == origin)
+        }) {
+            client.id = id.to_string();
+            client.name = name.to_string();
+            client.last_seen = now;
+        } else {
+            self.paired_clients.push(PairedClient {
+                id: id.to_string(),
+                name: name.to_string(),
+                origin: origin.to_string(),
+                paired: now.clone(),
+                last_seen: now,
+                token: String::new(),
+            });
+        }
+
+        while self.paired_clients.len() > ATOM_MAX_PAIRED_CLIENTS {
+            // the timestamps sort as text
+            let Some(oldest) = self
+                .paired_clients
+                .iter()
+                .enumerate()
+                .filter(|(_, client)| client.token.is_empty())
+                .min_by(|(_, a), (_, b)| a.last_seen.cmp(&b.last_seen))
+                .map(|(index, _)| index)
+            else {
+                break;
+            };
+            self.paired_clients.remove(oldest);
+        }
+    }
+
+    /**
+     * a new token for a client that paired, the one it had before stops working
+     */
+    pub fn issue_client_token(&mut self, id: &str) -> Option<String> {
+        let client = self
+            .paired_clients
+            .iter_mut()
+            .find(|client| client.id == id)?;
+        client.token = generate_api_token();
+        Some(client.token.clone())
+    }
+
+    /**
+     * drops the client with its token and renews the pairing token, which the client knows
+     * and could pair again with under another id. Clients that paired keep their own tokens
+     */
+    pub fn revoke_paired_client(&mut self, id: &str) {
+        self.paired_clients.retain(|client| client.id != id);
+        if !self.revoked_clients.iter().any(|revoked| revoked == id) {
+            self.revoked_clients.push(id.to_string());
+        }
+        self.api_token = generate_api_token();
+    }
+}
+
+impl Default for AtomSettings {
+    fn default() -> Self {
         let downloads_dir = get_downloads_directory("");
 
         Self {
-            config_dir: config_dir_path.clone(),
-            cache_dir: cache_dir_path.clone(),
+            config_dir: default_config_dir(),
+            data_dir: default_data_dir(),
+            cache_dir: default_cache_dir(),
             downloads_dir,
             threads: 6,
             sidebar_collapsed: true,
@@ -97,6 +413,156 @@ impl Default for AtomSettings {
             show_confirm_dialog: false,
             reset_settings: false,
             metadata_always_enabled: false,
+            api_address: default_api_address(),
+            api_token: generate_api_token(),
+            paired_clients: vec![],
+            revoked_clients: vec![],
+            ftp_active_mode: false,
+            torrent_port: default_torrent_port(),
+            torrent_seed_ratio: default_torrent_seed_ratio(),
+            torrent_seed_minutes: default_torrent_seed_minutes(),
+            torrent_dht: default_torrent_dht(),
+            find_checksums: default_find_checksums(),
+            file_problems: vec![],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn without_token() -> String {
+        toml::to_string(&AtomSettings::default())
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with("api_token"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn missing_token_is_not_generated_on_parse() {
+        let first: AtomSettings = toml::from_str(&without_token()).unwrap();
+        let second: AtomSettings = toml::from_str(&without_token()).unwrap();
+        assert!(first.api_token.is_empty());
+        assert!(second.api_token.is_empty());
+    }
+
+    #[test]
+    fn missing_token_keeps_the_one_in_use() {
+        let current = AtomSettings::default();
+        let mut external: AtomSettings = toml::from_str(&without_token()).unwrap();
+        assert!(external.sanitize(&current).is_empty());
+        assert_eq!(external.api_token, current.api_token);
+
+        let merged = current.merged_with(&current, &external);
+        assert_eq!(merged.api_token, current.api_token);
+    }
+
+    #[test]
+    fn edited_token_is_taken_on_merge() {
+        let current = AtomSettings::default();
+        let external = AtomSettings {
+            api_token: "edited".to_string(),
+            ..current.clone()
+        };
+        assert_eq!(current.merged_with(&current, &external).api_token, "edited");
+    }
+
+    #[test]
+    fn paired_clients_are_deduplicated_by_id() {
+        let mut settings = AtomSettings::default();
+        settings.record_paired_client("atom-cli", "atom-cli", "local process");
+        settings.record_paired_client("atom-cli", "curl/8.5.0", "local process");
+        assert_eq!(settings.paired_clients.len(), 1);
+        assert_eq!(settings.paired_clients[0].name, "curl/8.5.0");
+    }
+
+    #[test]
+    fn entries_without_id_are_adopted() {
+        let mut settings = AtomSettings::default();
+        settings.paired_clients.push(PairedClient {
+            name: "browser-extension".to_string(),
+            origin: "chrome-extension://abc".to_string(),
+            ..Default::default()
+        });
+        settings.record_paired_client(
+            "chrome-extension://abc",
+            "browser-extension",
+            "chrome-extension://abc",
+        );
+        assert_eq!(settings.paired_clients.len(), 1);
+        assert_eq!(settings.paired_clients[0].id, "chrome-extension://abc");
+    }
+
+    #[test]
+    fn paired_clients_are_capped() {
+        let mut settings = AtomSettings::default();
+        (0..ATOM_MAX_PAIRED_CLIENTS + 5).for_each(|index| {
+            settings.record_paired_client(&index.to_string(), "script", "local process");
+        });
+        assert_eq!(settings.paired_clients.len(), ATOM_MAX_PAIRED_CLIENTS);
+    }
+
+    #[test]
+    fn revoked_clients_are_removed_once() {
+        let mut settings = AtomSettings::default();
+        settings.record_paired_client("atom-cli", "atom-cli", "local process");
+        settings.revoke_paired_client("atom-cli");
+        settings.revoke_paired_client("atom-cli");
+        assert!(settings.paired_clients.is_empty());
+        assert_eq!(settings.revoked_clients, vec!["atom-cli".to_string()]);
+    }
+
+    #[test]
+    fn debug_output_hides_tokens() {
+        let mut settings = AtomSettings::default();
+        settings.record_paired_client("a", "extension", "chrome-extension://a");
+        let client_token = settings.issue_client_token("a").unwrap();
+
+        let output = format!("{settings:#?}");
+        assert!(!output.contains(&settings.api_token));
+        assert!(!output.contains(&client_token));
+        assert!(output.contains("chrome-extension://a"));
+    }
+
+    #[test]
+    fn revoking_renews_the_pairing_token_only() {
+        let mut settings = AtomSettings::default();
+        settings.record_paired_client("a", "extension", "chrome-extension://a");
+        settings.record_paired_client("b", "extension", "moz-extension://b");
+        let kept = settings.issue_client_token("b").unwrap();
+        settings.issue_client_token("a").unwrap();
+        let pairing_token = settings.api_token.clone();
+
+        settings.revoke_paired_client("a");
+        assert_ne!(settings.api_token, pairing_token);
+        assert_eq!(settings.paired_clients.len(), 1);
+        assert_eq!(settings.paired_clients[0].token, kept);
+        assert_eq!(settings.issue_client_token("a"), None);
+    }
+
+    #[test]
+    fn made_up_ids_never_push_out_paired_clients() {
+        let mut settings = AtomSettings::default();
+        settings.record_paired_client("paired", "extension", "chrome-extension://a");
+        let token = settings.issue_client_token("paired").unwrap();
+        // seen long ago, the first to go if tokens didn't count
+        settings.paired_clients[0].last_seen = "2000-01-01 00:00".to_string();
+
+        for index in 0..ATOM_MAX_PAIRED_CLIENTS * 2 {
+            settings.record_paired_client(&format!("fake-{index}"), "script", "");
+        }
+
+        assert_eq!(settings.paired_clients.len(), ATOM_MAX_PAIRED_CLIENTS);
+        assert!(settings
+            .paired_clients
+            .iter()
+            .any(|client| client.id == "paired" && client.token == token));
+        assert!(settings
+            .paired_clients
+            .iter()
+            .any(|client| client.id == format!("fake-{}", ATOM_MAX_PAIRED_CLIENTS * 2 - 1)));
+    }
+}
@@ -1,5 +1,8 @@
 use super::AtomSettings;
-use crate::messages::{Message, SettingsMessage};
+use crate::{
+    messages::{Message, SettingsMessage},
+    utils::helpers::generate_api_token,
+};
 use iced::Task as Command;
 
 impl AtomSettings {
@@ -33,6 +36,13 @@ impl AtomSettings {
             SettingsMessage::NewDownloadPositionChanged(pos) => self.new_download_pos = pos,
             SettingsMessage::ScalingChanged(scaling) => self.scaling = scaling,
             SettingsMessage::TextSizeChanged(text_size) => self.font_size = text_size,
+            SettingsMessage::ApiAddressChanged(address) => self.api_address = address,
+            SettingsMessage::RegenerateApiToken => {
+                self.api_token = generate_api_token();
+                self.paired_clients.clear();
+                self.revoked_clients.clear();
+            }
+            SettingsMessage::RevokeClient(id) => self.revoke_paired_client(&id),
             SettingsMessage::ThreadsChanged(threads) => self.threads = threads,
             SettingsMessage::NotificationToggle(checked) => self.show_notifications = checked,
             SettingsMessage::QuitActionToggle(checked) => self.minimize_to_tray = checked,
@@ -43,6 +53,20 @@ impl AtomSettings {
                 self.metadata_always_enabled = checked
             }
             SettingsMessage::ScrollbarsVisible(checked) => self.scrollbars_visible = checked,
+            SettingsMessage::FtpActiveModeToggle(checked) => self.ftp_active_mode = checked,
+            SettingsMessage::TorrentDhtToggle(checked) => self.torrent_dht = checked,
+            SettingsMessage::FindChecksumsToggle(checked) => self.find_checksums = checked,
+            SettingsMessage::TorrentPortChanged(port) => {
+                if port.is_empty() {
+                    self.torrent_port = 0;
+                } else if let Ok(port) = port.parse() {
+                    self.torrent_port = port;
+                }
+            }
+            SettingsMessage::TorrentSeedRatioChanged(ratio) => self.torrent_seed_ratio = ratio,
+            SettingsMessage::TorrentSeedMinutesChanged(minutes) => {
+                self.torrent_seed_minutes = minutes
+            }
             SettingsMessage::ClosePane => {}
             SettingsMessage::HideDialog => {
                 self.show_confirm_dialog = false;
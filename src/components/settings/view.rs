@@ -4,7 +4,7 @@ use crate::{
     icons,
     messages::SettingsMessage,
     style::{container::AtomStyleContainer, input::AtomStyleInput, AtomTheme},
-    utils::helpers::ATOM_INPUT_DEFAULT_PADDING,
+    utils::helpers::{ATOM_DEFAULT_API_ADDRESS, ATOM_INPUT_DEFAULT_PADDING},
 };
 use iced::{
     widget::{column as col, container, pick_list, row, slider, text, text_input},
@@ -19,6 +19,27 @@ impl AtomSettings {
         settings: &AtomSettings,
         theme: &AtomTheme,
     ) -> Element<SettingsMessage, AtomTheme, Renderer> {
+        let toggles_text_size = settings.font_size - 1.0;
+
+        // problems found when settings.toml was last loaded, from the saved settings since the
+        // pane works on a copy
+        let file_problems = (!settings.file_problems.is_empty()).then(|| {
+            container(
+                settings.file_problems.iter().fold(
+                    col![text(
+                        "settings.toml was edited outside ATOM, these values were not applied:"
+                    )]
+                    .spacing(5),
+                    |problems, problem| {
+                        problems.push(text(format!("• {problem}")).size(toggles_text_size))
+                    },
+                ),
+            )
+            .width(Fill)
+            .padding(10)
+            .class(AtomStyleContainer::PillError)
+        });
+
         let config_dir_col = col!()
             .spacing(5)
             .push(text("Configuration Directory"))
@@ -38,6 +59,16 @@ impl AtomSettings {
                     ),
             );
 
+        let data_dir_col = col!()
+            .spacing(5)
+            .push(text("Data Directory (download list)"))
+            .push(
+                text_input("", &self.data_dir.to_string_lossy())
+                    .width(Fill)
+                    .class(AtomStyleInput::Disabled)
+                    .padding(ATOM_INPUT_DEFAULT_PADDING),
+            );
+
         let temp_dir_col = col!()
             .spacing(5)
             .push(text(
@@ -52,6 +83,70 @@ impl AtomSettings {
                 ),
             );
 
+        let api_address_col = col!()
+            .spacing(5)
+            .push(text(
+                "Local API Address (browser extension and scripts send downloads here)",
+            ))
+            .push(
+                text_input(ATOM_DEFAULT_API_ADDRESS, &self.api_address)
+                    .on_input(SettingsMessage::ApiAddressChanged)
+                    .padding(ATOM_INPUT_DEFAULT_PADDING),
+            );
+
+        let paired_clients = if self.paired_clients.is_empty() {
+            col![text("No paired clients yet").size(toggles_text_size)]
+        } else {
+            self.paired_clients
+                .iter()
+                .fold(col!().spacing(5), |column, client| {
+                    column.push(
+                        row![
+                            text(&client.name).size(toggles_text_size).width(Fill),
+                            text(&client.origin).size(toggles_text_size).width(Fill),
+                            text(format!("last seen {}", client.last_seen))
+                                .size(toggles_text_size)
+                                .width(Shrink),
+                            GuiElements::tooltip_top(
+                                GuiElements::round_button(icons::close_line())
+                                    .on_press(SettingsMessage::RevokeClient(client.id.clone())),
+                                "Revoke, also renews the pairing token",
+                            ),
+                        ]
+                        .spacing(10)
+                        .align_y(Alignment::Center),
+                    )
+                })
+        };
+
+        let api_token_col = col!()
+            .spacing(5)
+            .push(text(
+                "Pairing Token (paste into the browser extension or pass to the atom CLI)",
+            ))
+            .push(
+                row!()
+                    .spacing(10)
+                    .align_y(Alignment::Center)
+                    .push(
+                        text_input("", &self.api_token)
+                            .width(Fill)
+                            .class(AtomStyleInput::Disabled)
+                            .padding(ATOM_INPUT_DEFAULT_PADDING),
+                    )
+                    .push(GuiElements::tooltip_top(
+                        GuiElements::primary_button(icons::rotation(), "regenerate")
+                            .on_press(SettingsMessage::RegenerateApiToken),
+                        "Revokes every paired client",
+                    )),
+            )
+            .push(
+                container(paired_clients)
+                    .width(Fill)
+                    .padding(15)
+                    .class(AtomStyleContainer::ListContainer),
+            );
+
         let default_dir_col = col!()
             .spacing(5)
             .push(text(
@@ -84,8 +179,6 @@ impl AtomSettings {
         ]
         .spacing(20);
 
-        let toggles_text_size = settings.font_size - 1.0;
-
         let notification_toggler = GuiElements::toggle(
             self.show_notifications,
             SettingsMessage::NotificationToggle,
@@ -146,20 +239,62 @@ impl AtomSettings {
         )
         .text_size(toggles_text_size);
 
+        let label = "FTP servers connect back to this computer for data, for servers that can't be connected to in passive mode";
+        let ftp_active_toggler = GuiElements::tooltip_top(
+            GuiElements::toggle(
+                self.ftp_active_mode,
+                SettingsMessage::FtpActiveModeToggle,
+                "FTP Active Mode",
+            )
+            .text_size(toggles_text_size),
+            label,
+        );
+
+        let label = "Find peers through the DHT as well as the trackers, private torrents only use their trackers";
+        let torrent_dht_toggler = GuiElements::tooltip_top(
+            GuiElements::toggle(
+                self.torrent_dht,
+                SettingsMessage::TorrentDhtToggle,
+                "BitTorrent DHT",
+            )
+            .text_size(toggles_text_size),
+            label,
+        );
+
+        let label = "Downloads without an expected hash look for one in the response headers and in checksum files next to them";
+        let find_checksums_toggler = GuiElements::tooltip_top(
+            GuiElements::toggle(
+                self.find_checksums,
+                SettingsMessage::FindChecksumsToggle,
+                "Find Checksums",
+            )
+            .text_size(toggles_text_size),
+            label,
+        );
+
         let options_row = container(
             col![row![
-                col![notification_toggler, auto_start_toggler, scrollbar_toggler]
-                    .spacing(10)
-                    .width(Fill)
-                    .align_x(Alignment::Start),
-                col![close_btn_toggler, maximized_toggler]
+                col![
+                    notification_toggler,
+                    auto_start_toggler,
+                    scrollbar_toggler,
+                    find_checksums_toggler
+                ]
+                .spacing(10)
+                .width(Fill)
+                .align_x(Alignment::Start),
+                col![close_btn_toggler, maximized_toggler, ftp_active_toggler]
                     .spacing(10)
                     .width(Fill)
                     .align_x(Alignment::Center),
-                col![stretch_list_toggler, always_show_metadata_toggler]
-                    .spacing(10)
-                    .width(Fill)
-                    .align_x(Alignment::End),
+                col![
+                    stretch_list_toggler,
+                    always_show_metadata_toggler,
+                    torrent_dht_toggler
+                ]
+                .spacing(10)
+                .width(Fill)
+                .align_x(Alignment::End),
             ]
             .spacing(10)
             .align_y(Alignment::Start)
@@ -177,9 +312,13 @@ impl AtomSettings {
             .push(GuiElements::scrollbar(
                 col!()
                     .spacing(20)
+                    .push_maybe(file_problems)
                     .push(config_dir_col)
+                    .push(data_dir_col)
                     .push(temp_dir_col)
                     .push(default_dir_col)
+                    .push(api_address_col)
+                    .push(api_token_col)
                     .push(
                         row!()
                             .spacing(10)
@@ -277,6 +416,65 @@ impl AtomSettings {
                         .padding(20)
                         .class(AtomStyleContainer::ListContainer),
                     )
+                    .push(
+                        container(
+                            row![
+                                col![
+                                    text("Torrent Port"),
+                                    text_input("6881", &self.torrent_port.to_string())
+                                        .on_input(SettingsMessage::TorrentPortChanged)
+                                        .padding(ATOM_INPUT_DEFAULT_PADDING),
+                                ]
+                                .spacing(5)
+                                .width(Fill),
+                                GuiElements::vertical_separator().into(),
+                                col![
+                                    row![
+                                        text("Seed Ratio").width(Fill),
+                                        text(match self.torrent_seed_ratio {
+                                            0.0 => "off".to_string(),
+                                            ratio => format!("{ratio:.1}"),
+                                        })
+                                        .width(Shrink)
+                                    ]
+                                    .spacing(10),
+                                    GuiElements::tooltip_bottom(
+                                        slider(0.0..=5.0, self.torrent_seed_ratio, |ratio| {
+                                            SettingsMessage::TorrentSeedRatioChanged(ratio)
+                                        })
+                                        .step(0.1)
+                                        .width(Fill),
+                                        "Finished torrents upload until they sent this many times their size"
+                                    ),
+                                ]
+                                .spacing(5)
+                                .width(Fill),
+                                GuiElements::vertical_separator().into(),
+                                col![
+                                    row![
+                                        text("Seed Time").width(Fill),
+                                        text(match self.torrent_seed_minutes {
+                                            0 => "no limit".to_string(),
+                                            minutes => format!("{minutes} min"),
+                                        })
+                                        .width(Shrink)
+                                    ]
+                                    .spacing(10),
+                                    slider(0..=240, self.torrent_seed_minutes, |minutes| {
+                                        SettingsMessage::TorrentSeedMinutesChanged(minutes)
+                                    })
+                                    .step(5u32)
+                                    .width(Fill),
+                                ]
+                                .spacing(5)
+                                .width(Fill)
+                            ]
+                            .align_y(Alignment::Center)
+                            .spacing(30),
+                        )
+                        .padding(20)
+                        .class(AtomStyleContainer::ListContainer),
+                    )
                     .push(options_row)
                     .push(buttons_row)
                     .width(Fill),
@@ -0,0 +1,902 @@
+use super::{
+    segments::{fetch_text, get, resolve, Segment, Track},
+    DownloadJob,
+};
+use reqwest::{header::RANGE, Client, Url};
+use roxmltree::{Document, Node};
+use std::{collections::HashMap, fmt, path::Path};
+use tracing::warn;
+
+const DASH_MIME_TYPE: &str = "application/dash+xml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackKind {
+    Video,
+    Audio,
+}
+
+/**
+ * one video or audio encoding the manifest offers
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Representation {
+    pub id: String,
+    pub kind: TrackKind,
+    pub bandwidth: u64,     // bits per second
+    pub resolution: String, // empty for audio
+    pub language: String,   // empty when the manifest doesn't tell
+    pub codecs: String,
+    pub mime_type: String,
+}
+
+impl fmt::Display for Representation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.kind {
+            TrackKind::Video => &self.resolution,
+            TrackKind::Audio => &self.language,
+        };
+        if !label.is_empty() {
+            write!(f, "{label}, ")?;
+        }
+        match self.kind {
+            TrackKind::Video => write!(f, "{:.2} Mbps", self.bandwidth as f64 / 1_000_000.0)?,
+            TrackKind::Audio => write!(f, "{} kbps", self.bandwidth / 1000)?,
+        }
+        if !self.codecs.is_empty() {
+            write!(f, " ({})", self.codecs)?;
+        }
+        Ok(())
+    }
+}
+
+impl Representation {
+    fn extension(&self) -> &'static str {
+        match (self.kind, self.mime_type.ends_with("webm")) {
+            (_, true) => "webm",
+            (TrackKind::Video, false) => "mp4",
+            (TrackKind::Audio, false) => "m4a",
+        }
+    }
+}
+
+/**
+ * where the segments of a representation are, SegmentBase indexes need fetching first
+ */
+#[derive(Debug)]
+enum SegmentSource {
+    Listed(Vec<Segment>),
+    Indexed { url: String, index: (usize, usize) },
+}
+
+pub fn is_dash_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| url.path().to_lowercase().ends_with(".mpd"))
+}
+
+pub fn is_dash_content_type(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case(DASH_MIME_TYPE))
+}
+
+pub fn is_manifest(content: &str) -> bool {
+    let content = content.trim_start_matches('\u{feff}').trim_start();
+    content.starts_with('<') && content.contains("<MPD")
+}
+
+/**
+ * the video and audio representations of a manifest, highest bandwidth first
+ */
+pub async fn representations(
+    client: Client,
+    url: String,
+    headers: HashMap<String, String>,
+) -> Result<Vec<Representation>, String> {
+    let (base, content) = fetch_text(&client, &url, &headers).await?;
+    Ok(parse_manifest(&content, &base)?
+        .into_iter()
+        .map(|(representation, _)| representation)
+        .collect())
+}
+
+/**
+ * a track per chosen representation, the best video and the best audio when none is chosen.
+ * The first track is saved under the download's name, the others next to it
+ */
+pub async fn load(
+    client: &Client,
+    job: &DownloadJob,
+    chosen: &[String],
+) -> Result<Vec<Track>, String> {
+    let (base, content) = fetch_text(client, &job.url, &job.headers).await?;
+    let mut parsed = parse_manifest(&content, &base)?;
+
+    let mut picked = vec![];
+    if chosen.is_empty() {
+        for kind in [TrackKind::Video, TrackKind::Audio] {
+            if let Some(index) = parsed
+                .iter()
+                .position(|(representation, _)| representation.kind == kind)
+            {
+                picked.push(parsed.remove(index));
+            }
+        }
+    } else {
+        for id in chosen {
+            let index = parsed
+                .iter()
+                .position(|(representation, _)| representation.id == *id)
+                .ok_or_else(|| format!("the manifest has no representation {id}"))?;
+            picked.push(parsed.remove(index));
+        }
+    }
+
+    let stem = Path::new(&job.file_name)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let mut tracks: Vec<Track> = vec![];
+    for (representation, source) in picked {
+        let segments = match source {
+            SegmentSource::Listed(segments) => segments,
+            SegmentSource::Indexed { url, index } => {
+                indexed_segments(client, &job.headers, url, index).await?
+            }
+        };
+
+        let file_name = if tracks.is_empty() {
+            job.file_name.clone()
+        } else {
+            let kind = match representation.kind {
+                TrackKind::Video => "video",
+                TrackKind::Audio => "audio",
+            };
+            let extension = representation.extension();
+            let mut file_name = format!("{stem}.{kind}.{extension}");
+            let mut number = 1;
+            while tracks.iter().any(|track| track.file_name == file_name) {
+                number += 1;
+                file_name = format!("{stem}.{kind}{number}.{extension}");
+            }
+            file_name
+        };
+        tracks.push(Track {
+            file_name,
+            segments,
+        });
+    }
+
+    Ok(tracks)
+}
+
+/**
+ * static manifests only, of a manifest with several periods only the first one is read
+ */
+fn parse_manifest(
+    content: &str,
+    base: &Url,
+) -> Result<Vec<(Representation, SegmentSource)>, String> {
+    let document =
+        Document::parse(content).map_err(|error| format!("invalid manifest: {error}"))?;
+    let mpd = document.root_element();
+    if !mpd.has_tag_name("MPD") {
+        return Err("not a DASH manifest".to_string());
+    }
+    if mpd.attribute("type") == Some("dynamic") {
+        return Err("live DASH streams aren't supported".to_string());
+    }
+
+    let period = child(mpd, "Period").ok_or("the manifest has no period")?;
+    if mpd
+        .children()
+        .filter(|node| node.has_tag_name("Period"))
+        .count()
+        > 1
+    {
+        warn!("DASH manifest with several periods, only the first one is downloaded");
+    }
+    let period_duration = period
+        .attribute("duration")
+        .or(mpd.attribute("mediaPresentationDuration"))
+        .and_then(parse_duration);
+    let base = base_url(period, &base_url(mpd, base));
+
+    let mut representations = vec![];
+    for adaptation in period
+        .children()
+        .filter(|node| node.has_tag_name("AdaptationSet"))
+    {
+        let adaptation_base = base_url(adaptation, &base);
+        for node in adaptation
+            .children()
+            .filter(|node| node.has_tag_name("Representation"))
+        {
+            let id = node.attribute("id").unwrap_or_default().to_string();
+            let mime_type = node
+                .attribute("mimeType")
+                .or(adaptation.attribute("mimeType"))
+                .unwrap_or_default()
+                .to_string();
+            let kind = match adaptation
+                .attribute("contentType")
+                .unwrap_or_else(|| mime_type.split('/').next().unwrap_or_default())
+            {
+                "video" => TrackKind::Video,
+                "audio" => TrackKind::Audio,
+                _ => continue,
+            };
+            if child(adaptation, "ContentProtection").is_some()
+                || child(node, "ContentProtection").is_some()
+            {
+                warn!(id, "DRM protected DASH representation skipped");
+                continue;
+            }
+
+            let representation = Representation {
+                kind,
+                bandwidth: node
+                    .attribute("bandwidth")
+                    .and_then(|bandwidth| bandwidth.parse().ok())
+                    .unwrap_or_default(),
+                resolution: match (
+                    node.attribute("width").or(adaptation.attribute("width")),
+                    node.attribute("height").or(adaptation.attribute("height")),
+                ) {
+                    (Some(width), Some(height)) => format!("{width}x{height}"),
+                    _ => String::default(),
+                },
+                language: adaptation.attribute("lang").unwrap_or_default().to_string(),
+                codecs: node
+                    .attribute("codecs")
+                    .or(adaptation.attribute("codecs"))
+                    .unwrap_or_default()
+                    .to_string(),
+                mime_type,
+                id,
+            };
+
+            let url = base_url(node, &adaptation_base);
+            // segment information is inherited from the adaptation set and the period
+            let levels = [node, adaptation, period];
+            let source = segment_source(&representation, &levels, &url, period_duration)
+                .map_err(|error| format!("representation {}: {error}", representation.id))?;
+            representations.push((representation, source));
+        }
+    }
+
+    if representations.is_empty() {
+        return Err("the manifest has no unprotected video or audio".to_string());
+    }
+    representations.sort_by_key(|(representation, _)| std::cmp::Reverse(representation.bandwidth));
+    Ok(representations)
+}
+
+fn segment_source(
+    representation: &Representation,
+    levels: &[Node],
+    url: &Url,
+    period_duration: Option<f64>,
+) -> Result<SegmentSource, String> {
+    let templates: Vec<Node> = levels
+        .iter()
+        .filter_map(|level| child(*level, "SegmentTemplate"))
+        .collect();
+    if !templates.is_empty() {
+        return template_segments(representation, &templates, url, period_duration)
+            .map(SegmentSource::Listed);
+    }
+
+    if let Some(list) = levels.iter().find_map(|level| child(*level, "SegmentList")) {
+        let mut segments = vec![];
+        if let Some(initialization) = child(list, "Initialization") {
+            segments.push(Segment {
+                url: match initialization.attribute("sourceURL") {
+                    Some(source) => resolve(url, source)?,
+                    None => url.to_string(),
+                },
+                range: initialization
+                    .attribute("range")
+                    .map(parse_range)
+                    .transpose()?,
+                key: None,
+            });
+        }
+        for segment_url in list
+            .children()
+            .filter(|node| node.has_tag_name("SegmentURL"))
+        {
+            segments.push(Segment {
+                url: match segment_url.attribute("media") {
+                    Some(media) => resolve(url, media)?,
+                    None => url.to_string(),
+                },
+                range: segment_url
+                    .attribute("mediaRange")
+                    .map(parse_range)
+                    .transpose()?,
+                key: None,
+            });
+        }
+        return Ok(SegmentSource::Listed(segments));
+    }
+
+    let index = levels
+        .iter()
+        .find_map(|level| child(*level, "SegmentBase"))
+        .and_then(|base| base.attribute("indexRange"));
+    match index {
+        Some(index) => Ok(SegmentSource::Indexed {
+            url: url.to_string(),
+            index: parse_range(index)?,
+        }),
+        // a single file without an index is downloaded whole
+        None => Ok(SegmentSource::Listed(vec![Segment {
+            url: url.to_string(),
+            range: None,
+            key: None,
+        }])),
+    }
+}
+
+/**
+ * numbers or times from a SegmentTimeline, or as many segments of `@duration` as fit the period
+ */
+fn template_segments(
+    representation: &Representation,
+    templates: &[Node],
+    url: &Url,
+    period_duration: Option<f64>,
+) -> Result<Vec<Segment>, String> {
+    let attribute = |name: &str| {
+        templates
+            .iter()
+            .find_map(|template| template.attribute(name))
+    };
+    let number = |name: &str, default: u64| {
+        attribute(name)
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(default)
+    };
+    let timescale = number("timescale", 1).max(1);
+    let start_number = number("startNumber", 1);
+    let media = attribute("media").ok_or("a segment template without media")?;
+    let fill = |template: &str, number: u64, time: u64| {
+        resolve(
+            url,
+            &fill_template(
+                template,
+                &representation.id,
+                representation.bandwidth,
+                number,
+                time,
+            ),
+        )
+    };
+    let period_end = period_duration.map(|duration| (duration * timescale as f64) as u64);
+
+    let mut segments = vec![];
+    if let Some(initialization) = attribute("initialization") {
+        segments.push(Segment {
+            url: fill(initialization, 0, 0)?,
+            range: None,
+            key: None,
+        });
+    }
+
+    if let Some(timeline) = templates
+        .iter()
+        .find_map(|template| child(*template, "SegmentTimeline"))
+    {
+        let entries: Vec<Node> = timeline
+            .children()
+            .filter(|node| node.has_tag_name("S"))
+            .collect();
+        let (mut time, mut number) = (0, start_number);
+        for (index, entry) in entries.iter().enumerate() {
+            if let Some(start) = entry.attribute("t").and_then(|t| t.parse().ok()) {
+                time = start;
+            }
+            let duration: u64 = entry
+                .attribute("d")
+                .and_then(|d| d.parse().ok())
+                .filter(|d| *d > 0)
+                .ok_or("a timeline entry without a duration")?;
+            let repeat: i64 = entry
+                .attribute("r")
+                .and_then(|r| r.parse().ok())
+                .unwrap_or(0);
+            // a negative repeat runs up to the next entry or the end of the period
+            let repeat = if repeat < 0 {
+                let end = entries
+                    .get(index + 1)
+                    .and_then(|next| next.attribute("t"))
+                    .and_then(|t| t.parse().ok())
+                    .or(period_end)
+                    .ok_or("an open-ended timeline without a period duration")?;
+                end.saturating_sub(time)
+                    .div_ceil(duration)
+                    .saturating_sub(1)
+            } else {
+                repeat as u64
+            };
+            for _ in 0..=repeat {
+                segments.push(Segment {
+                    url: fill(media, number, time)?,
+                    range: None,
+                    key: None,
+                });
+                time += duration;
+                number += 1;
+            }
+        }
+    } else {
+        let duration = number("duration", 0);
+        let period_end = period_end.ok_or("a segment template without a period duration")?;
+        if duration == 0 {
+            return Err("a segment template without a duration".to_string());
+        }
+        for index in 0..period_end.div_ceil(duration) {
+            segments.push(Segment {
+                url: fill(media, start_number + index, index * duration)?,
+                range: None,
+                key: None,
+            });
+        }
+    }
+
+    Ok(segments)
+}
+
+/**
+ * `$RepresentationID$`, `$Number$`, `$Bandwidth$` and `$Time$`, the numbers with an optional
+ * `%0<width>d` format, and `$$`
+ */
+fn fill_template(template: &str, id: &str, bandwidth: u64, number: u64, time: u64) -> String {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        filled.push_str(&rest[..start]);
+        let identifier_and_rest = &rest[start + 1..];
+        let Some(end) = identifier_and_rest.find('$') else {
+            rest = &rest[start..];
+            break;
+        };
+        let identifier = &identifier_and_rest[..end];
+        let (name, format) = identifier.split_once('%').unwrap_or((identifier, ""));
+        let width = format.trim_end_matches('d').parse().unwrap_or(0);
+        match name {
+            "" => filled.push('$'),
+            "RepresentationID" => filled.push_str(id),
+            "Number" => filled.push_str(&format!("{number:0width$}")),
+            "Bandwidth" => filled.push_str(&format!("{bandwidth:0width$}")),
+            "Time" => filled.push_str(&format!("{time:0width$}")),
+            _ => filled.push_str(&format!("${identifier}$")),
+        }
+        rest = &identifier_and_rest[end + 1..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/**
+ * the index of a SegmentBase file lists its subsegments, each becomes a ranged segment after
+ * one for everything before the first subsegment. Without a usable index the file is downloaded
+ * whole
+ */
+async fn indexed_segments(
+    client: &Client,
+    headers: &HashMap<String, String>,
+    url: String,
+    index: (usize, usize),
+) -> Result<Vec<Segment>, String> {
+    let bytes = get(client, &url, headers)
+        .header(RANGE, format!("bytes={}-{}", index.0, index.1))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|error| {
+            format!(
+                "loading the segment index failed: {:?}",
+                error.without_url()
+            )
+        })?
+        .bytes()
+        .await
+        .map_err(|error| {
+            format!(
+                "loading the segment index failed: {:?}",
+                error.without_url()
+            )
+        })?;
+
+    match parse_sidx(&bytes, index.0) {
+        Some(ranges) if !ranges.is_empty() && ranges[0].0 > 0 => {
+            let mut segments = vec![Segment {
+                url: url.clone(),
+                range: Some((0, ranges[0].0 - 1)),
+                key: None,
+            }];
+            segments.extend(ranges.into_iter().map(|range| Segment {
+                url: url.clone(),
+                range: Some(range),
+                key: None,
+            }));
+            Ok(segments)
+        }
+        _ => {
+            warn!("unusable DASH segment index, downloading the file whole");
+            Ok(vec![Segment {
+                url,
+                range: None,
+                key: None,
+            }])
+        }
+    }
+}
+
+/**
+ * the subsegment byte ranges of an ISO BMFF `sidx` box starting at `box_start` in the file,
+ * none for anything but a plain one level index
+ */
+fn parse_sidx(bytes: &[u8], box_start: usize) -> Option<Vec<(usize, usize)>> {
+    let read = |at: usize, len: usize| {
+        bytes.get(at..at + len).map(|field| {
+            field
+                .iter()
+                .fold(0u64, |value, byte| value << 8 | *byte as u64)
+        })
+    };
+    if bytes.get(4..8)? != b"sidx" {
+        return None;
+    }
+    let (box_len, mut at) = match read(0, 4)? {
+        1 => (read(8, 8)?, 16),
+        len => (len, 8),
+    };
+
+    let version = *bytes.get(at)?;
+    // version and flags, reference ID, timescale
+    at += 12;
+    let first_offset = if version == 0 {
+        at += 4;
+        read(at, 4)?
+    } else {
+        at += 8;
+        read(at, 8)?
+    };
+    at += if version == 0 { 4 } else { 8 };
+    // reserved
+    at += 2;
+    let count = read(at, 2)?;
+    at += 2;
+
+    let mut start = box_start + box_len as usize + first_offset as usize;
+    let mut ranges = vec![];
+    for _ in 0..count {
+        let reference = read(at, 4)?;
+        // a reference to another index
+        if reference >> 31 == 1 {
+            return None;
+        }
+        let len = (reference & 0x7fff_ffff) as usize;
+        if len == 0 {
+            return None;
+        }
+        ranges.push((start, start + len - 1));
+        start += len;
+        at += 12;
+    }
+    Some(ranges)
+}
+
+/**
+ * `first-last`
+ */
+fn parse_range(range: &str) -> Result<(usize, usize), String> {
+    range
+        .split_once('-')
+        .and_then(|(first, last)| Some((first.trim().parse().ok()?, last.trim().parse().ok()?)))
+        .filter(|(first, last)| first <= last)
+        .ok_or_else(|| format!("invalid byte range {range}"))
+}
+
+/**
+ * ISO 8601 durations as used by manifests, `PT1H2M3.5S` or `P1DT2H`, in seconds
+ */
+fn parse_duration(duration: &str) -> Option<f64> {
+    let duration = duration.strip_prefix('P')?;
+    let (days, time) = duration.split_once('T').unwrap_or((duration, ""));
+    let mut seconds = match days.strip_suffix('D') {
+        Some(days) => days.parse::<f64>().ok()? * 86400.0,
+        None if days.is_empty() => 0.0,
+        None => return None,
+    };
+
+    let mut number = String::new();
+    for character in time.chars() {
+        let unit = match character {
+            'H' => 3600.0,
+            'M' => 60.0,
+            'S' => 1.0,
+            _ => {
+                number.push(character);
+                continue;
+            }
+        };
+        seconds += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+    Some(seconds)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn base_url(node: Node, parent: &Url) -> Url {
+    child(node, "BaseURL")
+        .and_then(|base| base.text())
+        .and_then(|base| parent.join(base.trim()).ok())
+        .unwrap_or_else(|| parent.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT10S">
+  <BaseURL>media/</BaseURL>
+  <Period>
+    <AdaptationSet mimeType="video/mp4" codecs="avc1.64001f">
+      <SegmentTemplate initialization="$RepresentationID$/init.mp4"
+          media="$RepresentationID$/$Number%05d$.m4s" duration="4" startNumber="1"/>
+      <Representation id="360p" bandwidth="800000" width="640" height="360"/>
+      <Representation id="720p" bandwidth="3000000" width="1280" height="720"/>
+    </AdaptationSet>
+    <AdaptationSet contentType="audio" lang="en" mimeType="audio/mp4">
+      <Representation id="audio" bandwidth="128000" codecs="mp4a.40.2">
+        <SegmentTemplate timescale="1000" media="a/$Time$.m4s">
+          <SegmentTimeline><S t="0" d="2000" r="2"/><S d="1000" r="-1"/></SegmentTimeline>
+        </SegmentTemplate>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet mimeType="video/mp4">
+      <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011"/>
+      <Representation id="drm" bandwidth="9000000"/>
+    </AdaptationSet>
+    <AdaptationSet mimeType="text/vtt">
+      <Representation id="subtitles" bandwidth="1000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+    fn base() -> Url {
+        Url::parse("https://cdn.example/v/manifest.mpd").unwrap()
+    }
+
+    fn listed(source: &SegmentSource) -> Vec<(String, Option<(usize, usize)>)> {
+        match source {
+            SegmentSource::Listed(segments) => segments
+                .iter()
+                .map(|segment| (segment.url.clone(), segment.range))
+                .collect(),
+            source => panic!("not a segment list: {source:?}"),
+        }
+    }
+
+    fn urls(source: &SegmentSource) -> Vec<String> {
+        listed(source).into_iter().map(|(url, _)| url).collect()
+    }
+
+    #[test]
+    fn representations_by_bandwidth_without_drm_or_text() {
+        let parsed = parse_manifest(MANIFEST, &base()).unwrap();
+        let ids: Vec<&str> = parsed
+            .iter()
+            .map(|(representation, _)| representation.id.as_str())
+            .collect();
+        assert_eq!(ids, ["720p", "360p", "audio"]);
+
+        assert_eq!(
+            parsed[0].0,
+            Representation {
+                id: "720p".to_string(),
+                kind: TrackKind::Video,
+                bandwidth: 3_000_000,
+                resolution: "1280x720".to_string(),
+                language: String::new(),
+                codecs: "avc1.64001f".to_string(),
+                mime_type: "video/mp4".to_string(),
+            }
+        );
+        assert_eq!(parsed[0].0.to_string(), "1280x720, 3.00 Mbps (avc1.64001f)");
+        assert_eq!(parsed[2].0.kind, TrackKind::Audio);
+        assert_eq!(parsed[2].0.to_string(), "en, 128 kbps (mp4a.40.2)");
+        assert_eq!(parsed[2].0.extension(), "m4a");
+    }
+
+    #[test]
+    fn templates_with_a_duration_fill_the_period() {
+        let parsed = parse_manifest(MANIFEST, &base()).unwrap();
+        assert_eq!(
+            urls(&parsed[0].1),
+            [
+                "https://cdn.example/v/media/720p/init.mp4",
+                "https://cdn.example/v/media/720p/00001.m4s",
+                "https://cdn.example/v/media/720p/00002.m4s",
+                "https://cdn.example/v/media/720p/00003.m4s",
+            ]
+        );
+    }
+
+    #[test]
+    fn timelines_repeat_up_to_the_period_end() {
+        let parsed = parse_manifest(MANIFEST, &base()).unwrap();
+        let times: Vec<String> = urls(&parsed[2].1)
+            .iter()
+            .map(|url| {
+                url.trim_start_matches("https://cdn.example/v/media/a/")
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            times,
+            ["0.m4s", "2000.m4s", "4000.m4s", "6000.m4s", "7000.m4s", "8000.m4s", "9000.m4s"]
+        );
+    }
+
+    #[test]
+    fn segment_lists_and_indexes() {
+        let manifest = r#"<MPD mediaPresentationDuration="PT4S"><Period>
+            <AdaptationSet mimeType="video/webm">
+              <Representation id="list" bandwidth="2">
+                <BaseURL>https://other.example/list.webm</BaseURL>
+                <SegmentList>
+                  <Initialization range="0-99"/>
+                  <SegmentURL mediaRange="100-999"/>
+                  <SegmentURL media="part2.webm"/>
+                </SegmentList>
+              </Representation>
+              <Representation id="indexed" bandwidth="1">
+                <BaseURL>indexed.webm</BaseURL>
+                <SegmentBase indexRange="700-899"/>
+              </Representation>
+              <Representation id="whole" bandwidth="0">
+                <BaseURL>whole.webm</BaseURL>
+              </Representation>
+            </AdaptationSet></Period></MPD>"#;
+
+        let parsed = parse_manifest(manifest, &base()).unwrap();
+        assert_eq!(parsed[0].0.extension(), "webm");
+        assert_eq!(
+            listed(&parsed[0].1),
+            [
+                ("https://other.example/list.webm".to_string(), Some((0, 99))),
+                (
+                    "https://other.example/list.webm".to_string(),
+                    Some((100, 999))
+                ),
+                ("https://other.example/part2.webm".to_string(), None),
+            ]
+        );
+        assert!(matches!(
+            &parsed[1].1,
+            SegmentSource::Indexed { url, index: (700, 899) }
+                if url == "https://cdn.example/v/indexed.webm"
+        ));
+        assert_eq!(
+            listed(&parsed[2].1),
+            [("https://cdn.example/v/whole.webm".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn invalid_manifests() {
+        let video = |inner: &str| {
+            format!(
+                r#"<MPD mediaPresentationDuration="PT4S"><Period><AdaptationSet mimeType="video/mp4">{inner}</AdaptationSet></Period></MPD>"#
+            )
+        };
+        for (manifest, error) in [
+            ("<html/>".to_string(), "not a DASH manifest"),
+            (
+                r#"<MPD type="dynamic"><Period/></MPD>"#.to_string(),
+                "live DASH streams aren't supported",
+            ),
+            ("<MPD/>".to_string(), "the manifest has no period"),
+            (
+                "<MPD><Period/></MPD>".to_string(),
+                "the manifest has no unprotected video or audio",
+            ),
+            (
+                video(
+                    r#"<Representation id="v"><SegmentTemplate media="$Number$.m4s"/></Representation>"#,
+                ),
+                "representation v: a segment template without a duration",
+            ),
+            (
+                video(r#"<Representation id="v"><SegmentTemplate duration="2"/></Representation>"#),
+                "representation v: a segment template without media",
+            ),
+            (
+                video(r#"<Representation id="v"><SegmentBase indexRange="9-1"/></Representation>"#),
+                "representation v: invalid byte range 9-1",
+            ),
+        ] {
+            assert_eq!(
+                parse_manifest(&manifest, &base()).map(|_| ()),
+                Err(error.to_string()),
+                "{manifest}"
+            );
+        }
+        assert!(parse_manifest("<MPD", &base())
+            .unwrap_err()
+            .starts_with("invalid manifest"));
+    }
+
+    #[test]
+    fn template_identifiers() {
+        assert_eq!(
+            fill_template(
+                "$RepresentationID$/$Number%03d$-$Bandwidth$-$Time$.m4s",
+                "v1",
+                800,
+                7,
+                90
+            ),
+            "v1/007-800-90.m4s"
+        );
+        assert_eq!(
+            fill_template("a$$b$Other$c$", "v1", 0, 0, 0),
+            "a$b$Other$c$"
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("PT1H2M3.5S"), Some(3723.5));
+        assert_eq!(parse_duration("P1DT2H"), Some(93600.0));
+        assert_eq!(parse_duration("PT0S"), Some(0.0));
+        assert_eq!(parse_duration("1H"), None);
+        assert_eq!(parse_duration("P1WT1S"), None);
+    }
+
+    #[test]
+    fn segment_indexes() {
+        let mut sidx = vec![];
+        sidx.extend(56u32.to_be_bytes());
+        sidx.extend(b"sidx");
+        sidx.extend([0; 4]); // version 0 and flags
+        sidx.extend(1u32.to_be_bytes()); // reference ID
+        sidx.extend(1000u32.to_be_bytes()); // timescale
+        sidx.extend(0u32.to_be_bytes()); // earliest presentation time
+        sidx.extend(0u32.to_be_bytes()); // first offset
+        sidx.extend([0; 2]);
+        sidx.extend(2u16.to_be_bytes());
+        for len in [1000u32, 2000] {
+            sidx.extend(len.to_be_bytes());
+            sidx.extend([0; 8]);
+        }
+
+        assert_eq!(
+            parse_sidx(&sidx, 100),
+            Some(vec![(156, 1155), (1156, 3155)])
+        );
+        // a reference to another index isn't followed
+        sidx[32] |= 0x80;
+        assert_eq!(parse_sidx(&sidx, 100), None);
+        assert_eq!(parse_sidx(b"\0\0\0\x08moov", 0), None);
+    }
+
+    #[test]
+    fn manifest_urls_and_content_types() {
+        assert!(is_dash_url("https://cdn.example/v/Manifest.MPD?token=1"));
+        assert!(!is_dash_url("https://cdn.example/mpd/video.mp4"));
+        assert!(is_dash_content_type("application/dash+xml; charset=utf-8"));
+        assert!(!is_dash_content_type("application/xml"));
+        assert!(is_manifest("\u{feff}<?xml version=\"1.0\"?>\n<MPD>"));
+        assert!(!is_manifest("<html><body>MPD</body></html>"));
+    }
+}
@@ -0,0 +1,487 @@
+use super::{
+    segments::{fetch_text, get, resolve, Segment, SegmentKey, Track},
+    DownloadJob,
+};
+use reqwest::{Client, Url};
+use std::{collections::HashMap, fmt};
+use tracing::{debug, warn};
+
+const HLS_MIME_TYPES: [&str; 4] = [
+    "application/vnd.apple.mpegurl",
+    "application/x-mpegurl",
+    "audio/mpegurl",
+    "audio/x-mpegurl",
+];
+const AES_128_KEY_LEN: usize = 16;
+
+/**
+ * one stream of a master playlist
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    pub url: String,
+    pub bandwidth: u64,     // bits per second
+    pub resolution: String, // empty for audio only variants
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mbps = self.bandwidth as f64 / 1_000_000.0;
+        if self.resolution.is_empty() {
+            write!(f, "{mbps:.2} Mbps")
+        } else {
+            write!(f, "{}, {mbps:.2} Mbps", self.resolution)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Playlist {
+    Master(Vec<Variant>), // highest bandwidth first
+    Media { segments: Vec<Segment>, ended: bool },
+}
+
+pub fn is_hls_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| url.path().to_lowercase().ends_with(".m3u8"))
+}
+
+pub fn is_hls_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    HLS_MIME_TYPES.contains(&&mime[..])
+}
+
+/**
+ * an extended M3U using HLS tags, the playlists of media players don't
+ */
+pub fn is_playlist(content: &str) -> bool {
+    let content = content.trim_start_matches('\u{feff}').trim_start();
+    content.starts_with("#EXTM3U") && content.contains("#EXT-X-")
+}
+
+/**
+ * the variants of a master playlist, empty when the URL is a media playlist already
+ */
+pub async fn variants(
+    client: Client,
+    url: String,
+    headers: HashMap<String, String>,
+) -> Result<Vec<Variant>, String> {
+    let (base, content) = fetch_text(&client, &url, &headers).await?;
+    match parse_playlist(&content, &base)? {
+        Playlist::Master(variants) => Ok(variants),
+        Playlist::Media { .. } => Ok(vec![]),
+    }
+}
+
+pub fn parse_playlist(content: &str, base: &Url) -> Result<Playlist, String> {
+    if !is_playlist(content) {
+        return Err("not an HLS playlist".to_string());
+    }
+
+    let mut variants = vec![];
+    let mut segments = vec![];
+    let mut ended = false;
+    let mut sequence: u128 = 0;
+    let mut key: Option<(String, Option<[u8; 16]>)> = None;
+    let mut stream_info: Option<HashMap<String, String>> = None;
+    let mut byte_range: Option<(usize, Option<usize>)> = None;
+    // byte ranges without an offset continue where the previous range of the same URL ended
+    let mut range_ends: HashMap<String, usize> = HashMap::new();
+    let mut map: Option<(String, Option<(usize, usize)>)> = None;
+
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        if let Some(tag) = line.strip_prefix('#') {
+            let (name, value) = tag.split_once(':').unwrap_or((tag, ""));
+            match name {
+                "EXT-X-STREAM-INF" => stream_info = Some(attributes(value)),
+                "EXT-X-MEDIA-SEQUENCE" => sequence = value.parse().unwrap_or_default(),
+                "EXT-X-BYTERANGE" => byte_range = Some(parse_byte_range(value)?),
+                "EXT-X-ENDLIST" => ended = true,
+                "EXT-X-KEY" => {
+                    let attributes = attributes(value);
+                    key = match attributes.get("METHOD").map(String::as_str) {
+                        Some("NONE") => None,
+                        Some("AES-128") => {
+                            let uri = attributes
+                                .get("URI")
+                                .ok_or("an AES-128 key without a URI")?;
+                            let iv = attributes.get("IV").map(|iv| parse_iv(iv)).transpose()?;
+                            Some((resolve(base, uri)?, iv))
+                        }
+                        method => {
+                            return Err(format!(
+                                "{} encrypted HLS streams aren't supported",
+                                method.unwrap_or("unknown")
+                            ))
+                        }
+                    };
+                }
+                // fragmented MP4 streams start with an initialization section
+                "EXT-X-MAP" => {
+                    let attributes = attributes(value);
+                    let url = resolve(base, attributes.get("URI").ok_or("a map without a URI")?)?;
+                    let range = match attributes.get("BYTERANGE") {
+                        Some(range) => match parse_byte_range(range)? {
+                            (length, Some(offset)) => Some((offset, offset + length - 1)),
+                            _ => return Err("a map byte range without an offset".to_string()),
+                        },
+                        None => None,
+                    };
+                    if map.as_ref() != Some(&(url.clone(), range)) {
+                        // an encrypted map needs an explicit IV
+                        segments.push(Segment {
+                            url: url.clone(),
+                            range,
+                            key: key.as_ref().and_then(|(key_url, iv)| {
+                                iv.map(|iv| SegmentKey {
+                                    url: key_url.clone(),
+                                    iv,
+                                })
+                            }),
+                        });
+                        map = Some((url, range));
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let url = resolve(base, line)?;
+        if let Some(stream_info) = stream_info.take() {
+            variants.push(Variant {
+                url,
+                bandwidth: stream_info
+                    .get("BANDWIDTH")
+                    .and_then(|bandwidth| bandwidth.parse().ok())
+                    .unwrap_or_default(),
+                resolution: stream_info.get("RESOLUTION").cloned().unwrap_or_default(),
+            });
+            continue;
+        }
+
+        let range = byte_range.take().map(|(length, offset)| {
+            let start = offset.unwrap_or_else(|| range_ends.get(&url).copied().unwrap_or(0));
+            range_ends.insert(url.clone(), start + length);
+            (start, start + length - 1)
+        });
+        // without an IV the media sequence number is the IV
+        segments.push(Segment {
+            key: key.as_ref().map(|(key_url, iv)| SegmentKey {
+                url: key_url.clone(),
+                iv: iv.unwrap_or_else(|| sequence.to_be_bytes()),
+            }),
+            url,
+            range,
+        });
+        sequence += 1;
+    }
+
+    if !variants.is_empty() {
+        variants.sort_by_key(|variant| std::cmp::Reverse(variant.bandwidth));
+        Ok(Playlist::Master(variants))
+    } else if segments.is_empty() {
+        Err("the playlist lists no segments".to_string())
+    } else {
+        Ok(Playlist::Media { segments, ended })
+    }
+}
+
+/**
+ * `NAME=value,NAME="quoted, value"` attribute lists
+ */
+fn attributes(list: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = list;
+    while let Some((name, value)) = rest.split_once('=') {
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        attributes.insert(name.trim().to_string(), value.to_string());
+        rest = remaining.trim_start_matches(',');
+    }
+    attributes
+}
+
+/**
+ * `length[@offset]`
+ */
+fn parse_byte_range(range: &str) -> Result<(usize, Option<usize>), String> {
+    let invalid = || format!("invalid byte range {range}");
+    let (length, offset) = match range.split_once('@') {
+        Some((length, offset)) => (length, Some(offset.parse().map_err(|_| invalid())?)),
+        None => (range, None),
+    };
+    match length.parse::<usize>() {
+        Ok(length) if length > 0 => Ok((length, offset)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_iv(iv: &str) -> Result<[u8; 16], String> {
+    iv.strip_prefix("0x")
+        .or_else(|| iv.strip_prefix("0X"))
+        .and_then(|hex| u128::from_str_radix(hex, 16).ok())
+        .map(u128::to_be_bytes)
+        .ok_or_else(|| format!("invalid IV {iv}"))
+}
+
+/**
+ * the segments of the playlist, following a master playlist to its highest bandwidth variant,
+ * and the keys of the encrypted ones
+ */
+pub async fn load(
+    client: &Client,
+    job: &DownloadJob,
+) -> Result<(Track, HashMap<String, Vec<u8>>), String> {
+    let (base, content) = fetch_text(client, &job.url, &job.headers).await?;
+    let playlist = match parse_playlist(&content, &base)? {
+        Playlist::Master(variants) => {
+            let variant = &variants[0];
+            debug!(variant = %variant, "downloading the best variant of the master playlist");
+            let (base, content) = fetch_text(client, &variant.url, &job.headers).await?;
+            parse_playlist(&content, &base)?
+        }
+        playlist => playlist,
+    };
+    let Playlist::Media { segments, ended } = playlist else {
+        return Err("the variant playlist is a master playlist as well".to_string());
+    };
+    if !ended {
+        warn!("live HLS playlist, only the segments listed now are downloaded");
+    }
+
+    let mut keys = HashMap::new();
+    for segment in &segments {
+        let Some(key) = &segment.key else {
+            continue;
+        };
+        if keys.contains_key(&key.url) {
+            continue;
+        }
+        let bytes = get(client, &key.url, &job.headers)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| format!("loading the key failed: {:?}", error.without_url()))?
+            .bytes()
+            .await
+            .map_err(|error| format!("loading the key failed: {:?}", error.without_url()))?;
+        if bytes.len() != AES_128_KEY_LEN {
+            return Err(format!("the key is {} bytes, not 16", bytes.len()));
+        }
+        keys.insert(key.url.clone(), bytes.to_vec());
+    }
+
+    Ok((
+        Track {
+            file_name: job.file_name.clone(),
+            segments,
+        },
+        keys,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://cdn.example/video/master.m3u8").unwrap()
+    }
+
+    fn media(content: &str) -> (Vec<Segment>, bool) {
+        match parse_playlist(content, &base()).unwrap() {
+            Playlist::Media { segments, ended } => (segments, ended),
+            playlist => panic!("not a media playlist: {playlist:?}"),
+        }
+    }
+
+    #[test]
+    fn master_playlists_list_variants_by_bandwidth() {
+        let playlist = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS=\"avc1.4d401e,mp4a.40.2\",RESOLUTION=640x360\n\
+            low/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=64000\n\
+            https://audio.example/audio.m3u8\n\
+            #EXT-X-STREAM-INF:RESOLUTION=1920x1080,BANDWIDTH=5000000\n\
+            /hd/index.m3u8\n";
+
+        let Playlist::Master(variants) = parse_playlist(playlist, &base()).unwrap() else {
+            panic!("not a master playlist");
+        };
+        assert_eq!(
+            variants,
+            [
+                Variant {
+                    url: "https://cdn.example/hd/index.m3u8".to_string(),
+                    bandwidth: 5_000_000,
+                    resolution: "1920x1080".to_string(),
+                },
+                Variant {
+                    url: "https://cdn.example/video/low/index.m3u8".to_string(),
+                    bandwidth: 800_000,
+                    resolution: "640x360".to_string(),
+                },
+                Variant {
+                    url: "https://audio.example/audio.m3u8".to_string(),
+                    bandwidth: 64_000,
+                    resolution: String::new(),
+                },
+            ]
+        );
+        assert_eq!(variants[1].to_string(), "640x360, 0.80 Mbps");
+        assert_eq!(variants[2].to_string(), "0.06 Mbps");
+    }
+
+    #[test]
+    fn media_playlists_list_segments() {
+        let (segments, ended) = media(
+            "#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXTINF:10,\nseg0.ts\n\n#EXTINF:10,\n  seg1.ts  \n#EXT-X-ENDLIST\n",
+        );
+        let urls: Vec<&str> = segments
+            .iter()
+            .map(|segment| segment.url.as_str())
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "https://cdn.example/video/seg0.ts",
+                "https://cdn.example/video/seg1.ts"
+            ]
+        );
+        assert!(ended);
+        assert!(segments
+            .iter()
+            .all(|segment| segment.key.is_none() && segment.range.is_none()));
+
+        let (_, ended) = media("\u{feff}#EXTM3U\n#EXT-X-TARGETDURATION:10\nlive.ts\n");
+        assert!(!ended);
+    }
+
+    #[test]
+    fn aes_128_keys_and_ivs() {
+        let (segments, _) = media(
+            "#EXTM3U\n#EXT-X-MEDIA-SEQUENCE:7\n\
+             #EXT-X-KEY:METHOD=AES-128,URI=\"keys/a.key\"\n\
+             seg7.ts\nseg8.ts\n\
+             #EXT-X-KEY:METHOD=AES-128,URI=\"https://keys.example/b.key\",IV=0x000102030405060708090A0B0C0D0E0F\n\
+             seg9.ts\n\
+             #EXT-X-KEY:METHOD=NONE\n\
+             seg10.ts\n",
+        );
+
+        // without an IV the media sequence number is the IV
+        let sequence_iv = |sequence: u128| sequence.to_be_bytes();
+        assert_eq!(
+            segments[0].key,
+            Some(SegmentKey {
+                url: "https://cdn.example/video/keys/a.key".to_string(),
+                iv: sequence_iv(7),
+            })
+        );
+        assert_eq!(segments[1].key.as_ref().unwrap().iv, sequence_iv(8));
+        assert_eq!(
+            segments[2].key,
+            Some(SegmentKey {
+                url: "https://keys.example/b.key".to_string(),
+                iv: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+            })
+        );
+        assert_eq!(segments[3].key, None);
+    }
+
+    #[test]
+    fn byte_ranges_continue_from_the_previous_range() {
+        let (segments, _) = media(
+            "#EXTM3U\n\
+             #EXT-X-BYTERANGE:1000@500\nall.ts\n\
+             #EXT-X-BYTERANGE:200\nall.ts\n\
+             #EXT-X-BYTERANGE:300\nother.ts\n\
+             whole.ts\n",
+        );
+        let ranges: Vec<_> = segments.iter().map(|segment| segment.range).collect();
+        assert_eq!(
+            ranges,
+            [Some((500, 1499)), Some((1500, 1699)), Some((0, 299)), None]
+        );
+    }
+
+    #[test]
+    fn maps_come_before_their_segments_once() {
+        let (segments, _) = media(
+            "#EXTM3U\n\
+             #EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@0\"\n\
+             seg0.m4s\n\
+             #EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@0\"\n\
+             seg1.m4s\n",
+        );
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].url, "https://cdn.example/video/init.mp4");
+        assert_eq!(segments[0].range, Some((0, 719)));
+        assert_eq!(segments[2].url, "https://cdn.example/video/seg1.m4s");
+    }
+
+    #[test]
+    fn invalid_playlists() {
+        for (playlist, error) in [
+            ("#EXTM3U\n#EXTINF:10,\nseg.ts\n", "not an HLS playlist"),
+            ("<html></html>", "not an HLS playlist"),
+            (
+                "#EXTM3U\n#EXT-X-ENDLIST\n",
+                "the playlist lists no segments",
+            ),
+            (
+                "#EXTM3U\n#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"k\"\nseg.ts\n",
+                "SAMPLE-AES encrypted HLS streams aren't supported",
+            ),
+            (
+                "#EXTM3U\n#EXT-X-KEY:METHOD=AES-128\nseg.ts\n",
+                "an AES-128 key without a URI",
+            ),
+            (
+                "#EXTM3U\n#EXT-X-KEY:METHOD=AES-128,URI=\"k\",IV=0xnothex\nseg.ts\n",
+                "invalid IV 0xnothex",
+            ),
+            (
+                "#EXTM3U\n#EXT-X-BYTERANGE:0@10\nseg.ts\n",
+                "invalid byte range 0@10",
+            ),
+            (
+                "#EXTM3U\n#EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720\"\nseg.ts\n",
+                "a map byte range without an offset",
+            ),
+        ] {
+            assert_eq!(
+                parse_playlist(playlist, &base()).map(|_| ()),
+                Err(error.to_string()),
+                "{playlist}"
+            );
+        }
+    }
+
+    #[test]
+    fn playlist_urls_and_content_types() {
+        assert!(is_hls_url("https://cdn.example/live/INDEX.M3U8?token=1"));
+        assert!(!is_hls_url("https://cdn.example/m3u8/video.mp4"));
+        assert!(is_hls_content_type(
+            "application/vnd.apple.mpegurl; charset=utf-8"
+        ));
+        assert!(is_hls_content_type("Audio/X-MpegURL"));
+        assert!(!is_hls_content_type("text/plain"));
+        // plain M3U lists of media players aren't HLS
+        assert!(!is_playlist(
+            "#EXTM3U\n#EXTINF:123,Artist - Title\nsong.mp3\n"
+        ));
+    }
+}
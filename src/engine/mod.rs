@@ -0,0 +1,248 @@
+pub mod dash;
+mod ftp;
+pub mod hls;
+pub mod probe;
+mod segments;
+mod sftp;
+pub mod torrent;
+mod transfer;
+use crate::utils::{
+    checksum::Checksum,
+    redact::{redact_body, redact_headers, redact_url},
+};
+use iced::futures::{
+    channel::mpsc::{unbounded, UnboundedSender},
+    stream::BoxStream,
+};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use torrent::{TorrentFile, TorrentOptions};
+use uuid::Uuid;
+
+/**
+ * media delivered as a playlist or manifest of segments rather than as one file
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StreamFormat {
+    Hls,
+    Dash { representations: Vec<String> }, // ids, none for the best video and audio
+}
+
+impl StreamFormat {
+    pub fn from_url(url: &str) -> Option<Self> {
+        if hls::is_hls_url(url) {
+            Some(Self::Hls)
+        } else if dash::is_dash_url(url) {
+            Some(Self::Dash {
+                representations: vec![],
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        if hls::is_hls_content_type(content_type) {
+            Some(Self::Hls)
+        } else if dash::is_dash_content_type(content_type) {
+            Some(Self::Dash {
+                representations: vec![],
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn from_content(content: &str) -> Option<Self> {
+        if hls::is_playlist(content) {
+            Some(Self::Hls)
+        } else if dash::is_manifest(content) {
+            Some(Self::Dash {
+                representations: vec![],
+            })
+        } else {
+            None
+        }
+    }
+
+    /**
+     * playlist and manifest names, and names without an extension, get the extension of the
+     * joined output, a name with another extension is kept as picked
+     */
+    pub fn file_name(&self, file_name: &str) -> String {
+        let path = Path::new(file_name);
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some(extension) if !matches!(extension, "m3u8" | "m3u" | "mpd") => {
+                file_name.to_string()
+            }
+            _ => path
+                .with_extension(match self {
+                    Self::Hls => "ts",
+                    Self::Dash { .. } => "mp4",
+                })
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+}
+
+/**
+ * everything the engine needs to fetch one file, independent of how a front-end stores it
+ */
+#[derive(Clone)]
+pub struct DownloadJob {
+    pub url: String,
+    pub mirrors: Vec<String>, // same file on other servers, threaded downloads spread over them
+    pub method: Method,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>, // sent with every request when not empty
+    pub file_path: String,
+    pub file_name: String,
+    pub cache_dir: PathBuf,
+    pub threads: u8,
+    pub sequential: bool,
+    pub size: usize,        // 0 = unknown, probed before starting
+    pub downloaded: usize,  // 0 with an unknown size = fresh download
+    pub speed_limit: usize, // bytes per second, 0 = unlimited
+    pub stream: Option<StreamFormat>,
+    pub ftp_active: bool, // FTP servers connect back for data instead of being connected to
+    pub torrent: TorrentOptions,
+    pub find_checksum: bool, // looks for the expected hash in headers and checksum files
+}
+
+// same rules as `AtomDownload`, URL, headers and body may carry credentials
+impl fmt::Debug for DownloadJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DownloadJob")
+            .field("url", &redact_url(&self.url))
+            .field(
+                "mirrors",
+                &self
+                    .mirrors
+                    .iter()
+                    .map(|url| redact_url(url))
+                    .collect::<Vec<_>>(),
+            )
+            .field("method", &self.method)
+            .field("headers", &redact_headers(&self.headers))
+            .field("body", &redact_body(&self.body))
+            .field("file_path", &self.file_path)
+            .field("file_name", &self.file_name)
+            .field("cache_dir", &self.cache_dir)
+            .field("threads", &self.threads)
+            .field("sequential", &self.sequential)
+            .field("size", &self.size)
+            .field("downloaded", &self.downloaded)
+            .field("speed_limit", &self.speed_limit)
+            .field("stream", &self.stream)
+            .field("ftp_active", &self.ftp_active)
+            .field("torrent", &self.torrent)
+            .field("find_checksum", &self.find_checksum)
+            .finish()
+    }
+}
+
+/**
+ * who a torrent is exchanging pieces with and how far it is
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SwarmStatus {
+    pub peers: usize,
+    pub seeds: usize,
+    pub uploaded: usize,
+    pub pieces: (usize, usize), // verified and wanted
+}
+
+#[derive(Debug, Clone)]
+pub enum EngineCommand {
+    Pause,
+    SetSpeedLimit(usize), // bytes per second, 0 = unlimited
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    SizeKnown {
+        size: usize,
+        downloaded: usize,
+    },
+    Progress(usize), // total bytes downloaded
+    Downloaded,      // every chunk is in, joining starts
+    Joining(usize),  // bytes joined since the previous event
+    Finished,
+    Paused,
+    Error(String),
+    MirrorDropped(String), // failed or too slow, its chunks moved to the other servers
+    // the URL turned out to be a playlist or manifest, saved under this file name
+    StreamDetected {
+        format: StreamFormat,
+        file_name: String,
+    },
+    Segments {
+        done: usize,
+        total: usize,
+        downloaded: usize,
+    },
+    // the torrent's metadata is in, its files are saved under this name
+    TorrentLoaded {
+        file_name: String,
+        files: Vec<TorrentFile>,
+    },
+    Swarm(SwarmStatus),
+    Seeding, // every picked file is in, pieces are uploaded until the seeding limits
+    ChecksumFound(Checksum), // the hash the server publishes for the file
+}
+
+/**
+ * sends commands to a running engine, dropping the event stream stops the engine as well
+ */
+#[derive(Debug, Clone)]
+pub struct EngineHandle(UnboundedSender<EngineCommand>);
+
+impl EngineHandle {
+    pub fn send(&self, command: EngineCommand) -> bool {
+        self.0.unbounded_send(command).is_ok()
+    }
+}
+
+/**
+ * starts the download lazily, nothing happens until the returned stream is polled
+ */
+pub fn start(job: DownloadJob, client: Client) -> (EngineHandle, BoxStream<'static, EngineEvent>) {
+    let (sender, receiver) = unbounded();
+    (EngineHandle(sender), transfer::run(job, client, receiver))
+}
+
+/**
+ * handles of the running engines, shared by whoever drives them and whoever controls them
+ */
+#[derive(Debug, Clone, Default)]
+pub struct EngineRegistry(Arc<Mutex<HashMap<Uuid, EngineHandle>>>);
+
+impl EngineRegistry {
+    pub fn register(&self, id: Uuid, handle: EngineHandle) {
+        if let Ok(mut handles) = self.0.lock() {
+            handles.insert(id, handle);
+        }
+    }
+
+    pub fn send(&self, id: Uuid, command: EngineCommand) -> bool {
+        let Ok(mut handles) = self.0.lock() else {
+            return false;
+        };
+
+        let sent = handles.get(&id).is_some_and(|handle| handle.send(command));
+        if !sent {
+            handles.remove(&id);
+        }
+        sent
+    }
+}
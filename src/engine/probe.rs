@@ -0,0 +1,291 @@
+use crate::utils::{
+    checksum::{self, Checksum, ChecksumAlgorithm},
+    helpers::{hashmap2headermap, ATOM_USER_AGENT},
+    redact::without_credentials,
+};
+use reqwest::{
+    header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, RANGE, USER_AGENT},
+    Client, Method, RequestBuilder, StatusCode, Url,
+};
+use std::collections::HashMap;
+
+const SIDECAR_MAX_LEN: u64 = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum DownloadType {
+    Sequential,
+    Threaded,
+}
+
+#[derive(Debug)]
+pub struct DownloadProperties {
+    pub content_length: usize,
+    pub download_type: DownloadType,
+    pub error: String,
+    pub etag: String,               // empty when the server sends none
+    pub content_type: String,       // empty when the server sends none
+    pub checksum: Option<Checksum>, // announced by the server for the whole file
+}
+
+impl DownloadProperties {
+    /**
+     * whether another server's copy is the same file: same size, and the same ETag when both
+     * send a strong one (weak ETags and servers without ETags only compare by size)
+     */
+    pub fn same_file(&self, other: &DownloadProperties) -> bool {
+        let strong = |etag: &str| !etag.is_empty() && !etag.starts_with("W/");
+        self.error.is_empty()
+            && other.error.is_empty()
+            && self.content_length > 0
+            && self.content_length == other.content_length
+            && (!strong(&self.etag) || !strong(&other.etag) || self.etag == other.etag)
+    }
+}
+
+pub async fn get_content_length(
+    client: Client,
+    link: &str,
+    headers: &HashMap<String, String>,
+) -> DownloadProperties {
+    let mut size = DownloadProperties {
+        content_length: 0,
+        download_type: DownloadType::Sequential,
+        error: "".to_string(),
+        etag: "".to_string(),
+        content_type: "".to_string(),
+        checksum: None,
+    };
+
+    match client
+        .request(Method::HEAD, link)
+        .header(USER_AGENT, ATOM_USER_AGENT)
+        // .header("Referer", referrer)
+        .headers(hashmap2headermap(headers))
+        .send()
+        .await
+    {
+        Ok(response) => {
+            if !response.status().is_success() {
+                size.error = "Error, unable to get content length!".to_string();
+            } else {
+                let headers = response.headers();
+                size.etag = headers
+                    .get(ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                size.content_type = headers
+                    .get(CONTENT_TYPE)
+                    .and_then(|content_type| content_type.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                size.checksum = checksum::from_headers(headers, true);
+                match (headers.get(ACCEPT_RANGES), headers.get(CONTENT_LENGTH)) {
+                    // todo:
+                    // accept-ranges may be missing
+                    (Some(_), Some(cl)) => {
+                        let cl = cl
+                            .to_str()
+                            .unwrap_or_default()
+                            .parse::<u64>()
+                            .unwrap_or_default();
+
+                        size.content_length = cl as usize;
+                        size.download_type = DownloadType::Threaded;
+                    }
+                    (None, Some(cl)) => {
+                        let cl = cl
+                            .to_str()
+                            .unwrap_or_default()
+                            .parse::<u64>()
+                            .unwrap_or_default();
+
+                        size.content_length = cl as usize;
+                        size.download_type = DownloadType::Sequential;
+                    }
+                    (_, _) => {
+                        size.content_length = 0;
+                        size.download_type = DownloadType::Sequential;
+                    }
+                }
+            }
+        }
+        Err(_) => {
+            size.content_length = 0;
+            size.download_type = DownloadType::Sequential;
+        }
+    }
+
+    size
+}
+
+/**
+ * what a request other than GET answers, found by sending it for its first byte since HEAD
+ * can't stand in for it. A partial answer means the ranges can be fetched in threads
+ */
+pub async fn get_range_properties(request: RequestBuilder) -> DownloadProperties {
+    let mut properties = DownloadProperties {
+        content_length: 0,
+        download_type: DownloadType::Sequential,
+        error: "".to_string(),
+        etag: "".to_string(),
+        content_type: "".to_string(),
+        checksum: None,
+    };
+
+    let Ok(response) = request.header(RANGE, "bytes=0-0").send().await else {
+        return properties;
+    };
+    if !response.status().is_success() {
+        properties.error = "Error, unable to get content length!".to_string();
+        return properties;
+    }
+
+    let headers = response.headers();
+    let text = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    properties.etag = text(ETAG);
+    properties.content_type = text(CONTENT_TYPE);
+    properties.checksum =
+        checksum::from_headers(headers, response.status() != StatusCode::PARTIAL_CONTENT);
+    if response.status() == StatusCode::PARTIAL_CONTENT {
+        // `bytes 0-0/<size>`, an unknown size (`*`) leaves the download sequential
+        if let Some(size) = text(CONTENT_RANGE)
+            .rsplit_once('/')
+            .and_then(|(_, size)| size.parse().ok())
+        {
+            properties.content_length = size;
+            properties.download_type = DownloadType::Threaded;
+        }
+    } else {
+        properties.content_length = text(CONTENT_LENGTH).parse().unwrap_or_default();
+    }
+    properties
+}
+
+/**
+ * the expected hash of a file from a checksum file published next to it, `<file>.sha256`
+ * first and then a `SHA256SUMS` listing in the same directory, asked for without the
+ * download's credentials and only from its own origin
+ */
+pub async fn get_sidecar_checksum(
+    client: &Client,
+    link: &str,
+    headers: &HashMap<String, String>,
+) -> Option<Checksum> {
+    let url = Url::parse(link).ok()?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return None;
+    }
+    let file_name = url
+        .path_segments()?
+        .next_back()
+        .filter(|name| !name.is_empty())
+        .map(|name| urlencoding::decode(name).map_or(name.to_string(), |name| name.into_owned()))?;
+
+    let mut sidecar = url.clone();
+    sidecar.set_path(&format!("{}.sha256", url.path()));
+    sidecar.set_query(None);
+    let listing = url.join("SHA256SUMS").ok()?;
+    // cookies and tokens were given for the file, not for guesses next to it
+    let headers = without_credentials(headers);
+
+    for (sidecar, single_file) in [(sidecar, true), (listing, false)] {
+        if sidecar.origin() != url.origin() {
+            continue;
+        }
+        let Some(text) = get_small_text(client, sidecar, &headers).await else {
+            continue;
+        };
+        if let Some(checksum) =
+            checksum::from_listing(&text, ChecksumAlgorithm::Sha256, &file_name, single_file)
+        {
+            return Some(checksum);
+        }
+    }
+    None
+}
+
+// checksum files are a few lines, anything bigger is not one
+async fn get_small_text(
+    client: &Client,
+    url: Url,
+    headers: &HashMap<String, String>,
+) -> Option<String> {
+    let origin = url.origin();
+    let mut response = client
+        .get(url)
+        .header(USER_AGENT, ATOM_USER_AGENT)
+        .headers(hashmap2headermap(headers))
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?;
+    // a redirect to another site says nothing about this file
+    if response.url().origin() != origin {
+        return None;
+    }
+    if response.content_length().unwrap_or_default() > SIDECAR_MAX_LEN {
+        return None;
+    }
+
+    let mut body = vec![];
+    while let Some(chunk) = response.chunk().await.ok()? {
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > SIDECAR_MAX_LEN {
+            return None;
+        }
+    }
+    String::from_utf8(body).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tiny_http::{Response, Server};
+
+    #[tokio::test]
+    async fn sidecar_requests_leave_credentials_out() {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let link = format!("http://{}/files/file.bin?sig=1", server.server_addr());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let log = requests.clone();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let headers: Vec<String> = request
+                    .headers()
+                    .iter()
+                    .map(|header| header.field.as_str().as_str().to_lowercase())
+                    .collect();
+                log.lock()
+                    .unwrap()
+                    .push((request.url().to_string(), headers));
+                request.respond(Response::empty(404)).ok();
+            }
+        });
+        let headers = HashMap::from([
+            ("Cookie".to_string(), "session=1".to_string()),
+            ("Authorization".to_string(), "Bearer 1".to_string()),
+            ("Referer".to_string(), "https://example.com/".to_string()),
+        ]);
+
+        let checksum = get_sidecar_checksum(&Client::new(), &link, &headers).await;
+
+        assert!(checksum.is_none());
+        let requests = requests.lock().unwrap();
+        let urls: Vec<&str> = requests.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(urls, ["/files/file.bin.sha256", "/files/SHA256SUMS"]);
+        for (_, headers) in requests.iter() {
+            assert!(headers.contains(&"referer".to_string()));
+            assert!(!headers.contains(&"cookie".to_string()));
+            assert!(!headers.contains(&"authorization".to_string()));
+        }
+    }
+}
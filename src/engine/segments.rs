@@ -0,0 +1,264 @@
+use super::DownloadJob;
+use crate::utils::{
+    helpers::{hashmap2headermap, stream_cache_dir, ATOM_USER_AGENT},
+    redact::redact_url,
+};
+use iced::futures::{
+    future::BoxFuture,
+    stream::{FuturesUnordered, StreamExt},
+    FutureExt,
+};
+use openssl::symm::{decrypt, Cipher};
+use reqwest::{
+    header::{RANGE, USER_AGENT},
+    Client, RequestBuilder, StatusCode, Url,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentKey {
+    pub url: String,
+    pub iv: [u8; 16],
+}
+
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub url: String,
+    pub range: Option<(usize, usize)>, // first and last byte, inclusive
+    pub key: Option<SegmentKey>,       // AES-128, none for clear segments
+}
+
+/**
+ * segments joined into one output file
+ */
+#[derive(Debug)]
+pub struct Track {
+    pub file_name: String,
+    pub segments: Vec<Segment>,
+}
+
+pub fn get(client: &Client, url: &str, headers: &HashMap<String, String>) -> RequestBuilder {
+    client
+        .get(url)
+        .header(USER_AGENT, ATOM_USER_AGENT)
+        .headers(hashmap2headermap(headers))
+}
+
+pub fn resolve(base: &Url, uri: &str) -> Result<String, String> {
+    base.join(uri)
+        .map(String::from)
+        .map_err(|error| format!("invalid URI {}: {error}", redact_url(uri)))
+}
+
+/**
+ * a playlist or manifest and the URL it came from after redirects, relative URIs are resolved
+ * against it
+ */
+pub async fn fetch_text(
+    client: &Client,
+    url: &str,
+    headers: &HashMap<String, String>,
+) -> Result<(Url, String), String> {
+    let response = get(client, url, headers).send().await.map_err(|error| {
+        format!(
+            "loading {} failed: {:?}",
+            redact_url(url),
+            error.without_url()
+        )
+    })?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "The server responded with an {} status code for {}",
+            response.status(),
+            redact_url(url)
+        ));
+    }
+
+    let base = response.url().clone();
+    let content = response.text().await.map_err(|error| {
+        format!(
+            "loading {} failed: {:?}",
+            redact_url(url),
+            error.without_url()
+        )
+    })?;
+    Ok((base, content))
+}
+
+fn segment_file(dir: &Path, track: usize, index: usize) -> PathBuf {
+    dir.join(format!("{}-{}", track + 1, index + 1))
+}
+
+/**
+ * downloads the segments of every track into the cache, a few at a time, segments already there
+ * from an earlier run are kept
+ */
+pub struct SegmentTransfer {
+    client: Client,
+    headers: HashMap<String, String>,
+    file_path: String,
+    tracks: Vec<Track>,
+    keys: HashMap<String, Vec<u8>>, // by key URL
+    dir: PathBuf,
+    threads: usize,
+    queue: VecDeque<(usize, usize)>, // track and segment not in the cache and not requested yet
+    running: FuturesUnordered<BoxFuture<'static, Result<usize, String>>>,
+    pub done: usize,
+    pub downloaded: usize, // bytes of the segments done
+}
+
+impl fmt::Debug for SegmentTransfer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SegmentTransfer")
+            .field("tracks", &self.tracks.len())
+            .field("segments", &self.total())
+            .field("dir", &self.dir)
+            .field("threads", &self.threads)
+            .field("queued", &self.queue.len())
+            .field("running", &self.running.len())
+            .field("done", &self.done)
+            .field("downloaded", &self.downloaded)
+            .finish()
+    }
+}
+
+impl SegmentTransfer {
+    pub fn new(
+        client: Client,
+        job: &DownloadJob,
+        tracks: Vec<Track>,
+        keys: HashMap<String, Vec<u8>>,
+    ) -> Result<Self, String> {
+        let dir = stream_cache_dir(&job.cache_dir, &job.file_name);
+        std::fs::create_dir_all(&dir)
+            .map_err(|error| format!("creating {} failed: {error}", dir.display()))?;
+
+        let mut queue = VecDeque::new();
+        let (mut done, mut downloaded) = (0, 0);
+        for (track, segments) in tracks.iter().enumerate() {
+            for index in 0..segments.segments.len() {
+                match std::fs::metadata(segment_file(&dir, track, index)) {
+                    Ok(metadata) => {
+                        done += 1;
+                        downloaded += metadata.len() as usize;
+                    }
+                    Err(_) => queue.push_back((track, index)),
+                }
+            }
+        }
+
+        Ok(Self {
+            client,
+            headers: job.headers.clone(),
+            file_path: job.file_path.clone(),
+            tracks,
+            keys,
+            dir,
+            threads: job.threads.max(1) as usize,
+            queue,
+            running: FuturesUnordered::new(),
+            done,
+            downloaded,
+        })
+    }
+
+    pub fn total(&self) -> usize {
+        self.tracks.iter().map(|track| track.segments.len()).sum()
+    }
+
+    /**
+     * each output file with its segment files in order, joined once all are in
+     */
+    pub fn outputs(&self) -> Vec<(String, Vec<String>)> {
+        self.tracks
+            .iter()
+            .enumerate()
+            .map(|(track, segments)| {
+                (
+                    Path::new(&self.file_path)
+                        .join(&segments.file_name)
+                        .to_string_lossy()
+                        .to_string(),
+                    (0..segments.segments.len())
+                        .map(|index| {
+                            segment_file(&self.dir, track, index)
+                                .to_string_lossy()
+                                .to_string()
+                        })
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    /**
+     * keeps up to `threads` segments in flight and waits for the next one, none once every
+     * segment is in
+     */
+    pub async fn next(&mut self) -> Option<Result<usize, String>> {
+        while self.running.len() < self.threads {
+            let Some((track, index)) = self.queue.pop_front() else {
+                break;
+            };
+            let fetch = self.fetch(track, index);
+            self.running.push(fetch);
+        }
+
+        let result = self.running.next().await?;
+        if let Ok(bytes) = result {
+            self.done += 1;
+            self.downloaded += bytes;
+        }
+        Some(result)
+    }
+
+    fn fetch(&self, track: usize, index: usize) -> BoxFuture<'static, Result<usize, String>> {
+        let segment = &self.tracks[track].segments[index];
+        let key = segment
+            .key
+            .as_ref()
+            .and_then(|key| self.keys.get(&key.url).map(|bytes| (bytes.clone(), key.iv)));
+        let mut request = get(&self.client, &segment.url, &self.headers);
+        if let Some((start, end)) = segment.range {
+            request = request.header(RANGE, format!("bytes={start}-{end}"));
+        }
+        let ranged = segment.range.is_some();
+        let path = segment_file(&self.dir, track, index);
+        let number = index + 1;
+
+        async move {
+            let response = request
+                .send()
+                .await
+                .map_err(|error| format!("segment {number} failed: {:?}", error.without_url()))?;
+            let status = response.status();
+            if !status.is_success() || (ranged && status != StatusCode::PARTIAL_CONTENT) {
+                return Err(format!(
+                    "The server responded with {status} for segment {number}"
+                ));
+            }
+
+            let mut bytes = response
+                .bytes()
+                .await
+                .map_err(|error| format!("segment {number} failed: {:?}", error.without_url()))?
+                .to_vec();
+            if let Some((key, iv)) = key {
+                bytes = decrypt(Cipher::aes_128_cbc(), &key, Some(&iv), &bytes)
+                    .map_err(|_| format!("decrypting segment {number} failed"))?;
+            }
+
+            // written under another name first so a segment file in the cache is always whole
+            let part = path.with_extension("part");
+            std::fs::write(&part, &bytes)
+                .and_then(|_| std::fs::rename(&part, &path))
+                .map_err(|error| format!("saving segment {number} failed: {error}"))?;
+            Ok(bytes.len())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+
+// deeper nesting than any torrent, tracker or peer message uses, keeps bad input off the stack
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(dict) => dict.get(key.as_bytes()),
+            _ => None,
+        }
+    }
+
+    pub fn int(&self) -> Option<i64> {
+        match self {
+            Value::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn str(&self) -> Option<&str> {
+        self.bytes()
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+    }
+
+    pub fn list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Int(value) => out.extend(format!("i{value}e").as_bytes()),
+            Value::Bytes(bytes) => {
+                out.extend(format!("{}:", bytes.len()).as_bytes());
+                out.extend(bytes);
+            }
+            Value::List(list) => {
+                out.push(b'l');
+                list.iter().for_each(|value| value.encode_into(out));
+                out.push(b'e');
+            }
+            Value::Dict(dict) => {
+                out.push(b'd');
+                dict.iter().for_each(|(key, value)| {
+                    Value::Bytes(key.clone()).encode_into(out);
+                    value.encode_into(out);
+                });
+                out.push(b'e');
+            }
+        }
+    }
+}
+
+/**
+ * builds a dictionary from string keys, for the messages ATOM sends
+ */
+pub fn dict<const N: usize>(entries: [(&str, Value); N]) -> Value {
+    Value::Dict(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value))
+            .collect(),
+    )
+}
+
+/**
+ * decodes one value from the start of `data`, returns it with the number of bytes it took,
+ * peer messages carry raw data after the dictionary
+ */
+pub fn decode_prefix(data: &[u8]) -> Result<(Value, usize), String> {
+    let mut decoder = Decoder {
+        data,
+        position: 0,
+        info: None,
+    };
+    let value = decoder.value(0)?;
+    Ok((value, decoder.position))
+}
+
+pub fn decode(data: &[u8]) -> Result<Value, String> {
+    let (value, length) = decode_prefix(data)?;
+    if length != data.len() {
+        return Err("unexpected data after the bencoded value".to_string());
+    }
+    Ok(value)
+}
+
+/**
+ * decodes a torrent file, returns it with the bytes of its `info` dictionary exactly as they
+ * are in the file, the info hash is taken over them
+ */
+pub fn decode_torrent(data: &[u8]) -> Result<(Value, &[u8]), String> {
+    let mut decoder = Decoder {
+        data,
+        position: 0,
+        info: None,
+    };
+    let value = decoder.value(0)?;
+    let (start, end) = decoder.info.ok_or("the torrent has no info dictionary")?;
+    Ok((value, &data[start..end]))
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+    info: Option<(usize, usize)>, // span of the top level `info` value
+}
+
+impl Decoder<'_> {
+    fn peek(&self) -> Result<u8, String> {
+        self.data
+            .get(self.position)
+            .copied()
+            .ok_or_else(|| "the bencoded data ends early".to_string())
+    }
+
+    fn until(&mut self, end: u8) -> Result<&[u8], String> {
+        let start = self.position;
+        let length = self.data[start..]
+            .iter()
+            .position(|byte| *byte == end)
+            .ok_or("the bencoded data ends early")?;
+        self.position += length + 1;
+        Ok(&self.data[start..start + length])
+    }
+
+    fn number(digits: &[u8]) -> Result<i64, String> {
+        std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| "invalid number in the bencoded data".to_string())
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, String> {
+        let length = Self::number(self.until(b':')?)?;
+        let length = usize::try_from(length).map_err(|_| "negative string length")?;
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.data.len())
+            .ok_or("the bencoded data ends early")?;
+        let bytes = self.data[self.position..end].to_vec();
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("the bencoded data is nested too deeply".to_string());
+        }
+
+        match self.peek()? {
+            b'i' => {
+                self.position += 1;
+                Ok(Value::Int(Self::number(self.until(b'e')?)?))
+            }
+            b'l' => {
+                self.position += 1;
+                let mut list = vec![];
+                while self.peek()? != b'e' {
+                    list.push(self.value(depth + 1)?);
+                }
+                self.position += 1;
+                Ok(Value::List(list))
+            }
+            b'd' => {
+                self.position += 1;
+                let mut dict = BTreeMap::new();
+                while self.peek()? != b'e' {
+                    let key = self.bytes()?;
+                    let start = self.position;
+                    let value = self.value(depth + 1)?;
+                    if depth == 0 && key == b"info" {
+                        self.info = Some((start, self.position));
+                    }
+                    dict.insert(key, value);
+                }
+                self.position += 1;
+                Ok(Value::Dict(dict))
+            }
+            b'0'..=b'9' => Ok(Value::Bytes(self.bytes()?)),
+            _ => Err("invalid bencoded data".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let value = dict([
+            ("int", Value::Int(-42)),
+            ("bytes", Value::Bytes(b"spam".to_vec())),
+            (
+                "list",
+                Value::List(vec![Value::Int(0), Value::Bytes(vec![0, 255])]),
+            ),
+            ("dict", dict([("nested", Value::Bytes(vec![]))])),
+        ]);
+        let encoded = value.encode();
+        // keys are sorted, as the info hash needs them
+        assert!(encoded.starts_with(b"d5:bytes4:spam4:dictd6:nested0:e3:inti-42e4:list"));
+        assert_eq!(decode(&encoded), Ok(value));
+    }
+
+    #[test]
+    fn invalid_data_is_rejected() {
+        for data in [
+            &b""[..],
+            b"i42",
+            b"ie",
+            b"i4x2e",
+            b"5:spam",
+            b"-1:x",
+            b"l",
+            b"d3:key",
+            b"x",
+            b"99999999999999999999:x",
+        ] {
+            assert!(decode(data).is_err(), "{:?}", String::from_utf8_lossy(data));
+        }
+        assert!(decode(b"i1ei2e").is_err());
+    }
+
+    #[test]
+    fn prefix_leaves_trailing_data() {
+        assert_eq!(
+            decode_prefix(b"d1:ai1eeraw piece"),
+            Ok((dict([("a", Value::Int(1))]), 8))
+        );
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| [vec![b'l'; depth], vec![b'e'; depth]].concat();
+        assert!(decode(&nested(MAX_DEPTH + 1)).is_ok());
+        assert!(decode(&nested(MAX_DEPTH + 2)).is_err());
+    }
+
+    #[test]
+    fn torrent_keeps_the_info_bytes() {
+        // an unsorted info dictionary, re-encoding it would change the hash
+        let data = b"d8:announce3:url4:infod4:name1:x6:lengthi1eee";
+        let (torrent, info) = decode_torrent(data).unwrap();
+        assert_eq!(info, b"d4:name1:x6:lengthi1ee");
+        assert_eq!(torrent.get("announce").and_then(Value::str), Some("url"));
+
+        // only the top level counts
+        assert!(decode_torrent(b"d1:ad4:infoi1eee").is_err());
+    }
+}
@@ -0,0 +1,483 @@
+use super::bencode::{self, Value};
+use openssl::sha::sha1;
+use reqwest::Url;
+use std::net::SocketAddr;
+
+pub type InfoHash = [u8; 20];
+
+/**
+ * one file of a torrent, where it sits in the torrent's bytes
+ */
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    pub path: Vec<String>, // below the torrent's folder, checked to stay inside it
+    pub length: usize,
+    pub offset: usize,
+}
+
+/**
+ * the `info` dictionary of a torrent with the trackers that came with it
+ */
+#[derive(Clone)]
+pub struct Metainfo {
+    pub info_hash: InfoHash,
+    pub name: String,
+    pub piece_length: usize,
+    pub pieces: Vec<InfoHash>,
+    pub files: Vec<FileEntry>, // without padding files, their bytes are zeros
+    pub length: usize,
+    pub multi_file: bool, // the files go into a folder named after the torrent
+    pub private: bool,    // peers only come from the trackers, never from the DHT
+    pub trackers: Vec<String>,
+    pub info: Vec<u8>, // as received, sent to peers asking for the metadata
+}
+
+impl std::fmt::Debug for Metainfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metainfo")
+            .field("info_hash", &hex(&self.info_hash))
+            .field("name", &self.name)
+            .field("piece_length", &self.piece_length)
+            .field("pieces", &self.pieces.len())
+            .field("files", &self.files.len())
+            .field("private", &self.private)
+            .field("trackers", &self.trackers)
+            .finish()
+    }
+}
+
+impl Metainfo {
+    pub fn from_torrent(data: &[u8]) -> Result<Self, String> {
+        let (torrent, info) = bencode::decode_torrent(data)?;
+
+        let mut trackers: Vec<String> = torrent
+            .get("announce-list")
+            .and_then(Value::list)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Value::list)
+            .flatten()
+            .filter_map(Value::str)
+            .map(str::to_string)
+            .collect();
+        if let Some(announce) = torrent.get("announce").and_then(Value::str) {
+            trackers.push(announce.to_string());
+        }
+
+        Self::from_info(info, trackers)
+    }
+
+    /**
+     * the metadata of a magnet link, as fetched from peers
+     */
+    pub fn from_info(info: &[u8], trackers: Vec<String>) -> Result<Self, String> {
+        let dict = bencode::decode(info)?;
+        let name = dict
+            .get("name.utf-8")
+            .or_else(|| dict.get("name"))
+            .and_then(Value::str)
+            .ok_or("the torrent has no name")?;
+        let name = safe_component(name)?;
+
+        let piece_length = dict
+            .get("piece length")
+            .and_then(Value::int)
+            .and_then(|length| usize::try_from(length).ok())
+            .filter(|length| *length > 0)
+            .ok_or("the torrent has no piece length")?;
+        let pieces = dict
+            .get("pieces")
+            .and_then(Value::bytes)
+            .filter(|pieces| pieces.len() % 20 == 0)
+            .ok_or(
+                "the torrent lists no piece hashes, BitTorrent v2 only torrents are not supported",
+            )?
+            .chunks(20)
+            .map(|hash| hash.try_into().unwrap_or_default())
+            .collect::<Vec<InfoHash>>();
+
+        let mut files = vec![];
+        let mut offset = 0;
+        let multi_file = dict.get("files").is_some();
+        if let Some(list) = dict.get("files") {
+            for file in list.list().ok_or("the torrent's file list is invalid")? {
+                let length = file
+                    .get("length")
+                    .and_then(Value::int)
+                    .and_then(|length| usize::try_from(length).ok())
+                    .ok_or("a file of the torrent has no length")?;
+                let path = file
+                    .get("path.utf-8")
+                    .or_else(|| file.get("path"))
+                    .and_then(Value::list)
+                    .filter(|path| !path.is_empty())
+                    .ok_or("a file of the torrent has no path")?
+                    .iter()
+                    .map(|component| {
+                        component
+                            .str()
+                            .ok_or_else(|| "a file path of the torrent is not UTF-8".to_string())
+                            .and_then(safe_component)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                // padding files (BEP 47) hold zeros to align the next file, nobody wants them
+                let padding = file
+                    .get("attr")
+                    .and_then(Value::str)
+                    .is_some_and(|attr| attr.contains('p'));
+                if !padding {
+                    files.push(FileEntry {
+                        path,
+                        length,
+                        offset,
+                    });
+                }
+                offset += length;
+            }
+        } else {
+            let length = dict
+                .get("length")
+                .and_then(Value::int)
+                .and_then(|length| usize::try_from(length).ok())
+                .ok_or("the torrent has no length")?;
+            files.push(FileEntry {
+                path: vec![name.clone()],
+                length,
+                offset: 0,
+            });
+            offset = length;
+        }
+
+        if pieces.len() != offset.div_ceil(piece_length) {
+            return Err(format!(
+                "the torrent has {} piece hashes for {offset} bytes",
+                pieces.len()
+            ));
+        }
+
+        let mut unique = vec![];
+        trackers.into_iter().for_each(|tracker| {
+            if !unique.contains(&tracker) {
+                unique.push(tracker);
+            }
+        });
+
+        Ok(Self {
+            info_hash: sha1(info),
+            name,
+            piece_length,
+            pieces,
+            files,
+            length: offset,
+            multi_file,
+            private: dict.get("private").and_then(Value::int) == Some(1),
+            trackers: unique,
+            info: info.to_vec(),
+        })
+    }
+
+    pub fn piece_size(&self, piece: usize) -> usize {
+        let start = piece * self.piece_length;
+        self.piece_length.min(self.length - start)
+    }
+
+    /**
+     * the files, selected ones as listed by index, all of them when none are
+     */
+    pub fn selected(&self, files: &[usize]) -> Vec<bool> {
+        (0..self.files.len())
+            .map(|index| files.is_empty() || files.contains(&index))
+            .collect()
+    }
+}
+
+/**
+ * a file or folder name from the torrent, which may not leave the download folder
+ */
+fn safe_component(component: &str) -> Result<String, String> {
+    let unsafe_name = component.is_empty()
+        || component == "."
+        || component == ".."
+        || component
+            .chars()
+            .any(|c| matches!(c, '/' | '\\' | '\0') || (cfg!(windows) && c == ':'));
+    if unsafe_name {
+        return Err(format!("the torrent has an unsafe file name `{component}`"));
+    }
+    Ok(component.to_string())
+}
+
+/**
+ * what a `magnet:` link says about a torrent, the rest comes from peers
+ */
+#[derive(Debug, Clone)]
+pub struct Magnet {
+    pub info_hash: InfoHash,
+    pub name: Option<String>,
+    pub trackers: Vec<String>,
+    pub peers: Vec<SocketAddr>, // `x.pe`, peers to try right away
+}
+
+impl Magnet {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let parsed = Url::parse(url).map_err(|_| "invalid magnet link".to_string())?;
+        if parsed.scheme() != "magnet" {
+            return Err("not a magnet link".to_string());
+        }
+
+        let mut magnet = Self {
+            info_hash: [0; 20],
+            name: None,
+            trackers: vec![],
+            peers: vec![],
+        };
+        let mut found = false;
+        for (key, value) in parsed.query_pairs() {
+            match &key[..] {
+                "xt" => {
+                    if let Some(hash) = value.strip_prefix("urn:btih:").and_then(parse_info_hash) {
+                        magnet.info_hash = hash;
+                        found = true;
+                    }
+                }
+                "dn" => magnet.name = safe_component(&value).ok(),
+                "tr" => magnet.trackers.push(value.to_string()),
+                "x.pe" => magnet.peers.extend(value.parse::<SocketAddr>().ok()),
+                _ => {}
+            }
+        }
+
+        if !found {
+            return Err(
+                "the magnet link has no BitTorrent info hash (xt=urn:btih:...)".to_string(),
+            );
+        }
+        Ok(magnet)
+    }
+}
+
+/**
+ * 40 hex digits, or 32 base32 characters as older magnet links have them
+ */
+fn parse_info_hash(value: &str) -> Option<InfoHash> {
+    let bytes = match value.len() {
+        40 => (0..40)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?,
+        32 => {
+            let mut bits = 0u64;
+            let mut count = 0;
+            let mut bytes = vec![];
+            for c in value.to_ascii_uppercase().chars() {
+                let digit = match c {
+                    'A'..='Z' => c as u64 - 'A' as u64,
+                    '2'..='7' => c as u64 - '2' as u64 + 26,
+                    _ => return None,
+                };
+                bits = (bits << 5) | digit;
+                count += 5;
+                if count >= 8 {
+                    count -= 8;
+                    bytes.push((bits >> count) as u8);
+                }
+            }
+            bytes
+        }
+        _ => return None,
+    };
+    bytes.try_into().ok()
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::torrent::bencode::dict;
+
+    fn file(length: i64, path: &[&str]) -> Value {
+        dict([
+            ("length", Value::Int(length)),
+            (
+                "path",
+                Value::List(
+                    path.iter()
+                        .map(|component| Value::Bytes(component.as_bytes().to_vec()))
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+
+    fn info(name: &str, files: Vec<Value>, pieces: usize) -> Vec<u8> {
+        dict([
+            ("name", Value::Bytes(name.as_bytes().to_vec())),
+            ("piece length", Value::Int(16)),
+            ("pieces", Value::Bytes(vec![7; 20 * pieces])),
+            ("files", Value::List(files)),
+        ])
+        .encode()
+    }
+
+    fn torrent(info: &[u8]) -> Vec<u8> {
+        [
+            &b"d8:announce14:http://tracker13:announce-listll14:http://tracker"[..],
+            b"el14:udp://tracker2ee4:info",
+            info,
+            b"e",
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn multi_file_torrent() {
+        let info = info(
+            "folder",
+            vec![
+                file(20, &["a.bin"]),
+                file(0, &["empty"]),
+                file(5, &["sub", "b.bin"]),
+            ],
+            2,
+        );
+        let meta = Metainfo::from_torrent(&torrent(&info)).unwrap();
+
+        assert_eq!(meta.info_hash, sha1(&info));
+        assert_eq!(meta.info, info);
+        assert_eq!(meta.name, "folder");
+        assert!(meta.multi_file);
+        assert_eq!(meta.length, 25);
+        assert_eq!(meta.pieces.len(), 2);
+        assert_eq!((meta.piece_size(0), meta.piece_size(1)), (16, 9));
+        let files: Vec<_> = meta
+            .files
+            .iter()
+            .map(|file| (file.path.join("/"), file.length, file.offset))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("a.bin".to_string(), 20, 0),
+                ("empty".to_string(), 0, 20),
+                ("sub/b.bin".to_string(), 5, 20)
+            ]
+        );
+        // the announce-list comes first, without the repeated tracker
+        assert_eq!(meta.trackers, ["http://tracker", "udp://tracker2"]);
+        assert_eq!(meta.selected(&[]), [true, true, true]);
+        assert_eq!(meta.selected(&[0, 2]), [true, false, true]);
+    }
+
+    #[test]
+    fn single_file_torrent() {
+        let info = dict([
+            ("name", Value::Bytes(b"file.iso".to_vec())),
+            ("piece length", Value::Int(16)),
+            ("pieces", Value::Bytes(vec![0; 20])),
+            ("length", Value::Int(16)),
+            ("private", Value::Int(1)),
+        ])
+        .encode();
+        let meta = Metainfo::from_info(&info, vec![]).unwrap();
+
+        assert!(!meta.multi_file);
+        assert!(meta.private);
+        assert_eq!(meta.files.len(), 1);
+        assert_eq!(meta.files[0].path, ["file.iso"]);
+        assert_eq!(meta.files[0].length, 16);
+    }
+
+    #[test]
+    fn padding_files_are_skipped() {
+        let padding = match file(12, &[".pad", "12"]) {
+            Value::Dict(mut entries) => {
+                entries.insert(b"attr".to_vec(), Value::Bytes(b"p".to_vec()));
+                Value::Dict(entries)
+            }
+            _ => unreachable!(),
+        };
+        let info = info("folder", vec![file(4, &["a"]), padding, file(3, &["b"])], 2);
+        let meta = Metainfo::from_info(&info, vec![]).unwrap();
+
+        assert_eq!(meta.length, 19);
+        let files: Vec<_> = meta
+            .files
+            .iter()
+            .map(|file| (file.path.join("/"), file.offset))
+            .collect();
+        assert_eq!(files, [("a".to_string(), 0), ("b".to_string(), 16)]);
+    }
+
+    #[test]
+    fn unsafe_names_are_rejected() {
+        for path in [
+            &[".."][..],
+            &["sub", ".."],
+            &["a/b"],
+            &["a\\b"],
+            &[""],
+            &["."],
+        ] {
+            let info = info("folder", vec![file(1, path)], 1);
+            assert!(Metainfo::from_info(&info, vec![]).is_err(), "{path:?}");
+        }
+        assert!(Metainfo::from_info(&info("..", vec![file(1, &["a"])], 1), vec![]).is_err());
+        assert!(Metainfo::from_info(&info("folder", vec![file(1, &[])], 1), vec![]).is_err());
+    }
+
+    #[test]
+    fn piece_count_must_match_the_length() {
+        assert!(Metainfo::from_info(&info("folder", vec![file(17, &["a"])], 1), vec![]).is_err());
+        assert!(Metainfo::from_info(&info("folder", vec![file(16, &["a"])], 2), vec![]).is_err());
+        assert!(Metainfo::from_info(&info("folder", vec![file(16, &["a"])], 1), vec![]).is_ok());
+    }
+
+    #[test]
+    fn magnet_links() {
+        let magnet = Magnet::parse(
+            "magnet:?xt=urn:btih:0123456789ABCDEF0123456789abcdef01234567&dn=Some%20Name\
+             &tr=http%3A%2F%2Ftracker%2Fannounce&tr=udp%3A%2F%2Ftracker%3A80\
+             &x.pe=127.0.0.1%3A6881&x.pe=not-an-address",
+        )
+        .unwrap();
+        assert_eq!(
+            hex(&magnet.info_hash),
+            "0123456789abcdef0123456789abcdef01234567"
+        );
+        assert_eq!(magnet.name.as_deref(), Some("Some Name"));
+        assert_eq!(
+            magnet.trackers,
+            ["http://tracker/announce", "udp://tracker:80"]
+        );
+        assert_eq!(
+            magnet.peers,
+            ["127.0.0.1:6881".parse::<SocketAddr>().unwrap()]
+        );
+
+        // base32, as older links have it
+        let base32 = Magnet::parse("magnet:?xt=urn:btih:AERUKZ4JVPG66AJDIVTYTK6N54ASGRLH").unwrap();
+        assert_eq!(base32.info_hash, magnet.info_hash);
+        assert_eq!(base32.name, None);
+
+        let unsafe_name =
+            Magnet::parse("magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef01234567&dn=..")
+                .unwrap();
+        assert_eq!(unsafe_name.name, None);
+    }
+
+    #[test]
+    fn invalid_magnet_links() {
+        for url in [
+            "magnet:?dn=name",
+            "magnet:?xt=urn:btih:0123",
+            "magnet:?xt=urn:btih:0123456789abcdef0123456789abcdef0123456g",
+            "magnet:?xt=urn:sha1:0123456789abcdef0123456789abcdef01234567",
+            "http://example.com/?xt=urn:btih:0123456789abcdef0123456789abcdef01234567",
+        ] {
+            assert!(Magnet::parse(url).is_err(), "{url}");
+        }
+    }
+}
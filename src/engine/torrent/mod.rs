@@ -0,0 +1,190 @@
+mod bencode;
+mod metainfo;
+mod peer;
+mod storage;
+mod swarm;
+mod tracker;
+use super::segments;
+use crate::utils::redact::redact_url;
+use metainfo::{Magnet, Metainfo};
+use reqwest::{Client, Url};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+pub use swarm::{Progress, TorrentTransfer};
+
+pub const TORRENT_EXTENSION: &str = "torrent";
+// torrent files are small, anything bigger is not one
+const MAX_TORRENT_LEN: usize = 10 * 1024 * 1024;
+// how long the add-download form waits for the metadata of a magnet link
+const FILES_TIMEOUT: Duration = Duration::from_secs(60);
+
+/**
+ * how a torrent is downloaded and seeded, from the download and the settings
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct TorrentOptions {
+    pub files: Vec<usize>, // indexes of the files to download, none for all of them
+    pub port: u16,         // for incoming peers, another one is taken when it is in use
+    pub seed_ratio: f64,   // uploaded bytes per downloaded byte to seed, 0 = no seeding
+    pub seed_time: Duration, // longest time to seed, 0 = until the ratio is reached
+    pub dht: bool,
+}
+
+impl Default for TorrentOptions {
+    fn default() -> Self {
+        Self {
+            files: vec![],
+            port: 6881,
+            seed_ratio: 1.0,
+            seed_time: Duration::from_secs(60 * 60),
+            dht: true,
+        }
+    }
+}
+
+/**
+ * a file in a torrent, as the file picker and the metadata pane show it
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorrentFile {
+    pub path: String, // below the torrent's folder, `/` separated
+    pub length: usize,
+}
+
+pub fn is_magnet_url(url: &str) -> bool {
+    url.get(..7)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("magnet:"))
+}
+
+/**
+ * magnet links, and torrent files on the web or on disk
+ */
+pub fn is_torrent_url(url: &str) -> bool {
+    is_magnet_url(url)
+        || Url::parse(url).is_ok_and(|url| {
+            matches!(url.scheme(), "http" | "https" | "file")
+                && url.path().to_lowercase().ends_with(".torrent")
+        })
+}
+
+pub fn is_torrent_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(TORRENT_EXTENSION))
+}
+
+/**
+ * a torrent file on disk as the URL a download keeps
+ */
+pub fn torrent_file_url(path: &Path) -> Option<String> {
+    std::path::absolute(path)
+        .ok()
+        .and_then(|path| Url::from_file_path(path).ok())
+        .map(String::from)
+}
+
+/**
+ * the name a magnet link is saved under until its metadata is in
+ */
+pub fn magnet_name(url: &str) -> Option<String> {
+    let magnet = Magnet::parse(url).ok()?;
+    Some(
+        magnet
+            .name
+            .unwrap_or_else(|| metainfo::hex(&magnet.info_hash)),
+    )
+}
+
+enum Source {
+    Torrent(Metainfo),
+    Magnet(Magnet),
+}
+
+async fn load(
+    client: &Client,
+    url: &str,
+    headers: &HashMap<String, String>,
+) -> Result<Source, String> {
+    if is_magnet_url(url) {
+        return Magnet::parse(url).map(Source::Magnet);
+    }
+
+    let parsed = Url::parse(url).map_err(|_| "invalid torrent URL".to_string())?;
+    let data = if parsed.scheme() == "file" {
+        let path = parsed
+            .to_file_path()
+            .map_err(|_| "invalid torrent file path".to_string())?;
+        let length = tokio::fs::metadata(&path)
+            .await
+            .map_err(|error| format!("can't read {}: {error}", path.display()))?
+            .len() as usize;
+        if length > MAX_TORRENT_LEN {
+            return Err(format!("{} is too big for a torrent file", path.display()));
+        }
+        tokio::fs::read(&path)
+            .await
+            .map_err(|error| format!("can't read {}: {error}", path.display()))?
+    } else {
+        let failed = |error: reqwest::Error| {
+            format!(
+                "loading {} failed: {:?}",
+                redact_url(url),
+                error.without_url()
+            )
+        };
+        let mut response = segments::get(client, url, headers)
+            .send()
+            .await
+            .map_err(failed)?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "The server responded with an {} status code for {}",
+                response.status(),
+                redact_url(url)
+            ));
+        }
+        let mut data = vec![];
+        while let Some(chunk) = response.chunk().await.map_err(failed)? {
+            data.extend(chunk);
+            if data.len() > MAX_TORRENT_LEN {
+                return Err(format!("{} is too big for a torrent file", redact_url(url)));
+            }
+        }
+        data
+    };
+    Metainfo::from_torrent(&data).map(Source::Torrent)
+}
+
+/**
+ * the files of a torrent to pick from, magnet links wait for their metadata from peers
+ */
+pub async fn files(
+    client: Client,
+    url: String,
+    headers: HashMap<String, String>,
+    cache_dir: PathBuf,
+    options: TorrentOptions,
+) -> Result<Vec<TorrentFile>, String> {
+    let meta = match load(&client, &url, &headers).await? {
+        Source::Torrent(meta) => meta,
+        Source::Magnet(magnet) => tokio::time::timeout(
+            FILES_TIMEOUT,
+            swarm::magnet_metadata(&client, &magnet, &cache_dir, &options),
+        )
+        .await
+        .map_err(|_| "no peer sent the torrent's metadata".to_string())??,
+    };
+    Ok(torrent_files(&meta))
+}
+
+fn torrent_files(meta: &Metainfo) -> Vec<TorrentFile> {
+    meta.files
+        .iter()
+        .map(|file| TorrentFile {
+            path: file.path.join("/"),
+            length: file.length,
+        })
+        .collect()
+}
@@ -0,0 +1,900 @@
+use super::{
+    bencode::{self, dict, Value},
+    metainfo::InfoHash,
+    swarm::{Context, PeerEvent},
+};
+use openssl::sha::sha1;
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::{broadcast::error::RecvError, mpsc},
+    task::JoinSet,
+};
+use tracing::debug;
+
+pub const BLOCK_LEN: usize = 16 * 1024;
+const PROTOCOL: &[u8; 19] = b"BitTorrent protocol";
+// a piece message with its header, bitfields of huge torrents and metadata pieces fit as well
+const MAX_MESSAGE_LEN: usize = 2 * 1024 * 1024;
+const MAX_METADATA_LEN: usize = 16 * 1024 * 1024;
+const METADATA_PIECE_LEN: usize = 16 * 1024;
+const UT_METADATA: u8 = 1; // the id peers send our metadata messages with
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(20);
+const METADATA_TIMEOUT: Duration = Duration::from_secs(60);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(60);
+// peers sending nothing for this long are gone, keep-alives go out well before
+const IDLE_TIMEOUT: Duration = Duration::from_secs(3 * 60);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(90);
+// a peer with requests in flight and no block for this long is dropped
+const SNUB_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_REQUESTS: usize = 32;
+const MAX_HASH_FAILURES: usize = 3;
+pub const MAX_UPLOADS: usize = 8;
+
+#[derive(Debug, PartialEq)]
+enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have(usize),
+    Bitfield(Vec<u8>),
+    Request(usize, usize, usize), // piece, offset and length
+    Piece(usize, usize, Vec<u8>),
+    Cancel(usize, usize, usize),
+    Extended(u8, Vec<u8>),
+    Other, // fast extension, DHT port and whatever else ATOM doesn't speak
+}
+
+impl Message {
+    fn decode(payload: &[u8]) -> Result<Self, String> {
+        let Some((&id, body)) = payload.split_first() else {
+            return Ok(Message::KeepAlive);
+        };
+        let int = |at: usize| -> Result<usize, String> {
+            body.get(at..at + 4)
+                .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+                .ok_or_else(|| format!("message {id} is too short"))
+        };
+        Ok(match id {
+            0 => Message::Choke,
+            1 => Message::Unchoke,
+            2 => Message::Interested,
+            3 => Message::NotInterested,
+            4 => Message::Have(int(0)?),
+            5 => Message::Bitfield(body.to_vec()),
+            6 => Message::Request(int(0)?, int(4)?, int(8)?),
+            7 => Message::Piece(int(0)?, int(4)?, body.get(8..).unwrap_or_default().to_vec()),
+            8 => Message::Cancel(int(0)?, int(4)?, int(8)?),
+            20 => match body.split_first() {
+                Some((&extension, data)) => Message::Extended(extension, data.to_vec()),
+                None => return Err("empty extension message".to_string()),
+            },
+            _ => Message::Other,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let ints = |id: u8, ints: &[usize]| {
+            let mut out = vec![id];
+            ints.iter()
+                .for_each(|int| out.extend((*int as u32).to_be_bytes()));
+            out
+        };
+        let payload = match self {
+            Message::KeepAlive | Message::Other => vec![],
+            Message::Choke => vec![0],
+            Message::Unchoke => vec![1],
+            Message::Interested => vec![2],
+            Message::NotInterested => vec![3],
+            Message::Have(piece) => ints(4, &[*piece]),
+            Message::Bitfield(bits) => [&[5], &bits[..]].concat(),
+            Message::Request(piece, offset, length) => ints(6, &[*piece, *offset, *length]),
+            Message::Piece(piece, offset, data) => {
+                [&ints(7, &[*piece, *offset])[..], data].concat()
+            }
+            Message::Cancel(piece, offset, length) => ints(8, &[*piece, *offset, *length]),
+            Message::Extended(extension, data) => [&[20, *extension], &data[..]].concat(),
+        };
+        [&(payload.len() as u32).to_be_bytes()[..], &payload].concat()
+    }
+}
+
+/**
+ * a peer after the handshake, messages are read by a task of their own so waiting on them
+ * can be cancelled
+ */
+struct Connection {
+    address: SocketAddr,
+    writer: BufWriter<OwnedWriteHalf>,
+    messages: mpsc::Receiver<Result<Message, String>>,
+    extensions: bool, // the peer speaks the extension protocol (BEP 10)
+    _reader: JoinSet<()>,
+}
+
+impl Connection {
+    async fn connect(
+        address: SocketAddr,
+        info_hash: &InfoHash,
+        peer_id: &InfoHash,
+    ) -> Result<Self, String> {
+        let stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address))
+            .await
+            .map_err(|_| "connection timed out".to_string())?
+            .map_err(|error| error.to_string())?;
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+            let (mut reader, mut writer) = stream.into_split();
+            writer
+                .write_all(&handshake(info_hash, peer_id))
+                .await
+                .map_err(|error| error.to_string())?;
+            let reserved = read_handshake(&mut reader, info_hash, peer_id).await?;
+            Ok(Self::new(address, reader, writer, reserved))
+        })
+        .await
+        .map_err(|_| "handshake timed out".to_string())?
+    }
+
+    /**
+     * a peer that connected to us, it speaks first
+     */
+    async fn accept(
+        stream: TcpStream,
+        info_hash: &InfoHash,
+        peer_id: &InfoHash,
+    ) -> Result<Self, String> {
+        let address = stream.peer_addr().map_err(|error| error.to_string())?;
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+            let (mut reader, mut writer) = stream.into_split();
+            let reserved = read_handshake(&mut reader, info_hash, peer_id).await?;
+            writer
+                .write_all(&handshake(info_hash, peer_id))
+                .await
+                .map_err(|error| error.to_string())?;
+            Ok(Self::new(address, reader, writer, reserved))
+        })
+        .await
+        .map_err(|_| "handshake timed out".to_string())?
+    }
+
+    fn new(
+        address: SocketAddr,
+        reader: OwnedReadHalf,
+        writer: OwnedWriteHalf,
+        reserved: [u8; 8],
+    ) -> Self {
+        let (sender, messages) = mpsc::channel(64);
+        let mut tasks = JoinSet::new();
+        tasks.spawn(async move {
+            let mut reader = BufReader::new(reader);
+            loop {
+                let message = read_message(&mut reader).await;
+                let failed = message.is_err();
+                if sender.send(message).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Self {
+            address,
+            writer: BufWriter::new(writer),
+            messages,
+            extensions: reserved[5] & 0x10 != 0,
+            _reader: tasks,
+        }
+    }
+
+    async fn send(&mut self, messages: &[Message]) -> Result<(), String> {
+        tokio::time::timeout(WRITE_TIMEOUT, async {
+            for message in messages {
+                self.writer.write_all(&message.encode()).await?;
+            }
+            self.writer.flush().await
+        })
+        .await
+        .map_err(|_| "the peer stopped reading".to_string())?
+        .map_err(|error| error.to_string())
+    }
+
+    async fn receive(&mut self) -> Result<Message, String> {
+        self.messages
+            .recv()
+            .await
+            .unwrap_or_else(|| Err("connection closed".to_string()))
+    }
+
+    /**
+     * the extension handshake, announces ut_metadata (BEP 9) and the metadata size once known
+     */
+    async fn send_extensions(
+        &mut self,
+        metadata_len: Option<usize>,
+        port: u16,
+    ) -> Result<(), String> {
+        if !self.extensions {
+            return Ok(());
+        }
+        let mut handshake = dict([
+            ("m", dict([("ut_metadata", Value::Int(UT_METADATA as i64))])),
+            ("p", Value::Int(port as i64)),
+            (
+                "v",
+                Value::Bytes(format!("ATOM {}", env!("CARGO_PKG_VERSION")).into_bytes()),
+            ),
+        ]);
+        if let (Some(length), Value::Dict(entries)) = (metadata_len, &mut handshake) {
+            entries.insert(b"metadata_size".to_vec(), Value::Int(length as i64));
+        }
+        self.send(&[Message::Extended(0, handshake.encode())]).await
+    }
+}
+
+fn handshake(info_hash: &InfoHash, peer_id: &InfoHash) -> Vec<u8> {
+    let mut reserved = [0u8; 8];
+    reserved[5] |= 0x10;
+    [&[19], &PROTOCOL[..], &reserved, info_hash, peer_id].concat()
+}
+
+async fn read_handshake(
+    reader: &mut OwnedReadHalf,
+    info_hash: &InfoHash,
+    peer_id: &InfoHash,
+) -> Result<[u8; 8], String> {
+    let mut handshake = [0u8; 68];
+    reader
+        .read_exact(&mut handshake)
+        .await
+        .map_err(|error| error.to_string())?;
+    if handshake[0] != 19 || &handshake[1..20] != PROTOCOL {
+        return Err("not a BitTorrent peer".to_string());
+    }
+    if &handshake[28..48] != info_hash {
+        return Err("the peer has another torrent".to_string());
+    }
+    if &handshake[48..68] == peer_id {
+        return Err("connected to ourselves".to_string());
+    }
+    Ok(handshake[20..28].try_into().unwrap_or_default())
+}
+
+async fn read_message(reader: &mut BufReader<OwnedReadHalf>) -> Result<Message, String> {
+    let length = reader.read_u32().await.map_err(|error| error.to_string())? as usize;
+    if length > MAX_MESSAGE_LEN {
+        return Err(format!("the peer sent a message of {length} bytes"));
+    }
+    let mut payload = vec![0; length];
+    reader
+        .read_exact(&mut payload)
+        .await
+        .map_err(|error| error.to_string())?;
+    Message::decode(&payload)
+}
+
+/**
+ * a ut_metadata message, the dictionary and the piece of metadata after it
+ */
+fn metadata_message(data: &[u8]) -> Result<(Value, &[u8]), String> {
+    let (message, length) = bencode::decode_prefix(data)?;
+    Ok((message, &data[length..]))
+}
+
+/**
+ * downloads the info dictionary of a magnet link from one peer (BEP 9)
+ */
+pub async fn fetch_metadata(
+    address: SocketAddr,
+    info_hash: InfoHash,
+    peer_id: InfoHash,
+    port: u16,
+) -> Result<Vec<u8>, String> {
+    let mut connection = Connection::connect(address, &info_hash, &peer_id).await?;
+    if !connection.extensions {
+        return Err("the peer can't send metadata".to_string());
+    }
+    connection.send_extensions(None, port).await?;
+
+    tokio::time::timeout(METADATA_TIMEOUT, async {
+        let mut metadata = vec![];
+        let mut received = vec![];
+        loop {
+            let Message::Extended(extension, data) = connection.receive().await? else {
+                continue;
+            };
+            if extension == 0 {
+                let handshake = bencode::decode(&data)?;
+                let id = handshake
+                    .get("m")
+                    .and_then(|m| m.get("ut_metadata"))
+                    .and_then(Value::int)
+                    .and_then(|id| u8::try_from(id).ok())
+                    .filter(|id| *id != 0)
+                    .ok_or("the peer can't send metadata")?;
+                let length = handshake
+                    .get("metadata_size")
+                    .and_then(Value::int)
+                    .and_then(|length| usize::try_from(length).ok())
+                    .filter(|length| (1..=MAX_METADATA_LEN).contains(length))
+                    .ok_or("the peer has no metadata")?;
+                metadata = vec![0; length];
+                received = vec![false; length.div_ceil(METADATA_PIECE_LEN)];
+                let requests: Vec<_> = (0..received.len())
+                    .map(|piece| {
+                        let request = dict([
+                            ("msg_type", Value::Int(0)),
+                            ("piece", Value::Int(piece as i64)),
+                        ]);
+                        Message::Extended(id, request.encode())
+                    })
+                    .collect();
+                connection.send(&requests).await?;
+            } else if extension == UT_METADATA {
+                let (message, piece_data) = metadata_message(&data)?;
+                if message.get("msg_type").and_then(Value::int) == Some(2) {
+                    return Err("the peer refused to send metadata".to_string());
+                }
+                let piece = message
+                    .get("piece")
+                    .and_then(Value::int)
+                    .and_then(|piece| usize::try_from(piece).ok())
+                    .filter(|piece| *piece < received.len())
+                    .ok_or("the peer sent an unknown metadata piece")?;
+                let start = piece * METADATA_PIECE_LEN;
+                let end = (start + METADATA_PIECE_LEN).min(metadata.len());
+                if piece_data.len() != end - start {
+                    return Err("the peer sent a metadata piece of the wrong size".to_string());
+                }
+                metadata[start..end].copy_from_slice(piece_data);
+                received[piece] = true;
+                if received.iter().all(|received| *received) {
+                    if sha1(&metadata) != info_hash {
+                        return Err("the peer sent metadata of another torrent".to_string());
+                    }
+                    return Ok(metadata);
+                }
+            }
+        }
+    })
+    .await
+    .map_err(|_| "the peer didn't send the metadata".to_string())?
+}
+
+/**
+ * a piece being downloaded from this peer
+ */
+struct ActivePiece {
+    index: usize,
+    data: Vec<u8>,
+    requested: usize, // offset of the next block to ask for
+    received: usize,
+}
+
+/**
+ * one peer exchanging pieces, ends with an error or once neither side needs the other
+ */
+pub struct Session {
+    context: Arc<Context>,
+    connection: Connection,
+    bitfield: Vec<bool>,
+    choked: bool,          // the peer doesn't send us blocks
+    interested: bool,      // we told the peer it has pieces we want
+    uploading: bool,       // we unchoked the peer
+    peer_interested: bool, // the peer wants pieces from us
+    metadata_id: Option<u8>,
+    active: Vec<ActivePiece>,
+    requests: Vec<(usize, usize, usize)>, // piece, offset and length in flight
+    last_block: Instant,
+    last_sent: Instant,
+    hash_failures: usize,
+    pieces_changed: bool, // either side got pieces, interest is looked at again
+}
+
+impl Session {
+    pub async fn connect(context: Arc<Context>, address: SocketAddr) -> Result<Self, String> {
+        let connection =
+            Connection::connect(address, &context.meta.info_hash, &context.peer_id).await?;
+        Ok(Self::new(context, connection))
+    }
+
+    pub async fn accept(context: Arc<Context>, stream: TcpStream) -> Result<Self, String> {
+        let connection =
+            Connection::accept(stream, &context.meta.info_hash, &context.peer_id).await?;
+        Ok(Self::new(context, connection))
+    }
+
+    fn new(context: Arc<Context>, connection: Connection) -> Self {
+        let pieces = context.meta.pieces.len();
+        Self {
+            context,
+            connection,
+            bitfield: vec![false; pieces],
+            choked: true,
+            interested: false,
+            uploading: false,
+            peer_interested: false,
+            metadata_id: None,
+            active: vec![],
+            requests: vec![],
+            last_block: Instant::now(),
+            last_sent: Instant::now(),
+            hash_failures: 0,
+            pieces_changed: true,
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.connection.address
+    }
+
+    pub async fn run(mut self) -> Result<(), String> {
+        let mut haves = self.context.haves.subscribe();
+        let (metadata_len, port) = (self.context.meta.info.len(), self.context.port);
+        self.connection
+            .send_extensions(Some(metadata_len), port)
+            .await?;
+        let bitfield = self.context.swarm().bitfield();
+        if bitfield.iter().any(|byte| *byte != 0) {
+            self.send(vec![Message::Bitfield(bitfield)]).await?;
+        }
+        self.context.swarm().peers.insert(self.address(), false);
+
+        let mut tick = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            tokio::select! {
+                message = tokio::time::timeout(IDLE_TIMEOUT, self.connection.receive()) => {
+                    let message = message.map_err(|_| "the peer went quiet".to_string())??;
+                    self.handle(message).await?;
+                }
+                have = haves.recv() => match have {
+                    Ok(piece) => self.completed_elsewhere(piece).await?,
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                _ = tick.tick() => {
+                    if !self.requests.is_empty() && self.last_block.elapsed() > SNUB_TIMEOUT {
+                        return Err("the peer stopped sending blocks".to_string());
+                    }
+                    if self.last_sent.elapsed() > KEEPALIVE_INTERVAL {
+                        self.send(vec![Message::KeepAlive]).await?;
+                    }
+                    // a finished torrent has nothing to trade with peers that have it all
+                    self.pieces_changed = true;
+                    let complete = self.context.swarm().is_complete();
+                    if complete && self.bitfield.iter().all(|have| *have) {
+                        return Ok(());
+                    }
+                }
+            }
+            self.update_interest().await?;
+            self.request_blocks().await?;
+        }
+    }
+
+    async fn send(&mut self, messages: Vec<Message>) -> Result<(), String> {
+        self.last_sent = Instant::now();
+        self.connection.send(&messages).await
+    }
+
+    async fn handle(&mut self, message: Message) -> Result<(), String> {
+        match message {
+            Message::Choke => {
+                // the peer drops our requests, the pieces go back to the swarm
+                self.choked = true;
+                self.release();
+            }
+            Message::Unchoke => {
+                self.choked = false;
+                self.last_block = Instant::now();
+            }
+            Message::Interested => {
+                self.peer_interested = true;
+                let unchoke = !self.uploading && self.context.swarm().take_upload_slot();
+                if unchoke {
+                    self.uploading = true;
+                    self.send(vec![Message::Unchoke]).await?;
+                }
+            }
+            Message::NotInterested => {
+                self.peer_interested = false;
+                if self.uploading {
+                    self.uploading = false;
+                    self.context.swarm().uploads -= 1;
+                    self.send(vec![Message::Choke]).await?;
+                }
+            }
+            Message::Have(piece) if piece < self.bitfield.len() && !self.bitfield[piece] => {
+                self.bitfield[piece] = true;
+                self.pieces_changed = true;
+                self.context.swarm().peer_has(&[piece]);
+            }
+            Message::Bitfield(bits) => {
+                let pieces: Vec<usize> = (0..self.bitfield.len())
+                    .filter(|piece| {
+                        bits.get(piece / 8)
+                            .is_some_and(|byte| byte & (0x80 >> (piece % 8)) != 0)
+                            && !self.bitfield[*piece]
+                    })
+                    .collect();
+                pieces.iter().for_each(|piece| self.bitfield[*piece] = true);
+                self.pieces_changed = true;
+                self.context.swarm().peer_has(&pieces);
+            }
+            Message::Request(piece, offset, length) => self.upload(piece, offset, length).await?,
+            Message::Piece(piece, offset, data) => self.received(piece, offset, data).await?,
+            Message::Extended(0, data) => {
+                self.metadata_id = bencode::decode(&data)
+                    .ok()
+                    .and_then(|handshake| {
+                        handshake
+                            .get("m")
+                            .and_then(|m| m.get("ut_metadata"))
+                            .and_then(Value::int)
+                    })
+                    .and_then(|id| u8::try_from(id).ok())
+                    .filter(|id| *id != 0);
+            }
+            Message::Extended(UT_METADATA, data) => self.send_metadata(&data).await?,
+            _ => {}
+        }
+
+        let seed = self.bitfield.iter().all(|have| *have);
+        if let Some(entry) = self.context.swarm().peers.get_mut(&self.connection.address) {
+            *entry = seed;
+        }
+        Ok(())
+    }
+
+    async fn update_interest(&mut self) -> Result<(), String> {
+        if !std::mem::take(&mut self.pieces_changed) {
+            return Ok(());
+        }
+        let interested = self.context.swarm().wants_from(&self.bitfield);
+        if interested != self.interested {
+            self.interested = interested;
+            let message = match interested {
+                true => Message::Interested,
+                false => Message::NotInterested,
+            };
+            self.send(vec![message]).await?;
+        }
+        Ok(())
+    }
+
+    /**
+     * keeps up to `MAX_REQUESTS` blocks in flight, starting new pieces as the active ones run
+     * out of blocks to ask for
+     */
+    async fn request_blocks(&mut self) -> Result<(), String> {
+        if self.choked || !self.interested {
+            return Ok(());
+        }
+
+        let mut requests = vec![];
+        while self.requests.len() < MAX_REQUESTS {
+            let next = self
+                .active
+                .iter_mut()
+                .find(|piece| piece.requested < piece.data.len());
+            let piece = match next {
+                Some(piece) => piece,
+                None => {
+                    let active: Vec<usize> = self.active.iter().map(|piece| piece.index).collect();
+                    let Some(index) = self.context.swarm().pick(&self.bitfield, &active) else {
+                        break;
+                    };
+                    self.active.push(ActivePiece {
+                        index,
+                        data: vec![0; self.context.meta.piece_size(index)],
+                        requested: 0,
+                        received: 0,
+                    });
+                    self.active.last_mut().unwrap()
+                }
+            };
+            let length = BLOCK_LEN.min(piece.data.len() - piece.requested);
+            let request = (piece.index, piece.requested, length);
+            piece.requested += length;
+            self.requests.push(request);
+            requests.push(Message::Request(request.0, request.1, request.2));
+        }
+
+        if !requests.is_empty() {
+            if self.requests.len() == requests.len() {
+                self.last_block = Instant::now();
+            }
+            self.send(requests).await?;
+        }
+        Ok(())
+    }
+
+    async fn received(&mut self, piece: usize, offset: usize, data: Vec<u8>) -> Result<(), String> {
+        let request = (piece, offset, data.len());
+        let Some(position) = self.requests.iter().position(|sent| *sent == request) else {
+            return Ok(()); // cancelled, or never asked for
+        };
+        self.requests.remove(position);
+        self.last_block = Instant::now();
+        self.context
+            .events
+            .send(PeerEvent::Block(data.len()))
+            .await
+            .map_err(|_| "the torrent stopped".to_string())?;
+
+        let Some(index) = self.active.iter().position(|active| active.index == piece) else {
+            return Ok(());
+        };
+        let active = &mut self.active[index];
+        active.data[offset..offset + data.len()].copy_from_slice(&data);
+        active.received += data.len();
+        if active.received < active.data.len() {
+            return Ok(());
+        }
+
+        let active = self.active.remove(index);
+        let context = self.context.clone();
+        let saved = tokio::task::spawn_blocking(move || {
+            if sha1(&active.data) != context.meta.pieces[piece] {
+                return Ok(false);
+            }
+            context.storage.write(piece, &active.data).map(|_| true)
+        })
+        .await
+        .map_err(|error| error.to_string())?;
+
+        match saved {
+            Ok(true) => {
+                let bytes = self.context.swarm().completed(piece);
+                if let Some(bytes) = bytes {
+                    self.context.haves.send(piece).ok();
+                    self.context
+                        .events
+                        .send(PeerEvent::Piece(bytes))
+                        .await
+                        .map_err(|_| "the torrent stopped".to_string())?;
+                }
+                Ok(())
+            }
+            Ok(false) => {
+                self.context.swarm().release(piece);
+                self.hash_failures += 1;
+                debug!(peer = %self.address(), piece, "piece failed its hash check");
+                match self.hash_failures < MAX_HASH_FAILURES {
+                    true => Ok(()),
+                    false => Err("the peer keeps sending bad pieces".to_string()),
+                }
+            }
+            Err(error) => {
+                self.context.swarm().release(piece);
+                let error = format!("failed to save {}: {error}", self.context.meta.name);
+                self.context
+                    .events
+                    .send(PeerEvent::Error(error.clone()))
+                    .await
+                    .ok();
+                Err(error)
+            }
+        }
+    }
+
+    /**
+     * another peer delivered a piece first, requests for it from this one are cancelled
+     */
+    async fn completed_elsewhere(&mut self, piece: usize) -> Result<(), String> {
+        self.pieces_changed = true;
+        let mut messages = vec![];
+        if let Some(index) = self.active.iter().position(|active| active.index == piece) {
+            self.active.remove(index);
+            self.context.swarm().release(piece);
+            self.requests.retain(|request| {
+                if request.0 == piece {
+                    messages.push(Message::Cancel(request.0, request.1, request.2));
+                }
+                request.0 != piece
+            });
+        }
+        if !self.bitfield.get(piece).copied().unwrap_or(true) {
+            messages.push(Message::Have(piece));
+        }
+        match messages.is_empty() {
+            true => Ok(()),
+            false => self.send(messages).await,
+        }
+    }
+
+    async fn upload(&mut self, piece: usize, offset: usize, length: usize) -> Result<(), String> {
+        let servable = self.uploading
+            && piece < self.bitfield.len()
+            && length <= 2 * BLOCK_LEN
+            && offset + length <= self.context.meta.piece_size(piece)
+            && self.context.swarm().have[piece]
+            && self.context.storage.is_kept(piece);
+        if !servable {
+            return Ok(());
+        }
+
+        let context = self.context.clone();
+        let start = piece * self.context.meta.piece_length + offset;
+        let data = tokio::task::spawn_blocking(move || context.storage.read(start, length))
+            .await
+            .map_err(|error| error.to_string())?
+            .map_err(|error| format!("failed to read {}: {error}", self.context.meta.name))?;
+        self.send(vec![Message::Piece(piece, offset, data)]).await?;
+        self.context
+            .events
+            .send(PeerEvent::Uploaded(length))
+            .await
+            .map_err(|_| "the torrent stopped".to_string())
+    }
+
+    /**
+     * answers a metadata request from a peer that came through a magnet link
+     */
+    async fn send_metadata(&mut self, data: &[u8]) -> Result<(), String> {
+        let (Ok((request, _)), Some(id)) = (metadata_message(data), self.metadata_id) else {
+            return Ok(());
+        };
+        if request.get("msg_type").and_then(Value::int) != Some(0) {
+            return Ok(());
+        }
+        let Some(piece) = request.get("piece").and_then(Value::int) else {
+            return Ok(());
+        };
+
+        let info = &self.context.meta.info;
+        let start = usize::try_from(piece)
+            .unwrap_or(usize::MAX)
+            .saturating_mul(METADATA_PIECE_LEN);
+        let message = match start < info.len() {
+            true => {
+                let end = (start + METADATA_PIECE_LEN).min(info.len());
+                let header = dict([
+                    ("msg_type", Value::Int(1)),
+                    ("piece", Value::Int(piece)),
+                    ("total_size", Value::Int(info.len() as i64)),
+                ]);
+                [header.encode(), info[start..end].to_vec()].concat()
+            }
+            false => dict([("msg_type", Value::Int(2)), ("piece", Value::Int(piece))]).encode(),
+        };
+        self.send(vec![Message::Extended(id, message)]).await
+    }
+
+    /**
+     * hands the unfinished pieces back and forgets the peer's pieces, on choke or disconnect
+     */
+    fn release(&mut self) {
+        let mut swarm = self.context.swarm();
+        self.active
+            .drain(..)
+            .for_each(|active| swarm.release(active.index));
+        self.requests.clear();
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.release();
+        let mut swarm = self.context.swarm();
+        let pieces: Vec<usize> = (0..self.bitfield.len())
+            .filter(|piece| self.bitfield[*piece])
+            .collect();
+        swarm.peer_lost(&pieces);
+        swarm.peers.remove(&self.connection.address);
+        if self.uploading {
+            swarm.uploads -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const INFO_HASH: InfoHash = [1; 20];
+    const PEER_ID: InfoHash = [2; 20];
+
+    #[test]
+    fn handshake_layout() {
+        let handshake = handshake(&INFO_HASH, &PEER_ID);
+        assert_eq!(handshake.len(), 68);
+        assert_eq!(&handshake[..20], b"\x13BitTorrent protocol");
+        // only the extension protocol bit is set
+        assert_eq!(&handshake[20..28], &[0, 0, 0, 0, 0, 0x10, 0, 0]);
+        assert_eq!(&handshake[28..48], &INFO_HASH);
+        assert_eq!(&handshake[48..], &PEER_ID);
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        for message in [
+            Message::KeepAlive,
+            Message::Choke,
+            Message::Unchoke,
+            Message::Interested,
+            Message::NotInterested,
+            Message::Have(7),
+            Message::Bitfield(vec![0xf0, 0x80]),
+            Message::Request(1, BLOCK_LEN, BLOCK_LEN),
+            Message::Piece(1, BLOCK_LEN, vec![9; 100]),
+            Message::Cancel(1, 0, BLOCK_LEN),
+            Message::Extended(3, b"d1:ai1ee".to_vec()),
+        ] {
+            let encoded = message.encode();
+            let length = u32::from_be_bytes(encoded[..4].try_into().unwrap()) as usize;
+            assert_eq!(length, encoded.len() - 4);
+            assert_eq!(Message::decode(&encoded[4..]), Ok(message));
+        }
+        assert_eq!(
+            Message::Request(1, 2, 3).encode(),
+            [0, 0, 0, 13, 6, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]
+        );
+    }
+
+    #[test]
+    fn invalid_messages() {
+        assert!(Message::decode(&[4, 0, 0]).is_err());
+        assert!(Message::decode(&[6, 0, 0, 0, 1, 0, 0, 0, 2]).is_err());
+        assert!(Message::decode(&[20]).is_err());
+        assert_eq!(Message::decode(&[13, 1, 2]), Ok(Message::Other));
+    }
+
+    /**
+     * what `read_handshake` makes of `sent`, over a real connection as it reads a socket
+     */
+    async fn receive_handshake(sent: Vec<u8>) -> Result<[u8; 8], String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        stream.write_all(&sent).await.unwrap();
+        let (mut reader, _writer) = accepted.into_split();
+        read_handshake(&mut reader, &INFO_HASH, &PEER_ID).await
+    }
+
+    #[tokio::test]
+    async fn handshakes_are_checked() {
+        let other_peer = [3; 20];
+        assert_eq!(
+            receive_handshake(handshake(&INFO_HASH, &other_peer)).await,
+            Ok([0, 0, 0, 0, 0, 0x10, 0, 0])
+        );
+        assert!(receive_handshake(handshake(&[9; 20], &other_peer))
+            .await
+            .is_err());
+        assert!(receive_handshake(handshake(&INFO_HASH, &PEER_ID))
+            .await
+            .is_err());
+        let mut wrong_protocol = handshake(&INFO_HASH, &other_peer);
+        wrong_protocol[1] = b'b';
+        assert!(receive_handshake(wrong_protocol).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_are_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        let mut reader = BufReader::new(accepted.into_split().0);
+
+        stream.write_all(&Message::Have(5).encode()).await.unwrap();
+        assert_eq!(read_message(&mut reader).await, Ok(Message::Have(5)));
+        stream
+            .write_all(&(MAX_MESSAGE_LEN as u32 + 1).to_be_bytes())
+            .await
+            .unwrap();
+        assert!(read_message(&mut reader).await.is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use super::metainfo::Metainfo;
+use openssl::sha::sha1;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/**
+ * where the bytes of a torrent go on disk, only the selected files are written
+ */
+#[derive(Debug)]
+pub struct Storage {
+    meta: Arc<Metainfo>,
+    paths: Vec<PathBuf>,
+    selected: Vec<bool>,
+}
+
+impl Storage {
+    /**
+     * a single file torrent is saved as `root`, the files of a multi file torrent below it
+     */
+    pub fn new(meta: Arc<Metainfo>, root: &Path, selected: Vec<bool>) -> Self {
+        let paths = meta
+            .files
+            .iter()
+            .map(|file| match meta.multi_file {
+                true => file
+                    .path
+                    .iter()
+                    .fold(root.to_path_buf(), |path, component| path.join(component)),
+                false => root.to_path_buf(),
+            })
+            .collect();
+        Self {
+            meta,
+            paths,
+            selected,
+        }
+    }
+
+    /**
+     * the files overlapping `start..start + length`: file index, offset in the file and
+     * range in the buffer
+     */
+    fn spans(
+        &self,
+        start: usize,
+        length: usize,
+    ) -> impl Iterator<Item = (usize, usize, std::ops::Range<usize>)> + '_ {
+        let end = start + length;
+        self.meta
+            .files
+            .iter()
+            .enumerate()
+            .filter(move |(_, file)| file.offset < end && file.offset + file.length > start)
+            .map(move |(index, file)| {
+                let from = file.offset.max(start);
+                let to = (file.offset + file.length).min(end);
+                (index, from - file.offset, from - start..to - start)
+            })
+    }
+
+    fn piece_span(&self, piece: usize) -> (usize, usize) {
+        (piece * self.meta.piece_length, self.meta.piece_size(piece))
+    }
+
+    /**
+     * bytes of the piece that belong to selected files, what it adds to the progress
+     */
+    pub fn wanted_bytes(&self, piece: usize) -> usize {
+        let (start, length) = self.piece_span(piece);
+        self.spans(start, length)
+            .filter(|(file, ..)| self.selected[*file])
+            .map(|(_, _, range)| range.len())
+            .sum()
+    }
+
+    /**
+     * every byte of the piece is saved once it is written, pieces that reach into a file that
+     * wasn't picked are only kept in part and can't be served to peers
+     */
+    pub fn is_kept(&self, piece: usize) -> bool {
+        let (start, length) = self.piece_span(piece);
+        self.spans(start, length)
+            .all(|(file, ..)| self.selected[file])
+    }
+
+    pub fn write(&self, piece: usize, data: &[u8]) -> io::Result<()> {
+        let (start, length) = self.piece_span(piece);
+        for (file, offset, range) in self.spans(start, length) {
+            if !self.selected[file] {
+                continue;
+            }
+            let path = &self.paths[file];
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut handle = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(path)?;
+            handle.seek(SeekFrom::Start(offset as u64))?;
+            handle.write_all(&data[range])?;
+        }
+        Ok(())
+    }
+
+    /**
+     * reads bytes of the torrent, the gaps of padding files are zeros
+     */
+    pub fn read(&self, start: usize, length: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; length];
+        for (file, offset, range) in self.spans(start, length) {
+            if !self.selected[file] {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "file not picked"));
+            }
+            let mut handle = File::open(&self.paths[file])?;
+            handle.seek(SeekFrom::Start(offset as u64))?;
+            handle.read_exact(&mut data[range])?;
+        }
+        Ok(data)
+    }
+
+    /**
+     * whether the piece is on disk and matches its hash, for resuming and seeding
+     */
+    pub fn verify(&self, piece: usize) -> bool {
+        let (start, length) = self.piece_span(piece);
+        // a file that isn't there yet has nothing to check, sparse zeros would be read otherwise
+        let on_disk = self.spans(start, length).all(|(file, offset, range)| {
+            self.selected[file]
+                && fs::metadata(&self.paths[file])
+                    .is_ok_and(|metadata| metadata.len() as usize >= offset + range.len())
+        });
+        on_disk
+            && self
+                .read(start, length)
+                .is_ok_and(|data| sha1(&data) == self.meta.pieces[piece])
+    }
+
+    /**
+     * empty files have no pieces, they are created once the rest is done
+     */
+    pub fn create_empty_files(&self) -> io::Result<()> {
+        for (file, path) in self.paths.iter().enumerate() {
+            if self.selected[file] && self.meta.files[file].length == 0 {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                OpenOptions::new()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::torrent::bencode::{dict, Value};
+    use uuid::Uuid;
+
+    const PIECE_LEN: usize = 16;
+
+    struct TestDir(PathBuf);
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn content(length: usize) -> Vec<u8> {
+        (0..length).map(|index| (index * 7 % 251) as u8).collect()
+    }
+
+    /**
+     * files of 20, 10 and 6 bytes in 16 byte pieces, the second one's bytes only share
+     * pieces with the others
+     */
+    fn storage(selected: Vec<bool>) -> (Storage, TestDir) {
+        let lengths = [("a", 20), ("b", 10), ("c", 6)];
+        let data = content(36);
+        let pieces: Vec<u8> = data.chunks(PIECE_LEN).flat_map(sha1).collect();
+        let files = lengths
+            .iter()
+            .map(|(name, length)| {
+                dict([
+                    ("length", Value::Int(*length)),
+                    (
+                        "path",
+                        Value::List(vec![Value::Bytes(name.as_bytes().to_vec())]),
+                    ),
+                ])
+            })
+            .collect();
+        let info = dict([
+            ("name", Value::Bytes(b"folder".to_vec())),
+            ("piece length", Value::Int(PIECE_LEN as i64)),
+            ("pieces", Value::Bytes(pieces)),
+            ("files", Value::List(files)),
+        ])
+        .encode();
+        let meta = Arc::new(Metainfo::from_info(&info, vec![]).unwrap());
+        let dir = TestDir(std::env::temp_dir().join(format!("atom-storage-{}", Uuid::new_v4())));
+        (Storage::new(meta, &dir.0.join("folder"), selected), dir)
+    }
+
+    fn piece(index: usize) -> Vec<u8> {
+        let data = content(36);
+        data[index * PIECE_LEN..((index + 1) * PIECE_LEN).min(36)].to_vec()
+    }
+
+    #[test]
+    fn pieces_are_split_across_files() {
+        let (storage, dir) = storage(vec![true; 3]);
+        assert!((0..3).all(|index| !storage.verify(index)));
+
+        (0..3).for_each(|index| storage.write(index, &piece(index)).unwrap());
+
+        assert!((0..3).all(|index| storage.verify(index)));
+        let data = content(36);
+        assert_eq!(fs::read(dir.0.join("folder/a")).unwrap(), &data[..20]);
+        assert_eq!(fs::read(dir.0.join("folder/b")).unwrap(), &data[20..30]);
+        assert_eq!(fs::read(dir.0.join("folder/c")).unwrap(), &data[30..]);
+        assert_eq!(storage.read(14, 10).unwrap(), &data[14..24]);
+    }
+
+    #[test]
+    fn bad_pieces_fail_verification() {
+        let (storage, _dir) = storage(vec![true; 3]);
+        let mut bad = piece(1);
+        bad[3] ^= 0xff;
+        storage.write(1, &bad).unwrap();
+        assert!(!storage.verify(1));
+
+        storage.write(1, &piece(1)).unwrap();
+        assert!(storage.verify(1));
+    }
+
+    #[test]
+    fn unselected_files_are_not_written() {
+        let (storage, dir) = storage(vec![true, false, true]);
+        assert_eq!(
+            (0..3)
+                .map(|index| storage.wanted_bytes(index))
+                .collect::<Vec<_>>(),
+            [16, 6, 4]
+        );
+        assert!(storage.is_kept(0));
+        assert!(!storage.is_kept(1));
+
+        (0..3).for_each(|index| storage.write(index, &piece(index)).unwrap());
+
+        assert!(!dir.0.join("folder/b").exists());
+        assert_eq!(
+            fs::read(dir.0.join("folder/c")).unwrap(),
+            &content(36)[30..]
+        );
+        // piece 1 is only kept in part, it can't be checked or read back
+        assert!(storage.verify(0) && storage.verify(2));
+        assert!(!storage.verify(1));
+        assert!(storage.read(16, 16).is_err());
+    }
+}
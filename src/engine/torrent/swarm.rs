@@ -0,0 +1,620 @@
+use super::{
+    metainfo::{hex, InfoHash, Magnet, Metainfo},
+    peer::{self, Session, MAX_UPLOADS},
+    storage::Storage,
+    torrent_files,
+    tracker::Announcer,
+    Source, TorrentFile, TorrentOptions,
+};
+use crate::engine::{DownloadJob, SwarmStatus};
+use reqwest::Client;
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, mpsc},
+    task::JoinSet,
+};
+use tracing::{debug, warn};
+
+const MAX_PEERS: usize = 40;
+const CONNECTS_PER_TICK: usize = 8;
+const METADATA_FETCHES: usize = 8;
+// a peer that failed or left is tried again after this long
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2 * 60);
+// a piece is downloaded from at most this many peers at once, only near the end
+const MAX_PIECE_OWNERS: u8 = 2;
+
+/**
+ * what the sessions tell the coordinator
+ */
+#[derive(Debug)]
+pub enum PeerEvent {
+    Block(usize),    // bytes received, for the speed limit
+    Piece(usize),    // a piece passed its hash check, its bytes in the picked files
+    Uploaded(usize), // bytes sent to a peer
+    Error(String),   // the files can't be written, the torrent stops
+}
+
+/**
+ * which pieces are in, which are wanted and who is downloading them
+ */
+#[derive(Debug)]
+pub struct Swarm {
+    pub have: Vec<bool>,
+    wanted_bytes: Vec<usize>, // 0 for pieces only in files that weren't picked
+    requested: Vec<u8>,       // sessions downloading each piece
+    availability: Vec<u32>,   // connected peers having each piece
+    missing: usize,
+    pub peers: HashMap<SocketAddr, bool>, // connected peers, seeds have every piece
+    pub uploads: usize,                   // peers we unchoked
+}
+
+impl Swarm {
+    fn new(have: Vec<bool>, wanted_bytes: Vec<usize>) -> Self {
+        let missing = (0..have.len())
+            .filter(|piece| wanted_bytes[*piece] > 0 && !have[*piece])
+            .count();
+        Self {
+            requested: vec![0; have.len()],
+            availability: vec![0; have.len()],
+            have,
+            wanted_bytes,
+            missing,
+            peers: HashMap::new(),
+            uploads: 0,
+        }
+    }
+
+    fn is_missing(&self, piece: usize) -> bool {
+        self.wanted_bytes[piece] > 0 && !self.have[piece]
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing == 0
+    }
+
+    /**
+     * our pieces as a bitfield message has them
+     */
+    pub fn bitfield(&self) -> Vec<u8> {
+        let mut bits = vec![0u8; self.have.len().div_ceil(8)];
+        (0..self.have.len())
+            .filter(|piece| self.have[*piece])
+            .for_each(|piece| bits[piece / 8] |= 0x80 >> (piece % 8));
+        bits
+    }
+
+    pub fn wants_from(&self, bitfield: &[bool]) -> bool {
+        (0..self.have.len()).any(|piece| bitfield[piece] && self.is_missing(piece))
+    }
+
+    /**
+     * the rarest missing piece the peer has that nobody downloads yet, near the end pieces
+     * already being downloaded are asked from a second peer
+     */
+    pub fn pick(&mut self, bitfield: &[bool], active: &[usize]) -> Option<usize> {
+        let candidates = (0..self.have.len())
+            .filter(|piece| bitfield[*piece] && self.is_missing(*piece) && !active.contains(piece));
+        let piece = candidates
+            .clone()
+            .filter(|piece| self.requested[*piece] == 0)
+            .min_by_key(|piece| self.availability[*piece])
+            .or_else(|| {
+                candidates
+                    .filter(|piece| self.requested[*piece] < MAX_PIECE_OWNERS)
+                    .min_by_key(|piece| self.requested[*piece])
+            })?;
+        self.requested[piece] += 1;
+        Some(piece)
+    }
+
+    pub fn release(&mut self, piece: usize) {
+        self.requested[piece] = self.requested[piece].saturating_sub(1);
+    }
+
+    /**
+     * marks a verified piece as in, returns its bytes the first time
+     */
+    pub fn completed(&mut self, piece: usize) -> Option<usize> {
+        self.release(piece);
+        if !self.is_missing(piece) {
+            return None;
+        }
+        self.have[piece] = true;
+        self.missing -= 1;
+        Some(self.wanted_bytes[piece])
+    }
+
+    pub fn peer_has(&mut self, pieces: &[usize]) {
+        pieces
+            .iter()
+            .for_each(|piece| self.availability[*piece] += 1);
+    }
+
+    pub fn peer_lost(&mut self, pieces: &[usize]) {
+        pieces.iter().for_each(|piece| {
+            self.availability[*piece] = self.availability[*piece].saturating_sub(1)
+        });
+    }
+
+    pub fn take_upload_slot(&mut self) -> bool {
+        let free = self.uploads < MAX_UPLOADS;
+        if free {
+            self.uploads += 1;
+        }
+        free
+    }
+}
+
+/**
+ * everything the sessions of one torrent share
+ */
+pub struct Context {
+    pub meta: Arc<Metainfo>,
+    pub storage: Storage,
+    pub peer_id: InfoHash,
+    pub port: u16,
+    pub haves: broadcast::Sender<usize>, // pieces as they come in, sessions pass them on
+    pub events: mpsc::Sender<PeerEvent>,
+    swarm: Mutex<Swarm>,
+}
+
+impl Context {
+    pub fn swarm(&self) -> MutexGuard<'_, Swarm> {
+        self.swarm.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/**
+ * what the torrent reports to the engine
+ */
+#[derive(Debug)]
+pub enum Progress {
+    Loaded {
+        name: String,
+        files: Vec<TorrentFile>,
+    },
+    Checked {
+        size: usize, // of the picked files
+        downloaded: usize,
+    },
+    Received {
+        bytes: usize,      // 0 when only `downloaded` changed
+        downloaded: usize, // verified bytes of the picked files
+    },
+    Swarm(SwarmStatus),
+    Seeding,
+    Finished,
+    Error(String),
+}
+
+/**
+ * a running torrent, from its metadata to the end of seeding. Dropping it stops the
+ * torrent and disconnects its peers
+ */
+pub struct TorrentTransfer {
+    progress: mpsc::Receiver<Progress>,
+    _coordinator: JoinSet<()>,
+}
+
+impl std::fmt::Debug for TorrentTransfer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TorrentTransfer").finish()
+    }
+}
+
+impl TorrentTransfer {
+    pub fn start(client: Client, job: DownloadJob) -> Self {
+        // small, so a speed limit on the engine side holds the peers back
+        let (sender, progress) = mpsc::channel(16);
+        let mut coordinator = JoinSet::new();
+        coordinator.spawn(async move {
+            if let Err(error) = run(client, job, sender.clone()).await {
+                sender.send(Progress::Error(error)).await.ok();
+            }
+        });
+        Self {
+            progress,
+            _coordinator: coordinator,
+        }
+    }
+
+    pub async fn next(&mut self) -> Option<Progress> {
+        self.progress.recv().await
+    }
+}
+
+/**
+ * peers from the trackers, the DHT and the magnet link, with when they were last tried
+ */
+struct Peers {
+    known: HashMap<SocketAddr, Option<Instant>>,
+    connected: HashSet<SocketAddr>,
+    found: mpsc::Receiver<Vec<SocketAddr>>,
+    sender: mpsc::Sender<Vec<SocketAddr>>,
+}
+
+impl Peers {
+    fn new() -> Self {
+        let (sender, found) = mpsc::channel(64);
+        Self {
+            known: HashMap::new(),
+            connected: HashSet::new(),
+            found,
+            sender,
+        }
+    }
+
+    fn add(&mut self, peers: Vec<SocketAddr>) {
+        peers.into_iter().for_each(|peer| {
+            self.known.entry(peer).or_insert(None);
+        });
+    }
+
+    fn next(&mut self) -> Option<SocketAddr> {
+        let (peer, tried) = self.known.iter_mut().find(|(peer, tried)| {
+            !self.connected.contains(*peer)
+                && tried.is_none_or(|tried| tried.elapsed() > RECONNECT_INTERVAL)
+        })?;
+        *tried = Some(Instant::now());
+        self.connected.insert(*peer);
+        Some(*peer)
+    }
+}
+
+fn peer_id() -> InfoHash {
+    let mut peer_id = [0u8; 20];
+    let prefix = format!("-AT{}-", env!("CARGO_PKG_VERSION").replace('.', ""));
+    let prefix = &prefix.as_bytes()[..prefix.len().min(8)];
+    peer_id[..prefix.len()].copy_from_slice(prefix);
+    let random = uuid::Uuid::new_v4().into_bytes();
+    peer_id[prefix.len()..].copy_from_slice(&random[..20 - prefix.len()]);
+    peer_id
+}
+
+/**
+ * the port from settings, any free one when another torrent or program has it
+ */
+async fn listen(port: u16) -> Result<TcpListener, String> {
+    match TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).await {
+        Ok(listener) => Ok(listener),
+        Err(error) => {
+            debug!(port, "torrent port taken, {error}");
+            TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))
+                .await
+                .map_err(|error| format!("can't listen for peers: {error}"))
+        }
+    }
+}
+
+/**
+ * the magnet's info dictionary, saved by an earlier run or fetched from peers (BEP 9)
+ */
+pub async fn magnet_metadata(
+    client: &Client,
+    magnet: &Magnet,
+    cache_dir: &Path,
+    options: &TorrentOptions,
+) -> Result<Metainfo, String> {
+    let mut peers = Peers::new();
+    let mut discovery = JoinSet::new();
+    let announcer = Announcer {
+        info_hash: magnet.info_hash,
+        peer_id: peer_id(),
+        port: options.port,
+        stats: Default::default(),
+    };
+    // the size is unknown until the metadata is in, anything but 0 keeps trackers from
+    // counting a seed
+    announcer.stats.lock().map(|mut stats| stats.left = 1).ok();
+    announcer.discover(
+        client,
+        &magnet.trackers,
+        options.dht,
+        peers.sender.clone(),
+        &mut discovery,
+    );
+    fetch_metadata(magnet, &announcer, &mut peers, cache_dir).await
+}
+
+async fn fetch_metadata(
+    magnet: &Magnet,
+    announcer: &Announcer,
+    peers: &mut Peers,
+    cache_dir: &Path,
+) -> Result<Metainfo, String> {
+    let cached = cache_dir.join(format!("{}.torrent", hex(&magnet.info_hash)));
+    if let Ok(data) = std::fs::read(&cached) {
+        match Metainfo::from_torrent(&data) {
+            Ok(mut meta) if meta.info_hash == magnet.info_hash => {
+                meta.trackers = magnet.trackers.clone();
+                return Ok(meta);
+            }
+            _ => debug!(?cached, "ignoring the saved metadata"),
+        }
+    }
+
+    peers.add(magnet.peers.clone());
+    let mut fetches = JoinSet::new();
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        while fetches.len() < METADATA_FETCHES {
+            let Some(address) = peers.next() else {
+                break;
+            };
+            let (info_hash, peer_id, port) = (magnet.info_hash, announcer.peer_id, announcer.port);
+            fetches.spawn(async move {
+                let metadata = peer::fetch_metadata(address, info_hash, peer_id, port).await;
+                (address, metadata)
+            });
+        }
+
+        tokio::select! {
+            Some(found) = peers.found.recv() => peers.add(found),
+            Some(Ok((address, metadata))) = fetches.join_next(), if !fetches.is_empty() => {
+                peers.connected.remove(&address);
+                match metadata.and_then(|info| Metainfo::from_info(&info, magnet.trackers.clone())) {
+                    Ok(meta) => {
+                        let torrent = [&b"d4:info"[..], &meta.info, b"e"].concat();
+                        if let Err(error) = std::fs::write(&cached, torrent) {
+                            warn!("Error: failed to save the torrent metadata, {error}");
+                        }
+                        // the other fetches end here, every peer is worth a try for pieces
+                        peers.connected.clear();
+                        peers.known.values_mut().for_each(|tried| *tried = None);
+                        return Ok(meta);
+                    }
+                    Err(error) => debug!(%address, "no metadata from peer, {error}"),
+                }
+            }
+            _ = tick.tick() => {}
+        }
+    }
+}
+
+/**
+ * the name the files are saved under, the torrent's own unless one was picked
+ */
+fn root_name(job: &DownloadJob, meta: &Metainfo) -> String {
+    let picked = &job.file_name;
+    if picked.is_empty()
+        || picked.to_lowercase().ends_with(".torrent")
+        || *picked == hex(&meta.info_hash)
+    {
+        meta.name.clone()
+    } else {
+        picked.clone()
+    }
+}
+
+async fn run(
+    client: Client,
+    job: DownloadJob,
+    progress: mpsc::Sender<Progress>,
+) -> Result<(), String> {
+    let options = job.torrent.clone();
+    let listener = listen(options.port).await?;
+    let port = listener
+        .local_addr()
+        .map_err(|error| error.to_string())?
+        .port();
+    let source = super::load(&client, &job.url, &job.headers).await?;
+    let info_hash = match &source {
+        Source::Torrent(meta) => meta.info_hash,
+        Source::Magnet(magnet) => magnet.info_hash,
+    };
+    let announcer = Announcer {
+        info_hash,
+        peer_id: peer_id(),
+        port,
+        stats: Default::default(),
+    };
+    let mut peers = Peers::new();
+    let mut discovery = JoinSet::new();
+
+    let meta = match source {
+        Source::Torrent(meta) => meta,
+        Source::Magnet(magnet) => {
+            announcer.stats.lock().map(|mut stats| stats.left = 1).ok();
+            announcer.discover(
+                &client,
+                &magnet.trackers,
+                options.dht,
+                peers.sender.clone(),
+                &mut discovery,
+            );
+            fetch_metadata(&magnet, &announcer, &mut peers, &job.cache_dir).await?
+        }
+    };
+    let meta = Arc::new(meta);
+    debug!(?meta, "torrent loaded");
+
+    let name = root_name(&job, &meta);
+    let send = |message| {
+        let progress = progress.clone();
+        async move {
+            // the engine is gone and this task about to be dropped
+            progress
+                .send(message)
+                .await
+                .map_err(|_| "the download stopped".to_string())
+        }
+    };
+    send(Progress::Loaded {
+        name: name.clone(),
+        files: torrent_files(&meta),
+    })
+    .await?;
+
+    // pieces already on disk count, a resumed torrent only fetches the rest
+    let storage = Storage::new(
+        meta.clone(),
+        &Path::new(&job.file_path).join(&name),
+        meta.selected(&options.files),
+    );
+    let pieces = meta.pieces.len();
+    let (storage, have, wanted_bytes) = tokio::task::spawn_blocking(move || {
+        let wanted_bytes: Vec<usize> = (0..pieces)
+            .map(|piece| storage.wanted_bytes(piece))
+            .collect();
+        let have = (0..wanted_bytes.len())
+            .map(|piece| wanted_bytes[piece] > 0 && storage.verify(piece))
+            .collect::<Vec<_>>();
+        (storage, have, wanted_bytes)
+    })
+    .await
+    .map_err(|error| error.to_string())?;
+    let size: usize = wanted_bytes.iter().sum();
+    let mut downloaded: usize = (0..have.len())
+        .filter(|piece| have[*piece])
+        .map(|piece| wanted_bytes[piece])
+        .sum();
+    send(Progress::Checked { size, downloaded }).await?;
+    if let Ok(mut stats) = announcer.stats.lock() {
+        stats.left = size - downloaded;
+    }
+
+    let (events_sender, mut events) = mpsc::channel(64);
+    let (haves, _) = broadcast::channel(256);
+    let context = Arc::new(Context {
+        meta: meta.clone(),
+        storage,
+        peer_id: announcer.peer_id,
+        port,
+        haves,
+        events: events_sender,
+        swarm: Mutex::new(Swarm::new(have, wanted_bytes)),
+    });
+
+    if discovery.is_empty() {
+        let dht = options.dht && !meta.private;
+        announcer.discover(
+            &client,
+            &meta.trackers,
+            dht,
+            peers.sender.clone(),
+            &mut discovery,
+        );
+    }
+
+    let mut sessions = JoinSet::new();
+    let mut uploaded = 0;
+    let mut seeding: Option<Instant> = None;
+    let mut status = SwarmStatus::default();
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        if seeding.is_none() && context.swarm().is_complete() {
+            context
+                .storage
+                .create_empty_files()
+                .map_err(|error| format!("failed to create {name}: {error}"))?;
+            if let Ok(mut stats) = announcer.stats.lock() {
+                stats.left = 0;
+            }
+            // the events of the last pieces may still be queued
+            downloaded = size;
+            send(Progress::Received {
+                bytes: 0,
+                downloaded,
+            })
+            .await?;
+            if options.seed_ratio <= 0.0 {
+                announcer.stop(&client, &meta.trackers);
+                send(Progress::Finished).await?;
+                return Ok(());
+            }
+            seeding = Some(Instant::now());
+            send(Progress::Seeding).await?;
+        }
+
+        tokio::select! {
+            biased;
+            Some(event) = events.recv() => match event {
+                PeerEvent::Block(bytes) => send(Progress::Received { bytes, downloaded }).await?,
+                PeerEvent::Piece(bytes) => {
+                    downloaded = (downloaded + bytes).min(size);
+                    send(Progress::Received { bytes: 0, downloaded }).await?;
+                }
+                PeerEvent::Uploaded(bytes) => uploaded += bytes,
+                PeerEvent::Error(error) => return Err(error),
+            },
+            Some(found) = peers.found.recv() => peers.add(found),
+            Ok((stream, address)) = listener.accept() => {
+                if sessions.len() < MAX_PEERS {
+                    let context = context.clone();
+                    sessions.spawn(async move {
+                        let left = match Session::accept(context, stream).await {
+                            Ok(session) => session.run().await,
+                            Err(error) => Err(error),
+                        };
+                        if let Err(error) = left {
+                            debug!(%address, "incoming peer left, {error}");
+                        }
+                        address
+                    });
+                }
+            }
+            Some(Ok(address)) = sessions.join_next(), if !sessions.is_empty() => {
+                peers.connected.remove(&address);
+            }
+            _ = tick.tick() => {
+                let connects = MAX_PEERS.saturating_sub(sessions.len()).min(CONNECTS_PER_TICK);
+                for _ in 0..connects {
+                    let Some(address) = peers.next() else {
+                        break;
+                    };
+                    let context = context.clone();
+                    sessions.spawn(async move {
+                        let left = match Session::connect(context, address).await {
+                            Ok(session) => session.run().await,
+                            Err(error) => Err(error),
+                        };
+                        if let Err(error) = left {
+                            debug!(%address, "peer left, {error}");
+                        }
+                        address
+                    });
+                }
+
+                if let Ok(mut stats) = announcer.stats.lock() {
+                    stats.downloaded = downloaded;
+                    stats.uploaded = uploaded;
+                }
+
+                let current = {
+                    let swarm = context.swarm();
+                    SwarmStatus {
+                        peers: swarm.peers.len(),
+                        seeds: swarm.peers.values().filter(|seed| **seed).count(),
+                        uploaded,
+                        pieces: (
+                            (0..swarm.have.len()).filter(|piece| swarm.have[*piece]).count(),
+                            (0..swarm.have.len()).filter(|piece| swarm.wanted_bytes[*piece] > 0).count(),
+                        ),
+                    }
+                };
+                if current != status {
+                    status = current;
+                    send(Progress::Swarm(status)).await?;
+                }
+
+                if let Some(since) = seeding {
+                    let ratio_reached = uploaded as f64 >= options.seed_ratio * size as f64;
+                    let time_up = !options.seed_time.is_zero() && since.elapsed() >= options.seed_time;
+                    if ratio_reached || time_up {
+                        debug!(uploaded, ratio_reached, time_up, "seeding done");
+                        announcer.stop(&client, &meta.trackers);
+                        send(Progress::Finished).await?;
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
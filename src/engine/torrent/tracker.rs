@@ -0,0 +1,511 @@
+use super::{
+    bencode::{self, Value},
+    metainfo::InfoHash,
+};
+use iced::futures::StreamExt;
+use librqbit_dht::{Dht, DhtBuilder, Id20};
+use reqwest::{Client, Url};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc::Sender, OnceCell},
+    task::JoinSet,
+};
+use tracing::{debug, warn};
+
+const TRACKER_TIMEOUT: Duration = Duration::from_secs(20);
+// trackers asking for more frequent or rarer announces are held to these
+const MIN_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(2 * 60);
+const NUM_WANT: u32 = 50;
+// how often the DHT is asked again, it finds new peers slowly
+const DHT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const UDP_PROTOCOL_ID: u64 = 0x41727101980;
+
+// one DHT node for all torrents, started with the first one that may use it
+static DHT: OnceCell<Option<Dht>> = OnceCell::const_new();
+
+/**
+ * what the trackers are told about the transfer
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub downloaded: usize,
+    pub uploaded: usize,
+    pub left: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AnnounceEvent {
+    Started,
+    Completed,
+    Regular,
+    Stopped,
+}
+
+/**
+ * who we are to the trackers
+ */
+#[derive(Debug, Clone)]
+pub struct Announcer {
+    pub info_hash: InfoHash,
+    pub peer_id: InfoHash,
+    pub port: u16,
+    pub stats: Arc<Mutex<Stats>>,
+}
+
+impl Announcer {
+    fn stats(&self) -> Stats {
+        self.stats.lock().map(|stats| *stats).unwrap_or_default()
+    }
+
+    /**
+     * announces to every tracker and asks the DHT, found peers go to `peers` until the tasks
+     * are dropped
+     */
+    pub fn discover(
+        &self,
+        client: &Client,
+        trackers: &[String],
+        dht: bool,
+        peers: Sender<Vec<SocketAddr>>,
+        tasks: &mut JoinSet<()>,
+    ) {
+        for tracker in trackers {
+            let (announcer, client, tracker, peers) =
+                (self.clone(), client.clone(), tracker.clone(), peers.clone());
+            tasks.spawn(async move { announcer.keep_announcing(&client, &tracker, peers).await });
+        }
+
+        if dht {
+            let (info_hash, port) = (self.info_hash, self.port);
+            tasks.spawn(async move { search_dht(info_hash, port, peers).await });
+        }
+    }
+
+    async fn keep_announcing(
+        &self,
+        client: &Client,
+        tracker: &str,
+        peers: Sender<Vec<SocketAddr>>,
+    ) {
+        let mut event = AnnounceEvent::Started;
+        let mut completed = self.stats().left == 0;
+        loop {
+            if !completed && self.stats().left == 0 {
+                completed = true;
+                event = AnnounceEvent::Completed;
+            }
+
+            let wait = match self.announce(client, tracker, event).await {
+                Ok((found, interval)) => {
+                    debug!(tracker, peers = found.len(), "tracker announce");
+                    if !found.is_empty() && peers.send(found).await.is_err() {
+                        return;
+                    }
+                    event = AnnounceEvent::Regular;
+                    interval.clamp(MIN_ANNOUNCE_INTERVAL, MAX_ANNOUNCE_INTERVAL)
+                }
+                Err(error) => {
+                    warn!(tracker, "Error: tracker announce failed, {error}");
+                    RETRY_INTERVAL
+                }
+            };
+
+            // looks at the stats every few seconds so completion is announced right away
+            let until = tokio::time::Instant::now() + wait;
+            while tokio::time::Instant::now() < until {
+                if !completed && self.stats().left == 0 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_secs(5).min(wait)).await;
+            }
+        }
+    }
+
+    /**
+     * tells the trackers the torrent is gone, without waiting on slow ones
+     */
+    pub fn stop(&self, client: &Client, trackers: &[String]) {
+        for tracker in trackers {
+            let (announcer, client, tracker) = (self.clone(), client.clone(), tracker.clone());
+            tokio::spawn(async move {
+                announcer
+                    .announce(&client, &tracker, AnnounceEvent::Stopped)
+                    .await
+                    .ok();
+            });
+        }
+    }
+
+    async fn announce(
+        &self,
+        client: &Client,
+        tracker: &str,
+        event: AnnounceEvent,
+    ) -> Result<(Vec<SocketAddr>, Duration), String> {
+        let url = Url::parse(tracker).map_err(|_| "invalid tracker URL".to_string())?;
+        let announce = match url.scheme() {
+            "http" | "https" => {
+                tokio::time::timeout(TRACKER_TIMEOUT, self.announce_http(client, tracker, event))
+                    .await
+            }
+            "udp" => tokio::time::timeout(TRACKER_TIMEOUT, self.announce_udp(&url, event)).await,
+            scheme => return Err(format!("{scheme}:// trackers are not supported")),
+        };
+        announce.map_err(|_| "the tracker didn't answer".to_string())?
+    }
+
+    async fn announce_http(
+        &self,
+        client: &Client,
+        tracker: &str,
+        event: AnnounceEvent,
+    ) -> Result<(Vec<SocketAddr>, Duration), String> {
+        let stats = self.stats();
+        let mut url = format!(
+            "{tracker}{}info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}&compact=1&numwant={NUM_WANT}",
+            if tracker.contains('?') { '&' } else { '?' },
+            urlencoding::encode_binary(&self.info_hash),
+            urlencoding::encode_binary(&self.peer_id),
+            self.port,
+            stats.uploaded,
+            stats.downloaded,
+            stats.left,
+        );
+        match event {
+            AnnounceEvent::Started => url.push_str("&event=started"),
+            AnnounceEvent::Completed => url.push_str("&event=completed"),
+            AnnounceEvent::Stopped => url.push_str("&event=stopped"),
+            AnnounceEvent::Regular => {}
+        }
+
+        let body = client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|error| format!("{:?}", error.without_url()))?
+            .bytes()
+            .await
+            .map_err(|error| format!("{:?}", error.without_url()))?;
+        let response = bencode::decode(&body)?;
+        if let Some(failure) = response.get("failure reason").and_then(Value::str) {
+            return Err(failure.to_string());
+        }
+
+        let mut peers = vec![];
+        match response.get("peers") {
+            Some(Value::Bytes(compact)) => peers.extend(compact_peers(compact, 4)),
+            Some(Value::List(list)) => peers.extend(list.iter().filter_map(|peer| {
+                let ip = peer.get("ip")?.str()?.parse().ok()?;
+                let port = u16::try_from(peer.get("port")?.int()?).ok()?;
+                Some(SocketAddr::new(ip, port))
+            })),
+            _ => {}
+        }
+        if let Some(compact) = response.get("peers6").and_then(Value::bytes) {
+            peers.extend(compact_peers(compact, 16));
+        }
+        let interval = response
+            .get("interval")
+            .and_then(Value::int)
+            .unwrap_or(0)
+            .max(0) as u64;
+        Ok((peers, Duration::from_secs(interval)))
+    }
+
+    /**
+     * BEP 15, a connect round trip and then the announce
+     */
+    async fn announce_udp(
+        &self,
+        url: &Url,
+        event: AnnounceEvent,
+    ) -> Result<(Vec<SocketAddr>, Duration), String> {
+        let host = url.host_str().ok_or("the tracker URL has no host")?;
+        let port = url.port().ok_or("the tracker URL has no port")?;
+        let address = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|error| error.to_string())?
+            .next()
+            .ok_or("the tracker host doesn't resolve")?;
+        let bind: SocketAddr = match address {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind)
+            .await
+            .map_err(|error| error.to_string())?;
+        socket
+            .connect(address)
+            .await
+            .map_err(|error| error.to_string())?;
+
+        let transaction = rand_u32();
+        let mut request = UDP_PROTOCOL_ID.to_be_bytes().to_vec();
+        request.extend(0u32.to_be_bytes());
+        request.extend(transaction.to_be_bytes());
+        let response = udp_round_trip(&socket, &request, 0, transaction, 16).await?;
+        let connection_id = &response[8..16];
+
+        let stats = self.stats();
+        let transaction = rand_u32();
+        let mut request = connection_id.to_vec();
+        request.extend(1u32.to_be_bytes());
+        request.extend(transaction.to_be_bytes());
+        request.extend(self.info_hash);
+        request.extend(self.peer_id);
+        request.extend((stats.downloaded as u64).to_be_bytes());
+        request.extend((stats.left as u64).to_be_bytes());
+        request.extend((stats.uploaded as u64).to_be_bytes());
+        request.extend(
+            match event {
+                AnnounceEvent::Regular => 0u32,
+                AnnounceEvent::Completed => 1,
+                AnnounceEvent::Started => 2,
+                AnnounceEvent::Stopped => 3,
+            }
+            .to_be_bytes(),
+        );
+        request.extend(0u32.to_be_bytes()); // the address the packet came from
+        request.extend(rand_u32().to_be_bytes());
+        request.extend(NUM_WANT.to_be_bytes());
+        request.extend(self.port.to_be_bytes());
+        let response = udp_round_trip(&socket, &request, 1, transaction, 20).await?;
+
+        let interval = u32::from_be_bytes(response[8..12].try_into().unwrap_or_default());
+        let width = if address.is_ipv4() { 4 } else { 16 };
+        Ok((
+            compact_peers(&response[20..], width),
+            Duration::from_secs(interval as u64),
+        ))
+    }
+}
+
+/**
+ * sends until an answer to `transaction` comes back, errors (action 3) carry a message
+ */
+async fn udp_round_trip(
+    socket: &UdpSocket,
+    request: &[u8],
+    action: u32,
+    transaction: u32,
+    min_length: usize,
+) -> Result<Vec<u8>, String> {
+    let mut buffer = vec![0; 65536];
+    for attempt in 0..3 {
+        socket
+            .send(request)
+            .await
+            .map_err(|error| error.to_string())?;
+        let wait = Duration::from_secs(5 * (attempt + 1));
+        let Ok(received) = tokio::time::timeout(wait, socket.recv(&mut buffer)).await else {
+            continue;
+        };
+        let length = received.map_err(|error| error.to_string())?;
+        let response = &buffer[..length];
+        if length < 8 || response[4..8] != transaction.to_be_bytes() {
+            continue;
+        }
+        if response[..4] == 3u32.to_be_bytes() {
+            return Err(String::from_utf8_lossy(&response[8..]).to_string());
+        }
+        if response[..4] != action.to_be_bytes() || length < min_length {
+            return Err("invalid tracker response".to_string());
+        }
+        return Ok(response.to_vec());
+    }
+    Err("the tracker didn't answer".to_string())
+}
+
+/**
+ * addresses of `width` bytes followed by a port, as trackers and the DHT send them
+ */
+pub fn compact_peers(data: &[u8], width: usize) -> Vec<SocketAddr> {
+    data.chunks_exact(width + 2)
+        .filter_map(|peer| {
+            let ip = match width {
+                4 => IpAddr::from(<[u8; 4]>::try_from(&peer[..4]).ok()?),
+                _ => IpAddr::from(<[u8; 16]>::try_from(&peer[..16]).ok()?),
+            };
+            let port = u16::from_be_bytes([peer[width], peer[width + 1]]);
+            (port != 0).then_some(SocketAddr::new(ip, port))
+        })
+        .collect()
+}
+
+fn rand_u32() -> u32 {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+async fn search_dht(info_hash: InfoHash, port: u16, peers: Sender<Vec<SocketAddr>>) {
+    let dht = DHT
+        .get_or_init(|| async {
+            DhtBuilder::new()
+                .await
+                .inspect_err(|error| warn!("Error: the DHT didn't start, {error}"))
+                .ok()
+        })
+        .await;
+    let Some(dht) = dht else {
+        return;
+    };
+
+    loop {
+        let mut found = dht.get_peers(Id20::new(info_hash), Some(port));
+        let search = async {
+            while let Some(peer) = found.next().await {
+                if peers.send(vec![peer]).await.is_err() {
+                    return false;
+                }
+            }
+            true
+        };
+        match tokio::time::timeout(DHT_INTERVAL, search).await {
+            Ok(false) => return,
+            Ok(true) => tokio::time::sleep(DHT_INTERVAL).await,
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn announcer() -> Announcer {
+        Announcer {
+            info_hash: [1; 20],
+            peer_id: [2; 20],
+            port: 6881,
+            stats: Arc::new(Mutex::new(Stats {
+                downloaded: 10,
+                uploaded: 20,
+                left: 30,
+            })),
+        }
+    }
+
+    /**
+     * announces to a tracker on a local socket that answers with `body`, gives the result and
+     * the request target the tracker saw
+     */
+    async fn announce_to(
+        body: &'static [u8],
+        event: AnnounceEvent,
+    ) -> (Result<(Vec<SocketAddr>, Duration), String>, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tracker = format!("http://{}/announce", listener.local_addr().unwrap());
+        let stub = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).await.unwrap();
+                assert!(read > 0, "the request ended early");
+                request.extend_from_slice(&buffer[..read]);
+            }
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            stream.write_all(body).await.unwrap();
+            let request = String::from_utf8_lossy(&request).to_string();
+            request.split(' ').nth(1).unwrap_or_default().to_string()
+        });
+
+        let result = announcer().announce(&Client::new(), &tracker, event).await;
+        (result, stub.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn compact_peers_and_interval() {
+        let (result, target) = announce_to(
+            b"d8:intervali900e5:peers12:\x7f\x00\x00\x01\x1a\xe1\x0a\x00\x00\x02\x00\x50\
+              6:peers618:\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x1a\xe2e",
+            AnnounceEvent::Started,
+        )
+        .await;
+
+        let (peers, interval) = result.unwrap();
+        assert_eq!(
+            peers,
+            [
+                "127.0.0.1:6881".parse::<SocketAddr>().unwrap(),
+                "10.0.0.2:80".parse().unwrap(),
+                "[::1]:6882".parse().unwrap(),
+            ]
+        );
+        assert_eq!(interval, Duration::from_secs(900));
+        assert!(target.starts_with(&format!(
+            "/announce?info_hash={}&peer_id={}&port=6881&uploaded=20&downloaded=10&left=30&compact=1",
+            "%01".repeat(20),
+            "%02".repeat(20)
+        )));
+        assert!(target.ends_with("&event=started"));
+    }
+
+    #[tokio::test]
+    async fn dictionary_peers_without_interval() {
+        let (result, target) = announce_to(
+            b"d5:peersld2:ip9:127.0.0.14:porti6881eed2:ip3:bad4:porti1eeee",
+            AnnounceEvent::Regular,
+        )
+        .await;
+
+        let (peers, interval) = result.unwrap();
+        assert_eq!(peers, ["127.0.0.1:6881".parse::<SocketAddr>().unwrap()]);
+        assert_eq!(interval, Duration::ZERO);
+        assert!(!target.contains("event="));
+    }
+
+    #[tokio::test]
+    async fn failure_reason_is_an_error() {
+        let (result, target) = announce_to(
+            b"d14:failure reason17:torrent not found8:intervali60ee",
+            AnnounceEvent::Stopped,
+        )
+        .await;
+
+        assert_eq!(result, Err("torrent not found".to_string()));
+        assert!(target.ends_with("&event=stopped"));
+    }
+
+    #[tokio::test]
+    async fn unsupported_trackers() {
+        let announcer = announcer();
+        let client = Client::new();
+        assert!(announcer
+            .announce(
+                &client,
+                "wss://tracker.example/announce",
+                AnnounceEvent::Regular
+            )
+            .await
+            .is_err());
+        assert!(announcer
+            .announce(&client, "not a url", AnnounceEvent::Regular)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn compact_peers_skip_a_partial_entry() {
+        assert_eq!(
+            compact_peers(&[192, 168, 1, 2, 0, 80, 1, 2, 3], 4),
+            ["192.168.1.2:80".parse::<SocketAddr>().unwrap()]
+        );
+    }
+}
@@ -0,0 +1,1809 @@
+use super::{
+    dash,
+    ftp::{self, Connection, FtpRange, FtpTarget, FtpTransfer},
+    hls,
+    probe::{
+        get_content_length, get_range_properties, get_sidecar_checksum, DownloadProperties,
+        DownloadType,
+    },
+    segments::SegmentTransfer,
+    sftp::{self, SftpRange, SftpSession, SftpTarget, SftpTransfer},
+    torrent::{self, Progress, TorrentTransfer},
+    DownloadJob, EngineCommand, EngineEvent, StreamFormat,
+};
+use crate::utils::{
+    helpers::{hashmap2headermap, split_file_name, ATOM_USER_AGENT},
+    redact::{redact_url, without_credentials},
+};
+use iced::futures::{
+    channel::mpsc::UnboundedReceiver,
+    future::{join, join_all},
+    stream::{unfold, BoxStream, StreamExt},
+};
+use reqwest::{
+    header::{CONTENT_TYPE, RANGE, USER_AGENT},
+    Client, Method, RequestBuilder, Response, StatusCode, Url,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+use tracing::{debug, error, warn};
+
+const JOIN_BUFFER_LEN: usize = 102400;
+const STREAM_SNIFF_MAX_SIZE: usize = 1024 * 1024; // larger files aren't taken for playlists
+const STREAM_SNIFF_LEN: usize = 1024;
+// the speed limit is averaged over this window so short bursts even out
+const SPEED_LIMIT_WINDOW: Duration = Duration::from_secs(2);
+// with other servers to fall back to, a mirror that sends nothing for this long is dropped
+const MIRROR_STALL_TIMEOUT: Duration = Duration::from_secs(15);
+// mirrors are compared once they had this long to get up to speed
+const SLOW_MIRROR_GRACE: Duration = Duration::from_secs(10);
+// a mirror this many times slower than the fastest one loses its chunks
+const SLOW_MIRROR_FACTOR: f64 = 4.0;
+// shorter transfers are mostly request latency and say little about the speed
+const MIN_RATE_SAMPLE: Duration = Duration::from_secs(1);
+
+struct SubDownloads {
+    response: Response,
+    file: BufWriter<File>,
+    source: usize,   // index into `Sources::urls`
+    next: usize,     // first byte of the chunk not received yet
+    end: usize,      // last byte of the chunk, inclusive
+    since: Instant,  // when `source` started serving the chunk
+    received: usize, // bytes `source` sent since then
+}
+
+impl SubDownloads {
+    fn rate(&self) -> f64 {
+        self.received as f64 / self.since.elapsed().as_secs_f64().max(0.001)
+    }
+}
+
+// `Response` prints the full URL and response headers (set-cookie etc.)
+impl fmt::Debug for SubDownloads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SubDownloads")
+            .field("url", &redact_url(self.response.url().as_str()))
+            .field("status", &self.response.status())
+            .field("file", &self.file)
+            .field("source", &self.source)
+            .field("next", &self.next)
+            .field("end", &self.end)
+            .field("received", &self.received)
+            .finish()
+    }
+}
+
+enum RangeError {
+    Ignored, // the only server left sends the whole file for range requests
+    Failed(String),
+}
+
+/**
+ * the servers a threaded download is spread over, the job URL first and then the mirrors
+ * that serve the same file
+ */
+struct Sources {
+    client: Client,
+    job: DownloadJob,
+    urls: Vec<String>,
+    live: Vec<bool>,
+    dropped: Vec<String>,    // not reported to the app yet
+    rates: Vec<Option<f64>>, // bytes per second, last measured
+}
+
+impl fmt::Debug for Sources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sources")
+            .field(
+                "urls",
+                &self
+                    .urls
+                    .iter()
+                    .map(|url| redact_url(url))
+                    .collect::<Vec<_>>(),
+            )
+            .field("live", &self.live)
+            .finish()
+    }
+}
+
+impl Sources {
+    /**
+     * keeps the mirrors that serve the same file as the job URL, by size and ETag
+     */
+    async fn probe(client: Client, job: DownloadJob) -> Self {
+        let mut urls = vec![job.url.clone()];
+        let mut skipped = vec![];
+        if !job.mirrors.is_empty() {
+            let (primary, mirrors) = join(
+                get_content_length(client.clone(), &job.url, &job.headers),
+                join_all(job.mirrors.iter().map(|url| {
+                    let (client, headers) = (client.clone(), source_headers(&job, url));
+                    async move { get_content_length(client, url, &headers).await }
+                })),
+            )
+            .await;
+
+            job.mirrors
+                .iter()
+                .zip(&mirrors)
+                .for_each(|(mirror, properties)| {
+                    // without a probe of the job URL only the size known from before is compared
+                    let same_file = properties.error.is_empty()
+                        && properties.content_length == job.size
+                        && (primary.content_length == 0 || primary.same_file(properties));
+                    if same_file {
+                        urls.push(mirror.clone());
+                    } else {
+                        skipped.push(mirror.clone());
+                        warn!(
+                            mirror = redact_url(mirror),
+                            "mirror skipped, it doesn't serve the same file"
+                        );
+                    }
+                });
+        }
+
+        Self {
+            live: vec![true; urls.len()],
+            rates: vec![None; urls.len()],
+            urls,
+            client,
+            job,
+            dropped: skipped,
+        }
+    }
+
+    fn live_count(&self) -> usize {
+        self.live.iter().filter(|live| **live).count()
+    }
+
+    fn drop_source(&mut self, source: usize, reason: &str) {
+        if std::mem::replace(&mut self.live[source], false) {
+            warn!(
+                url = redact_url(&self.urls[source]),
+                reason, "server dropped from the download"
+            );
+            self.dropped.push(self.urls[source].clone());
+        }
+    }
+
+    /**
+     * requests `start..=end`, trying `preferred` first and then the other live servers, a
+     * server that fails is dropped as long as another one is left
+     */
+    async fn open_range(
+        &mut self,
+        preferred: usize,
+        start: usize,
+        end: usize,
+    ) -> Result<(usize, Response), RangeError> {
+        let order: Vec<usize> = std::iter::once(preferred)
+            .chain((0..self.urls.len()).filter(|source| *source != preferred))
+            .collect();
+
+        for source in order {
+            if !self.live[source] {
+                continue;
+            }
+
+            let request = request(&self.client, &self.job, &self.urls[source]);
+            let range = format!("bytes={start}-{end}");
+            debug!(
+                url = redact_url(&self.urls[source]),
+                range, "requesting chunk"
+            );
+
+            let failure = match request.header(RANGE, range).send().await {
+                Ok(response) if response.status() == StatusCode::PARTIAL_CONTENT => {
+                    return Ok((source, response))
+                }
+                Ok(response) if response.status() == StatusCode::OK && self.live_count() == 1 => {
+                    return Err(RangeError::Ignored)
+                }
+                Ok(response) => format!("the server responded with {}", response.status()),
+                Err(error) => format!("request failed: {:?}", error.without_url()),
+            };
+
+            if self.live_count() == 1 {
+                return Err(RangeError::Failed(failure));
+            }
+            self.drop_source(source, &failure);
+        }
+
+        Err(RangeError::Failed("no server is left".to_string()))
+    }
+
+    /**
+     * hands a chunk to another server from where the current one left off
+     */
+    async fn reassign(
+        &mut self,
+        sub_download: &mut SubDownloads,
+        preferred: usize,
+    ) -> Result<(), String> {
+        sub_download
+            .file
+            .flush()
+            .map_err(|_| "writing to chunk file failed!".to_string())?;
+        self.record_rate(sub_download);
+
+        match self
+            .open_range(preferred, sub_download.next, sub_download.end)
+            .await
+        {
+            Ok((source, response)) => {
+                sub_download.response = response;
+                sub_download.source = source;
+                sub_download.since = Instant::now();
+                sub_download.received = 0;
+                Ok(())
+            }
+            Err(RangeError::Ignored) => {
+                Err("the last server left ignores range requests".to_string())
+            }
+            Err(RangeError::Failed(error)) => Err(error),
+        }
+    }
+
+    /**
+     * keeps the last transfer rate of each server, servers stay comparable after their own
+     * chunks are done
+     */
+    fn record_rate(&mut self, sub_download: &SubDownloads) {
+        if sub_download.received > 0 && sub_download.since.elapsed() >= MIN_RATE_SAMPLE {
+            self.rates[sub_download.source] = Some(sub_download.rate());
+        }
+    }
+
+    /**
+     * the slowest and the fastest live server when the slowest one is far behind, chunks are
+     * measured once they had time to get up to speed
+     */
+    fn too_slow(&mut self, sub_downloads: &[SubDownloads]) -> Option<(usize, usize)> {
+        sub_downloads
+            .iter()
+            .filter(|sub_download| sub_download.since.elapsed() >= SLOW_MIRROR_GRACE)
+            .for_each(|sub_download| self.record_rate(sub_download));
+
+        let rates: Vec<(usize, f64)> = (0..self.urls.len())
+            .filter(|source| self.live[*source])
+            .filter_map(|source| self.rates[source].map(|rate| (source, rate)))
+            .collect();
+        let slowest = rates.iter().min_by(|a, b| a.1.total_cmp(&b.1))?;
+        let fastest = rates.iter().max_by(|a, b| a.1.total_cmp(&b.1))?;
+
+        // only a server that still has chunks is worth dropping
+        let busy = sub_downloads
+            .iter()
+            .any(|sub_download| sub_download.source == slowest.0);
+        (busy && slowest.1 * SLOW_MIRROR_FACTOR < fastest.1).then_some((slowest.0, fastest.0))
+    }
+}
+
+/**
+ * the parts of an output file copied into it one after the other, each part is opened once it
+ * is reached so streams with thousands of segments don't run out of file handles
+ */
+#[derive(Debug)]
+struct Joining {
+    output: BufWriter<File>,
+    parts: Vec<String>,
+    current: usize,
+    reader: Option<BufReader<File>>,
+    outputs: Vec<(String, Vec<String>)>, // joined after this one
+}
+
+#[derive(Debug)]
+enum State {
+    Starting(Client, DownloadJob),
+    Probed(Client, DownloadJob, DownloadProperties), // the expected checksum was announced first
+    ThreadedStarting(Client, DownloadJob, String, Vec<String>),
+    SequentialDownloading(Response, BufWriter<File>, usize),
+    ThreadedDownloading(Sources, Vec<SubDownloads>, String, Vec<String>, usize),
+    StreamStarting(Client, DownloadJob),
+    StreamDownloading(SegmentTransfer),
+    FtpDownloading(FtpTransfer, String, Vec<String>), // chunk files, none for one connection
+    SftpDownloading(SftpTransfer, String, Vec<String>), // chunk files, none for one range
+    TorrentDownloading(TorrentTransfer),              // seeding as well, until its limits
+    FileJoining(Joining),
+    ThreadedFinished(String, Vec<String>),
+    TracksFinished(Vec<(String, Vec<String>)>), // output files and their segments
+    SequentialFinished,
+    Done,
+}
+
+impl State {
+    fn is_transferring(&self) -> bool {
+        matches!(
+            self,
+            State::Starting(..)
+                | State::Probed(..)
+                | State::ThreadedStarting(..)
+                | State::SequentialDownloading(..)
+                | State::ThreadedDownloading(..)
+                | State::StreamStarting(..)
+                | State::StreamDownloading(..)
+                | State::FtpDownloading(..)
+                | State::SftpDownloading(..)
+                | State::TorrentDownloading(..)
+        )
+    }
+}
+
+struct Controls {
+    commands: UnboundedReceiver<EngineCommand>,
+    speed_limit: usize,
+    window_start: Instant,
+    window_bytes: usize,
+}
+
+impl Controls {
+    fn new(commands: UnboundedReceiver<EngineCommand>, speed_limit: usize) -> Self {
+        Self {
+            commands,
+            speed_limit,
+            window_start: Instant::now(),
+            window_bytes: 0,
+        }
+    }
+
+    /**
+     * applies the pending commands, returns true when the engine should pause
+     */
+    fn apply_commands(&mut self) -> bool {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                EngineCommand::Pause => return true,
+                EngineCommand::SetSpeedLimit(speed_limit) => {
+                    self.speed_limit = speed_limit;
+                    self.window_start = Instant::now();
+                    self.window_bytes = 0;
+                }
+            }
+        }
+        false
+    }
+
+    async fn throttle(&mut self, bytes: usize) {
+        if self.speed_limit == 0 {
+            return;
+        }
+
+        if self.window_start.elapsed() > SPEED_LIMIT_WINDOW {
+            self.window_start = Instant::now();
+            self.window_bytes = 0;
+        }
+
+        self.window_bytes += bytes;
+        let expected = Duration::from_secs_f64(self.window_bytes as f64 / self.speed_limit as f64);
+        let elapsed = self.window_start.elapsed();
+        if expected > elapsed {
+            tokio::time::sleep(expected - elapsed).await;
+        }
+    }
+}
+
+pub fn run(
+    job: DownloadJob,
+    client: Client,
+    commands: UnboundedReceiver<EngineCommand>,
+) -> BoxStream<'static, EngineEvent> {
+    let controls = Controls::new(commands, job.speed_limit);
+
+    unfold(
+        (State::Starting(client, job), controls),
+        |(state, mut controls)| async move {
+            if state.is_transferring() && controls.apply_commands() {
+                return Some((EngineEvent::Paused, (State::Done, controls)));
+            }
+
+            let (event, state) = match state {
+                State::Done => return None,
+                State::SequentialFinished => (EngineEvent::Finished, State::Done),
+                State::ThreadedFinished(destination_file, files) => {
+                    handle_threaded_download_finish(&destination_file, &files, vec![])
+                }
+                State::TracksFinished(mut tracks) => {
+                    let (destination_file, files) = tracks.remove(0);
+                    handle_threaded_download_finish(&destination_file, &files, tracks)
+                }
+                State::ThreadedDownloading(
+                    sources,
+                    sub_downloads,
+                    destination_file,
+                    chunk_files,
+                    downloaded,
+                ) => {
+                    handle_threaded_downloading(
+                        sources,
+                        sub_downloads,
+                        destination_file,
+                        chunk_files,
+                        downloaded,
+                        &mut controls,
+                    )
+                    .await
+                }
+                State::ThreadedStarting(client, job, destination_file, chunk_files) => {
+                    handle_threaded_download_starting(job, destination_file, chunk_files, client)
+                        .await
+                }
+                State::StreamStarting(client, job) => handle_stream_starting(job, client).await,
+                State::StreamDownloading(segments) => {
+                    handle_stream_downloading(segments, &mut controls).await
+                }
+                State::FtpDownloading(transfer, destination_file, chunk_files) => {
+                    handle_ftp_downloading(transfer, destination_file, chunk_files, &mut controls)
+                        .await
+                }
+                State::SftpDownloading(transfer, destination_file, chunk_files) => {
+                    handle_sftp_downloading(transfer, destination_file, chunk_files, &mut controls)
+                        .await
+                }
+                State::TorrentDownloading(transfer) => {
+                    handle_torrent_downloading(transfer, &mut controls).await
+                }
+                State::SequentialDownloading(response, file, downloaded) => {
+                    handle_sequential_downloading(response, file, downloaded, &mut controls).await
+                }
+                State::Starting(client, job) => handle_download_starting(job, client).await,
+                State::Probed(client, job, options) => {
+                    handle_download_probed(job, client, options).await
+                }
+                State::FileJoining(joining) => handle_joining_progress(joining),
+            };
+
+            Some((event, (state, controls)))
+        },
+    )
+    .boxed()
+}
+
+/**
+ * the download's request as it was captured, every range and retry sends the same method,
+ * headers and body bytes
+ */
+fn request(client: &Client, job: &DownloadJob, url: &str) -> RequestBuilder {
+    let request = client
+        .request(job.method.clone(), url)
+        .header(USER_AGENT, ATOM_USER_AGENT)
+        .headers(hashmap2headermap(&source_headers(job, url)));
+    if job.body.is_empty() || !same_origin(&job.url, url) {
+        request
+    } else {
+        request.body(job.body.clone())
+    }
+}
+
+/**
+ * cookies and tokens were captured for the download's site, mirrors elsewhere get the other
+ * headers only
+ */
+fn source_headers(job: &DownloadJob, url: &str) -> HashMap<String, String> {
+    if same_origin(&job.url, url) {
+        job.headers.clone()
+    } else {
+        without_credentials(&job.headers)
+    }
+}
+
+fn same_origin(first: &str, second: &str) -> bool {
+    match (Url::parse(first), Url::parse(second)) {
+        (Ok(first), Ok(second)) => first.origin() == second.origin(),
+        _ => false,
+    }
+}
+
+fn error(message: impl Into<String>) -> (EngineEvent, State) {
+    (EngineEvent::Error(message.into()), State::Done)
+}
+
+async fn handle_download_starting(job: DownloadJob, client: Client) -> (EngineEvent, State) {
+    if job.stream.is_some() {
+        return handle_stream_starting(job, client).await;
+    }
+    if ftp::is_ftp_url(&job.url) {
+        return handle_ftp_starting(job).await;
+    }
+    if sftp::is_sftp_url(&job.url) {
+        return handle_sftp_starting(job).await;
+    }
+    if torrent::is_torrent_url(&job.url) {
+        // the size is known once the metadata is in and the files on disk are checked
+        return (
+            EngineEvent::Progress(job.downloaded),
+            State::TorrentDownloading(TorrentTransfer::start(client, job)),
+        );
+    }
+
+    let mut options = DownloadProperties {
+        content_length: job.size,
+        download_type: if job.sequential {
+            DownloadType::Sequential
+        } else {
+            DownloadType::Threaded
+        },
+        error: "".to_string(),
+        etag: "".to_string(),
+        content_type: "".to_string(),
+        checksum: None,
+    };
+
+    let fresh = job.downloaded == 0 && job.size == 0;
+    if fresh {
+        // a HEAD request tells nothing about what another method answers
+        options = if job.method == Method::GET {
+            get_content_length(client.clone(), &job.url, &job.headers).await
+        } else {
+            get_range_properties(request(&client, &job, &job.url)).await
+        };
+    }
+
+    if !options.error.is_empty() {
+        return error(options.error);
+    }
+
+    if fresh {
+        let mut format = StreamFormat::from_url(&job.url)
+            .or_else(|| StreamFormat::from_content_type(&options.content_type));
+        // sequential downloads look at their first chunk instead
+        if format.is_none()
+            && matches!(options.download_type, DownloadType::Threaded)
+            && options.content_length <= STREAM_SNIFF_MAX_SIZE
+        {
+            format = sniff_stream(&client, &job).await;
+        }
+        if let Some(format) = format {
+            return stream_detected(job, client, format);
+        }
+
+        if job.find_checksum {
+            let checksum = match options.checksum.take() {
+                Some(checksum) => Some(checksum),
+                None => get_sidecar_checksum(&client, &job.url, &job.headers).await,
+            };
+            if let Some(checksum) = checksum {
+                return (
+                    EngineEvent::ChecksumFound(checksum),
+                    State::Probed(client, job, options),
+                );
+            }
+        }
+    }
+
+    handle_download_probed(job, client, options).await
+}
+
+async fn handle_download_probed(
+    mut job: DownloadJob,
+    client: Client,
+    options: DownloadProperties,
+) -> (EngineEvent, State) {
+    let destination_file = Path::new(&job.file_path)
+        .join(&job.file_name)
+        .to_str()
+        .unwrap_or_default()
+        .to_string();
+
+    job.size = options.content_length;
+    match (options.download_type, job.sequential) {
+        (DownloadType::Threaded, false) if job.size > 0 => {
+            let files = chunk_files(&job);
+
+            let downloaded_bytes_len = files
+                .iter()
+                .filter_map(|file_path| std::fs::metadata(file_path).ok())
+                .fold(0, |size, metadata| size + metadata.len() as usize);
+
+            (
+                EngineEvent::SizeKnown {
+                    size: job.size,
+                    downloaded: downloaded_bytes_len,
+                },
+                State::ThreadedStarting(client, job, destination_file, files),
+            )
+        }
+        _ => handle_sequential_starting(job, client, &destination_file).await,
+    }
+}
+
+async fn handle_sequential_starting(
+    job: DownloadJob,
+    client: Client,
+    destination_file: &str,
+) -> (EngineEvent, State) {
+    let Ok(file) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(destination_file)
+    else {
+        return error(format!("failed to create {}!", job.file_name));
+    };
+
+    let mut file_size = file
+        .metadata()
+        .map_or(0, |metadata| metadata.len() as usize);
+    let mut request = request(&client, &job, &job.url);
+    if file_size > 0 {
+        request = request.header(RANGE, format!("bytes={file_size}-"));
+    }
+
+    let Ok(mut response) = request.send().await else {
+        return error("failed to create download client!");
+    };
+
+    let status = response.status();
+    // everything is already on disk
+    if status == StatusCode::RANGE_NOT_SATISFIABLE && job.size > 0 && file_size >= job.size {
+        return (
+            EngineEvent::SizeKnown {
+                size: job.size,
+                downloaded: file_size,
+            },
+            State::SequentialFinished,
+        );
+    }
+
+    if !status.is_success() {
+        return error(format!("The server responded with an {status} status code"));
+    }
+
+    // the server ignored the range and sends the whole file again
+    if file_size > 0 && status != StatusCode::PARTIAL_CONTENT {
+        debug!(
+            "server ignored the resume range, restarting {}",
+            job.file_name
+        );
+        if file.set_len(0).is_err() {
+            return error(format!("failed to truncate {}!", job.file_name));
+        }
+        file_size = 0;
+    }
+
+    // a playlist or manifest behind a URL that doesn't tell, recognised by its content type or
+    // content
+    let mut file = BufWriter::new(file);
+    if job.stream.is_none() && file_size == 0 {
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let first_chunk = match response.chunk().await {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                return self::error(format!("download error : {:?}", error.without_url()))
+            }
+        };
+
+        let format = StreamFormat::from_content_type(&content_type).or_else(|| {
+            first_chunk
+                .as_ref()
+                .and_then(|chunk| StreamFormat::from_content(&String::from_utf8_lossy(chunk)))
+        });
+        if let Some(format) = format {
+            drop(file);
+            std::fs::remove_file(destination_file).ok();
+            return stream_detected(job, client, format);
+        }
+
+        if let Some(chunk) = first_chunk {
+            if file.write_all(&chunk[..]).is_err() {
+                return error("error occurred while downloading!");
+            }
+            file_size = chunk.len();
+        }
+    }
+
+    (
+        EngineEvent::SizeKnown {
+            size: job.size,
+            downloaded: file_size,
+        },
+        State::SequentialDownloading(response, file, file_size),
+    )
+}
+
+/**
+ * a playlist or manifest recognised by its first bytes, for small files of servers that didn't
+ * name a stream content type
+ */
+async fn sniff_stream(client: &Client, job: &DownloadJob) -> Option<StreamFormat> {
+    let response = request(client, job, &job.url)
+        .header(RANGE, format!("bytes=0-{}", STREAM_SNIFF_LEN - 1))
+        .send()
+        .await
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let bytes = response.bytes().await.ok()?;
+    StreamFormat::from_content(&String::from_utf8_lossy(
+        &bytes[..bytes.len().min(STREAM_SNIFF_LEN)],
+    ))
+}
+
+/**
+ * switches to downloading the segments the playlist or manifest lists
+ */
+fn stream_detected(
+    mut job: DownloadJob,
+    client: Client,
+    format: StreamFormat,
+) -> (EngineEvent, State) {
+    debug!(
+        ?format,
+        "{} is a stream, downloading its segments", job.file_name
+    );
+    job.file_name = format.file_name(&job.file_name);
+    job.stream = Some(format.clone());
+
+    (
+        EngineEvent::StreamDetected {
+            format,
+            file_name: job.file_name.clone(),
+        },
+        State::StreamStarting(client, job),
+    )
+}
+
+async fn handle_stream_starting(job: DownloadJob, client: Client) -> (EngineEvent, State) {
+    let tracks = match &job.stream {
+        Some(StreamFormat::Dash { representations }) => dash::load(&client, &job, representations)
+            .await
+            .map(|tracks| (tracks, HashMap::new())),
+        _ => hls::load(&client, &job)
+            .await
+            .map(|(track, keys)| (vec![track], keys)),
+    };
+
+    match tracks.and_then(|(tracks, keys)| SegmentTransfer::new(client, &job, tracks, keys)) {
+        Ok(segments) => (
+            EngineEvent::Segments {
+                done: segments.done,
+                total: segments.total(),
+                downloaded: segments.downloaded,
+            },
+            State::StreamDownloading(segments),
+        ),
+        Err(error) => self::error(error),
+    }
+}
+
+/**
+ * segments are downloaded whole, so progress moves one segment at a time. Once all are in,
+ * each track is joined like the chunks of a threaded download
+ */
+#[tracing::instrument(skip(controls))]
+async fn handle_stream_downloading(
+    mut segments: SegmentTransfer,
+    controls: &mut Controls,
+) -> (EngineEvent, State) {
+    match segments.next().await {
+        Some(Ok(bytes)) => {
+            controls.throttle(bytes).await;
+            (
+                EngineEvent::Segments {
+                    done: segments.done,
+                    total: segments.total(),
+                    downloaded: segments.downloaded,
+                },
+                State::StreamDownloading(segments),
+            )
+        }
+        Some(Err(error)) => self::error(format!("download error : {error}")),
+        None => (
+            EngineEvent::Downloaded,
+            State::TracksFinished(segments.outputs()),
+        ),
+    }
+}
+
+/**
+ * one connection per chunk with a size and threads to spread over, otherwise one connection
+ * for the whole file, in both cases resuming where the files on disk end
+ */
+#[tracing::instrument]
+async fn handle_ftp_starting(mut job: DownloadJob) -> (EngineEvent, State) {
+    let target = match FtpTarget::parse(&job.url, job.ftp_active) {
+        Ok(target) => target,
+        Err(error) => return self::error(error),
+    };
+    let mut connection = match Connection::open(&target).await {
+        Ok(connection) => connection,
+        Err(error) => return self::error(format!("download error : {error}")),
+    };
+    // without a size the file is read to its end, from the start
+    job.size = connection.size().await.unwrap_or(0);
+
+    let destination_file = Path::new(&job.file_path)
+        .join(&job.file_name)
+        .to_string_lossy()
+        .to_string();
+    let (local, downloaded, files) = match local_ranges(&job, &destination_file) {
+        Ok(ranges) => ranges,
+        Err(error) => return self::error(error),
+    };
+
+    let mut connection = Some(connection);
+    let mut ranges = vec![];
+    for range in local {
+        let connection = match connection.take() {
+            Some(connection) => connection,
+            None => match Connection::open(&target).await {
+                Ok(connection) => connection,
+                Err(error) => return self::error(format!("download error : {error}")),
+            },
+        };
+        match connection.retrieve(range.start).await {
+            Ok(reader) => ranges.push(FtpRange::new(reader, range.file, range.start, range.end)),
+            Err(error) => return self::error(format!("download error : {error}")),
+        }
+    }
+
+    (
+        EngineEvent::SizeKnown {
+            size: job.size,
+            downloaded,
+        },
+        State::FtpDownloading(
+            FtpTransfer::new(ranges, downloaded),
+            destination_file,
+            files,
+        ),
+    )
+}
+
+async fn handle_sftp_starting(mut job: DownloadJob) -> (EngineEvent, State) {
+    let target = match SftpTarget::parse(&job.url) {
+        Ok(target) => target,
+        Err(error) => return self::error(error),
+    };
+    let (session, size) = match SftpSession::open(target).await {
+        Ok(session) => session,
+        Err(error) => return self::error(format!("download error : {error}")),
+    };
+    job.size = size;
+
+    let destination_file = Path::new(&job.file_path)
+        .join(&job.file_name)
+        .to_string_lossy()
+        .to_string();
+    let (local, downloaded, files) = match local_ranges(&job, &destination_file) {
+        Ok(ranges) => ranges,
+        Err(error) => return self::error(error),
+    };
+    let ranges = local
+        .into_iter()
+        .map(|range| SftpRange {
+            file: range.file,
+            start: range.start,
+            end: range.end,
+        })
+        .collect();
+
+    (
+        EngineEvent::SizeKnown {
+            size: job.size,
+            downloaded,
+        },
+        State::SftpDownloading(
+            SftpTransfer::start(session, ranges, downloaded),
+            destination_file,
+            files,
+        ),
+    )
+}
+
+/**
+ * a part of the file still to download and the local file it is appended to
+ */
+struct LocalRange {
+    file: File,
+    start: usize,
+    end: Option<usize>, // last byte, inclusive, none to read to the end of the file
+}
+
+/**
+ * opens the local files of a download that isn't over HTTP, returns the ranges still
+ * missing, the bytes already on disk and the chunk files (none for one connection)
+ */
+fn local_ranges(
+    job: &DownloadJob,
+    destination_file: &str,
+) -> Result<(Vec<LocalRange>, usize, Vec<String>), String> {
+    let open = |path: &str| {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|_| format!("failed to create {}!", job.file_name))
+    };
+
+    let threads = job.threads.max(1) as usize;
+    if job.size == 0 || job.sequential || threads == 1 {
+        let file = open(destination_file)?;
+        let mut offset = file
+            .metadata()
+            .map_or(0, |metadata| metadata.len() as usize);
+        if job.size == 0 || offset > job.size {
+            file.set_len(0)
+                .map_err(|_| format!("failed to truncate {}!", job.file_name))?;
+            offset = 0;
+        }
+        let range = LocalRange {
+            file,
+            start: offset,
+            end: None,
+        };
+        return Ok((vec![range], offset, vec![]));
+    }
+
+    let files = chunk_files(job);
+    let mut ranges = vec![];
+    let mut downloaded = 0;
+    for (i, path) in files.iter().enumerate() {
+        let (start, end) = chunk_range(job.size, threads, i);
+        let end = end.min(job.size - 1);
+        let file = open(path)?;
+        let file_len = file
+            .metadata()
+            .map_or(0, |metadata| metadata.len() as usize);
+        downloaded += file_len;
+        if start + file_len > end {
+            continue;
+        }
+        ranges.push(LocalRange {
+            file,
+            start: start + file_len,
+            end: Some(end),
+        });
+    }
+    Ok((ranges, downloaded, files))
+}
+
+#[tracing::instrument(skip(controls))]
+async fn handle_ftp_downloading(
+    mut transfer: FtpTransfer,
+    destination_file: String,
+    chunk_files: Vec<String>,
+    controls: &mut Controls,
+) -> (EngineEvent, State) {
+    match transfer.next().await {
+        Some(Ok(bytes)) => {
+            controls.throttle(bytes).await;
+            (
+                EngineEvent::Progress(transfer.downloaded),
+                State::FtpDownloading(transfer, destination_file, chunk_files),
+            )
+        }
+        Some(Err(error)) => self::error(format!("download error : {error}")),
+        None if chunk_files.is_empty() => (EngineEvent::Finished, State::Done),
+        None => (
+            EngineEvent::Downloaded,
+            State::ThreadedFinished(destination_file, chunk_files),
+        ),
+    }
+}
+
+#[tracing::instrument(skip(controls))]
+async fn handle_sftp_downloading(
+    mut transfer: SftpTransfer,
+    destination_file: String,
+    chunk_files: Vec<String>,
+    controls: &mut Controls,
+) -> (EngineEvent, State) {
+    match transfer.next().await {
+        Some(Ok(bytes)) => {
+            controls.throttle(bytes).await;
+            (
+                EngineEvent::Progress(transfer.downloaded),
+                State::SftpDownloading(transfer, destination_file, chunk_files),
+            )
+        }
+        Some(Err(error)) => self::error(format!("download error : {error}")),
+        None if chunk_files.is_empty() => (EngineEvent::Finished, State::Done),
+        None => (
+            EngineEvent::Downloaded,
+            State::ThreadedFinished(destination_file, chunk_files),
+        ),
+    }
+}
+
+#[tracing::instrument(skip(controls))]
+async fn handle_torrent_downloading(
+    mut transfer: TorrentTransfer,
+    controls: &mut Controls,
+) -> (EngineEvent, State) {
+    let event = match transfer.next().await {
+        Some(Progress::Received { bytes, downloaded }) => {
+            controls.throttle(bytes).await;
+            EngineEvent::Progress(downloaded)
+        }
+        Some(Progress::Loaded { name, files }) => EngineEvent::TorrentLoaded {
+            file_name: name,
+            files,
+        },
+        Some(Progress::Checked { size, downloaded }) => EngineEvent::SizeKnown { size, downloaded },
+        Some(Progress::Swarm(status)) => EngineEvent::Swarm(status),
+        Some(Progress::Seeding) => EngineEvent::Seeding,
+        Some(Progress::Finished) => return (EngineEvent::Finished, State::Done),
+        Some(Progress::Error(error)) => return self::error(format!("download error : {error}")),
+        None => return self::error("download error : the torrent stopped"),
+    };
+    (event, State::TorrentDownloading(transfer))
+}
+
+async fn handle_sequential_downloading(
+    mut response: Response,
+    mut file: BufWriter<File>,
+    mut downloaded: usize,
+    controls: &mut Controls,
+) -> (EngineEvent, State) {
+    match response.chunk().await {
+        Ok(Some(chunk)) => {
+            if file.write_all(&chunk[..]).is_err() {
+                return error("error occurred while downloading!");
+            }
+            downloaded += chunk.len();
+            controls.throttle(chunk.len()).await;
+
+            (
+                EngineEvent::Progress(downloaded),
+                State::SequentialDownloading(response, file, downloaded),
+            )
+        }
+        Ok(None) => {
+            if file.flush().is_err() {
+                return error("error occurred while downloading!");
+            }
+            (EngineEvent::Finished, State::Done)
+        }
+        Err(error) => self::error(format!("download error : {:?}", error.without_url())),
+    }
+}
+
+/**
+ * the byte range of chunk `index`, ranges are inclusive and every chunk but the last one is
+ * `size / threads + 1` bytes, the last one runs to `size`
+ */
+fn chunk_range(size: usize, threads: usize, index: usize) -> (usize, usize) {
+    let chunk_size = size / threads;
+    let start = (index * chunk_size) + index;
+    let end = if (index + 1) == threads {
+        size
+    } else {
+        start + chunk_size
+    };
+    (start, end)
+}
+
+fn chunk_files(job: &DownloadJob) -> Vec<String> {
+    split_file_name(&job.file_name, job.threads.max(1))
+        .iter()
+        .map(|m| job.cache_dir.join(m).to_string_lossy().to_string())
+        .collect()
+}
+
+#[tracing::instrument]
+async fn handle_threaded_download_starting(
+    mut job: DownloadJob,
+    destination_file: String,
+    chunk_files: Vec<String>,
+    client: Client,
+) -> (EngineEvent, State) {
+    let threads = job.threads.max(1) as usize;
+    let mut sub_downloads: Vec<SubDownloads> = vec![];
+    let mut downloaded = 0;
+    let chunk_size = job.size / threads;
+    let mut sources = Sources::probe(client.clone(), job.clone()).await;
+
+    let mut chunks = vec![];
+    for (i, f) in chunk_files.iter().enumerate() {
+        let (chunk_start, chunk_end) = chunk_range(job.size, threads, i);
+
+        let Ok(file) = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(f)
+        else {
+            return error(format!("Error: failed to create {}!", job.file_name));
+        };
+
+        let file_len = file
+            .metadata()
+            .map_or(0, |metadata| metadata.len() as usize);
+        downloaded += file_len;
+
+        debug!("file_len: {file_len}, chunk_len: {chunk_size}, chunk_start: {chunk_start}");
+
+        if chunk_start + file_len > chunk_end || (chunk_start + file_len) >= job.size {
+            continue;
+        }
+
+        // chunks take turns between the servers
+        chunks.push((
+            i % sources.urls.len(),
+            chunk_start + file_len,
+            chunk_end,
+            file,
+        ));
+    }
+
+    for (source, start, end, file) in chunks {
+        match sources.open_range(source, start, end).await {
+            Ok((source, response)) => sub_downloads.push(SubDownloads {
+                response,
+                file: BufWriter::new(file),
+                source,
+                next: start,
+                end,
+                since: Instant::now(),
+                received: 0,
+            }),
+            // ranges are ignored, every chunk would get the whole file
+            Err(RangeError::Ignored) => {
+                debug!(
+                    "server ignored range requests, downloading {} sequentially",
+                    job.file_name
+                );
+                sub_downloads.clear();
+                chunk_files.iter().for_each(|file| {
+                    std::fs::remove_file(file).ok();
+                });
+                std::fs::remove_file(&destination_file).ok();
+
+                job.sequential = true;
+                job.downloaded = 0;
+                return (
+                    EngineEvent::SizeKnown {
+                        size: job.size,
+                        downloaded: 0,
+                    },
+                    State::Starting(client, job),
+                );
+            }
+            Err(RangeError::Failed(error)) => {
+                debug!(error, "chunk request failed");
+                return self::error(format!(
+                    "The server has returned an error status code for {}!",
+                    job.file_name
+                ));
+            }
+        }
+    }
+
+    (
+        EngineEvent::Progress(downloaded),
+        State::ThreadedDownloading(
+            sources,
+            sub_downloads,
+            destination_file,
+            chunk_files,
+            downloaded,
+        ),
+    )
+}
+
+#[tracing::instrument(skip(controls))]
+async fn handle_threaded_downloading(
+    mut sources: Sources,
+    sub_downloads: Vec<SubDownloads>,
+    destination_file: String,
+    chunk_files: Vec<String>,
+    mut downloaded: usize,
+    controls: &mut Controls,
+) -> (EngineEvent, State) {
+    let mut filtered_sub_downloads = vec![];
+    let mut received = 0;
+
+    for mut sub_download in sub_downloads.into_iter() {
+        // its server was dropped while another chunk was read
+        if !sources.live[sub_download.source] {
+            let source = sub_download.source;
+            if let Err(error) = sources.reassign(&mut sub_download, source).await {
+                return self::error(format!("download error : {error}"));
+            }
+        }
+
+        // a stalled server is only given up on when there is another one to take over
+        let chunk = if sources.live_count() > 1 {
+            match tokio::time::timeout(MIRROR_STALL_TIMEOUT, sub_download.response.chunk()).await {
+                Ok(chunk) => chunk.map_err(|error| format!("{:?}", error.without_url())),
+                Err(_) => Err(format!(
+                    "nothing received for {}s",
+                    MIRROR_STALL_TIMEOUT.as_secs()
+                )),
+            }
+        } else {
+            sub_download
+                .response
+                .chunk()
+                .await
+                .map_err(|error| format!("{:?}", error.without_url()))
+        };
+
+        let failure = match chunk {
+            Ok(Some(chunk)) => {
+                if sub_download.file.write_all(&chunk[..]).is_err() {
+                    return error("writing to chunk file failed!");
+                }
+                received += chunk.len();
+                sub_download.next += chunk.len();
+                sub_download.received += chunk.len();
+                filtered_sub_downloads.push(sub_download);
+                continue;
+            }
+            // the last chunk's range runs one byte past the end of the file, a single server
+            // that closes early is taken at its word as before
+            Ok(None)
+                if sources.live_count() == 1
+                    || sub_download.next > sub_download.end.min(sources.job.size - 1) =>
+            {
+                if sub_download.file.flush().is_err() {
+                    return error("writing to chunk file failed!");
+                }
+                sources.record_rate(&sub_download);
+                continue;
+            }
+            Ok(None) => "the connection closed before the end of the chunk".to_string(),
+            Err(error) => error,
+        };
+
+        if sources.live_count() == 1 {
+            return error(format!("download error : {failure}"));
+        }
+        sources.drop_source(sub_download.source, &failure);
+        let source = sub_download.source;
+        if let Err(error) = sources.reassign(&mut sub_download, source).await {
+            return self::error(format!("download error : {error}"));
+        }
+        filtered_sub_downloads.push(sub_download);
+    }
+
+    if let Some((slowest, fastest)) = sources.too_slow(&filtered_sub_downloads) {
+        sources.drop_source(slowest, "too slow compared to the other servers");
+        for sub_download in filtered_sub_downloads
+            .iter_mut()
+            .filter(|sub_download| sub_download.source == slowest)
+        {
+            if let Err(error) = sources.reassign(sub_download, fastest).await {
+                return self::error(format!("download error : {error}"));
+            }
+        }
+    }
+
+    downloaded += received;
+    controls.throttle(received).await;
+
+    debug!(filtered_downloads = ?filtered_sub_downloads);
+
+    if filtered_sub_downloads.is_empty() {
+        (
+            EngineEvent::Downloaded,
+            State::ThreadedFinished(destination_file, chunk_files),
+        )
+    } else {
+        (
+            sources.dropped.pop().map_or(
+                EngineEvent::Progress(downloaded),
+                EngineEvent::MirrorDropped,
+            ),
+            State::ThreadedDownloading(
+                sources,
+                filtered_sub_downloads,
+                destination_file,
+                chunk_files,
+                downloaded,
+            ),
+        )
+    }
+}
+
+#[tracing::instrument]
+fn handle_threaded_download_finish(
+    destination_file: &str,
+    chunk_files: &[String],
+    outputs: Vec<(String, Vec<String>)>,
+) -> (EngineEvent, State) {
+    match File::create(destination_file) {
+        Ok(out) => {
+            debug!(chunk_files=?chunk_files);
+
+            (
+                EngineEvent::Joining(0),
+                State::FileJoining(Joining {
+                    output: BufWriter::new(out),
+                    parts: chunk_files.to_vec(),
+                    current: 0,
+                    reader: None,
+                    outputs,
+                }),
+            )
+        }
+        Err(error) => {
+            error!("[ATOM] : {}", error);
+            self::error("Error in joining file!")
+        }
+    }
+}
+
+#[tracing::instrument]
+fn handle_joining_progress(mut joining: Joining) -> (EngineEvent, State) {
+    if joining.current >= joining.parts.len() {
+        if joining.output.flush().is_err() {
+            return error("Error in joining file!");
+        }
+        return if joining.outputs.is_empty() {
+            (EngineEvent::Joining(0), State::SequentialFinished)
+        } else {
+            (
+                EngineEvent::Joining(0),
+                State::TracksFinished(joining.outputs),
+            )
+        };
+    }
+
+    let mut reader = match joining.reader.take() {
+        Some(reader) => reader,
+        None => match File::open(&joining.parts[joining.current]) {
+            Ok(file) => BufReader::new(file),
+            Err(_) => {
+                error!(
+                    "[ATOM] : opening {} failed!",
+                    joining.parts[joining.current]
+                );
+                return error("Error in joining file!");
+            }
+        },
+    };
+
+    let mut buffer = vec![0; JOIN_BUFFER_LEN];
+    let copied = match reader.read(&mut buffer) {
+        Ok(0) => {
+            joining.current += 1;
+            0
+        }
+        Ok(read) => {
+            if joining.output.write_all(&buffer[..read]).is_err() {
+                return error("Error in joining file!");
+            }
+            joining.reader = Some(reader);
+            read
+        }
+        Err(_) => return error("Error in joining file!"),
+    };
+
+    (EngineEvent::Joining(copied), State::FileJoining(joining))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{start, torrent::TorrentOptions};
+    use std::{
+        io::Cursor,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+    use tiny_http::{Header, Response as HttpResponse, Server};
+
+    const FILE_LEN: usize = 256 * 1024 + 7;
+
+    type RequestLog = Arc<Mutex<Vec<(String, Option<String>)>>>; // method and Range
+
+    #[derive(Clone, Copy)]
+    enum Behavior {
+        Ranges,
+        IgnoresRanges,  // advertises ranges, then answers every GET with the whole file
+        Status(u16),    // every request
+        GetStatus(u16), // HEAD is fine, every GET fails
+        DropsConnection, // sends half of what Content-Length announces
+    }
+
+    struct TestServer {
+        url: String,
+        requests: RequestLog,
+        credentialed: Arc<AtomicUsize>, // requests with a Cookie or Authorization header
+    }
+
+    fn file_content() -> Vec<u8> {
+        (0..FILE_LEN)
+            .map(|index| (index * 31 % 251) as u8)
+            .collect()
+    }
+
+    /**
+     * serves `file_content` on its own thread, every request on a thread of its own as the
+     * engine keeps several ranges open at once
+     */
+    fn serve(behavior: Behavior) -> TestServer {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file.bin", server.server_addr());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let credentialed = Arc::new(AtomicUsize::new(0));
+        let (log, credentials) = (requests.clone(), credentialed.clone());
+
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let range = request
+                    .headers()
+                    .iter()
+                    .find(|header| header.field.equiv("Range"))
+                    .map(|header| header.value.to_string());
+                if request.headers().iter().any(|header| {
+                    header.field.equiv("Cookie") || header.field.equiv("Authorization")
+                }) {
+                    credentials.fetch_add(1, Ordering::Relaxed);
+                }
+                log.lock()
+                    .unwrap()
+                    .push((request.method().to_string(), range.clone()));
+                std::thread::spawn(move || answer(request, behavior, range));
+            }
+        });
+
+        TestServer {
+            url,
+            requests,
+            credentialed,
+        }
+    }
+
+    fn answer(request: tiny_http::Request, behavior: Behavior, range: Option<String>) {
+        let content = file_content();
+        let header = |name: &str, value: &str| Header::from_bytes(name, value).unwrap();
+        let is_head = *request.method() == tiny_http::Method::Head;
+
+        let (status, body, headers) = match (behavior, range) {
+            (Behavior::Status(status), _) => (status, vec![], vec![]),
+            (Behavior::GetStatus(status), _) if !is_head => (status, vec![], vec![]),
+            (Behavior::Ranges | Behavior::DropsConnection, Some(range)) => {
+                let (start, end) = range.trim_start_matches("bytes=").split_once('-').unwrap();
+                let start: usize = start.parse().unwrap();
+                // the engine asks one byte past the end for the last chunk
+                let end = end
+                    .parse::<usize>()
+                    .map_or(FILE_LEN - 1, |end| end.min(FILE_LEN - 1));
+                (
+                    206,
+                    content[start..=end].to_vec(),
+                    vec![header(
+                        "Content-Range",
+                        &format!("bytes {start}-{end}/{FILE_LEN}"),
+                    )],
+                )
+            }
+            _ => (200, content, vec![]),
+        };
+
+        let length = body.len();
+        let sent = match behavior {
+            Behavior::DropsConnection if !is_head => body[..length / 2].to_vec(),
+            _ => body,
+        };
+        let headers = headers
+            .into_iter()
+            .chain([header("Accept-Ranges", "bytes")])
+            .collect();
+        // a plain Content-Length, chunked answers can't be cut short
+        let response = HttpResponse::new(
+            status.into(),
+            headers,
+            Cursor::new(sent),
+            Some(length),
+            None,
+        )
+        .with_chunked_threshold(usize::MAX);
+        request.respond(response).ok();
+    }
+
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "atom-transfer-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn file(&self) -> PathBuf {
+            self.0.join("file.bin")
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn job(url: &str, dir: &TestDir, threads: u8) -> DownloadJob {
+        DownloadJob {
+            url: url.to_string(),
+            mirrors: vec![],
+            method: Method::GET,
+            headers: HashMap::new(),
+            body: vec![],
+            file_path: dir.0.to_string_lossy().to_string(),
+            file_name: "file.bin".to_string(),
+            cache_dir: dir.0.clone(),
+            threads,
+            sequential: false,
+            size: 0,
+            downloaded: 0,
+            speed_limit: 0,
+            stream: None,
+            ftp_active: false,
+            torrent: TorrentOptions::default(),
+            find_checksum: false,
+        }
+    }
+
+    async fn run_to_end(job: DownloadJob) -> Vec<EngineEvent> {
+        let (_handle, events) = start(job, Client::new());
+        tokio::time::timeout(Duration::from_secs(30), events.collect())
+            .await
+            .expect("the download did not end")
+    }
+
+    fn ranges(server: &TestServer) -> Vec<String> {
+        let mut ranges: Vec<String> = server
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(method, _)| method == "GET")
+            .filter_map(|(_, range)| range.clone())
+            .collect();
+        ranges.sort();
+        ranges
+    }
+
+    fn chunk_files_left(dir: &TestDir) -> usize {
+        std::fs::read_dir(&dir.0)
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().contains(".atom."))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn threaded_download_joins_its_ranges() {
+        let server = serve(Behavior::Ranges);
+        let dir = TestDir::new();
+
+        let events = run_to_end(job(&server.url, &dir, 4)).await;
+
+        assert_eq!(events.last(), Some(&EngineEvent::Finished), "{events:?}");
+        assert!(events.contains(&EngineEvent::SizeKnown {
+            size: FILE_LEN,
+            downloaded: 0
+        }));
+        assert!(events.contains(&EngineEvent::Downloaded));
+        assert_eq!(std::fs::read(dir.file()).unwrap(), file_content());
+        let expected: Vec<String> = (0..4)
+            .map(|index| {
+                let (start, end) = chunk_range(FILE_LEN, 4, index);
+                format!("bytes={start}-{end}")
+            })
+            .collect();
+        // the first kilobyte is fetched once more to tell playlists from files
+        assert!(
+            expected.iter().all(|range| ranges(&server).contains(range)),
+            "{:?}",
+            ranges(&server)
+        );
+    }
+
+    #[tokio::test]
+    async fn mirrors_on_other_origins_get_no_credentials() {
+        let server = serve(Behavior::Ranges);
+        let mirror = serve(Behavior::Ranges);
+        let dir = TestDir::new();
+        let mut job = job(&server.url, &dir, 4);
+        job.mirrors = vec![mirror.url.clone()];
+        job.headers = HashMap::from([
+            ("Cookie".to_string(), "session=1".to_string()),
+            ("Authorization".to_string(), "Bearer 1".to_string()),
+        ]);
+
+        let events = run_to_end(job).await;
+
+        assert_eq!(events.last(), Some(&EngineEvent::Finished), "{events:?}");
+        assert_eq!(std::fs::read(dir.file()).unwrap(), file_content());
+        // the mirror was probed and served ranges, always without the credentials
+        assert!(!ranges(&mirror).is_empty());
+        assert_eq!(mirror.credentialed.load(Ordering::Relaxed), 0);
+        assert!(server.credentialed.load(Ordering::Relaxed) > 0);
+    }
+
+    #[tokio::test]
+    async fn threaded_download_resumes_from_its_chunk_files() {
+        let server = serve(Behavior::Ranges);
+        let dir = TestDir::new();
+        let content = file_content();
+        let mut job = job(&server.url, &dir, 2);
+
+        // half of the first chunk and all of the second made it before the pause
+        let (first_start, first_end) = chunk_range(FILE_LEN, 2, 0);
+        let (second_start, _) = chunk_range(FILE_LEN, 2, 1);
+        let half = (first_end - first_start) / 2;
+        let files = chunk_files(&job);
+        std::fs::write(&files[0], &content[..half]).unwrap();
+        std::fs::write(&files[1], &content[second_start..]).unwrap();
+        job.size = FILE_LEN;
+        job.downloaded = half + FILE_LEN - second_start;
+
+        let events = run_to_end(job).await;
+
+        assert_eq!(events.last(), Some(&EngineEvent::Finished), "{events:?}");
+        assert_eq!(
+            events.first(),
+            Some(&EngineEvent::SizeKnown {
+                size: FILE_LEN,
+                downloaded: half + FILE_LEN - second_start
+            })
+        );
+        // only the rest of the first chunk is asked for
+        assert_eq!(ranges(&server), vec![format!("bytes={half}-{first_end}")]);
+        assert_eq!(std::fs::read(dir.file()).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn sequential_download_resumes_from_the_partial_file() {
+        let server = serve(Behavior::Ranges);
+        let dir = TestDir::new();
+        let content = file_content();
+        std::fs::write(dir.file(), &content[..1000]).unwrap();
+        let mut job = job(&server.url, &dir, 1);
+        job.sequential = true;
+        job.size = FILE_LEN;
+        job.downloaded = 1000;
+
+        let events = run_to_end(job).await;
+
+        assert_eq!(events.last(), Some(&EngineEvent::Finished), "{events:?}");
+        assert_eq!(ranges(&server), vec!["bytes=1000-".to_string()]);
+        assert_eq!(std::fs::read(dir.file()).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn ignored_ranges_fall_back_to_a_single_stream() {
+        let server = serve(Behavior::IgnoresRanges);
+        let dir = TestDir::new();
+
+        let events = run_to_end(job(&server.url, &dir, 4)).await;
+
+        assert_eq!(events.last(), Some(&EngineEvent::Finished), "{events:?}");
+        assert!(!events.contains(&EngineEvent::Downloaded));
+        assert_eq!(std::fs::read(dir.file()).unwrap(), file_content());
+        assert_eq!(chunk_files_left(&dir), 0);
+        // the last GET is the single stream, without a range
+        let requests = server.requests.lock().unwrap();
+        assert_eq!(requests.last(), Some(&("GET".to_string(), None)));
+    }
+
+    #[tokio::test]
+    async fn sequential_restart_when_the_resume_range_is_ignored() {
+        let server = serve(Behavior::IgnoresRanges);
+        let dir = TestDir::new();
+        std::fs::write(dir.file(), b"stale partial content").unwrap();
+        let mut job = job(&server.url, &dir, 1);
+        job.sequential = true;
+        job.size = FILE_LEN;
+        job.downloaded = 21;
+
+        let events = run_to_end(job).await;
+
+        assert_eq!(events.last(), Some(&EngineEvent::Finished), "{events:?}");
+        assert_eq!(std::fs::read(dir.file()).unwrap(), file_content());
+    }
+
+    #[tokio::test]
+    async fn error_statuses_fail_the_download() {
+        for status in [403, 404, 500, 503] {
+            let server = serve(Behavior::Status(status));
+            let dir = TestDir::new();
+
+            let events = run_to_end(job(&server.url, &dir, 4)).await;
+
+            assert!(
+                matches!(events.as_slice(), [EngineEvent::Error(_)]),
+                "{status}: {events:?}"
+            );
+            assert!(!dir.file().exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn failing_ranges_fail_the_download() {
+        for status in [416, 500] {
+            let server = serve(Behavior::GetStatus(status));
+            let dir = TestDir::new();
+
+            let events = run_to_end(job(&server.url, &dir, 4)).await;
+
+            assert!(
+                matches!(events.last(), Some(EngineEvent::Error(_))),
+                "{status}: {events:?}"
+            );
+            assert!(!events.contains(&EngineEvent::Finished));
+        }
+    }
+
+    #[tokio::test]
+    async fn dropped_connections_fail_the_download() {
+        for (threads, sequential) in [(4, false), (1, true)] {
+            let server = serve(Behavior::DropsConnection);
+            let dir = TestDir::new();
+            let mut job = job(&server.url, &dir, threads);
+            job.sequential = sequential;
+            // the server closes after each answer, so a short body ends the connection
+            job.headers
+                .insert("connection".to_string(), "close".to_string());
+
+            let events = run_to_end(job).await;
+
+            assert!(
+                matches!(events.last(), Some(EngineEvent::Error(error)) if error.starts_with("download error")),
+                "{threads} threads: {events:?}"
+            );
+            assert!(!events.contains(&EngineEvent::Finished));
+        }
+    }
+
+    #[test]
+    fn local_ranges_resume_where_the_files_end() {
+        let dir = TestDir::new();
+        let mut job = job("sftp://example.com/file.bin", &dir, 1);
+        let destination = dir.file().to_string_lossy().to_string();
+        std::fs::write(&destination, [0; 1000]).unwrap();
+
+        job.size = 5000;
+        let (ranges, downloaded, files) = local_ranges(&job, &destination).unwrap();
+        assert_eq!(downloaded, 1000);
+        assert!(files.is_empty());
+        assert_eq!((ranges[0].start, ranges[0].end), (1000, None));
+
+        // more on disk than the file holds, or no size to check against, starts over
+        for size in [500, 0] {
+            std::fs::write(&destination, [0; 1000]).unwrap();
+            job.size = size;
+            let (ranges, downloaded, _) = local_ranges(&job, &destination).unwrap();
+            assert_eq!((ranges[0].start, downloaded), (0, 0), "{size}");
+            assert_eq!(std::fs::metadata(&destination).unwrap().len(), 0);
+        }
+
+        // a complete chunk has nothing left, the other continues after what it holds
+        job.threads = 2;
+        job.size = 1000;
+        for (path, len) in chunk_files(&job).iter().zip([501, 100]) {
+            std::fs::write(path, vec![0; len]).unwrap();
+        }
+        let (ranges, downloaded, files) = local_ranges(&job, &destination).unwrap();
+        assert_eq!(downloaded, 601);
+        assert_eq!(files, chunk_files(&job));
+        let ranges: Vec<(usize, Option<usize>)> = ranges
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect();
+        assert_eq!(ranges, [(601, Some(999))]);
+    }
+}
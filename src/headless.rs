@@ -0,0 +1,198 @@
+use crate::{
+    api,
+    components::atom::Atom,
+    messages::{Message, TitleBarMessage},
+    utils::helpers::{ATOM_AUTOSAVE_INTERVAL, ATOM_SETTINGS_POLL_INTERVAL},
+};
+use iced::{
+    futures::{future, Stream, StreamExt},
+    Task as Command,
+};
+use iced_runtime::Action;
+use std::{collections::HashMap, process::ExitCode, time::Duration};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/**
+ * runs the download engine, capture listener and local API without opening any window
+ */
+pub fn run() -> ExitCode {
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(error) => {
+            error!("Error: cannot start the async runtime: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    runtime.block_on(daemon())
+}
+
+async fn daemon() -> ExitCode {
+    let mut atom = Atom::new(true);
+    if !atom
+        .instance
+        .as_ref()
+        .is_some_and(|instance| instance.is_single())
+    {
+        error!("Error: another ATOM instance is already running, exiting.");
+        return ExitCode::FAILURE;
+    }
+
+    let (sender, mut receiver) = unbounded_channel();
+    let mut running: HashMap<(Uuid, bool), JoinHandle<()>> = HashMap::new();
+
+    let mut api_server = None;
+    sync_api_server(&atom, &mut api_server, &sender);
+
+    let exit_sender = sender.clone();
+    tokio::spawn(async move {
+        if let Err(error) = tokio::signal::ctrl_c().await {
+            warn!("Error: cannot listen for Ctrl+C: {error}");
+            return;
+        }
+        exit_sender
+            .send(Message::TitleBar(TitleBarMessage::AppExit))
+            .ok();
+    });
+
+    tick(ATOM_AUTOSAVE_INTERVAL, Message::AutoSave, sender.clone());
+    tick(
+        ATOM_SETTINGS_POLL_INTERVAL,
+        Message::CheckSettingsFile,
+        sender.clone(),
+    );
+
+    println!(
+        "ATOM is running headless with {} download(s), local API on {}, press Ctrl+C to stop",
+        atom.downloads.len(),
+        atom.settings.api_address
+    );
+
+    sync_engines(&atom, &mut running, &sender);
+    while let Some(message) = receiver.recv().await {
+        if let Message::StatusBar(status) = &message {
+            info!("{status}");
+        }
+
+        let command = atom.update(message);
+        run_command(command, &sender);
+
+        if atom.should_exit {
+            break;
+        }
+        sync_engines(&atom, &mut running, &sender);
+        sync_api_server(&atom, &mut api_server, &sender);
+    }
+
+    running.values().for_each(JoinHandle::abort);
+    ExitCode::SUCCESS
+}
+
+/**
+ * mirrors iced's subscription diffing: one engine (or checksum check) per active download,
+ * started once and dropped when the download stops
+ */
+fn sync_engines(
+    atom: &Atom,
+    running: &mut HashMap<(Uuid, bool), JoinHandle<()>>,
+    sender: &UnboundedSender<Message>,
+) {
+    running.retain(|(id, verifying), engine| {
+        let active = atom
+            .downloads
+            .get(id)
+            .is_some_and(|download| download.is_active() && download.is_verifying() == *verifying);
+        if !active {
+            engine.abort();
+        }
+        active
+    });
+
+    atom.downloads.iter().for_each(|(&id, download)| {
+        let key = (id, download.is_verifying());
+        if running.contains_key(&key) {
+            return;
+        }
+        if let Some(stream) = download.stream(&atom.settings, atom.client.clone(), &atom.engines) {
+            running.insert(key, forward(stream, sender.clone()));
+        }
+    });
+}
+
+/**
+ * restarts the local API when a reloaded settings.toml changes its address, token and revoked
+ * clients apply in place. The listener thread stops once its stream is dropped
+ */
+fn sync_api_server(
+    atom: &Atom,
+    api_server: &mut Option<(String, JoinHandle<()>)>,
+    sender: &UnboundedSender<Message>,
+) {
+    atom.api_auth.sync(&atom.settings);
+    let address = &atom.settings.api_address;
+    if api_server
+        .as_ref()
+        .is_some_and(|(running, _)| running == address)
+    {
+        return;
+    }
+
+    if let Some((_, server)) = api_server.take() {
+        server.abort();
+    }
+    let server = forward(
+        api::server::serve(address.clone(), atom.api_auth.clone(), atom.events.clone()),
+        sender.clone(),
+    );
+    *api_server = Some((address.clone(), server));
+}
+
+/**
+ * sends `message` every `period` until the daemon stops listening
+ */
+fn tick(period: Duration, message: Message, sender: UnboundedSender<Message>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if sender.send(message.clone()).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/**
+ * only plain message outputs matter here, window and widget actions have nothing to act on
+ */
+fn run_command(command: Command<Message>, sender: &UnboundedSender<Message>) {
+    if let Some(stream) = iced_runtime::task::into_stream(command) {
+        forward(
+            stream.filter_map(|action| {
+                future::ready(match action {
+                    Action::Output(message) => Some(message),
+                    _ => None,
+                })
+            }),
+            sender.clone(),
+        );
+    }
+}
+
+fn forward(
+    stream: impl Stream<Item = Message> + Send + 'static,
+    sender: UnboundedSender<Message>,
+) -> JoinHandle<()> {
+    tokio::spawn(stream.for_each(move |message| {
+        sender.send(message).ok();
+        future::ready(())
+    }))
+}
@@ -2,22 +2,25 @@
 //  cargo rustc --release -- -C link-args="resources.res"
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use crate::app::App;
+use crate::{app::App, cli::CliCommand, utils::paths};
 use font::MONOSPACED_FONT_BYTES;
 use iced::Font;
 use tracing_subscriber::{prelude::*, registry, EnvFilter};
+mod api;
 mod app;
+mod cli;
 mod components;
 mod elements;
+mod engine;
 mod font;
+mod headless;
 mod icons;
 mod messages;
 mod style;
 mod utils;
-use std::{env, fs::File, sync::Arc};
+use std::{env, fs::File, process::ExitCode, sync::Arc};
 
-#[tracing::instrument]
-fn main() -> iced::Result {
+fn init_logging() {
     let stdout_log = tracing_subscriber::fmt::layer().pretty();
 
     if let Ok(log_file_path) = env::var("ATOM_LOG_FILE") {
@@ -36,9 +39,44 @@ fn main() -> iced::Result {
             .with(EnvFilter::from_default_env())
             .init();
     }
+}
+
+#[tracing::instrument]
+fn main() -> ExitCode {
+    // subcommands talk to the running instance and exit
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        cli::attach_console();
+    }
+    let args = match cli::take_config_dir(&args) {
+        Ok((config_dir, args)) => {
+            if let Some(config_dir) = config_dir {
+                paths::set_config_dir(config_dir);
+            }
+            args
+        }
+        Err(error) => return cli::usage_error(&error),
+    };
+    let open = match cli::parse(args) {
+        Ok(Some(CliCommand::Headless)) => {
+            init_logging();
+            return headless::run();
+        }
+        // with nothing running the app starts and opens them itself
+        Ok(Some(CliCommand::Open(items))) if !cli::instance_running() => items,
+        Ok(Some(command)) => return cli::run(command),
+        Err(error) => return cli::usage_error(&error),
+        // a second launch hands over to the running instance, when that fails the app still
+        // starts and tells that another instance is running
+        Ok(None) if cli::instance_running() && cli::show_running_instance() => {
+            return ExitCode::SUCCESS
+        }
+        Ok(None) => vec![],
+    };
+    init_logging();
 
     // run app
-    iced::daemon(App::title, App::update, App::view)
+    let result = iced::daemon(App::title, App::update, App::view)
         .theme(App::theme)
         .scale_factor(App::scale_factor)
         .subscription(App::subscription)
@@ -50,5 +88,13 @@ fn main() -> iced::Result {
             ..Default::default()
         })
         .font(MONOSPACED_FONT_BYTES)
-        .run_with(App::new)
+        .run_with(move || App::new(open));
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            tracing::error!("Error: {error:?}");
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -2,17 +2,16 @@ use iced::{window::Id, Size};
 use tray_icon::menu::MenuId;
 
 use crate::{
-    components::download::{AtomDownload, DownloadType},
-    utils::json_from_browser::JSONFromBrowser,
+    api::{ApiClient, ApiRequest, ApiResponder},
+    components::download::AtomDownload,
+    engine::{dash::Representation, hls::Variant, torrent::TorrentFile, StreamFormat, SwarmStatus},
+    utils::{
+        checksum::{Checksum, ChecksumAlgorithm},
+        json_from_browser::JSONFromBrowser,
+    },
 };
 use std::path::PathBuf;
-
-#[derive(Debug)]
-pub struct DownloadProperties {
-    pub content_length: usize,
-    pub download_type: DownloadType,
-    pub error: String,
-}
+use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub enum DownloadMessage {
@@ -24,6 +23,14 @@ pub enum DownloadMessage {
     DownloadDoneJoining,
     Finished,
     Error(String),
+    MirrorDropped(String),
+    StreamDetected(StreamFormat, String), // the download is a stream saved under this name
+    Segments(usize, usize, usize),        // segments done, segments in total, bytes downloaded
+    Verified(Result<(), String>),         // checksum check of the finished file
+    TorrentLoaded(String, Vec<TorrentFile>), // the torrent's files, saved under this name
+    Swarm(SwarmStatus),
+    Seeding,                 // the torrent is in and uploads until its seeding limits
+    ChecksumFound(Checksum), // published by the server, checked once the download finishes
     DownloadSelected,
     MarkDeleted,
     RemoveDownload(bool), // force delete is true (for trash)
@@ -40,6 +47,16 @@ pub enum DownloadFormMessage {
     EditHeaderValue(String, String),
     DeleteHeader(String),
     AddHeader,
+    MirrorUrlChange(String),
+    AddMirror,
+    DeleteMirror(String),
+    ChecksumChange(String),
+    StreamVariants(String, Result<Vec<Variant>, String>), // for the playlist URL
+    VariantSelected(Variant),
+    StreamRepresentations(String, Result<Vec<Representation>, String>), // for the manifest URL
+    RepresentationSelected(Representation),
+    TorrentFiles(String, Result<Vec<TorrentFile>, String>), // for the torrent URL
+    TorrentFileToggled(usize, bool),
     FileSavePathChanged(Option<PathBuf>),
     HeaderFilePath(Option<PathBuf>),
     BrowseSaveAsFolder,
@@ -76,11 +93,20 @@ pub enum SettingsMessage {
     ListBackgroundToggle(bool),
     AlwaysShowPreviewPaneToggle(bool),
     ScrollbarsVisible(bool),
+    FtpActiveModeToggle(bool),
+    TorrentDhtToggle(bool),
+    FindChecksumsToggle(bool),
+    TorrentPortChanged(String),
+    TorrentSeedRatioChanged(f64),
+    TorrentSeedMinutesChanged(u32),
     ThemeChanged(String),
     ListLayoutChanged(String),
     NewDownloadPositionChanged(String),
     ScalingChanged(f64),
     TextSizeChanged(f32),
+    ApiAddressChanged(String),
+    RegenerateApiToken,
+    RevokeClient(String),
     DownloadDirSelected(Option<PathBuf>),
     // BrowseCacheDirClicked,
     ClosePane,
@@ -131,8 +157,12 @@ pub enum MetadataMessage {
     PreviewFile,
     DeleteFile,
     ClosePane,
+    ChecksumAlgorithmSelected(ChecksumAlgorithm),
     CalculateChecksum,
-    Checksum(String, String), // file path and url
+    CancelChecksum,
+    ChecksumProgress(usize),                  // bytes hashed so far
+    Checksum(Uuid, Result<Checksum, String>), // of the download's file
+    ExpectedChecksumChange(String),
     Ignore,
 }
 
@@ -156,13 +186,17 @@ pub enum Message {
     Sidebar(SidebarMessage),
     DownloadForm(DownloadFormMessage, Option<Id>),
     NewDownloadReceivedFromBrowser(JSONFromBrowser),
+    Api(ApiClient, ApiRequest, ApiResponder),
     AddNewDownload(AtomDownload),
+    Open(Vec<String>), // URLs and links files from `atom <URL|file>...`, empty just shows the app
     SaveDownloads,
+    AutoSave,          // periodic, only saves when progress changed since the last save
+    CheckSettingsFile, // periodic, picks up edits other programs made to settings.toml
     GotoHomePage,
-    Download(DownloadMessage, usize),
+    Download(DownloadMessage, Uuid),
     DownloadsListFilter(DownloadsListFilterMessage),
     Settings(SettingsMessage),
-    ShowMetadata(usize),
+    ShowMetadata(Uuid),
     Metadata(MetadataMessage),
     Import(ImportMessage),
     TrayMessages(TrayMessage),
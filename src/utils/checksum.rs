@@ -0,0 +1,537 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::{Digest, Md5};
+use reqwest::header::HeaderMap;
+use ring::digest::{Algorithm, Context, SHA1_FOR_LEGACY_USE_ONLY, SHA256, SHA384, SHA512};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::File,
+    io::Read,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+const READ_BUFFER_LEN: usize = 1024 * 1024;
+
+// weakest first, so the strongest of several hashes is the largest
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha1,
+    #[default]
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    pub const ALL: [Self; 5] = [
+        Self::Md5,
+        Self::Sha1,
+        Self::Sha256,
+        Self::Sha384,
+        Self::Sha512,
+    ];
+
+    /**
+     * `sha-256`, `SHA256`, `sha_256` and the like, as found in metalinks, headers and file names
+     */
+    pub fn from_name(name: &str) -> Option<Self> {
+        match &name.to_lowercase().replace(['-', '_'], "")[..] {
+            "md5" => Some(Self::Md5),
+            "sha1" => Some(Self::Sha1),
+            "sha256" => Some(Self::Sha256),
+            "sha384" => Some(Self::Sha384),
+            "sha512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    /**
+     * the algorithm whose hex digests have this many characters
+     */
+    pub fn from_hex_len(len: usize) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.hex_len() == len)
+    }
+
+    fn output_len(&self) -> usize {
+        match self {
+            Self::Md5 => 16,
+            Self::Sha1 => SHA1_FOR_LEGACY_USE_ONLY.output_len(),
+            Self::Sha256 => SHA256.output_len(),
+            Self::Sha384 => SHA384.output_len(),
+            Self::Sha512 => SHA512.output_len(),
+        }
+    }
+
+    pub fn hex_len(&self) -> usize {
+        self.output_len() * 2
+    }
+
+    fn hasher(&self) -> Hasher {
+        let algorithm: &'static Algorithm = match self {
+            Self::Md5 => return Hasher::Md5(Md5::new()),
+            Self::Sha1 => &SHA1_FOR_LEGACY_USE_ONLY,
+            Self::Sha256 => &SHA256,
+            Self::Sha384 => &SHA384,
+            Self::Sha512 => &SHA512,
+        };
+        Hasher::Ring(Context::new(algorithm))
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Md5 => "MD5",
+            Self::Sha1 => "SHA-1",
+            Self::Sha256 => "SHA-256",
+            Self::Sha384 => "SHA-384",
+            Self::Sha512 => "SHA-512",
+        })
+    }
+}
+
+// ring has no MD5, it is still what Content-MD5 and cloud storage send
+enum Hasher {
+    Ring(Context),
+    Md5(Md5),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Ring(context) => context.update(data),
+            Self::Md5(md5) => md5.update(data),
+        }
+    }
+
+    fn finish(self) -> String {
+        let digest = match self {
+            Self::Ring(context) => context.finish().as_ref().to_vec(),
+            Self::Md5(md5) => md5.finalize().to_vec(),
+        };
+        digest.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+}
+
+/**
+ * the hash a finished download is expected to have
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub digest: String, // lowercase hex
+}
+
+impl Checksum {
+    /**
+     * none when the digest isn't hex of the algorithm's length
+     */
+    pub fn new(algorithm: ChecksumAlgorithm, digest: &str) -> Option<Self> {
+        let digest = digest.trim().to_lowercase();
+        (digest.len() == algorithm.hex_len() && digest.chars().all(|c| c.is_ascii_hexdigit()))
+            .then_some(Self { algorithm, digest })
+    }
+
+    /**
+     * hex, or base64 as headers carry it
+     */
+    pub fn from_encoded(algorithm: ChecksumAlgorithm, digest: &str) -> Option<Self> {
+        Self::new(algorithm, digest).or_else(|| {
+            let bytes = STANDARD.decode(digest.trim()).ok()?;
+            (bytes.len() == algorithm.output_len()).then(|| Self {
+                algorithm,
+                digest: bytes.iter().map(|byte| format!("{byte:02x}")).collect(),
+            })
+        })
+    }
+
+    /**
+     * what a user pastes: `sha256:<hex>`, `SHA-256 <hex>` or a bare hex digest whose length
+     * tells the algorithm
+     */
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        match text.split_once([':', '=', ' ']) {
+            Some((name, digest)) => Self::from_encoded(
+                ChecksumAlgorithm::from_name(name.trim())?,
+                digest.trim_start_matches([':', '=', ' ']),
+            ),
+            None => Self::new(ChecksumAlgorithm::from_hex_len(text.len())?, text),
+        }
+    }
+
+    /**
+     * hashes the file and compares, the error tells both digests apart
+     */
+    pub fn verify(&self, path: &Path) -> Result<(), String> {
+        let digest = file_digest(path, self.algorithm)
+            .map_err(|error| format!("verifying {} failed: {error}", path.display()))?;
+        if digest == self.digest {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} mismatch, expected {} but the file has {digest}",
+                self.algorithm, self.digest
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.algorithm, self.digest)
+    }
+}
+
+/**
+ * the strongest whole-file hash a response announces: `Repr-Digest`, `Digest`, `x-goog-hash`
+ * and, unless the response is a partial one, `Content-MD5`
+ */
+pub fn from_headers(headers: &HeaderMap, whole_file: bool) -> Option<Checksum> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|item| item.trim().split_once('='))
+            .collect::<Vec<_>>()
+    };
+
+    let mut checksums = vec![];
+    // RFC 9530 wraps the base64 in colons
+    values("repr-digest")
+        .into_iter()
+        .chain(values("x-goog-hash"))
+        .for_each(|(name, digest)| {
+            if let Some(algorithm) = ChecksumAlgorithm::from_name(name) {
+                checksums.extend(Checksum::from_encoded(algorithm, digest.trim_matches(':')));
+            }
+        });
+    // RFC 3230 calls SHA-1 just `SHA`
+    values("digest").into_iter().for_each(|(name, digest)| {
+        let algorithm = if name.eq_ignore_ascii_case("sha") {
+            Some(ChecksumAlgorithm::Sha1)
+        } else {
+            ChecksumAlgorithm::from_name(name)
+        };
+        if let Some(algorithm) = algorithm {
+            checksums.extend(Checksum::from_encoded(algorithm, digest));
+        }
+    });
+    if whole_file {
+        headers
+            .get_all("content-md5")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .for_each(|digest| {
+                checksums.extend(Checksum::from_encoded(ChecksumAlgorithm::Md5, digest));
+            });
+    }
+
+    checksums
+        .into_iter()
+        .max_by_key(|checksum| checksum.algorithm)
+}
+
+/**
+ * the digest of `file_name` in a `sha256sum` style listing (`<hex>  <name>`, `<hex> *<name>`)
+ * or a BSD style one (`SHA256 (<name>) = <hex>`). A listing of a single unnamed or differently
+ * named file counts when `single_file` is set, as `<file>.sha256` sidecars often are
+ */
+pub fn from_listing(
+    listing: &str,
+    algorithm: ChecksumAlgorithm,
+    file_name: &str,
+    single_file: bool,
+) -> Option<Checksum> {
+    let entries = listing
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (digest, name) = match line.split_once(" (") {
+                Some((_, rest)) => rest
+                    .rsplit_once(") = ")
+                    .map(|(name, digest)| (digest, name))?,
+                None => line
+                    .split_once(char::is_whitespace)
+                    .map_or((line, ""), |(digest, name)| (digest, name.trim_start())),
+            };
+            let name = name.trim_start_matches('*');
+            let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+            Some((Checksum::new(algorithm, digest)?, name))
+        })
+        .collect::<Vec<_>>();
+
+    let single = single_file && entries.len() == 1;
+    entries
+        .into_iter()
+        .find(|(_, name)| single || name.is_empty() || *name == file_name)
+        .map(|(checksum, _)| checksum)
+}
+
+pub fn file_digest(path: &Path, algorithm: ChecksumAlgorithm) -> std::io::Result<String> {
+    file_digest_with_progress(path, algorithm, &AtomicBool::new(false), |_| {})
+        .map(Option::unwrap_or_default)
+}
+
+/**
+ * hashes in large reads and reports the bytes hashed so far after each, none once `cancel`
+ * is set. Blocks, run it off the async runtime
+ */
+pub fn file_digest_with_progress(
+    path: &Path,
+    algorithm: ChecksumAlgorithm,
+    cancel: &AtomicBool,
+    mut progress: impl FnMut(usize),
+) -> std::io::Result<Option<String>> {
+    let mut file = File::open(path)?;
+    let mut hasher = algorithm.hasher();
+    let mut buffer = vec![0; READ_BUFFER_LEN];
+    let mut hashed = 0;
+
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        match file.read(&mut buffer)? {
+            0 => break,
+            count => {
+                hasher.update(&buffer[..count]);
+                hashed += count;
+                progress(hashed);
+            }
+        }
+    }
+
+    Ok(Some(hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use std::path::PathBuf;
+
+    const MD5_ABC: &str = "900150983cd24fb0d6963f7d28e17f72";
+    const SHA1_ABC: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+    const SHA256_ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(name: &str, content: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("atom-checksum-{}-{name}", std::process::id()));
+            std::fs::write(&path, content).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    fn sha256(digest: &str) -> Option<Checksum> {
+        Checksum::new(ChecksumAlgorithm::Sha256, digest)
+    }
+
+    #[test]
+    fn pasted_checksums() {
+        for text in [
+            format!("sha256:{SHA256_ABC}"),
+            format!("SHA-256 {}", SHA256_ABC.to_uppercase()),
+            "sha_256=ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=".to_string(),
+            format!("  {SHA256_ABC}\n"),
+        ] {
+            assert_eq!(Checksum::parse(&text), sha256(SHA256_ABC), "{text}");
+        }
+        assert_eq!(
+            Checksum::parse(MD5_ABC),
+            Checksum::new(ChecksumAlgorithm::Md5, MD5_ABC)
+        );
+        for text in ["sha256:abc", "crc32:352441c2", "not a digest", ""] {
+            assert_eq!(Checksum::parse(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn digest_headers() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.append(*name, HeaderValue::from_static(value));
+            }
+            headers
+        };
+
+        // the strongest wins, whatever header it came in
+        let checksum = from_headers(
+            &headers(&[
+                ("content-md5", "kAFQmDzST7DWlj99KOF/cg=="),
+                ("digest", "SHA=qZk+NkcGgWq6PiVxeFDCbJzQ2J0=,unixsum=30637"),
+                (
+                    "repr-digest",
+                    "sha-256=:ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=:",
+                ),
+            ]),
+            true,
+        );
+        assert_eq!(checksum, sha256(SHA256_ABC));
+
+        let checksum = from_headers(
+            &headers(&[("digest", "sha=qZk+NkcGgWq6PiVxeFDCbJzQ2J0=")]),
+            true,
+        );
+        assert_eq!(checksum, Checksum::new(ChecksumAlgorithm::Sha1, SHA1_ABC));
+        let checksum = from_headers(
+            &headers(&[(
+                "x-goog-hash",
+                "crc32c=n03x6A==, md5=kAFQmDzST7DWlj99KOF/cg==",
+            )]),
+            true,
+        );
+        assert_eq!(checksum, Checksum::new(ChecksumAlgorithm::Md5, MD5_ABC));
+
+        // Content-MD5 of a partial response only covers the part
+        let partial = headers(&[("content-md5", "kAFQmDzST7DWlj99KOF/cg==")]);
+        assert_eq!(from_headers(&partial, false), None);
+        // a digest of the wrong length
+        let short = headers(&[("digest", "sha-256=qZk+NkcGgWq6PiVxeFDCbJzQ2J0=")]);
+        assert_eq!(from_headers(&short, true), None);
+    }
+
+    #[test]
+    fn sidecar_listings() {
+        let listing = format!(
+            "# release checksums\n\
+             {MD5_ABC}{MD5_ABC}  other.iso\n\
+             not a checksum line\n\
+             {SHA256_ABC} *dist/example.iso\n\
+             SHA256 (example.tar.gz) = {SHA256_ABC}\n\
+             {}  short.iso\n",
+            &SHA256_ABC[1..]
+        );
+        let find = |name| from_listing(&listing, ChecksumAlgorithm::Sha256, name, false);
+        assert_eq!(find("example.iso"), sha256(SHA256_ABC));
+        assert_eq!(find("example.tar.gz"), sha256(SHA256_ABC));
+        assert_eq!(
+            find("other.iso"),
+            Some(Checksum {
+                algorithm: ChecksumAlgorithm::Sha256,
+                digest: format!("{MD5_ABC}{MD5_ABC}"),
+            })
+        );
+        // malformed lines never match
+        assert_eq!(find("short.iso"), None);
+        assert_eq!(find("missing.iso"), None);
+
+        // a sidecar names one file, possibly under another name, or none at all
+        let sidecar = format!("{SHA256_ABC}  renamed.iso\n");
+        assert_eq!(
+            from_listing(&sidecar, ChecksumAlgorithm::Sha256, "example.iso", true),
+            sha256(SHA256_ABC)
+        );
+        assert_eq!(
+            from_listing(&sidecar, ChecksumAlgorithm::Sha256, "example.iso", false),
+            None
+        );
+        assert_eq!(
+            from_listing(SHA256_ABC, ChecksumAlgorithm::Sha256, "example.iso", false),
+            sha256(SHA256_ABC)
+        );
+        assert_eq!(
+            from_listing(
+                "<html>not found</html>",
+                ChecksumAlgorithm::Sha256,
+                "x",
+                true
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn verification_matches_and_mismatches() {
+        let file = TestFile::new("verify", b"abc");
+        assert_eq!(sha256(SHA256_ABC).unwrap().verify(&file.0), Ok(()));
+        assert_eq!(
+            Checksum::new(ChecksumAlgorithm::Md5, MD5_ABC)
+                .unwrap()
+                .verify(&file.0),
+            Ok(())
+        );
+
+        let other = "0".repeat(64);
+        assert_eq!(
+            sha256(&other).unwrap().verify(&file.0),
+            Err(format!(
+                "SHA-256 mismatch, expected {other} but the file has {SHA256_ABC}"
+            ))
+        );
+        assert!(sha256(SHA256_ABC)
+            .unwrap()
+            .verify(&file.0.with_extension("missing"))
+            .unwrap_err()
+            .starts_with("verifying"));
+    }
+
+    #[test]
+    fn every_algorithm_streams_the_file() {
+        let file = TestFile::new("algorithms", b"abc");
+        let expected = [
+            MD5_ABC,
+            SHA1_ABC,
+            SHA256_ABC,
+            "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed\
+             8086072ba1e7cc2358baeca134c825a7",
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+        ];
+        for (algorithm, digest) in ChecksumAlgorithm::ALL.into_iter().zip(expected) {
+            assert_eq!(digest.len(), algorithm.hex_len());
+            assert_eq!(
+                file_digest(&file.0, algorithm).unwrap(),
+                digest,
+                "{algorithm}"
+            );
+        }
+    }
+
+    #[test]
+    fn progress_and_cancelling() {
+        let content = vec![7; READ_BUFFER_LEN * 2 + 5];
+        let file = TestFile::new("progress", &content);
+
+        let mut reported = vec![];
+        let digest = file_digest_with_progress(
+            &file.0,
+            ChecksumAlgorithm::Sha256,
+            &AtomicBool::new(false),
+            |hashed| reported.push(hashed),
+        )
+        .unwrap();
+        assert_eq!(
+            digest,
+            Some(file_digest(&file.0, ChecksumAlgorithm::Sha256).unwrap())
+        );
+        assert_eq!(reported.last(), Some(&content.len()));
+        assert!(reported.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let cancel = AtomicBool::new(false);
+        let digest = file_digest_with_progress(&file.0, ChecksumAlgorithm::Md5, &cancel, |_| {
+            cancel.store(true, Ordering::Relaxed)
+        })
+        .unwrap();
+        assert_eq!(digest, None);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::utils::{helpers::ATOM_ICON, json_from_browser::ATOM_URI_SCHEME, paths};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+const DESKTOP_FILE_NAME: &str = "atom.desktop";
+const ICON_SIZE: u32 = 256;
+// links files and metalinks show up in "Open with", atom:// links open the add window
+const MIME_TYPES: [&str; 4] = [
+    "application/metalink+xml",
+    "application/metalink4+xml",
+    "text/uri-list",
+    "x-scheme-handler/atom",
+];
+
+/**
+ * XDG desktop entry and icon locations under the user's data directory
+ */
+struct DesktopPaths {
+    applications: PathBuf,
+    desktop_file: PathBuf,
+    icon: PathBuf,
+}
+
+impl DesktopPaths {
+    fn resolve() -> Result<Self, String> {
+        let data_dir = directories::BaseDirs::new()
+            .ok_or("no home directory found")?
+            .data_dir()
+            .to_path_buf();
+        let applications = data_dir.join("applications");
+
+        Ok(Self {
+            desktop_file: applications.join(DESKTOP_FILE_NAME),
+            applications,
+            icon: data_dir
+                .join("icons/hicolor")
+                .join(format!("{ICON_SIZE}x{ICON_SIZE}"))
+                .join("apps/atom.png"),
+        })
+    }
+}
+
+/**
+ * writes the desktop entry and icon and registers ATOM for `atom://` links, returns the
+ * desktop file and whether the scheme handler could be registered
+ */
+pub fn install() -> Result<(PathBuf, bool), String> {
+    if !cfg!(target_os = "linux") {
+        return Err("desktop integration is only available on Linux".to_string());
+    }
+
+    let paths = DesktopPaths::resolve()?;
+    let exe = std::env::current_exe()
+        .map_err(|error| format!("cannot find the ATOM executable: {error}"))?;
+
+    write_icon(&paths.icon)?;
+    fs::create_dir_all(&paths.applications)
+        .and_then(|_| fs::write(&paths.desktop_file, desktop_entry(&exe)))
+        .map_err(|error| format!("cannot write {}: {error}", paths.desktop_file.display()))?;
+
+    run_quietly("update-desktop-database", &[&paths.applications]);
+    let registered = run_quietly(
+        "xdg-mime",
+        &[
+            "default",
+            DESKTOP_FILE_NAME,
+            &format!("x-scheme-handler/{ATOM_URI_SCHEME}"),
+        ],
+    );
+
+    Ok((paths.desktop_file, registered))
+}
+
+/**
+ * removes what `install` wrote, returns the files that were removed
+ */
+pub fn uninstall() -> Result<Vec<PathBuf>, String> {
+    let paths = DesktopPaths::resolve()?;
+    let removed = [paths.desktop_file, paths.icon]
+        .into_iter()
+        .filter(|path| fs::remove_file(path).is_ok())
+        .collect();
+
+    run_quietly("update-desktop-database", &[&paths.applications]);
+    Ok(removed)
+}
+
+fn desktop_entry(exe: &Path) -> String {
+    let mut exec = quote_exec_arg(&exe.to_string_lossy());
+    if let Some(config_dir) = paths::explicit_config_dir() {
+        exec.push_str(" --config-dir ");
+        exec.push_str(&quote_exec_arg(&config_dir.to_string_lossy()));
+    }
+
+    format!(
+        "[Desktop Entry]
+Type=Application
+Name=ATOM Download Manager
+GenericName=Download Manager
+Comment=Download files with multiple connections
+Exec={exec} %U
+Icon=atom
+Terminal=false
+Categories=Network;FileTransfer;
+MimeType={};
+StartupWMClass=atom
+",
+        MIME_TYPES.join(";")
+    )
+}
+
+/**
+ * quoting rules of the desktop entry spec for Exec arguments
+ */
+fn quote_exec_arg(arg: &str) -> String {
+    if !arg.contains(|c: char| c.is_whitespace() || "\"'\\><~|&;$*?#()`".contains(c)) {
+        return arg.replace('%', "%%");
+    }
+
+    let escaped: String = arg
+        .chars()
+        .flat_map(|c| match c {
+            '"' | '`' | '$' | '\\' => vec!['\\', c],
+            '%' => vec!['%', '%'],
+            c => vec![c],
+        })
+        .collect();
+    // the key file unescapes the value before the quotes are read, so backslashes double again
+    format!("\"{}\"", escaped.replace('\\', "\\\\"))
+}
+
+fn write_icon(path: &Path) -> Result<(), String> {
+    let icon = image::load_from_memory(ATOM_ICON)
+        .map_err(|error| format!("cannot read the ATOM icon: {error}"))?
+        .resize_exact(ICON_SIZE, ICON_SIZE, image::imageops::FilterType::Lanczos3);
+
+    path.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .map_err(|error| error.to_string())
+        .and_then(|_| icon.save(path).map_err(|error| error.to_string()))
+        .map_err(|error| format!("cannot write {}: {error}", path.display()))
+}
+
+/**
+ * the XDG tools are optional, a missing one only means the desktop picks the file up later
+ */
+fn run_quietly<S: AsRef<std::ffi::OsStr>>(program: &str, args: &[S]) -> bool {
+    Command::new(program)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_arguments_are_quoted() {
+        for (arg, quoted) in [
+            ("/usr/bin/atom", "/usr/bin/atom"),
+            ("/opt/100%/atom", "/opt/100%%/atom"),
+            ("/home/me/My Apps/atom", r#""/home/me/My Apps/atom""#),
+            ("/tmp/50% off/atom", r#""/tmp/50%% off/atom""#),
+            ("/tmp/$HOME/atom", r#""/tmp/\\$HOME/atom""#),
+            (r#"/tmp/say "hi"/atom"#, r#""/tmp/say \\"hi\\"/atom""#),
+            ("/tmp/it's/atom", r#""/tmp/it's/atom""#),
+            ("/tmp/`id`/atom", r#""/tmp/\\`id\\`/atom""#),
+            (r"C:\atom", r#""C:\\\\atom""#),
+        ] {
+            assert_eq!(quote_exec_arg(arg), quoted, "{arg}");
+        }
+    }
+
+    #[test]
+    fn entries_open_links_with_atom() {
+        let entry = desktop_entry(Path::new("/opt/My Apps/atom"));
+        // `--config-dir` follows when one was picked
+        let exec = entry
+            .lines()
+            .find(|line| line.starts_with("Exec="))
+            .unwrap();
+        assert!(exec.starts_with("Exec=\"/opt/My Apps/atom\""), "{exec}");
+        assert!(exec.ends_with(" %U"), "{exec}");
+        assert!(entry.contains("x-scheme-handler/atom;"));
+    }
+}
@@ -1,39 +1,110 @@
 use crate::{
-    components::{
-        download::{AtomDownload, DownloadType},
-        settings::AtomSettings,
+    components::{download::AtomDownload, settings::AtomSettings},
+    utils::{
+        request::encode_body,
+        storage::{load_versioned, write_atomic, Migration, StorageError},
     },
-    messages::DownloadProperties,
-};
-use reqwest::{
-    header::{HeaderMap, HeaderName, HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, USER_AGENT},
-    Client, Method,
 };
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use ring::rand::{SecureRandom, SystemRandom};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::HashMap,
     path::{Path, PathBuf},
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tracing::debug;
+use tracing::{debug, warn};
+use uuid::Uuid;
 
 pub const ATOM_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 11_2_2) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/89.0.4389.72 Safari/537.36";
 pub const ATOM_INPUT_DEFAULT_PADDING: u16 = 6;
-pub const ATOM_SOCKET_ADDRESS: &str = "127.0.0.1:6682";
+pub const ATOM_MAX_THREADS: u8 = 16;
+pub const ATOM_DEFAULT_API_ADDRESS: &str = "127.0.0.1:6682";
+pub const ATOM_MAX_PAIRED_CLIENTS: usize = 20;
+pub const ATOM_INSTANCE_ID: &str = "fade9985-845c-4ca3-84b2-8a1b29a6c636";
 pub const ATOM_ICON: &[u8] = include_bytes!("../../resources/images/icon.ico");
 pub const METADATA_PANEL_WIDTH: u16 = 210;
 pub const SIDEBAR_WIDTH: u16 = 210;
+pub const ATOM_AUTOSAVE_INTERVAL: Duration = Duration::from_secs(5);
+pub const ATOM_SETTINGS_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub const DOWNLOADS_SCHEMA_VERSION: i64 = 2;
+pub const SETTINGS_SCHEMA_VERSION: i64 = 1;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TomlDownloads {
+    #[serde(default)]
+    pub version: i64,
     pub downloads: Vec<AtomDownload>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TomlSettings {
+    #[serde(default)]
+    pub version: i64,
     pub settings: AtomSettings,
 }
 
+const DOWNLOADS_MIGRATIONS: [Migration; 2] = [
+    // v0 -> v1: downloads get a stable id and a list position, the file order is kept
+    |table| {
+        if let Some(toml::Value::Array(downloads)) = table.get_mut("downloads") {
+            downloads
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(position, download)| Some((position, download.as_table_mut()?)))
+                .for_each(|(position, download)| {
+                    download
+                        .entry("id")
+                        .or_insert_with(|| Uuid::new_v4().to_string().into());
+                    download.insert("position".to_string(), (position as i64).into());
+                });
+        }
+    },
+    // v1 -> v2: the method is the HTTP method name and the body is saved as base64, v1 sent
+    // bodies as a form unless the headers said otherwise
+    |table| {
+        if let Some(toml::Value::Array(downloads)) = table.get_mut("downloads") {
+            downloads
+                .iter_mut()
+                .filter_map(toml::Value::as_table_mut)
+                .for_each(|download| {
+                    if let Some(toml::Value::String(method)) = download.get_mut("method") {
+                        *method = method.to_uppercase();
+                    }
+                    let Some(toml::Value::String(body)) = download.get_mut("request_body") else {
+                        return;
+                    };
+                    if body.is_empty() {
+                        return;
+                    }
+                    *body = encode_body(body.as_bytes());
+
+                    if let Some(headers) = download
+                        .entry("headers")
+                        .or_insert_with(|| toml::Table::new().into())
+                        .as_table_mut()
+                    {
+                        if !headers
+                            .keys()
+                            .any(|name| name.eq_ignore_ascii_case("content-type"))
+                        {
+                            headers.insert(
+                                "Content-Type".to_string(),
+                                "application/x-www-form-urlencoded".into(),
+                            );
+                        }
+                    }
+                });
+        }
+    },
+];
+
+const SETTINGS_MIGRATIONS: [Migration; 1] = [
+    // v0 -> v1: nothing but the version field changed
+    |_| {},
+];
+
 pub enum ListViewColumns {
     FileName,
     FileSize,
@@ -51,6 +122,18 @@ pub fn get_current_time_in_millis() -> usize {
         .as_millis() as usize
 }
 
+/**
+ * random hex secret used to pair the browser extension and scripts with the local API
+ */
+pub fn generate_api_token() -> String {
+    let mut bytes = [0u8; 24];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        warn!("Warning: system random generator failed, API token is time based");
+        bytes[..16].copy_from_slice(&(get_current_time_in_millis() as u128).to_le_bytes());
+    }
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
 pub fn get_formatted_time(time: u64) -> String {
     if time < 60 {
         format!("{:0>2} second(s)", time)
@@ -117,69 +200,6 @@ pub fn hashmap2headermap(headers: &HashMap<String, String>) -> HeaderMap {
     header_map
 }
 
-pub async fn get_content_length(
-    client: Client,
-    link: &str,
-    headers: &HashMap<String, String>,
-) -> DownloadProperties {
-    let mut size = DownloadProperties {
-        content_length: 0,
-        download_type: DownloadType::Sequential,
-        error: "".to_string(),
-    };
-
-    match client
-        .request(Method::HEAD, link)
-        .header(USER_AGENT, ATOM_USER_AGENT)
-        // .header("Referer", referrer)
-        .headers(hashmap2headermap(headers))
-        .send()
-        .await
-    {
-        Ok(response) => {
-            if !response.status().is_success() {
-                size.error = "Error, unable to get content length!".to_string();
-            } else {
-                let headers = response.headers();
-                match (headers.get(ACCEPT_RANGES), headers.get(CONTENT_LENGTH)) {
-                    // todo:
-                    // accept-ranges may be missing
-                    (Some(_), Some(cl)) => {
-                        let cl = cl
-                            .to_str()
-                            .unwrap_or_default()
-                            .parse::<u64>()
-                            .unwrap_or_default();
-
-                        size.content_length = cl as usize;
-                        size.download_type = DownloadType::Threaded;
-                    }
-                    (None, Some(cl)) => {
-                        let cl = cl
-                            .to_str()
-                            .unwrap_or_default()
-                            .parse::<u64>()
-                            .unwrap_or_default();
-
-                        size.content_length = cl as usize;
-                        size.download_type = DownloadType::Sequential;
-                    }
-                    (_, _) => {
-                        size.content_length = 0;
-                        size.download_type = DownloadType::Sequential;
-                    }
-                }
-            }
-        }
-        Err(_) => {
-            size.content_length = 0;
-            size.download_type = DownloadType::Sequential;
-        }
-    }
-
-    size
-}
-
 /**
  * get user's downloads directory, if param is passed that would be appended to the download path
  */
@@ -202,16 +222,6 @@ pub fn get_downloads_directory(file_name: &str) -> String {
     file_path
 }
 
-/**
- * get user's cache dir for storing temporary files
- */
-pub fn get_conf_directory<'a>() -> Result<PathBuf, &'a str> {
-    directories::BaseDirs::new().map_or_else(
-        || Err("basedir failed (get_conf_directory)!"),
-        |basedirs| Ok(basedirs.cache_dir().join("atom")),
-    )
-}
-
 /**
  * opens specified file according to the OS
  */
@@ -256,8 +266,16 @@ pub fn split_file_name(file_name: &str, split_count: u8) -> Vec<String> {
         .collect()
 }
 
+/**
+ * where the segments of a stream download wait to be joined
+ */
+pub fn stream_cache_dir(cache_dir: &Path, file_name: &str) -> PathBuf {
+    cache_dir.join(format!("{file_name}.atom.segments"))
+}
+
 pub fn save_settings_toml(settings: &AtomSettings) -> bool {
     let toml_settings = TomlSettings {
+        version: SETTINGS_SCHEMA_VERSION,
         settings: settings.to_owned(),
     };
 
@@ -268,7 +286,8 @@ pub fn save_settings_toml(settings: &AtomSettings) -> bool {
         }
         let path = path.join("settings.toml");
 
-        if std::fs::write(path, serialized).is_err() {
+        if let Err(error) = write_atomic(&path, &serialized) {
+            warn!("Error: writing {path:?} failed: {error}");
             return false;
         }
     }
@@ -276,44 +295,63 @@ pub fn save_settings_toml(settings: &AtomSettings) -> bool {
 }
 
 pub fn save_downloads_toml(downloads: Vec<AtomDownload>, toml_path: &PathBuf) -> bool {
-    let toml_downloads = TomlDownloads { downloads };
+    let toml_downloads = TomlDownloads {
+        version: DOWNLOADS_SCHEMA_VERSION,
+        downloads,
+    };
 
     if let Ok(serialized) = toml::to_string(&toml_downloads) {
-        if std::fs::write(toml_path, serialized).is_err() {
+        if let Err(error) = write_atomic(toml_path, &serialized) {
+            warn!("Error: writing {toml_path:?} failed: {error}");
             return false;
         }
     }
     true
 }
 
-pub fn parse_settings_toml(settings_path: &PathBuf) -> AtomSettings {
-    std::fs::read_to_string(settings_path).map_or_else(
-        |_| AtomSettings::default(),
-        |contents| {
-            toml::from_str::<TomlSettings>(&contents).map_or_else(
-                |_| AtomSettings::default(),
-                |toml_settings| toml_settings.settings,
-            )
-        },
-    )
+/**
+ * a missing file gives the defaults, a damaged one is an error so it never gets overwritten
+ */
+pub fn parse_settings_toml(settings_path: &Path) -> Result<AtomSettings, StorageError> {
+    load_versioned::<TomlSettings>(settings_path, SETTINGS_SCHEMA_VERSION, &SETTINGS_MIGRATIONS)
+        .map(|toml_settings| {
+            toml_settings.map_or_else(AtomSettings::default, |toml_settings| {
+                toml_settings.settings
+            })
+        })
 }
 
-pub fn parse_downloads_toml(downloads_file_path: &PathBuf) -> BTreeMap<usize, AtomDownload> {
-    let mut downloads: BTreeMap<usize, AtomDownload> = BTreeMap::new();
-
-    if let Ok(contents) = std::fs::read_to_string(downloads_file_path) {
-        if let Ok(deserialized) = toml::from_str::<TomlDownloads>(&contents) {
-            deserialized
-                .downloads
-                .into_iter()
-                .enumerate()
-                .for_each(|(index, download)| {
-                    downloads.insert(get_current_time_in_millis() + index, download);
-                });
-        }
+/**
+ * a missing file is an empty list, a damaged one is an error so it never gets overwritten
+ */
+pub fn parse_downloads_toml(
+    downloads_file_path: &Path,
+) -> Result<HashMap<Uuid, AtomDownload>, StorageError> {
+    let mut downloads: HashMap<Uuid, AtomDownload> = HashMap::new();
+
+    if let Some(mut deserialized) = load_versioned::<TomlDownloads>(
+        downloads_file_path,
+        DOWNLOADS_SCHEMA_VERSION,
+        &DOWNLOADS_MIGRATIONS,
+    )? {
+        deserialized
+            .downloads
+            .sort_by_key(|download| download.position);
+        deserialized
+            .downloads
+            .into_iter()
+            .enumerate()
+            .for_each(|(position, mut download)| {
+                // hand edited lists may repeat an id
+                if downloads.contains_key(&download.id) {
+                    download.id = Uuid::new_v4();
+                }
+                download.position = position as i64;
+                downloads.insert(download.id, download);
+            });
     }
 
-    downloads
+    Ok(downloads)
 }
 
 pub fn check_responsive_threshold(
@@ -339,3 +377,44 @@ pub fn check_responsive_threshold(
 
     scaled_width < threshold
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrate_v1(download: &str) -> toml::Table {
+        let mut table: toml::Table = format!("[[downloads]]\n{download}").parse().unwrap();
+        DOWNLOADS_MIGRATIONS[1](&mut table);
+        table["downloads"][0].as_table().unwrap().clone()
+    }
+
+    #[test]
+    fn v1_bodies_keep_their_form_content_type() {
+        let download = migrate_v1("method = \"post\"\nrequest_body = \"a=1&b=2\"");
+
+        assert_eq!(download["method"].as_str(), Some("POST"));
+        assert_eq!(
+            download["request_body"].as_str(),
+            Some(encode_body(b"a=1&b=2").as_str())
+        );
+        assert_eq!(
+            download["headers"]["Content-Type"].as_str(),
+            Some("application/x-www-form-urlencoded")
+        );
+    }
+
+    #[test]
+    fn v1_content_types_and_empty_bodies_are_left_alone() {
+        let download = migrate_v1(
+            "request_body = \"{}\"\n[downloads.headers]\ncontent-type = \"application/json\"",
+        );
+        assert_eq!(download["headers"].as_table().unwrap().len(), 1);
+        assert_eq!(
+            download["headers"]["content-type"].as_str(),
+            Some("application/json")
+        );
+
+        let download = migrate_v1("method = \"get\"\nrequest_body = \"\"");
+        assert!(!download.contains_key("headers"));
+    }
+}
@@ -1,13 +1,184 @@
+use crate::utils::redact::{redact_body, redact_headers, redact_url};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, path::Path};
 
-#[derive(Debug, Clone, Deserialize)]
+pub const ATOM_URI_SCHEME: &str = "atom";
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
 pub struct JSONFromBrowser {
     pub sequential: bool,
     pub method: String,
     pub body: String,
+    pub body_base64: bool, // binary bodies, the extension sends raw request bodies this way
     pub headers: HashMap<String, String>,
     pub url: String,
+    pub mirrors: Vec<String>, // other URLs serving the same file
     pub file_name: String,
     pub size: usize,
+    pub file_path: String, // downloads directory from settings when empty
+    pub threads: u8,       // threads from settings when 0
+    pub start: bool,       // adds without the confirmation window (scripts and the CLI)
+    pub torrent_files: Vec<usize>, // files of a torrent to download, all of them when empty
+    pub checksum: String,  // expected hash, `sha256:<hex>` or bare hex
+    // an `atom://` link any web page can open, never added without the confirmation window
+    #[serde(skip)]
+    pub from_uri: bool,
+}
+
+impl fmt::Debug for JSONFromBrowser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JSONFromBrowser")
+            .field("sequential", &self.sequential)
+            .field("method", &self.method)
+            .field("body", &redact_body(self.body.as_bytes()))
+            .field("body_base64", &self.body_base64)
+            .field("headers", &redact_headers(&self.headers))
+            .field("url", &redact_url(&self.url))
+            .field(
+                "mirrors",
+                &self
+                    .mirrors
+                    .iter()
+                    .map(|url| redact_url(url))
+                    .collect::<Vec<_>>(),
+            )
+            .field("file_name", &self.file_name)
+            .field("size", &self.size)
+            .field("file_path", &self.file_path)
+            .field("threads", &self.threads)
+            .field("start", &self.start)
+            .field("torrent_files", &self.torrent_files)
+            .field("checksum", &self.checksum)
+            .field("from_uri", &self.from_uri)
+            .finish()
+    }
+}
+
+impl JSONFromBrowser {
+    /**
+     * `atom://add?url=<URL>[&mirror=<URL>...][&name=<file name>][&referer=<URL>]` from links
+     * on web pages, the download always goes through the confirmation window and the name
+     * can't leave the downloads directory
+     */
+    pub fn from_atom_uri(uri: &str) -> Result<Self, String> {
+        let uri = reqwest::Url::parse(uri).map_err(|error| format!("invalid link: {error}"))?;
+        if uri.scheme() != ATOM_URI_SCHEME || uri.host_str() != Some("add") {
+            return Err(format!(
+                "unsupported link, expected {ATOM_URI_SCHEME}://add?url=..."
+            ));
+        }
+
+        let mut json = Self {
+            from_uri: true,
+            ..Default::default()
+        };
+        for (key, value) in uri.query_pairs() {
+            match &key[..] {
+                "url" => json.url = value.to_string(),
+                "mirror" => json.mirrors.push(value.to_string()),
+                "name" => {
+                    json.file_name = Path::new(&*value)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                }
+                "referer" => {
+                    json.headers
+                        .insert("referer".to_string(), value.to_string());
+                }
+                _ => {}
+            }
+        }
+
+        match reqwest::Url::parse(&json.url) {
+            Ok(url) if ["http", "https"].contains(&url.scheme()) => {}
+            _ => return Err("the link has no http(s) url parameter".to_string()),
+        }
+        json.mirrors.retain(|mirror| {
+            reqwest::Url::parse(mirror).is_ok_and(|url| ["http", "https"].contains(&url.scheme()))
+        });
+        Ok(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atom_links() {
+        let json = JSONFromBrowser::from_atom_uri(
+            "atom://add?url=https%3A%2F%2Fexample.com%2Ffile.iso\
+             &mirror=https%3A%2F%2Fmirror.example%2Ffile.iso&mirror=ftp%3A%2F%2Fftp.example%2Ffile.iso\
+             &name=Release.iso&referer=https%3A%2F%2Fexample.com%2Fdownloads&start=true",
+        )
+        .unwrap();
+
+        assert_eq!(json.url, "https://example.com/file.iso");
+        assert_eq!(json.mirrors, ["https://mirror.example/file.iso"]);
+        assert_eq!(json.file_name, "Release.iso");
+        assert_eq!(
+            json.headers,
+            HashMap::from([(
+                "referer".to_string(),
+                "https://example.com/downloads".to_string()
+            )])
+        );
+        // nothing in the link skips the confirmation window
+        assert!(json.from_uri);
+        assert!(!json.start);
+    }
+
+    #[test]
+    fn names_stay_in_the_downloads_directory() {
+        for (name, file_name) in [
+            ("..%2F..%2F.bashrc", ".bashrc"),
+            ("%2Fetc%2Fcron.d%2Fjob", "job"),
+            ("..", ""),
+            ("", ""),
+        ] {
+            let json = JSONFromBrowser::from_atom_uri(&format!(
+                "atom://add?url=https%3A%2F%2Fexample.com%2Ffile.iso&name={name}"
+            ))
+            .unwrap();
+            assert_eq!(json.file_name, file_name, "{name}");
+        }
+    }
+
+    #[test]
+    fn only_http_downloads() {
+        for (uri, error) in [
+            (
+                "atom://add?url=ftp%3A%2F%2Fexample.com%2Ffile.iso",
+                "the link has no http(s) url parameter",
+            ),
+            (
+                "atom://add?url=file%3A%2F%2F%2Fetc%2Fpasswd",
+                "the link has no http(s) url parameter",
+            ),
+            (
+                "atom://add?url=javascript%3Aalert(1)",
+                "the link has no http(s) url parameter",
+            ),
+            (
+                "atom://add?name=file.iso",
+                "the link has no http(s) url parameter",
+            ),
+            (
+                "atom://remove?url=https%3A%2F%2Fexample.com%2Ffile.iso",
+                "unsupported link, expected atom://add?url=...",
+            ),
+            (
+                "https://add?url=https%3A%2F%2Fexample.com%2Ffile.iso",
+                "unsupported link, expected atom://add?url=...",
+            ),
+        ] {
+            assert_eq!(
+                JSONFromBrowser::from_atom_uri(uri).map(|_| ()),
+                Err(error.to_string()),
+                "{uri}"
+            );
+        }
+    }
 }
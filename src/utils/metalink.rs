@@ -0,0 +1,246 @@
+use crate::utils::checksum::{Checksum, ChecksumAlgorithm};
+use roxmltree::{Document, Node};
+use std::path::Path;
+use tracing::warn;
+
+pub const METALINK_EXTENSIONS: [&str; 2] = ["meta4", "metalink"];
+const METALINK_V4_NAMESPACE: &str = "urn:ietf:params:xml:ns:metalink";
+// metalink 4 priorities run from 1 (best) to 999999, urls without one go last
+const LOWEST_PRIORITY: i64 = 999_999;
+
+/**
+ * one `<file>` of a metalink, reduced to what a download can use
+ */
+#[derive(Debug, Default)]
+pub struct MetalinkFile {
+    pub name: String,
+    pub size: usize,                // 0 when the metalink doesn't tell
+    pub urls: Vec<String>,          // http(s) only, most preferred first
+    pub checksum: Option<Checksum>, // the strongest hash ATOM can check
+}
+
+pub fn is_metalink(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        METALINK_EXTENSIONS.contains(&&extension.to_string_lossy().to_lowercase()[..])
+    })
+}
+
+pub fn read_metalink(path: &Path) -> Result<Vec<MetalinkFile>, String> {
+    let xml = std::fs::read_to_string(path)
+        .map_err(|error| format!("reading {} failed: {error}", path.display()))?;
+    parse_metalink(&xml)
+}
+
+/**
+ * reads Metalink 4 (RFC 5854, `.meta4`) and Metalink 3 (`.metalink`) documents, files
+ * without an http(s) url are skipped
+ */
+pub fn parse_metalink(xml: &str) -> Result<Vec<MetalinkFile>, String> {
+    let document = Document::parse(xml).map_err(|error| format!("invalid metalink: {error}"))?;
+    let root = document.root_element();
+    if root.tag_name().name() != "metalink" {
+        return Err("not a metalink document".to_string());
+    }
+    // version 3 uses its own namespace (or none at all) and keeps hashes in <verification>
+    let version_4 = root.tag_name().namespace() == Some(METALINK_V4_NAMESPACE);
+
+    let files: Vec<MetalinkFile> = root
+        .descendants()
+        .filter(|node| node.has_tag_name("file"))
+        .filter_map(|file| parse_file(file, version_4))
+        .collect();
+
+    if files.is_empty() {
+        Err("the metalink has no file with an http(s) url".to_string())
+    } else {
+        Ok(files)
+    }
+}
+
+fn parse_file(file: Node, version_4: bool) -> Option<MetalinkFile> {
+    // the name may carry directories, only the file name is kept so nothing lands outside
+    // the download folder
+    let name = Path::new(file.attribute("name").unwrap_or_default())
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if name.is_empty() {
+        warn!("Warning: metalink file without a usable name skipped");
+        return None;
+    }
+
+    let size = child_text(file, "size")
+        .and_then(|size| size.parse::<usize>().ok())
+        .unwrap_or_default();
+
+    let mut urls: Vec<(i64, String)> = file
+        .descendants()
+        .filter(|node| node.has_tag_name("url"))
+        .filter_map(|url| {
+            let link = url.text()?.trim().to_string();
+            reqwest::Url::parse(&link)
+                .is_ok_and(|parsed| matches!(parsed.scheme(), "http" | "https"))
+                .then_some(link)
+                .map(|link| (url_rank(url, version_4), link))
+        })
+        .collect();
+    urls.sort_by_key(|(rank, _)| *rank);
+    let mut urls: Vec<String> = urls.into_iter().map(|(_, url)| url).collect();
+    urls.dedup();
+    if urls.is_empty() {
+        warn!("Warning: metalink file {name} has no http(s) url, skipped");
+        return None;
+    }
+
+    // piece hashes sit deeper, only the whole file hashes count
+    let hash_parent = if version_4 { "file" } else { "verification" };
+    let checksum = file
+        .descendants()
+        .filter(|node| {
+            node.has_tag_name("hash")
+                && node
+                    .parent()
+                    .is_some_and(|parent| parent.has_tag_name(hash_parent))
+        })
+        .filter_map(|hash| {
+            Checksum::new(
+                ChecksumAlgorithm::from_name(hash.attribute("type")?)?,
+                hash.text()?,
+            )
+        })
+        .max_by_key(|checksum| checksum.algorithm);
+
+    Some(MetalinkFile {
+        name,
+        size,
+        urls,
+        checksum,
+    })
+}
+
+/**
+ * smaller ranks first: metalink 4 priorities go up from 1, metalink 3 preferences go down
+ * from 100
+ */
+fn url_rank(url: Node, version_4: bool) -> i64 {
+    if version_4 {
+        url.attribute("priority")
+            .and_then(|priority| priority.parse().ok())
+            .unwrap_or(LOWEST_PRIORITY)
+    } else {
+        -url.attribute("preference")
+            .and_then(|preference| preference.parse::<i64>().ok())
+            .unwrap_or_default()
+    }
+}
+
+fn child_text<'a>(node: Node<'a, 'a>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .and_then(|child| child.text())
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const MD5: &str = "d41d8cd98f00b204e9800998ecf8427e";
+
+    const META4: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="../images/example.iso">
+    <size>4096</size>
+    <hash type="md5">D41D8CD98F00B204E9800998ECF8427E</hash>
+    <hash type="sha-256">e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855</hash>
+    <pieces length="1024" type="sha-512">
+      <hash>00</hash>
+    </pieces>
+    <url>https://unranked.example/example.iso</url>
+    <url priority="2">https://second.example/example.iso</url>
+    <url priority="1">https://first.example/example.iso</url>
+    <url priority="1">https://first.example/example.iso</url>
+    <url priority="1">ftp://ftp.example/example.iso</url>
+  </file>
+  <file name="torrent-only.iso">
+    <metaurl mediatype="torrent">https://example.com/torrent-only.torrent</metaurl>
+  </file>
+</metalink>"#;
+
+    #[test]
+    fn metalink_4_files() {
+        let files = parse_metalink(META4).unwrap();
+        assert_eq!(files.len(), 1);
+        let file = &files[0];
+        assert_eq!(file.name, "example.iso");
+        assert_eq!(file.size, 4096);
+        assert_eq!(
+            file.urls,
+            [
+                "https://first.example/example.iso",
+                "https://second.example/example.iso",
+                "https://unranked.example/example.iso",
+            ]
+        );
+        // the strongest whole-file hash, pieces don't count
+        assert_eq!(
+            file.checksum,
+            Checksum::new(ChecksumAlgorithm::Sha256, SHA256)
+        );
+    }
+
+    #[test]
+    fn metalink_3_files() {
+        let xml = format!(
+            r#"<metalink version="3.0" xmlns="http://www.metalinker.org/">
+              <files>
+                <file name="example.tar.gz">
+                  <verification><hash type="md5">{MD5}</hash></verification>
+                  <resources>
+                    <url type="http" preference="10">http://low.example/example.tar.gz</url>
+                    <url type="http" preference="100">http://high.example/example.tar.gz</url>
+                  </resources>
+                </file>
+              </files>
+            </metalink>"#
+        );
+
+        let files = parse_metalink(&xml).unwrap();
+        assert_eq!(files[0].name, "example.tar.gz");
+        assert_eq!(files[0].size, 0);
+        assert_eq!(
+            files[0].urls,
+            [
+                "http://high.example/example.tar.gz",
+                "http://low.example/example.tar.gz",
+            ]
+        );
+        assert_eq!(
+            files[0].checksum,
+            Checksum::new(ChecksumAlgorithm::Md5, MD5)
+        );
+    }
+
+    #[test]
+    fn documents_without_a_download() {
+        assert_eq!(
+            parse_metalink("<rss/>").unwrap_err(),
+            "not a metalink document"
+        );
+        assert_eq!(
+            parse_metalink(
+                r#"<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+                  <file name="/"><url>https://example.com/</url></file>
+                </metalink>"#
+            )
+            .unwrap_err(),
+            "the metalink has no file with an http(s) url"
+        );
+        assert!(parse_metalink("<metalink>")
+            .unwrap_err()
+            .starts_with("invalid metalink"));
+        assert!(is_metalink(Path::new("downloads/Example.META4")));
+        assert!(!is_metalink(Path::new("example.xml")));
+    }
+}
@@ -1,2 +1,10 @@
+pub mod checksum;
+pub mod desktop;
 pub mod helpers;
 pub mod json_from_browser;
+pub mod metalink;
+pub mod netrc;
+pub mod paths;
+pub mod redact;
+pub mod request;
+pub mod storage;
@@ -0,0 +1,66 @@
+use directories::BaseDirs;
+use std::path::PathBuf;
+
+// curl, wget and ftp clients read the same file, `$NETRC` points somewhere else
+const NETRC_ENV: &str = "NETRC";
+#[cfg(target_os = "windows")]
+const NETRC_FILE: &str = "_netrc";
+#[cfg(not(target_os = "windows"))]
+const NETRC_FILE: &str = ".netrc";
+
+/**
+ * login and password saved for `host` in the user's netrc file, the `default` entry when no
+ * machine matches
+ */
+pub fn credentials(host: &str) -> Option<(String, String)> {
+    let path = std::env::var_os(NETRC_ENV)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| BaseDirs::new().map(|dirs| dirs.home_dir().join(NETRC_FILE)))?;
+    let content = std::fs::read_to_string(path).ok()?;
+    parse(&content, host)
+}
+
+fn parse(content: &str, host: &str) -> Option<(String, String)> {
+    let mut tokens = content
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(str::split_whitespace);
+    let mut found = None;
+    // the entry being read: whether it applies, and its login and password so far
+    let mut entry: Option<(bool, String, String)> = None;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" => {
+                if let Some((true, login, password)) = entry.take() {
+                    found.get_or_insert((login, password));
+                }
+                let matches = match token {
+                    "machine" => tokens.next().is_some_and(|machine| machine == host),
+                    _ => found.is_none(),
+                };
+                entry = Some((matches, String::new(), String::new()));
+            }
+            "login" | "password" => {
+                let value = tokens.next().unwrap_or_default().to_string();
+                if let Some((_, login, password)) = entry.as_mut() {
+                    match token {
+                        "login" => *login = value,
+                        _ => *password = value,
+                    }
+                }
+            }
+            // their values aren't keywords, macro bodies are not supported
+            "account" | "macdef" => {
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+    if let Some((true, login, password)) = entry {
+        found.get_or_insert((login, password));
+    }
+
+    found.filter(|(login, _)| !login.is_empty())
+}
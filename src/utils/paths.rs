@@ -0,0 +1,271 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+use tracing::{info, warn};
+
+pub const ATOM_CONFIG_DIR_ENV: &str = "ATOM_CONFIG_DIR";
+// an empty file with this name next to the executable turns on portable mode
+const PORTABLE_MARKER: &str = "portable";
+const PORTABLE_DIR: &str = "atom-data";
+const STATE_FILES: [(&str, StateKind); 2] = [
+    ("settings.toml", StateKind::Config),
+    ("downloads.toml", StateKind::Data),
+];
+
+static CONFIG_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+static DIRS: OnceLock<AtomDirs> = OnceLock::new();
+
+#[derive(Clone, Copy)]
+enum StateKind {
+    Config,
+    Data,
+}
+
+/**
+ * where ATOM keeps its files:
+ *  config  settings.toml
+ *  data    downloads.toml
+ *  cache   chunk files, safe to wipe while nothing is downloading
+ */
+#[derive(Debug, Clone)]
+pub struct AtomDirs {
+    pub config: PathBuf,
+    pub data: PathBuf,
+    pub cache: PathBuf,
+    // where versions before the split kept settings and downloads
+    legacy: Option<PathBuf>,
+}
+
+impl AtomDirs {
+    /**
+     * `--config-dir`, then `ATOM_CONFIG_DIR`, then portable mode, then the OS directories
+     */
+    fn resolve() -> Self {
+        let single = |dir: PathBuf| Self {
+            cache: dir.join("cache"),
+            config: dir.clone(),
+            data: dir,
+            legacy: None,
+        };
+
+        if let Some(dir) = CONFIG_DIR_OVERRIDE.get() {
+            return single(dir.clone());
+        }
+
+        if let Some(dir) = std::env::var_os(ATOM_CONFIG_DIR_ENV).filter(|dir| !dir.is_empty()) {
+            return single(PathBuf::from(dir));
+        }
+
+        if let Some(dir) = std::env::current_exe()
+            .ok()
+            .and_then(|exe| portable_dir(exe.parent()?))
+        {
+            return single(dir);
+        }
+
+        match directories::BaseDirs::new() {
+            Some(base_dirs) => {
+                let legacy = base_dirs.cache_dir().join("atom");
+                Self {
+                    config: base_dirs.config_dir().join("atom"),
+                    data: base_dirs.data_dir().join("atom"),
+                    // chunk files stay where they always were
+                    cache: legacy.join("cache"),
+                    legacy: Some(legacy),
+                }
+            }
+            None => {
+                warn!("Error: no home directory found, keeping files in the working directory");
+                single(PathBuf::from(PORTABLE_DIR))
+            }
+        }
+    }
+
+    fn dir(&self, kind: StateKind) -> &Path {
+        match kind {
+            StateKind::Config => &self.config,
+            StateKind::Data => &self.data,
+        }
+    }
+
+    pub fn settings_file(&self) -> PathBuf {
+        self.config.join("settings.toml")
+    }
+
+    pub fn downloads_file(&self) -> PathBuf {
+        self.data.join("downloads.toml")
+    }
+
+    pub fn create(&self) -> io::Result<()> {
+        [&self.config, &self.data, &self.cache]
+            .into_iter()
+            .try_for_each(fs::create_dir_all)
+    }
+
+    /**
+     * moves settings, downloads and their backups out of the old cache location, files that
+     * already exist at the new location win
+     */
+    pub fn migrate_legacy_files(&self) {
+        let Some(legacy) = &self.legacy else {
+            return;
+        };
+
+        let Ok(entries) = fs::read_dir(legacy) else {
+            return;
+        };
+        entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name())
+            .for_each(|name| {
+                let Some((_, kind)) = STATE_FILES
+                    .iter()
+                    .find(|(file_name, _)| name.to_string_lossy().starts_with(file_name))
+                else {
+                    return;
+                };
+                let from = legacy.join(&name);
+                let to = self.dir(*kind).join(&name);
+                if to.exists() {
+                    return;
+                }
+                match move_file(&from, &to) {
+                    Ok(()) => info!("moved {from:?} to {to:?}"),
+                    Err(error) => warn!("Error: moving {from:?} to {to:?} failed: {error}"),
+                }
+            });
+    }
+}
+
+/**
+ * has to run before anything asks for a directory, later calls are ignored
+ */
+pub fn set_config_dir(dir: PathBuf) {
+    let dir = std::path::absolute(&dir).unwrap_or(dir);
+    CONFIG_DIR_OVERRIDE.set(dir).ok();
+}
+
+/**
+ * the directory picked with `--config-dir` or `ATOM_CONFIG_DIR`, launchers have to pass it on
+ */
+pub fn explicit_config_dir() -> Option<PathBuf> {
+    CONFIG_DIR_OVERRIDE.get().cloned().or_else(|| {
+        std::env::var_os(ATOM_CONFIG_DIR_ENV)
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| std::path::absolute(&dir).unwrap_or(dir.into()))
+    })
+}
+
+pub fn atom_dirs() -> &'static AtomDirs {
+    DIRS.get_or_init(AtomDirs::resolve)
+}
+
+/**
+ * the data directory next to the executable when the portable marker is there
+ */
+fn portable_dir(exe_dir: &Path) -> Option<PathBuf> {
+    exe_dir
+        .join(PORTABLE_MARKER)
+        .is_file()
+        .then(|| exe_dir.join(PORTABLE_DIR))
+}
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // rename fails across file systems, e.g. when ~/.cache is a tmpfs
+    fs::rename(from, to).or_else(|_| {
+        fs::copy(from, to)?;
+        fs::remove_file(from)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "atom-paths-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn portable_marker_next_to_the_executable() {
+        let dir = TestDir::new();
+        assert_eq!(portable_dir(&dir.0), None);
+
+        // a directory of that name isn't the marker
+        fs::create_dir(dir.0.join(PORTABLE_MARKER)).unwrap();
+        assert_eq!(portable_dir(&dir.0), None);
+        fs::remove_dir(dir.0.join(PORTABLE_MARKER)).unwrap();
+
+        fs::write(dir.0.join(PORTABLE_MARKER), "").unwrap();
+        assert_eq!(portable_dir(&dir.0), Some(dir.0.join(PORTABLE_DIR)));
+    }
+
+    #[test]
+    fn legacy_files_move_to_their_directories() {
+        let dir = TestDir::new();
+        let legacy = dir.0.join("cache-atom");
+        let dirs = AtomDirs {
+            config: dir.0.join("config"),
+            data: dir.0.join("data"),
+            cache: legacy.join("cache"),
+            legacy: Some(legacy.clone()),
+        };
+        fs::create_dir_all(&dirs.cache).unwrap();
+        for name in [
+            "settings.toml",
+            "settings.toml.bak.1",
+            "downloads.toml",
+            "downloads.toml.bak.2",
+            "unrelated.txt",
+        ] {
+            fs::write(legacy.join(name), format!("old {name}")).unwrap();
+        }
+        // a file already at the new location wins
+        fs::create_dir_all(&dirs.data).unwrap();
+        fs::write(dirs.downloads_file(), "new").unwrap();
+
+        dirs.migrate_legacy_files();
+
+        assert_eq!(
+            fs::read_to_string(dirs.settings_file()).unwrap(),
+            "old settings.toml"
+        );
+        assert!(dirs.config.join("settings.toml.bak.1").exists());
+        assert!(dirs.data.join("downloads.toml.bak.2").exists());
+        assert_eq!(fs::read_to_string(dirs.downloads_file()).unwrap(), "new");
+
+        let mut left: Vec<String> = fs::read_dir(&legacy)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["cache", "downloads.toml", "unrelated.txt"]);
+
+        // nothing left to move the second time
+        dirs.migrate_legacy_files();
+        assert_eq!(fs::read_to_string(dirs.downloads_file()).unwrap(), "new");
+    }
+}
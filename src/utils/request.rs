@@ -0,0 +1,68 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::Method;
+use serde::{Deserialize, Deserializer, Serializer};
+
+// matched case-insensitively, anything else is sent the way it was captured
+const STANDARD_METHODS: [Method; 9] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+    Method::HEAD,
+    Method::OPTIONS,
+    Method::CONNECT,
+    Method::TRACE,
+];
+
+/**
+ * the method of a replayed request, GET when none was given
+ */
+pub fn parse_method(method: &str) -> Result<String, String> {
+    let method = method.trim();
+    if method.is_empty() {
+        return Ok(Method::GET.to_string());
+    }
+    if let Some(standard) = STANDARD_METHODS
+        .iter()
+        .find(|standard| standard.as_str().eq_ignore_ascii_case(method))
+    {
+        return Ok(standard.to_string());
+    }
+    Method::from_bytes(method.as_bytes())
+        .map(|method| method.to_string())
+        .map_err(|_| format!("`{method}` is not an HTTP method"))
+}
+
+/**
+ * the body bytes of a replayed request, binary bodies come base64 encoded
+ */
+pub fn decode_body(body: &str, base64: bool) -> Result<Vec<u8>, String> {
+    if base64 {
+        STANDARD
+            .decode(body.trim())
+            .map_err(|error| format!("the body is not valid base64, {error}"))
+    } else {
+        Ok(body.as_bytes().to_vec())
+    }
+}
+
+pub fn encode_body(body: &[u8]) -> String {
+    STANDARD.encode(body)
+}
+
+/**
+ * request bodies are saved as base64, so binary ones survive downloads.toml unchanged
+ */
+pub mod base64_body {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(body: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(body))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let body = String::deserialize(deserializer)?;
+        STANDARD.decode(body).map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,346 @@
+use serde::de::DeserializeOwned;
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use toml::{Table, Value};
+use tracing::warn;
+
+const BACKUP_COUNT: usize = 3;
+// saves happen all the time, backups only roll when the newest one is this old
+const BACKUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/**
+ * modification time and size of a file, cheap enough to poll for changes made by other programs
+ */
+pub type FileStamp = Option<(SystemTime, u64)>;
+
+/**
+ * upgrades a file one schema version, the migration at index N turns version N into N + 1
+ */
+pub type Migration = fn(&mut Table);
+
+#[derive(Debug)]
+pub enum StorageError {
+    Unreadable(String),
+    Corrupt(String),
+    Newer(i64), // written by a newer ATOM, this one would lose data by rewriting it
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreadable(error) => write!(f, "it can't be read ({error})"),
+            Self::Corrupt(error) => write!(f, "it is damaged ({error})"),
+            Self::Newer(version) => write!(
+                f,
+                "it was written by a newer ATOM (schema version {version})"
+            ),
+        }
+    }
+}
+
+/**
+ * writes to a temporary file next to the target, syncs it and renames it over the target so
+ * a crash leaves either the old or the new file, never a truncated one
+ */
+pub fn write_atomic(path: &Path, contents: &str) -> io::Result<()> {
+    let temp_path = sibling_path(path, ".tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    if let Err(error) = rotate_backups(path) {
+        warn!("Error: rotating backups of {path:?} failed: {error}");
+    }
+
+    fs::rename(&temp_path, path)?;
+
+    // the rename itself only survives a crash once the directory is synced
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        File::open(parent).and_then(|dir| dir.sync_all()).ok();
+    }
+
+    Ok(())
+}
+
+/**
+ * newest first, `<file>.bak.1` is the most recent one
+ */
+pub fn backup_path(path: &Path, index: usize) -> PathBuf {
+    sibling_path(path, &format!(".bak.{index}"))
+}
+
+pub fn existing_backups(path: &Path) -> Vec<PathBuf> {
+    (1..=BACKUP_COUNT)
+        .map(|index| backup_path(path, index))
+        .filter(|backup| backup.exists())
+        .collect()
+}
+
+fn rotate_backups(path: &Path) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let newest = backup_path(path, 1);
+    let recent = fs::metadata(&newest)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age < BACKUP_INTERVAL);
+    if recent {
+        return Ok(());
+    }
+
+    for index in (1..BACKUP_COUNT).rev() {
+        let backup = backup_path(path, index);
+        if backup.exists() {
+            fs::rename(&backup, backup_path(path, index + 1))?;
+        }
+    }
+    fs::copy(path, newest).map(|_| ())
+}
+
+/**
+ * moves a damaged file out of the way so it is neither loaded nor overwritten, returns where
+ * it was moved to
+ */
+pub fn set_aside(path: &Path) -> io::Result<PathBuf> {
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let damaged = sibling_path(path, &format!(".damaged-{timestamp}"));
+    fs::rename(path, &damaged)?;
+    Ok(damaged)
+}
+
+/**
+ * reads a versioned TOML file, running the migrations it needs before deserializing it,
+ * a missing file is `Ok(None)`
+ */
+pub fn load_versioned<T: DeserializeOwned>(
+    path: &Path,
+    version: i64,
+    migrations: &[Migration],
+) -> Result<Option<T>, StorageError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(StorageError::Unreadable(error.to_string())),
+    };
+
+    let mut table = contents
+        .parse::<Table>()
+        .map_err(|error| StorageError::Corrupt(error.message().to_string()))?;
+
+    // files written before versioning have no version field
+    let file_version = match table.get("version") {
+        None => 0,
+        Some(Value::Integer(file_version)) if *file_version >= 0 => *file_version,
+        Some(_) => return Err(StorageError::Corrupt("invalid version".to_string())),
+    };
+    if file_version > version {
+        return Err(StorageError::Newer(file_version));
+    }
+
+    migrations
+        .iter()
+        .skip(file_version as usize)
+        .for_each(|migration| migration(&mut table));
+    table.insert("version".to_string(), Value::Integer(version));
+
+    Value::Table(table)
+        .try_into()
+        .map(Some)
+        .map_err(|error: toml::de::Error| StorageError::Corrupt(error.message().to_string()))
+}
+
+pub fn file_stamp(path: &Path) -> FileStamp {
+    fs::metadata(path)
+        .and_then(|metadata| Ok((metadata.modified()?, metadata.len())))
+        .ok()
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const VERSION: i64 = 2;
+    const MIGRATIONS: [Migration; 2] = [
+        // v0 -> v1: `title` became `name`
+        |table| {
+            if let Some(title) = table.remove("title") {
+                table.insert("name".to_string(), title);
+            }
+        },
+        // v1 -> v2: `count` was added
+        |table| {
+            table.entry("count").or_insert(Value::Integer(1));
+        },
+    ];
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Stored {
+        version: i64,
+        name: String,
+        count: i64,
+    }
+
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "atom-storage-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn file(&self) -> PathBuf {
+            self.0.join("settings.toml")
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    // lets the next save roll the backups
+    fn age_newest_backup(path: &Path) {
+        File::options()
+            .write(true)
+            .open(backup_path(path, 1))
+            .unwrap()
+            .set_modified(SystemTime::now() - BACKUP_INTERVAL * 2)
+            .unwrap();
+    }
+
+    fn load(path: &Path) -> Result<Option<Stored>, StorageError> {
+        load_versioned(path, VERSION, &MIGRATIONS)
+    }
+
+    #[test]
+    fn writes_replace_the_file() {
+        let dir = TestDir::new();
+        let path = dir.file();
+
+        write_atomic(&path, "first").unwrap();
+        assert_eq!(read(&path), "first");
+        assert!(existing_backups(&path).is_empty());
+
+        write_atomic(&path, "second").unwrap();
+        assert_eq!(read(&path), "second");
+        assert_eq!(read(&backup_path(&path, 1)), "first");
+        let names: Vec<String> = fs::read_dir(&dir.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert!(
+            !names.iter().any(|name| name.ends_with(".tmp")),
+            "{names:?}"
+        );
+    }
+
+    #[test]
+    fn backups_rotate_at_most_hourly() {
+        let dir = TestDir::new();
+        let path = dir.file();
+        write_atomic(&path, "1").unwrap();
+        write_atomic(&path, "2").unwrap();
+        // the newest backup is recent, so it stays the one from before
+        write_atomic(&path, "3").unwrap();
+        assert_eq!(existing_backups(&path), [backup_path(&path, 1)]);
+        assert_eq!(read(&backup_path(&path, 1)), "1");
+
+        for content in ["4", "5", "6"] {
+            age_newest_backup(&path);
+            write_atomic(&path, content).unwrap();
+        }
+        assert_eq!(read(&path), "6");
+        let backups: Vec<String> = existing_backups(&path)
+            .iter()
+            .map(|backup| read(backup))
+            .collect();
+        assert_eq!(backups, ["5", "4", "3"]);
+        assert!(!backup_path(&path, BACKUP_COUNT + 1).exists());
+    }
+
+    #[test]
+    fn damaged_files_are_set_aside_for_a_backup() {
+        let dir = TestDir::new();
+        let path = dir.file();
+        write_atomic(&path, "version = 2\nname = \"kept\"\ncount = 3\n").unwrap();
+        write_atomic(&path, "version = 2\nname = [\"cut short").unwrap();
+
+        assert!(matches!(load(&path), Err(StorageError::Corrupt(_))));
+        let damaged = set_aside(&path).unwrap();
+        assert!(!path.exists());
+        assert!(read(&damaged).contains("cut short"));
+        assert!(damaged
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("settings.toml.damaged-"));
+
+        let restored = existing_backups(&path)
+            .into_iter()
+            .find_map(|backup| load(&backup).ok().flatten());
+        assert_eq!(
+            restored,
+            Some(Stored {
+                version: 2,
+                name: "kept".to_string(),
+                count: 3
+            })
+        );
+        // nothing to load is not an error
+        assert!(matches!(load(&path), Ok(None)));
+    }
+
+    #[test]
+    fn older_files_are_migrated() {
+        let dir = TestDir::new();
+        let path = dir.file();
+        let stored = |name: &str, count| Stored {
+            version: VERSION,
+            name: name.to_string(),
+            count,
+        };
+
+        // before versioning, every migration runs
+        write_atomic(&path, "title = \"old\"\n").unwrap();
+        assert_eq!(load(&path).unwrap(), Some(stored("old", 1)));
+        // a version 1 file only needs the second one
+        write_atomic(&path, "version = 1\ntitle = \"x\"\nname = \"v1\"\n").unwrap();
+        assert_eq!(load(&path).unwrap(), Some(stored("v1", 1)));
+        write_atomic(&path, "version = 2\nname = \"v2\"\ncount = 5\n").unwrap();
+        assert_eq!(load(&path).unwrap(), Some(stored("v2", 5)));
+
+        write_atomic(&path, "version = 3\nname = \"new\"\ncount = 5\n").unwrap();
+        assert!(matches!(load(&path), Err(StorageError::Newer(3))));
+        write_atomic(&path, "version = \"2\"\n").unwrap();
+        assert!(matches!(load(&path), Err(StorageError::Corrupt(_))));
+    }
+}